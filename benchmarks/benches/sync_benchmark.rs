@@ -6,6 +6,7 @@ use yrs::{Doc, Text, Transact};
 
 // Server dependencies
 use syncline::server::db::Db;
+use syncline::server::storage::Storage;
 
 fn bench_apply_diff(c: &mut Criterion) {
     let mut group = c.benchmark_group("Client Apply Diff");
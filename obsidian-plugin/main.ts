@@ -23,12 +23,19 @@ interface SynclineSettings {
   autoSync: boolean;
   /** Stable per-installation ActorId (UUIDv4, hyphenated). Minted on first run. */
   actorId: string | null;
+  /**
+   * Binaries larger than this many MB are downloaded when first opened
+   * instead of up front; until then they are placeholder files. `null`
+   * downloads everything.
+   */
+  fetchOnOpenOverMb: number | null;
 }
 
 const DEFAULT_SETTINGS: SynclineSettings = {
   serverUrl: "ws://localhost:3030/sync",
   autoSync: true,
   actorId: null,
+  fetchOnOpenOverMb: null,
 };
 
 type SyncStatus = "synced" | "syncing" | "error" | "disconnected";
@@ -66,6 +73,7 @@ interface SynclineV1Client {
   onContentChanged(cb: (nodeId: string) => void): void;
   onBlob(cb: (hash: string, data: Uint8Array) => void): void;
   onStatus(cb: (status: string) => void): void;
+  onError(cb: (kind: string, docId: string, message: string) => void): void;
   connect(): void;
   disconnect(): void;
   isConnected(): boolean;
@@ -86,6 +94,8 @@ interface SynclineV1Client {
   contentSnapshot(nodeIdHex: string): Uint8Array | undefined;
   sendBlob(bytes: Uint8Array): string;
   requestBlob(blobHashHex: string): void;
+  placeholderBytes(blobHashHex: string, size: number): Uint8Array;
+  placeholderHash(bytes: Uint8Array): string | undefined;
   free(): void;
 }
 
@@ -342,6 +352,9 @@ export default class SynclinePlugin extends Plugin {
   statusCheckInterval: number | null = null;
   reconnectTimeout: number | null = null;
   reconnectAttempts = 0;
+  /** Set when the server refuses our protocol version: reconnecting
+   *  can't help until one side is upgraded. Cleared by `disconnect`. */
+  incompatibleServer = false;
 
   // ---------------------------------------------------------------
   // Lifecycle
@@ -383,6 +396,7 @@ export default class SynclinePlugin extends Plugin {
     this.registerEvent(this.app.vault.on("create", this.onFileCreate));
     this.registerEvent(this.app.vault.on("delete", this.onFileDelete));
     this.registerEvent(this.app.vault.on("rename", this.onFileRename));
+    this.registerEvent(this.app.workspace.on("file-open", this.onFileOpen));
 
     if (this.settings.autoSync) {
       await this.connect();
@@ -725,6 +739,9 @@ export default class SynclinePlugin extends Plugin {
       this.client.onStatus((s) => {
         console.debug("[Syncline] status:", s);
       });
+      this.client.onError((kind, docId, message) => {
+        this.onServerError(kind, docId, message);
+      });
 
       this.client.connect();
 
@@ -775,7 +792,7 @@ export default class SynclinePlugin extends Plugin {
   }
 
   scheduleReconnect() {
-    if (this.reconnectTimeout !== null) return;
+    if (this.reconnectTimeout !== null || this.incompatibleServer) return;
     const delay = Math.min(1000 * Math.pow(2, this.reconnectAttempts), 30000);
     this.reconnectAttempts++;
     console.debug(`[Syncline] reconnect in ${delay}ms (attempt ${this.reconnectAttempts})`);
@@ -792,6 +809,7 @@ export default class SynclinePlugin extends Plugin {
       this.reconnectTimeout = null;
     }
     this.reconnectAttempts = 0;
+    this.incompatibleServer = false;
     if (this.client) {
       this.client.disconnect();
       this.client.free();
@@ -1225,9 +1243,15 @@ export default class SynclinePlugin extends Plugin {
   private async ensureBinaryInSync(row: ProjectionRow): Promise<void> {
     if (!this.client || !row.blob_hash) return;
     const file = this.app.vault.getAbstractFileByPath(row.path);
+    const onOpen = this.fetchesOnOpen(row);
     if (file instanceof TFile) {
       try {
         const data = await this.app.vault.readBinary(file);
+        const stub = this.client.placeholderHash(new Uint8Array(data));
+        if (stub !== undefined && onOpen) {
+          if (stub !== row.blob_hash) await this.writePlaceholder(row);
+          return;
+        }
         const localHash = await sha256Hex(data);
         if (localHash === row.blob_hash) return;
         this.tryRequestBlob(row.blob_hash);
@@ -1236,6 +1260,8 @@ export default class SynclinePlugin extends Plugin {
         if (isMissingFileError(e)) return;
         console.error(`[Syncline] ensureBinaryInSync ${row.path}:`, e);
       }
+    } else if (onOpen) {
+      await this.writePlaceholder(row);
     } else {
       // File missing on disk — request unconditionally. tryRequestBlob
       // dedupes per-hash, but for the missing-file branch that's wrong:
@@ -1254,6 +1280,66 @@ export default class SynclinePlugin extends Plugin {
     }
   }
 
+  /** True if `row`'s blob waits until the file is opened. */
+  private fetchesOnOpen(row: ProjectionRow): boolean {
+    const limit = this.settings.fetchOnOpenOverMb;
+    return limit !== null && row.size > limit * 1024 * 1024;
+  }
+
+  /**
+   * Put a placeholder at `row.path` for a binary fetched on open. The
+   * stub names the blob; `onFileOpen` requests it and `onBlobReceived`
+   * writes the real bytes over it.
+   */
+  private async writePlaceholder(row: ProjectionRow): Promise<void> {
+    if (!this.client || !row.blob_hash) return;
+    const bytes = this.client.placeholderBytes(row.blob_hash, row.size);
+    const buffer = bytes.buffer.slice(
+      bytes.byteOffset,
+      bytes.byteOffset + bytes.byteLength,
+    ) as ArrayBuffer;
+    const file = this.app.vault.getAbstractFileByPath(row.path);
+    const kind: 'modify' | 'create' = file instanceof TFile ? 'modify' : 'create';
+    this.ignoreEvents[kind].add(row.path);
+    try {
+      if (file instanceof TFile) {
+        await this.app.vault.modifyBinary(file, buffer);
+      } else {
+        await this.ensureParentFolders(row.path);
+        await this.app.vault.createBinary(row.path, buffer);
+      }
+    } catch (e) {
+      if (!isAlreadyExistsError(e)) {
+        console.error(`[Syncline] write placeholder ${row.path}:`, e);
+      }
+    } finally {
+      setTimeout(() => this.ignoreEvents[kind].delete(row.path), IGNORE_CHANGES_TIMEOUT_MS);
+    }
+  }
+
+  /** Fetch a placeholder's blob the first time its file is opened. */
+  onFileOpen = (file: TFile | null) => {
+    if (!file || !this.client) return;
+    const row = this.lastProjection.get(file.path);
+    if (!row || row.kind !== "binary" || !row.blob_hash || !this.fetchesOnOpen(row)) return;
+    const hash = row.blob_hash;
+    void (async () => {
+      try {
+        const data = await this.app.vault.readBinary(file);
+        if (this.client?.placeholderHash(new Uint8Array(data)) === undefined) return;
+      } catch (e) {
+        if (!isMissingFileError(e)) console.error(`[Syncline] open ${file.path}:`, e);
+        return;
+      }
+      if (!this.client?.isConnected()) {
+        new Notice(`Syncline: connect to download ${file.name}`);
+        return;
+      }
+      new Notice(`Syncline: downloading ${file.name}…`);
+      this.tryRequestBlob(hash);
+    })();
+  };
+
   /**
    * Request a blob from the server, but only if the WebSocket is
    * actually connected. The reconcile loop runs eagerly after the
@@ -1280,6 +1366,28 @@ export default class SynclinePlugin extends Plugin {
     }
   }
 
+  /** What the user sees for each `MSG_ERROR` the server sends. */
+  private onServerError(kind: string, docId: string, message: string): void {
+    console.warn(`[Syncline] server error (${kind}) for ${docId}: ${message}`);
+    switch (kind) {
+      case "incompatible":
+        this.incompatibleServer = true;
+        new Notice(`Syncline: ${message}. Update the plugin or the server.`);
+        break;
+      case "blob-too-large":
+        new Notice(`Syncline: the server refused a file as too large (${message})`);
+        break;
+      case "blob-not-found":
+        // Not uploaded yet: the next reconcile asks again.
+        this.requestedBlobs.delete(docId);
+        break;
+      case "storage":
+        this.requestedBlobs.delete(docId);
+        new Notice("Syncline: the server failed to save or load a file; retrying");
+        break;
+    }
+  }
+
   private async onBlobReceived(hash: string, bytes: Uint8Array): Promise<void> {
     // A blob may satisfy multiple projection rows (e.g. a conflict copy).
     const matches = Array.from(this.lastProjection.values()).filter(
@@ -1350,6 +1458,9 @@ export default class SynclinePlugin extends Plugin {
       try {
         const data = await this.app.vault.readBinary(file);
         if (this.ignoreEvents.modify.has(file.path)) return;
+        // Placeholder bytes stand for a blob we haven't fetched; they
+        // are never the file's content.
+        if (this.client.placeholderHash(new Uint8Array(data)) !== undefined) return;
         const hash = await sha256Hex(data);
         if (row.blob_hash === hash) return;
         this.client.sendBlob(new Uint8Array(data));
@@ -1406,6 +1517,7 @@ export default class SynclinePlugin extends Plugin {
         void this.subscribeToContent(nodeId, content, null);
       } else {
         const data = await this.app.vault.readBinary(file);
+        if (this.client.placeholderHash(new Uint8Array(data)) !== undefined) return;
         const hash = await sha256Hex(data);
         this.client.sendBlob(new Uint8Array(data));
         try {
@@ -1508,6 +1620,24 @@ class SynclineSettingTab extends PluginSettingTab {
         }),
       );
 
+    new Setting(containerEl).setName("Storage").setHeading();
+    new Setting(containerEl)
+      .setName("Download large attachments when opened")
+      .setDesc(
+        "Attachments larger than this many MB are downloaded the first time you open them; until then they are small placeholder files. Leave empty to download everything.",
+      )
+      .addText((text) =>
+        text
+          .setPlaceholder("Download everything")
+          .setValue(this.plugin.settings.fetchOnOpenOverMb?.toString() ?? "")
+          .onChange(async (value) => {
+            const mb = Number.parseFloat(value);
+            this.plugin.settings.fetchOnOpenOverMb =
+              value.trim() === "" || !Number.isFinite(mb) || mb < 0 ? null : mb;
+            await this.plugin.saveSettings();
+          }),
+      );
+
     new Setting(containerEl).setName("Identity").setHeading();
     new Setting(containerEl)
       .setName("Actor ID")
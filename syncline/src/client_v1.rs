@@ -28,28 +28,49 @@
 //!   - conflict-copy path suffixing
 
 use crate::protocol::{
-    MANIFEST_DOC_ID, MAX_BLOB_SIZE, MSG_BLOB_REQUEST, MSG_BLOB_UPDATE, MSG_MANIFEST_SYNC,
-    MSG_MANIFEST_VERIFY, MSG_SYNC_STEP_1, MSG_SYNC_STEP_2, MSG_UPDATE, MSG_VERSION,
-    V1_PROTOCOL_MAJOR, V1_PROTOCOL_MINOR, decode_message, encode_message,
+    ACK_REFUSED, ACK_SAVED, BLOB_CHUNK_SIZE, CAP_ACKS, CAP_BLOB_CHUNKS, CAP_CHANGES,
+    CAP_COMPRESSION, CAP_HELLO, CAP_PATH_EQUIVALENCE, CAP_SYNC_BATCH, DEVICE_REVOKED_REASON, ERR_BLOB_NOT_FOUND,
+    ERR_BLOB_TOO_LARGE, ERR_COMPRESSION, ERR_STORAGE, ERR_VERSION, MANIFEST_DOC_ID, MAX_BLOB_SIZE,
+    MAX_STREAMED_BLOB_SIZE, MSG_ACK, MSG_BLOB_CHUNK, MSG_BLOB_REQUEST, MSG_BLOB_UPDATE, MSG_CAPS,
+    MSG_CHANGES, MSG_ERROR, MSG_HELLO, MSG_MANIFEST_SYNC, MSG_MANIFEST_VERIFY, MSG_SYNC_BATCH,
+    MSG_SYNC_STEP_1, MSG_SYNC_STEP_2, MSG_TAGGED, MSG_UPDATE, MSG_VERSION, SYNC_BATCH_MAX_DOCS,
+    V1_MINOR_CAPS, V1_PROTOCOL_MAJOR, V1_PROTOCOL_MINOR, decode_blob_chunk, decode_message,
+    encode_blob_chunk, encode_message,
 };
 use crate::client::watcher::DebouncedWatcher;
 use crate::ignore::IgnoreList;
-use crate::v1::blob_store::BlobStore;
-use crate::v1::hash::hash_hex;
-use crate::v1::disk::{migrate_vault_on_disk, read_or_create_actor_id};
+use crate::v1::blob_store::{BlobStore, BlobWriter};
+use crate::v1::conflict::{CONFLICT_NAME_KEY, ConflictTemplate};
+use crate::v1::hash::{hash_hex, hash_reader};
+use crate::v1::disk::{
+    migrate_vault_on_disk, read_or_create_actor_id, read_or_create_device_name,
+};
+use crate::v1::file_index::{FileIndex, Stat};
 use crate::v1::ids::{ActorId, Lamport, NodeId};
-use crate::v1::manifest::{Manifest, NodeKind};
-use crate::v1::projection::{Projection, project};
+use crate::v1::manifest::{Device, Manifest, NodeKind};
+use crate::v1::names::{NameMap, NameRules, Unportable, unportable};
+use crate::v1::outbox::{Outbox, Outgoing};
+use crate::v1::placeholder::{self, Placeholder};
+use crate::v1::projection::{
+    PATH_EQUIVALENCE_KEY, PathEquivalence, ProjectedEntry, Projection, project,
+};
+use crate::v1::selective::Selection;
 use crate::v1::sync::{
-    decode_version_handshake, encode_manifest_update, encode_verify_payload,
-    encode_version_handshake, handle_manifest_payload, manifest_step1_payload, projection_hash,
+    BatchEntry, Caps, Cursor, compress_frame, content_digest, decode_ack, decode_caps,
+    decode_changes_reply, decode_error, decode_version_handshake, decompress_frame, encode_caps,
+    encode_changes_request, encode_hello, encode_manifest_update, encode_sync_batch,
+    encode_tagged, encode_verify_payload, encode_version_handshake, handle_manifest_payload,
+    manifest_step1_payload, projection_hash,
 };
 use anyhow::{Context, Result};
-use futures_util::stream::SplitSink;
-use futures_util::{SinkExt, StreamExt};
-use std::collections::{HashMap, HashSet};
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{Sink, SinkExt, StreamExt};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
+use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::Poll;
 use std::time::Duration;
 use tokio::net::TcpStream;
 use tokio::time::Instant;
@@ -62,7 +83,58 @@ use yrs::updates::decoder::Decode;
 use yrs::updates::encoder::Encode;
 use yrs::{Doc, GetString, ReadTxn, StateVector, Text, Transact, Update};
 
-type WsSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, WsMessage>;
+/// The socket's write half. Once the handshake has agreed on
+/// [`CAP_COMPRESSION`], frames worth it go out deflated (see
+/// [`compress_frame`]).
+struct WsSink {
+    inner: SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, WsMessage>,
+    compress: bool,
+}
+
+impl WsSink {
+    fn new(inner: SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, WsMessage>) -> Self {
+        Self {
+            inner,
+            compress: false,
+        }
+    }
+}
+
+impl Sink<WsMessage> for WsSink {
+    type Error = tokio_tungstenite::tungstenite::Error;
+
+    fn poll_ready(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready_unpin(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: WsMessage) -> Result<(), Self::Error> {
+        let item = match item {
+            WsMessage::Binary(frame) if self.compress => match compress_frame(&frame) {
+                Some(packed) => WsMessage::Binary(packed.into()),
+                None => WsMessage::Binary(frame),
+            },
+            other => other,
+        };
+        self.inner.start_send_unpin(item)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_flush_unpin(cx)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_close_unpin(cx)
+    }
+}
 
 const RECONNECT_BASE_MS: u64 = 500;
 const RECONNECT_CAP_MS: u64 = 30_000;
@@ -81,11 +153,15 @@ const DEBOUNCE_MS: u64 = 500;
 const TEXT_EXTS: &[&str] = &["md", "txt"];
 
 /// Entry point for `syncline sync`. Blocks for the lifetime of the
-/// client, reconnecting on transport errors.
+/// client, reconnecting on transport errors. `name` overrides (and
+/// renames) this device in the manifest's device list. `rules` decides which
+/// manifest names must be escaped on this folder's filesystem (see
+/// [`crate::v1::names`]).
 pub async fn run_client(
     folder: PathBuf,
     url: String,
-    _name: Option<String>,
+    name: Option<String>,
+    rules: NameRules,
 ) -> Result<()> {
     banner(&folder, &url);
 
@@ -108,6 +184,8 @@ pub async fn run_client(
 
     let syncline_dir = folder.join(".syncline");
     let actor = read_or_create_actor_id(&syncline_dir)?;
+    let device = read_or_create_device_name(&syncline_dir, name.as_deref())?;
+    info!("device {device:?} (actor {})", actor.short());
 
     // Load (or create) the manifest once; it's the source of truth
     // for the whole run. The reconnect loop shares this manifest so
@@ -115,10 +193,24 @@ pub async fn run_client(
     let mut manifest = load_manifest(&syncline_dir, actor)?;
     let mut content = ContentStore::new(syncline_dir.join("content"));
     let blobs = BlobStore::new(syncline_dir.join("blobs"));
+    let mut names = NameMap::load(&syncline_dir, rules)?;
+    let mut outbox = Outbox::open(&syncline_dir)?;
 
     let mut attempt: u32 = 0;
     loop {
-        match run_session(&url, &mut manifest, &mut content, &blobs, &folder, &syncline_dir).await {
+        match run_session(
+            &url,
+            &mut manifest,
+            &mut content,
+            &blobs,
+            &mut names,
+            &mut outbox,
+            &folder,
+            &syncline_dir,
+            &device,
+        )
+        .await
+        {
             Ok(()) => {
                 // Graceful close (server shutdown). Retry after base
                 // backoff; this is not a hard error.
@@ -126,6 +218,7 @@ pub async fn run_client(
                 attempt = 0;
                 tokio::time::sleep(Duration::from_millis(RECONNECT_BASE_MS)).await;
             }
+            Err(e) if e.is::<DeviceRevoked>() || e.is::<IncompatibleServer>() => return Err(e),
             Err(e) => {
                 attempt = attempt.saturating_add(1);
                 let delay = backoff_ms(attempt);
@@ -136,6 +229,256 @@ pub async fn run_client(
     }
 }
 
+/// Entry point for `syncline status`: how many local changes are
+/// waiting in the outbox to be uploaded, or for the server to confirm
+/// it stored them.
+pub fn run_status(folder: &Path) -> Result<usize> {
+    let syncline_dir = folder.join(".syncline");
+    if !syncline_dir.is_dir() {
+        anyhow::bail!("{} is not a syncline vault", folder.display());
+    }
+    Outbox::open(&syncline_dir)?.len()
+}
+
+/// Entry point for `syncline config`: read or change the vault-wide
+/// settings kept in the manifest (see [`Manifest::config`]). With no
+/// key, returns every setting; with a key, that one; with a value or
+/// `unset`, changes it first.
+///
+/// Changes are saved to the local manifest and reach the server on the
+/// next `syncline sync`. Stop a running sync client for this folder
+/// first — it holds its own copy of the manifest and would overwrite
+/// the change.
+pub fn run_config(
+    folder: &Path,
+    key: Option<&str>,
+    value: Option<&str>,
+    unset: bool,
+) -> Result<Vec<(String, String)>> {
+    migrate_vault_on_disk(folder)?;
+    let syncline_dir = folder.join(".syncline");
+    let actor = read_or_create_actor_id(&syncline_dir)?;
+    let mut manifest = load_manifest(&syncline_dir, actor)?;
+
+    let Some(key) = key else {
+        return Ok(manifest.config_entries());
+    };
+    validate_config(key, value)?;
+    if unset {
+        manifest.remove_config(key);
+        save_manifest(&syncline_dir, &manifest)?;
+    } else if let Some(value) = value {
+        manifest.set_config(key, value);
+        save_manifest(&syncline_dir, &manifest)?;
+    }
+    Ok(manifest
+        .config(key)
+        .map(|v| vec![(key.to_string(), v)])
+        .unwrap_or_default())
+}
+
+/// Entry point for `syncline check-names`: every projected path that
+/// some platform can't store under its canonical name. Those files still
+/// sync — the client on that platform escapes them — but show up there
+/// under a lookalike name.
+pub fn run_check_names(folder: &Path) -> Result<Vec<Unportable>> {
+    migrate_vault_on_disk(folder)?;
+    let syncline_dir = folder.join(".syncline");
+    let actor = read_or_create_actor_id(&syncline_dir)?;
+    let manifest = load_manifest(&syncline_dir, actor)?;
+    let proj = project(&manifest);
+    Ok(unportable(proj.by_path.keys().map(String::as_str)))
+}
+
+/// Entry point for `syncline devices`: every device that has synced
+/// this vault, from the manifest's device records. With `retire`
+/// (a device name, or a prefix of its actor id), marks that device
+/// retired first.
+///
+/// Like [`run_config`], a change reaches the server on the next
+/// `syncline sync` and should not be made while one is running here.
+pub fn run_devices(folder: &Path, retire: Option<&str>) -> Result<Vec<Device>> {
+    migrate_vault_on_disk(folder)?;
+    let syncline_dir = folder.join(".syncline");
+    let actor = read_or_create_actor_id(&syncline_dir)?;
+    let mut manifest = load_manifest(&syncline_dir, actor)?;
+
+    if let Some(sel) = retire {
+        let matches: Vec<Device> = manifest
+            .devices()
+            .into_iter()
+            .filter(|d| d.name == sel || d.actor.to_string_hyphenated().starts_with(sel))
+            .collect();
+        match matches.as_slice() {
+            [] => anyhow::bail!("no device named {sel:?} or with an actor id starting {sel:?}"),
+            [d] => {
+                manifest.retire_device(d.actor);
+                save_manifest(&syncline_dir, &manifest)?;
+            }
+            many => anyhow::bail!(
+                "{sel:?} matches {} devices ({}); retire by actor id instead",
+                many.len(),
+                many.iter()
+                    .map(|d| format!("{} {}", d.name, d.actor.short()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+    Ok(manifest.devices())
+}
+
+/// What [`run_selective`] did.
+#[derive(Debug, Default)]
+pub struct SelectiveReport {
+    /// Folders excluded on this device afterwards, sorted.
+    pub excluded: Vec<String>,
+    /// Size above which binaries are fetched on demand, afterwards.
+    pub fetch_limit: Option<u64>,
+    /// Local copies removed from the newly excluded folder.
+    pub evicted: usize,
+    /// Files left in place because they differ from the synced copy.
+    pub kept: Vec<String>,
+}
+
+/// Entry point for `syncline selective`: change which folders this
+/// device keeps on disk (see [`Selection`]). Excluding a folder removes
+/// the local copies of its files that match the synced version, and the
+/// blobs only they used; files with unsynced changes are kept and
+/// reported. Including one again fetches it on the next sync.
+/// `fetch_limit` (when given) replaces the size above which binaries
+/// are left as placeholders until `syncline fetch`; files already
+/// downloaded stay.
+///
+/// The list is local to this device and never synced, so unlike
+/// [`run_config`] this is safe while `syncline sync` runs — the client
+/// rereads it on its next pass.
+pub fn run_selective(
+    folder: &Path,
+    exclude: Option<&str>,
+    include: Option<&str>,
+    fetch_limit: Option<Option<u64>>,
+    rules: NameRules,
+) -> Result<SelectiveReport> {
+    migrate_vault_on_disk(folder)?;
+    let syncline_dir = folder.join(".syncline");
+    let mut selection = Selection::load(folder);
+    let mut report = SelectiveReport::default();
+
+    if let Some(limit) = fetch_limit {
+        selection.set_fetch_limit(limit);
+        selection.save(folder)?;
+    }
+
+    if let Some(f) = include {
+        if !selection.include(f) {
+            anyhow::bail!("{f:?} is not excluded");
+        }
+        selection.save(folder)?;
+        // The change-feed cursor moved on without this folder's changes;
+        // forget it so the next sync checks every doc.
+        match fs::remove_file(syncline_dir.join("feed_cursor")) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    if let Some(f) = exclude {
+        let f = selection
+            .exclude(f)
+            .ok_or_else(|| anyhow::anyhow!("can't exclude {f:?}: not a folder inside the vault"))?;
+        selection.save(folder)?;
+
+        let actor = read_or_create_actor_id(&syncline_dir)?;
+        let manifest = load_manifest(&syncline_dir, actor)?;
+        let names = NameMap::load(&syncline_dir, rules)?;
+        let mut content = ContentStore::new(syncline_dir.join("content"));
+        let blobs = BlobStore::new(syncline_dir.join("blobs"));
+        let proj = project(&manifest);
+        let within = |p: &str| {
+            p.strip_prefix(f.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        };
+        // Blobs still referenced by a kept path stay in the store.
+        let still_used: HashSet<&str> = proj
+            .by_path
+            .iter()
+            .filter(|(p, _)| !selection.excludes(p))
+            .filter_map(|(_, e)| e.blob_hash.as_deref())
+            .collect();
+
+        for (path, entry) in &proj.by_path {
+            if !within(path) || entry.kind == NodeKind::Directory {
+                continue;
+            }
+            let full = folder.join(names.to_disk(path));
+            let Ok(bytes) = fs::read(&full) else {
+                continue;
+            };
+            let clean = match entry.kind {
+                _ if read_placeholder(&full).is_some() => true,
+                NodeKind::Text => {
+                    content.has_persisted(entry.id)
+                        && content.ensure_loaded(entry.id).is_ok()
+                        && content.current_text(entry.id).as_deref().map(str::as_bytes)
+                            == Some(bytes.as_slice())
+                }
+                _ => entry.blob_hash.as_deref() == Some(hash_hex(&bytes).as_str()),
+            };
+            if !clean {
+                report.kept.push(path.clone());
+                continue;
+            }
+            fs::remove_file(&full).with_context(|| format!("remove {}", full.display()))?;
+            report.evicted += 1;
+            if let Some(hash) = entry.blob_hash.as_deref()
+                && !still_used.contains(hash)
+            {
+                blobs.remove(hash)?;
+            }
+        }
+        // Drop the directories the eviction emptied, deepest first.
+        let root = folder.join(names.to_disk(&f));
+        for dent in WalkDir::new(&root).contents_first(true).into_iter().flatten() {
+            if dent.file_type().is_dir() {
+                let _ = fs::remove_dir(dent.path());
+            }
+        }
+        report.kept.sort();
+    }
+    report.excluded = selection.folders().to_vec();
+    report.fetch_limit = selection.fetch_limit();
+    Ok(report)
+}
+
+/// `actor`'s device name if it published one, else its short id.
+fn device_label(manifest: &Manifest, actor: ActorId) -> String {
+    manifest
+        .device_names()
+        .remove(&actor)
+        .unwrap_or_else(|| actor.short())
+}
+
+/// Reject unknown config keys and malformed values before they are
+/// synced to every peer.
+fn validate_config(key: &str, value: Option<&str>) -> Result<()> {
+    match key {
+        PATH_EQUIVALENCE_KEY => {
+            if let Some(v) = value {
+                v.parse::<PathEquivalence>()?;
+            }
+        }
+        CONFLICT_NAME_KEY => {
+            if let Some(v) = value {
+                v.parse::<ConflictTemplate>()?;
+            }
+        }
+        other => anyhow::bail!(
+            "unknown vault setting {other:?} (known: {PATH_EQUIVALENCE_KEY}, {CONFLICT_NAME_KEY})"
+        ),
+    }
+    Ok(())
+}
+
 /// Entry point for `syncline verify`. One-shot diagnostic: connect,
 /// handshake, send the local projection hash, and report whether the
 /// server agrees.
@@ -162,40 +505,10 @@ pub async fn run_verify(folder: PathBuf, url: String, timeout: Duration) -> Resu
 
     info!("connecting to {}", url);
     let (ws, _) = connect_async(&url).await.context("ws connect")?;
-    let (mut write, mut read) = ws.split();
-
-    let hs = encode_message(MSG_VERSION, MANIFEST_DOC_ID, &encode_version_handshake());
-    write
-        .send(WsMessage::Binary(hs.into()))
-        .await
-        .context("send version handshake")?;
-
-    let first = match read.next().await {
-        Some(Ok(WsMessage::Binary(b))) => b,
-        Some(Ok(WsMessage::Close(_))) | None => {
-            anyhow::bail!("server closed during handshake — likely non-v1 server");
-        }
-        Some(Ok(other)) => anyhow::bail!("unexpected frame during handshake: {other:?}"),
-        Some(Err(e)) => anyhow::bail!("transport error during handshake: {e}"),
-    };
-    let (t, d, payload) = decode_message(&first)
-        .ok_or_else(|| anyhow::anyhow!("malformed handshake reply frame"))?;
-    if t != MSG_VERSION || d != MANIFEST_DOC_ID {
-        anyhow::bail!("server did not reply with MSG_VERSION (got msg_type {t:#x})");
-    }
-    let Some((major, minor)) = decode_version_handshake(payload) else {
-        anyhow::bail!("server handshake payload is malformed");
-    };
-    if major != V1_PROTOCOL_MAJOR {
-        anyhow::bail!(
-            "server protocol {}.{} incompatible with client {}.{}",
-            major,
-            minor,
-            V1_PROTOCOL_MAJOR,
-            V1_PROTOCOL_MINOR
-        );
-    }
-    info!("v1 handshake OK (server {}.{})", major, minor);
+    let (write, mut read) = ws.split();
+    let mut write = WsSink::new(write);
+    let caps = version_handshake(&mut write, &mut read).await?;
+    send_hello(&mut write, caps, actor).await?;
 
     let verify_frame = encode_message(
         MSG_MANIFEST_VERIFY,
@@ -218,6 +531,10 @@ pub async fn run_verify(folder: PathBuf, url: String, timeout: Duration) -> Resu
             Ok(None) => anyhow::bail!("server dropped connection during verify"),
             Ok(Some(Err(e))) => anyhow::bail!("ws read error during verify: {e}"),
             Ok(Some(Ok(WsMessage::Binary(b)))) => {
+                let Some(b) = decompress_frame(&b) else {
+                    warn!("dropping malformed compressed frame during verify");
+                    continue;
+                };
                 let Some((msg_type, doc_id, _payload)) = decode_message(&b) else {
                     warn!("dropping malformed frame during verify");
                     continue;
@@ -239,20 +556,221 @@ pub async fn run_verify(folder: PathBuf, url: String, timeout: Duration) -> Resu
     }
 }
 
+/// Entry point for `syncline fetch`: download the binaries under `path`
+/// (a file or a folder) that this device fetches on demand, replacing
+/// their placeholders. Works from the local manifest, so it can run
+/// next to `syncline sync`; that client keeps the fetched blobs.
+/// Returns the vault paths written.
+pub async fn run_fetch(
+    folder: PathBuf,
+    url: String,
+    path: &str,
+    rules: NameRules,
+    timeout: Duration,
+) -> Result<Vec<String>> {
+    let _ = tokio::task::spawn_blocking({
+        let folder = folder.clone();
+        move || migrate_vault_on_disk(&folder)
+    })
+    .await??;
+
+    let syncline_dir = folder.join(".syncline");
+    let actor = read_or_create_actor_id(&syncline_dir)?;
+    let manifest = load_manifest(&syncline_dir, actor)?;
+    let names = NameMap::load(&syncline_dir, rules)?;
+    let blobs = BlobStore::new(syncline_dir.join("blobs"));
+    let path = path.trim_matches('/');
+    let wanted: Vec<(String, String)> = project(&manifest)
+        .by_path
+        .into_iter()
+        .filter(|(p, e)| {
+            e.kind == NodeKind::Binary
+                && (path.is_empty()
+                    || p.strip_prefix(path)
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/')))
+        })
+        .filter_map(|(p, e)| Some((p, e.blob_hash?)))
+        .collect();
+    if wanted.is_empty() {
+        anyhow::bail!("no binary file at or under {path:?}");
+    }
+
+    let mut missing: HashSet<String> = wanted
+        .iter()
+        .map(|(_, h)| h.clone())
+        .filter(|h| !blobs.has(h))
+        .collect();
+    if !missing.is_empty() {
+        info!("connecting to {}", url);
+        let (ws, _) = connect_async(&url).await.context("ws connect")?;
+        let (write, mut read) = ws.split();
+        let mut write = WsSink::new(write);
+        let caps = version_handshake(&mut write, &mut read).await?;
+        send_hello(&mut write, caps, actor).await?;
+        for hash in &missing {
+            let frame = encode_message(MSG_BLOB_REQUEST, hash, hash.as_bytes());
+            write
+                .send(WsMessage::Binary(frame.into()))
+                .await
+                .context("send blob request")?;
+        }
+        let mut inbound: HashMap<String, BlobWriter> = HashMap::new();
+        let deadline = tokio::time::Instant::now() + timeout;
+        while !missing.is_empty() {
+            let frame = match tokio::time::timeout_at(deadline, read.next()).await {
+                Err(_) => anyhow::bail!("timed out waiting for {} blob(s)", missing.len()),
+                Ok(None) | Ok(Some(Ok(WsMessage::Close(_)))) => {
+                    anyhow::bail!("server closed connection during fetch")
+                }
+                Ok(Some(Err(e))) => anyhow::bail!("ws read error during fetch: {e}"),
+                Ok(Some(Ok(WsMessage::Binary(b)))) => b,
+                Ok(Some(Ok(_))) => continue,
+            };
+            let Some(frame) = decompress_frame(&frame) else {
+                continue;
+            };
+            let Some((msg_type, doc_id, payload)) = decode_message(&frame) else {
+                continue;
+            };
+            if msg_type == MSG_ERROR && missing.contains(doc_id) {
+                let Some((code, message)) = decode_error(payload) else {
+                    continue;
+                };
+                let path = wanted
+                    .iter()
+                    .find(|(_, h)| h == doc_id)
+                    .map_or(doc_id, |(p, _)| p);
+                match code {
+                    ERR_BLOB_NOT_FOUND => {
+                        anyhow::bail!("{path} is not uploaded yet; try again once its device syncs")
+                    }
+                    _ => anyhow::bail!("server refused {path}: {message}"),
+                }
+            }
+            let done = match msg_type {
+                MSG_BLOB_UPDATE => handle_inbound_blob(doc_id, payload, &blobs).map(|()| true),
+                MSG_BLOB_CHUNK => handle_inbound_blob_chunk(doc_id, payload, &blobs, &mut inbound),
+                _ => Ok(false),
+            }?;
+            if done {
+                missing.remove(doc_id);
+            }
+        }
+    }
+
+    let mut written = Vec::new();
+    for (p, hash) in wanted {
+        let full = folder.join(names.to_disk(&p));
+        // Never overwrite a file the user has put in the placeholder's
+        // place; only a stub or a gap is ours to fill.
+        if full.exists() && read_placeholder(&full).is_none() {
+            continue;
+        }
+        atomic_write_from(&full, blobs.open(&hash)?)
+            .with_context(|| format!("write {}", full.display()))?;
+        written.push(p);
+    }
+    written.sort();
+    Ok(written)
+}
+
+/// Send our `MSG_VERSION` and `MSG_CAPS` and check the server's reply.
+/// Returns the features both sides support.
+async fn version_handshake(
+    write: &mut WsSink,
+    read: &mut SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
+) -> Result<Caps> {
+    let hs = encode_message(MSG_VERSION, MANIFEST_DOC_ID, &encode_version_handshake());
+    write
+        .send(WsMessage::Binary(hs.into()))
+        .await
+        .context("send version handshake")?;
+    // A server before 1.7 skips this as an unknown frame.
+    let caps = encode_message(MSG_CAPS, MANIFEST_DOC_ID, &encode_caps(Caps::ALL));
+    write
+        .send(WsMessage::Binary(caps.into()))
+        .await
+        .context("send capabilities")?;
+
+    // Server must echo its version back. If it closes the socket
+    // instead, that's a protocol mismatch on the other end.
+    let first = match read.next().await {
+        Some(Ok(WsMessage::Binary(b))) => b,
+        Some(Ok(WsMessage::Close(_))) | None => {
+            anyhow::bail!("server closed during handshake — likely non-v1 server");
+        }
+        Some(Ok(other)) => anyhow::bail!("unexpected frame during handshake: {other:?}"),
+        Some(Err(e)) => anyhow::bail!("transport error during handshake: {e}"),
+    };
+    let (t, d, payload) = decode_message(&first)
+        .ok_or_else(|| anyhow::anyhow!("malformed handshake reply frame"))?;
+    if t == MSG_ERROR
+        && let Some((ERR_VERSION, message)) = decode_error(payload)
+    {
+        return Err(IncompatibleServer(message.to_string()).into());
+    }
+    if t != MSG_VERSION || d != MANIFEST_DOC_ID {
+        anyhow::bail!("server did not reply with MSG_VERSION (got msg_type {t:#x})");
+    }
+    let Some((major, minor)) = decode_version_handshake(payload) else {
+        anyhow::bail!("server handshake payload is malformed");
+    };
+    if major != V1_PROTOCOL_MAJOR {
+        return Err(IncompatibleServer(format!(
+            "server protocol {major}.{minor} incompatible with client {V1_PROTOCOL_MAJOR}.{V1_PROTOCOL_MINOR}"
+        ))
+        .into());
+    }
+    let server_caps = if minor >= V1_MINOR_CAPS {
+        let frame = match read.next().await {
+            Some(Ok(WsMessage::Binary(b))) => b,
+            other => anyhow::bail!("expected the server's MSG_CAPS, got {other:?}"),
+        };
+        decode_message(&frame)
+            .filter(|(t, _, _)| *t == MSG_CAPS)
+            .and_then(|(_, _, payload)| decode_caps(payload))
+            .ok_or_else(|| anyhow::anyhow!("server did not follow MSG_VERSION with MSG_CAPS"))?
+    } else {
+        Caps::of_minor(minor)
+    };
+    let caps = Caps::ALL.intersect(server_caps);
+    write.compress = caps.has(CAP_COMPRESSION);
+    info!("v1 handshake OK (server {}.{}, caps {:#x})", major, minor, caps.0);
+    Ok(caps)
+}
+
+/// Name our actor to a server that reads `MSG_HELLO`. It must be the
+/// first frame after the handshake.
+async fn send_hello(write: &mut WsSink, caps: Caps, actor: ActorId) -> Result<()> {
+    if caps.has(CAP_HELLO) {
+        let frame = encode_message(MSG_HELLO, MANIFEST_DOC_ID, &encode_hello(actor));
+        write
+            .send(WsMessage::Binary(frame.into()))
+            .await
+            .context("send hello")?;
+    }
+    Ok(())
+}
+
 /// Single connect + sync session. Returns Ok when the server closes
 /// cleanly (or our read half drops), Err on any protocol or transport
 /// failure.
+#[allow(clippy::too_many_arguments)]
 async fn run_session(
     url: &str,
     manifest: &mut Manifest,
     content: &mut ContentStore,
     blobs: &BlobStore,
+    names: &mut NameMap,
+    outbox: &mut Outbox,
     folder: &Path,
     syncline_dir: &Path,
+    device: &str,
 ) -> Result<()> {
     info!("connecting to {}", url);
     let (ws, _) = connect_async(url).await.context("ws connect")?;
-    let (mut write, mut read) = ws.split();
+    let (write, mut read) = ws.split();
+    let mut write = WsSink::new(write);
 
     // Tracks text-node subdocs for which we've sent STEP_1 this session.
     // Prevents spamming the server after every manifest apply while still
@@ -261,51 +779,50 @@ async fn run_session(
     // Tracks blob hashes for which we've already sent MSG_BLOB_REQUEST
     // this session. Cleared on reconnect.
     let mut requested_blobs: HashSet<String> = HashSet::new();
+    // Blobs arriving as MSG_BLOB_CHUNK streams, keyed by hash. Each
+    // writer stages its bytes on disk, so only one chunk per blob is
+    // ever held in memory.
+    let mut inbound_blobs: HashMap<String, BlobWriter> = HashMap::new();
     // NodeId → last disk path we materialised. Lets reconcile detect
     // remote deletes / renames by diffing against a fresh projection:
     // any id in here whose projection entry is gone (or path changed)
     // has its stale disk file removed.
     let mut on_disk: HashMap<NodeId, String> = HashMap::new();
+    // Change-feed cursor from the server's latest answer, saved once
+    // every doc it named has been re-checked (see `MSG_CHANGES` below).
+    let mut pending_cursor: Option<Cursor> = None;
+    // Whether each `MSG_CHANGES` request in flight is a barrier: sent
+    // behind the re-checks of a pending cursor, so its answer means
+    // they have all been answered too.
+    let mut changes_asked: VecDeque<bool> = VecDeque::new();
+    let mut barrier_due = false;
 
     // --- Version handshake (step 1) -----------------------------------------
-    let hs = encode_message(
-        MSG_VERSION,
-        MANIFEST_DOC_ID,
-        &encode_version_handshake(),
-    );
-    write
-        .send(WsMessage::Binary(hs.into()))
-        .await
-        .context("send version handshake")?;
-
-    // Server must echo its version back. If it closes the socket
-    // instead, that's a protocol mismatch on the other end.
-    let first = match read.next().await {
-        Some(Ok(WsMessage::Binary(b))) => b,
-        Some(Ok(WsMessage::Close(_))) | None => {
-            anyhow::bail!("server closed during handshake — likely non-v1 server");
-        }
-        Some(Ok(other)) => anyhow::bail!("unexpected frame during handshake: {other:?}"),
-        Some(Err(e)) => anyhow::bail!("transport error during handshake: {e}"),
-    };
-    let (t, d, payload) = decode_message(&first)
-        .ok_or_else(|| anyhow::anyhow!("malformed handshake reply frame"))?;
-    if t != MSG_VERSION || d != MANIFEST_DOC_ID {
-        anyhow::bail!("server did not reply with MSG_VERSION (got msg_type {t:#x})");
-    }
-    let Some((major, minor)) = decode_version_handshake(payload) else {
-        anyhow::bail!("server handshake payload is malformed");
-    };
-    if major != V1_PROTOCOL_MAJOR {
-        anyhow::bail!(
-            "server protocol {}.{} incompatible with client {}.{}",
-            major,
-            minor,
-            V1_PROTOCOL_MAJOR,
-            V1_PROTOCOL_MINOR
+    let caps = version_handshake(&mut write, &mut read).await?;
+    // 1.0 servers only understand whole-blob MSG_BLOB_UPDATE frames.
+    let chunked_blobs = caps.has(CAP_BLOB_CHUNKS);
+    // Older servers get one content STEP_1 per doc.
+    let batch_sync = caps.has(CAP_SYNC_BATCH);
+    // A server without it may compare paths differently from the policy
+    // we'd seed, so new vaults stay exact there.
+    let seed_policy = caps.has(CAP_PATH_EQUIVALENCE);
+    send_hello(&mut write, caps, manifest.actor()).await?;
+    // Resume from the change feed before the manifest sync so its
+    // answer is in hand when the first content subscribe pass runs.
+    let change_feed = caps.has(CAP_CHANGES);
+    if change_feed {
+        let cursor = load_cursor(syncline_dir);
+        let frame = encode_message(
+            MSG_CHANGES,
+            MANIFEST_DOC_ID,
+            &encode_changes_request(cursor.as_ref()),
         );
+        write
+            .send(WsMessage::Binary(frame.into()))
+            .await
+            .context("send change-feed cursor")?;
+        changes_asked.push_back(false);
     }
-    info!("v1 handshake OK (server {}.{})", major, minor);
 
     // --- Initial manifest sync (step 2) -------------------------------------
     let step1 = manifest_step1_payload(manifest);
@@ -315,6 +832,17 @@ async fn run_session(
         .await
         .context("send manifest step1")?;
 
+    // Changes an earlier session staged but never got onto the wire, or
+    // that the server never confirmed storing.
+    outbox.start_session(caps.has(CAP_ACKS));
+    let waiting = outbox.len()?;
+    if waiting > 0 {
+        info!("sending {waiting} changes left waiting by the last session");
+        send_outbox(&mut write, outbox, blobs, chunked_blobs)
+            .await
+            .context("send outbox")?;
+    }
+
     // --- Read loop + polling scanner ---------------------------------------
     // The scan timer fires SCAN_INTERVAL after the *previous* scan ends,
     // not at a fixed cadence. Reset on both periodic and watcher-driven
@@ -365,8 +893,16 @@ async fn run_session(
                 };
                 let data = match msg {
                     Ok(WsMessage::Binary(b)) => b,
-                    Ok(WsMessage::Close(_)) => {
-                        info!("server closed connection");
+                    Ok(WsMessage::Close(frame)) => {
+                        match frame {
+                            Some(f) if f.reason.as_str() == DEVICE_REVOKED_REASON => {
+                                return Err(DeviceRevoked.into());
+                            }
+                            Some(f) if !f.reason.is_empty() => {
+                                warn!("server closed connection: {} ({})", f.reason, f.code)
+                            }
+                            _ => info!("server closed connection"),
+                        }
                         return Ok(());
                     }
                     Ok(WsMessage::Ping(_) | WsMessage::Pong(_)) => continue,
@@ -376,22 +912,116 @@ async fn run_session(
                     }
                     Err(e) => anyhow::bail!("ws read error: {e}"),
                 };
+                let Some(data) = decompress_frame(&data) else {
+                    warn!("dropping malformed compressed frame");
+                    continue;
+                };
                 let Some((msg_type, doc_id, payload)) = decode_message(&data) else {
                     warn!("dropping malformed frame");
                     continue;
                 };
+                if msg_type == MSG_ERROR {
+                    let Some((code, message)) = decode_error(payload) else {
+                        warn!("dropping malformed error frame");
+                        continue;
+                    };
+                    match code {
+                        ERR_BLOB_NOT_FOUND => {
+                            // Not uploaded yet: ask again once the
+                            // manifest next changes, which is when the
+                            // uploader publishes it.
+                            info!("blob {doc_id} isn't on the server yet; will ask again later");
+                            requested_blobs.remove(doc_id);
+                        }
+                        ERR_BLOB_TOO_LARGE => {
+                            error!("server refused blob {doc_id}: {message}");
+                        }
+                        ERR_STORAGE => {
+                            warn!("server storage failed for {doc_id}: {message}; will retry");
+                            inbound_blobs.remove(doc_id);
+                            requested_blobs.remove(doc_id);
+                        }
+                        ERR_VERSION => {
+                            return Err(IncompatibleServer(message.to_string()).into());
+                        }
+                        ERR_COMPRESSION => {
+                            // The failed ack behind this resends the
+                            // update; make that copy plain.
+                            warn!("server refused a compressed frame: {message}");
+                            write.compress = false;
+                        }
+                        other => warn!("server error {other} for {doc_id}: {message}"),
+                    }
+                    continue;
+                }
+                if msg_type == MSG_ACK {
+                    match decode_ack(payload) {
+                        Some((tag, ACK_SAVED)) => {
+                            outbox.acked(tag)?;
+                            if outbox.is_empty()? {
+                                info!("all changes saved to server");
+                            }
+                        }
+                        Some((tag, ACK_REFUSED)) => {
+                            warn!("server refused an update to {doc_id}; dropping it");
+                            outbox.acked(tag)?;
+                        }
+                        Some((tag, _)) => {
+                            warn!("server failed to store an update to {doc_id}; will send it again");
+                            outbox.failed(tag);
+                        }
+                        None => warn!("dropping malformed ack"),
+                    }
+                    continue;
+                }
                 if msg_type == MSG_BLOB_UPDATE {
                     if let Err(e) = handle_inbound_blob(doc_id, payload, blobs) {
                         warn!("inbound blob rejected: {e:?}");
                         continue;
                     }
-                    if let Err(e) = reconcile_projection_to_disk(
+                    if let Err(e) = reconcile_and_publish(
+                        &mut write,
+                        folder,
+                        syncline_dir,
+                        manifest,
+                        blobs,
+                        names,
+                        &mut on_disk,
+                        content,
+                        outbox,
+                        chunked_blobs,
+                    )
+                    .await
+                    {
+                        error!("reconcile after blob arrival: {e}");
+                    }
+                    continue;
+                }
+                if msg_type == MSG_BLOB_CHUNK {
+                    match handle_inbound_blob_chunk(doc_id, payload, blobs, &mut inbound_blobs) {
+                        Ok(false) => continue,
+                        Ok(true) => {}
+                        Err(e) => {
+                            warn!("inbound blob chunk rejected: {e:?}");
+                            // Let the next manifest pass ask again.
+                            requested_blobs.remove(doc_id);
+                            continue;
+                        }
+                    }
+                    if let Err(e) = reconcile_and_publish(
+                        &mut write,
                         folder,
+                        syncline_dir,
                         manifest,
                         blobs,
+                        names,
                         &mut on_disk,
-                        Some(content),
-                    ) {
+                        content,
+                        outbox,
+                        chunked_blobs,
+                    )
+                    .await
+                    {
                         error!("reconcile after blob arrival: {e}");
                     }
                     continue;
@@ -435,50 +1065,170 @@ async fn run_session(
                             // won't be falsely tombstoned.
                             if !did_initial_scan {
                                 did_initial_scan = true;
+                                if seed_policy && let Some(item) = seed_new_vault(manifest) {
+                                    // Sent along with the device record.
+                                    outbox.push(&[item])?;
+                                }
+                                if let Err(e) = publish_device(
+                                    &mut write,
+                                    manifest,
+                                    syncline_dir,
+                                    device,
+                                    outbox,
+                                    blobs,
+                                    chunked_blobs,
+                                )
+                                .await
+                                {
+                                    anyhow::bail!("publishing device record: {e}");
+                                }
                                 if let Err(e) = scan_once(
                                     folder,
                                     syncline_dir,
                                     manifest,
                                     content,
                                     blobs,
+                                    names,
                                     &mut write,
                                     &mut content_subscribed,
+                                    outbox,
+                                    chunked_blobs,
+                                    batch_sync,
+                                    None,
                                 )
                                 .await
                                 {
                                     warn!("initial scan failed: {e:?}");
                                 }
                             }
-                            if let Err(e) = reconcile_projection_to_disk(
+                            if let Err(e) = reconcile_and_publish(
+                                &mut write,
                                 folder,
+                                syncline_dir,
                                 manifest,
                                 blobs,
+                                names,
                                 &mut on_disk,
-                                Some(content),
-                            ) {
+                                content,
+                                outbox,
+                                chunked_blobs,
+                            )
+                            .await
+                            {
                                 error!("reconciling projection: {e}");
                             }
+                            let selection = Selection::load(folder);
                             if let Err(e) = subscribe_new_text_content(
                                 &mut write,
                                 manifest,
                                 content,
                                 &mut content_subscribed,
+                                &selection,
+                                batch_sync,
                             )
                             .await
                             {
                                 anyhow::bail!("content STEP_1 broadcast: {e}");
                             }
+                            if barrier_due {
+                                barrier_due = false;
+                                send_changes_barrier(
+                                    &mut write,
+                                    pending_cursor.as_ref(),
+                                    &mut changes_asked,
+                                )
+                                .await?;
+                            }
                             if let Err(e) = request_missing_blobs(
                                 &mut write,
                                 manifest,
                                 blobs,
                                 &mut requested_blobs,
+                                &selection,
                             )
                             .await
                             {
                                 anyhow::bail!("blob request broadcast: {e}");
                             }
                         }
+                        MSG_CHANGES => {
+                            let Some(reply) = decode_changes_reply(payload) else {
+                                warn!("dropping malformed change-feed reply");
+                                continue;
+                            };
+                            let barrier =
+                                !reply.pushed && changes_asked.pop_front().unwrap_or(false);
+                            if barrier {
+                                // A later barrier covers re-checks sent
+                                // since; only the last one may commit.
+                                if !changes_asked.is_empty() || barrier_due {
+                                    continue;
+                                }
+                                if let Some(cursor) = pending_cursor.take() {
+                                    debug!(seq = cursor.seq, "caught up with change feed");
+                                    if let Err(e) = save_cursor(syncline_dir, &cursor) {
+                                        error!("persisting change-feed cursor: {e}");
+                                    }
+                                }
+                                continue;
+                            }
+                            match &reply.docs {
+                                Some(docs) => {
+                                    debug!(
+                                        seq = reply.cursor.seq,
+                                        changed = docs.len(),
+                                        "change feed"
+                                    );
+                                    // Docs we hold that the server hasn't
+                                    // touched since need no re-check;
+                                    // later updates reach us on the feed.
+                                    let changed: HashSet<NodeId> =
+                                        docs.iter().filter_map(|d| parse_content_doc_id(d)).collect();
+                                    let proj = project(manifest);
+                                    let selection = Selection::load(folder);
+                                    for node_id in content.persisted() {
+                                        let excluded = proj
+                                            .by_id
+                                            .get(&node_id)
+                                            .is_some_and(|e| selection.excludes(&e.path));
+                                        if !changed.contains(&node_id) && !excluded {
+                                            content_subscribed.insert(node_id);
+                                        }
+                                    }
+                                    for node_id in &changed {
+                                        content_subscribed.remove(node_id);
+                                    }
+                                }
+                                None => {
+                                    debug!(seq = reply.cursor.seq, "change feed can't resume; checking every doc");
+                                    content_subscribed.clear();
+                                }
+                            }
+                            pending_cursor = Some(reply.cursor);
+                            barrier_due = true;
+                            if did_initial_scan {
+                                let selection = Selection::load(folder);
+                                if let Err(e) = subscribe_new_text_content(
+                                    &mut write,
+                                    manifest,
+                                    content,
+                                    &mut content_subscribed,
+                                    &selection,
+                                    batch_sync,
+                                )
+                                .await
+                                {
+                                    anyhow::bail!("content STEP_1 broadcast: {e}");
+                                }
+                                barrier_due = false;
+                                send_changes_barrier(
+                                    &mut write,
+                                    pending_cursor.as_ref(),
+                                    &mut changes_asked,
+                                )
+                                .await?;
+                            }
+                        }
                         other => {
                             debug!("ignoring manifest doc frame msg_type={:#x}", other);
                         }
@@ -489,6 +1239,13 @@ async fn run_session(
                 if let Some(node_id) = parse_content_doc_id(doc_id) {
                     match msg_type {
                         MSG_SYNC_STEP_2 | MSG_UPDATE => {
+                            // On the change feed, updates to docs this
+                            // device doesn't keep arrive too. A copy of
+                            // one would read as a local delete once its
+                            // folder is included again.
+                            if change_feed && excluded_here(folder, manifest, node_id) {
+                                continue;
+                            }
                             // Fold any local disk drift into the CRDT
                             // first so the incoming remote update merges
                             // against it (via Yrs) rather than letting
@@ -497,16 +1254,16 @@ async fn run_session(
                             // also needs broadcasting so the server and
                             // other peers see the user's local work.
                             match fold_disk_drift_into_content(
-                                folder, manifest, content, node_id,
+                                folder, manifest, content, names, node_id,
                             ) {
                                 Ok(Some(delta)) => {
-                                    let frame = encode_message(
-                                        MSG_UPDATE,
-                                        &content_doc_id(node_id),
-                                        &delta,
-                                    );
+                                    let drift = Outgoing::Content {
+                                        doc_id: content_doc_id(node_id),
+                                        update: delta,
+                                    };
+                                    outbox.push(&[drift]).context("stage disk-drift delta")?;
                                     if let Err(e) =
-                                        write.send(WsMessage::Binary(frame.into())).await
+                                        send_outbox(&mut write, outbox, blobs, chunked_blobs).await
                                     {
                                         anyhow::bail!(
                                             "forward disk-drift delta for {:?}: {e}",
@@ -532,9 +1289,9 @@ async fn run_session(
                             if let Err(e) = content.persist(node_id) {
                                 error!("persist content subdoc for {:?}: {e}", node_id);
                             }
-                            if let Err(e) =
-                                flush_content_to_disk(folder, manifest, content, node_id)
-                            {
+                            if let Err(e) = flush_content_to_disk(
+                                folder, manifest, content, names, node_id,
+                            ) {
                                 error!("write content to disk for {:?}: {e}", node_id);
                             }
                         }
@@ -588,8 +1345,13 @@ async fn run_session(
                         manifest,
                         content,
                         blobs,
+                        names,
                         &mut write,
                         &mut content_subscribed,
+                        outbox,
+                        chunked_blobs,
+                        batch_sync,
+                        None,
                     )
                     .await
                     {
@@ -615,15 +1377,25 @@ async fn run_session(
                             debug!("deferring watcher-driven scan until manifest bootstrap");
                             continue;
                         }
-                        debug!("watcher batch of {} events triggering scan", events.len());
+                        let paths = batch_scan_paths(&events, folder, syncline_dir);
+                        debug!(
+                            "watcher batch of {} events triggering {} scan",
+                            events.len(),
+                            if paths.is_some() { "targeted" } else { "full" }
+                        );
                         if let Err(e) = scan_once(
                             folder,
                             syncline_dir,
                             manifest,
                             content,
                             blobs,
+                            names,
                             &mut write,
                             &mut content_subscribed,
+                            outbox,
+                            chunked_blobs,
+                            batch_sync,
+                            paths.as_deref(),
                         )
                         .await
                         {
@@ -646,6 +1418,35 @@ async fn run_session(
     }
 }
 
+/// True iff `node_id` is projected into a folder this device excludes
+/// (see [`Selection`]).
+fn excluded_here(folder: &Path, manifest: &Manifest, node_id: NodeId) -> bool {
+    let selection = Selection::load(folder);
+    !selection.folders().is_empty()
+        && project(manifest)
+            .by_id
+            .get(&node_id)
+            .is_some_and(|e| selection.excludes(&e.path))
+}
+
+/// Ask for the change feed again behind the re-checks sent for
+/// `pending`. The server answers frames in order, so once this answer
+/// arrives every doc the cursor named has been brought up to date and
+/// the cursor can be saved.
+async fn send_changes_barrier(
+    write: &mut WsSink,
+    pending: Option<&Cursor>,
+    asked: &mut VecDeque<bool>,
+) -> Result<()> {
+    let frame = encode_message(MSG_CHANGES, MANIFEST_DOC_ID, &encode_changes_request(pending));
+    write
+        .send(WsMessage::Binary(frame.into()))
+        .await
+        .context("send change-feed barrier")?;
+    asked.push_back(true);
+    Ok(())
+}
+
 // ---------------------------------------------------------------------------
 // On-disk manifest IO
 // ---------------------------------------------------------------------------
@@ -669,6 +1470,24 @@ fn save_manifest(syncline_dir: &Path, manifest: &Manifest) -> Result<()> {
     atomic_write(&syncline_dir.join("manifest.bin"), &bytes)
 }
 
+/// The server change-feed cursor this vault has caught up to, kept in
+/// `.syncline/feed_cursor` as `<feed id> <seq>`. `None` (missing or
+/// unreadable) makes the server list no changes and every doc get
+/// checked.
+fn load_cursor(syncline_dir: &Path) -> Option<Cursor> {
+    let text = fs::read_to_string(syncline_dir.join("feed_cursor")).ok()?;
+    let (feed, seq) = text.trim().rsplit_once(' ')?;
+    Some(Cursor {
+        feed: feed.to_string(),
+        seq: seq.parse().ok()?,
+    })
+}
+
+fn save_cursor(syncline_dir: &Path, cursor: &Cursor) -> Result<()> {
+    let text = format!("{} {}\n", cursor.feed, cursor.seq);
+    atomic_write(&syncline_dir.join("feed_cursor"), text.as_bytes())
+}
+
 // ---------------------------------------------------------------------------
 // Polling scanner (Phase 3.3b.2 outbound)
 // ---------------------------------------------------------------------------
@@ -686,63 +1505,170 @@ fn save_manifest(syncline_dir: &Path, manifest: &Manifest) -> Result<()> {
 /// detection requires distinguishing "gone" from "not yet written", and
 /// the minimum viable client should not accidentally propagate apparent
 /// deletions triggered by transient I/O).
+///
+/// Files whose stat matches the [`FileIndex`] entry from an earlier
+/// scan are not read at all. Files that moved keep their node (see
+/// [`detect_moves`]).
+///
+/// `only` limits the walk to those vault-relative disk paths and what's
+/// under them, as a watcher batch reports them (see
+/// [`batch_scan_paths`]); `None` walks the whole vault.
+#[allow(clippy::too_many_arguments)]
 async fn scan_once(
     folder: &Path,
     syncline_dir: &Path,
     manifest: &mut Manifest,
     content: &mut ContentStore,
     blobs: &BlobStore,
+    names: &NameMap,
     write: &mut WsSink,
     subscribed: &mut HashSet<NodeId>,
+    outbox: &mut Outbox,
+    chunked_blobs: bool,
+    batch_sync: bool,
+    only: Option<&[String]>,
 ) -> Result<()> {
     let pre_sv = manifest.doc().transact().state_vector();
 
-    // Snapshot projection once for path lookups. The loop may grow the
-    // manifest, but we rely on `create_text` to detect duplicates and
-    // on the caller running scan_once single-threaded.
-    let proj = project(manifest);
-
     let mut pending_content: Vec<(NodeId, Vec<u8>)> = Vec::new();
-    // Binary uploads batched until after the walk. (hash_hex, bytes).
-    let mut pending_blobs: Vec<(String, Vec<u8>)> = Vec::new();
+    // Binary uploads batched until after the walk, by hash. The bytes
+    // are already in the local blob store and are streamed from there.
+    let mut pending_blobs: Vec<String> = Vec::new();
+    let max_blob_size = if chunked_blobs {
+        MAX_STREAMED_BLOB_SIZE
+    } else {
+        MAX_BLOB_SIZE as u64
+    };
     let mut new_files = 0usize;
     let mut modified_files = 0usize;
     let mut new_binary = 0usize;
     let mut modified_binary = 0usize;
-    // Paths we saw during this walk (rel_str form). After the walk we
-    // diff against `proj.by_path` to detect local deletions.
+    // Paths we saw during this walk, in manifest form. After the walk
+    // we diff against `proj.by_path` to detect local deletions.
     let mut visited_rel: HashSet<String> = HashSet::new();
+    let mut index = FileIndex::load(syncline_dir);
+    let mut skipped_unchanged = 0usize;
 
     let ignore = IgnoreList::load(folder);
-    let ignore_for_filter = ignore.clone();
-    let folder_for_filter = folder.to_path_buf();
-    for dent in WalkDir::new(folder)
-        .follow_links(false)
-        .into_iter()
-        .filter_entry(move |e| {
-            if e.depth() == 0 {
-                return true;
-            }
-            let Ok(rel) = e.path().strip_prefix(&folder_for_filter) else {
-                return true;
-            };
-            let rel_str = rel.to_string_lossy().replace('\\', "/");
-            let is_dir = e.file_type().is_dir();
-            !ignore_for_filter.is_ignored(&rel_str, is_dir)
-        })
-        .filter_map(|e| e.ok())
-    {
-        if !dent.file_type().is_file() {
-            continue;
+    // Excluded folders are neither uploaded from nor deleted by the walk:
+    // whatever is (or isn't) on disk there says nothing about the vault.
+    let selection = Selection::load(folder);
+    let keep = |e: &walkdir::DirEntry| {
+        if e.path() == folder {
+            return true;
         }
+        let Ok(rel) = e.path().strip_prefix(folder) else {
+            return true;
+        };
+        let rel_str = rel.to_string_lossy().replace('\\', "/");
+        let is_dir = e.file_type().is_dir();
+        !ignore.is_ignored(&rel_str, is_dir) && !selection.excludes(&names.to_manifest(&rel_str))
+    };
+    // A targeted scan walks only what the watcher saw change, and only
+    // projected paths at or under those can be deletions.
+    let scope: Option<HashSet<String>> =
+        only.map(|paths| paths.iter().map(|p| names.to_manifest(p)).collect());
+    let in_scope = |path: &str| {
+        scope.as_ref().is_none_or(|s| {
+            std::iter::successors(Some(path), |p| p.rsplit_once('/').map(|(parent, _)| parent))
+                .any(|p| s.contains(p))
+        })
+    };
+    let roots: Vec<PathBuf> = match only {
+        None => vec![folder.to_path_buf()],
+        Some(paths) => paths
+            .iter()
+            .map(|p| folder.join(p))
+            .filter(|p| p.symlink_metadata().is_ok())
+            .collect(),
+    };
+    let walked: Vec<walkdir::DirEntry> = roots
+        .iter()
+        .flat_map(|root| {
+            WalkDir::new(root)
+                .follow_links(false)
+                .into_iter()
+                .filter_entry(&keep)
+                .filter_map(|e| e.ok())
+        })
+        .filter(|e| e.file_type().is_file())
+        .collect();
+
+    // Projection snapshot for path lookups. The loop may grow the
+    // manifest; `created` below catches a path it creates twice, and
+    // the caller runs scan_once single-threaded.
+    let mut proj = project(manifest);
+    let moved = {
+        // Projected files missing where the walk looked that we had
+        // locally (see the deletion pass below): each is either a local
+        // delete or the old half of a move.
+        let walked_rel: HashSet<String> = walked
+            .iter()
+            .filter_map(|d| walked_path(folder, d, names))
+            .collect();
+        let gone: HashMap<&str, &ProjectedEntry> = proj
+            .by_path
+            .iter()
+            .filter(|(path, _)| {
+                !walked_rel.contains(path.as_str())
+                    && in_scope(path)
+                    && !selection.excludes(path)
+                    && !ignore.is_ignored(&names.to_disk(path), false)
+                    && folder.join(names.to_disk(path)).symlink_metadata().is_err()
+            })
+            .filter(|(_, entry)| match entry.kind {
+                NodeKind::Text => content.has_persisted(entry.id),
+                NodeKind::Binary => entry.blob_hash.as_deref().is_some_and(|h| blobs.has(h)),
+                NodeKind::Directory => false,
+            })
+            .map(|(path, entry)| (path.as_str(), entry))
+            .collect();
+        detect_moves(folder, &walked, &proj, &gone, &mut index, content, names)
+    };
+    if !moved.is_empty() {
+        apply_moves(folder, manifest, &proj, &moved, names);
+        proj = project(manifest);
+    }
+    // Projected paths by equivalence key, to recognise other spellings
+    // of a projected path (see `PathEquivalence`).
+    let policy = PathEquivalence::of(manifest);
+    let proj_keys: HashMap<String, &str> = proj
+        .by_path
+        .keys()
+        .map(|p| (policy.key(p), p.as_str()))
+        .collect();
+    // Raw paths of every file node, tombstones included, for the shadow
+    // check below: one manifest walk rather than one per walked file.
+    let raw_paths = manifest.entries_by_path();
+    // Text paths created by this walk, which `proj` predates.
+    let mut created: HashSet<String> = HashSet::new();
+
+    for dent in &walked {
         let abs = dent.path();
         let Ok(rel) = abs.strip_prefix(folder) else {
             continue;
         };
-        let rel_str = rel.to_string_lossy().replace('\\', "/");
+        // Disk names may be escaped for this platform; everything below
+        // works on the manifest spelling.
+        let mut rel_str = names.to_manifest(&rel.to_string_lossy().replace('\\', "/"));
         if is_unsafe_relative_path(&rel_str) {
             continue;
         }
+        // Another spelling of a projected path. If the filesystem folds
+        // it onto the projected file (macOS handing back NFD, any
+        // case-insensitive volume) it *is* that entry. Otherwise it's a
+        // second file the vault policy can't keep apart: it gets its own
+        // node and is moved to the conflict name projection gives it.
+        let mut spelling_collision = false;
+        if !proj.by_path.contains_key(&rel_str)
+            && let Some(projected) = proj_keys.get(&policy.key(&rel_str))
+        {
+            if same_file::is_same_file(abs, folder.join(names.to_disk(projected))).unwrap_or(false) {
+                rel_str = projected.to_string();
+            } else {
+                spelling_collision = true;
+            }
+        }
         visited_rel.insert(rel_str.clone());
 
         // Tombstone-shadow check (§5.2 LWW on `deleted`).
@@ -761,7 +1687,7 @@ async fn scan_once(
         // node has a `modify_stamp` strictly newer than its
         // `delete_stamp`, projection treats it as live and the entry
         // shows up in `proj.by_path`, so we never reach this branch.
-        if let Some(shadow) = manifest.find_entry_by_path(&rel_str) {
+        if let Some(shadow) = raw_paths.get(&rel_str) {
             if shadow.deleted && !proj.by_path.contains_key(&rel_str) {
                 debug!(
                     node = ?shadow.id,
@@ -785,48 +1711,99 @@ async fn scan_once(
             .and_then(|e| e.to_str())
             .unwrap_or("")
             .to_ascii_lowercase();
+        let meta = match dent.metadata() {
+            Ok(m) => m,
+            Err(e) => {
+                debug!("skip unreadable metadata {}: {}", rel_str, e);
+                continue;
+            }
+        };
+        // Taken before the read, so a write racing the read leaves a
+        // stale stat behind and the next scan looks again.
+        let stat = Stat::of(&meta);
+        let known_hash = stat
+            .as_ref()
+            .and_then(|s| index.unchanged(&rel_str, s))
+            .map(str::to_string);
         if !TEXT_EXTS.contains(&ext.as_str()) {
-            // Binary path: read bytes, hash, CAS-stash locally, and
-            // create/update the manifest entry. Actual upload is batched
-            // and sent at the end of the walk.
-            let meta = match fs::metadata(abs) {
-                Ok(m) => m,
-                Err(e) => {
-                    debug!("skip unreadable metadata {}: {}", rel_str, e);
-                    continue;
-                }
-            };
-            if meta.len() as usize > MAX_BLOB_SIZE {
+            // Binary path: stream the file into the local CAS (hashing
+            // on the way) and create/update the manifest entry. Actual
+            // upload is batched and sent at the end of the walk.
+            let projected_hash = proj
+                .by_path
+                .get(&rel_str)
+                .filter(|e| e.kind == NodeKind::Binary)
+                .and_then(|e| e.blob_hash.clone());
+            if known_hash.is_some() && known_hash == projected_hash {
+                skipped_unchanged += 1;
+                continue;
+            }
+            // A placeholder stands for a blob we haven't fetched; its
+            // bytes are never the file's content.
+            if meta.len() < placeholder::MAX_LEN && read_placeholder(abs).is_some() {
+                continue;
+            }
+            if meta.len() > max_blob_size {
                 warn!(
-                    "skipping {} ({} bytes > MAX_BLOB_SIZE {})",
-                    rel_str, meta.len(), MAX_BLOB_SIZE
+                    "skipping {} ({} bytes > blob size limit {})",
+                    rel_str, meta.len(), max_blob_size
                 );
                 continue;
             }
-            let bytes = match fs::read(abs) {
-                Ok(b) => b,
+            let file = match fs::File::open(abs) {
+                Ok(f) => f,
                 Err(e) => {
                     debug!("skip unreadable {}: {}", rel_str, e);
                     continue;
                 }
             };
-            match process_binary_file(&rel_str, &bytes, &proj, manifest, blobs)? {
-                BinaryScanOutcome::Unchanged => {}
+            let indexed = match process_binary_file(&rel_str, file, &proj, manifest, blobs)? {
+                BinaryScanOutcome::Unchanged => projected_hash,
                 BinaryScanOutcome::Skipped(reason) => {
                     debug!("binary {} skipped: {}", rel_str, reason);
+                    None
                 }
                 BinaryScanOutcome::Created { hash } => {
                     new_binary += 1;
-                    pending_blobs.push((hash, bytes));
+                    pending_blobs.push(hash.clone());
+                    if spelling_collision {
+                        move_to_projected_path(
+                            folder,
+                            abs,
+                            manifest,
+                            names,
+                            &rel_str,
+                            &mut visited_rel,
+                        );
+                        None
+                    } else {
+                        Some(hash)
+                    }
                 }
                 BinaryScanOutcome::Rehashed { hash } => {
                     modified_binary += 1;
-                    pending_blobs.push((hash, bytes));
+                    pending_blobs.push(hash.clone());
+                    Some(hash)
                 }
+            };
+            if let (Some(stat), Some(hash)) = (stat, indexed) {
+                index.record(&rel_str, stat, hash);
             }
             continue;
         }
 
+        // A text file we've folded in before and not touched since.
+        // Deletion and new-file detection still see it via `visited_rel`.
+        if known_hash.is_some()
+            && proj
+                .by_path
+                .get(&rel_str)
+                .is_some_and(|e| e.kind == NodeKind::Text && content.has_persisted(e.id))
+        {
+            skipped_unchanged += 1;
+            continue;
+        }
+
         let body = match fs::read_to_string(abs) {
             Ok(s) => s,
             Err(e) => {
@@ -854,6 +1831,9 @@ async fn scan_once(
                 pending_content.push((existing.id, update));
                 modified_files += 1;
             }
+            if let Some(stat) = stat {
+                index.record(&rel_str, stat, hash_hex(body.as_bytes()));
+            }
         } else if proj
             .by_path
             .get(&rel_str)
@@ -870,13 +1850,17 @@ async fn scan_once(
             // case we must not overwrite the remote's content — we
             // record a fresh node and let projection's conflict suffix
             // rule give the loser a unique name.
+            //
+            // `proj` and `created` already say whether the path is
+            // taken, so skip `create_text`'s own check: it projects the
+            // whole manifest, once per new file.
+            if !created.insert(rel_str.clone()) {
+                debug!("create_text({:?}) skipped: created twice in one walk", rel_str);
+                continue;
+            }
             let size = body.len() as u64;
-            let collides = proj.by_path.contains_key(&rel_str);
-            let create_result = if collides {
-                crate::v1::ops::create_text_allowing_collision(manifest, &rel_str, size)
-            } else {
-                crate::v1::ops::create_text(manifest, &rel_str, size)
-            };
+            let create_result =
+                crate::v1::ops::create_text_allowing_collision(manifest, &rel_str, size);
             match create_result {
                 Ok(nid) => {
                     // Seed the brand-new subdoc via `replace_text` (empty → body).
@@ -885,6 +1869,18 @@ async fn scan_once(
                         pending_content.push((nid, update));
                     }
                     new_files += 1;
+                    if spelling_collision {
+                        move_to_projected_path(
+                            folder,
+                            abs,
+                            manifest,
+                            names,
+                            &rel_str,
+                            &mut visited_rel,
+                        );
+                    } else if let Some(stat) = stat {
+                        index.record(&rel_str, stat, hash_hex(body.as_bytes()));
+                    }
                 }
                 Err(e) => {
                     debug!("create_text({:?}) skipped: {}", rel_str, e);
@@ -904,7 +1900,12 @@ async fn scan_once(
     let deletion_candidates: Vec<(NodeId, NodeKind, Option<String>, String)> = proj
         .by_path
         .iter()
-        .filter(|(path, _)| !visited_rel.contains(path.as_str()))
+        .filter(|(path, _)| {
+            !visited_rel.contains(path.as_str())
+                && in_scope(path)
+                && !selection.excludes(path)
+                && !ignore.is_ignored(&names.to_disk(path), false)
+        })
         .map(|(path, entry)| (entry.id, entry.kind, entry.blob_hash.clone(), path.clone()))
         .collect();
     for (id, kind, blob_hash, path) in deletion_candidates {
@@ -950,52 +1951,32 @@ async fn scan_once(
     if post_sv != pre_sv {
         save_manifest(syncline_dir, manifest)?;
     }
-
-    // Bulk-send in chunks with `tokio::task::yield_now()` between them.
-    // The naive tight loop starved the runtime: the WS pong-handler
-    // task and the broadcast-channel forward task both share this
-    // worker, and a sustained burst of `write.send().await` calls (no
-    // intervening yields, since `Sink::send` doesn't reliably yield
-    // when the sink's buffer has room) prevents them from running.
-    // Server-side, that lets the broadcast channel grow, the kernel
-    // recv window saturate, and the connection RST under load.
-    // Yielding every BURST_SIZE frames is enough to keep both tasks
-    // alive on the scanner-CLI workload (#60).
-    const BURST_SIZE: usize = 32;
-    for (i, (hash, bytes)) in pending_blobs.iter().enumerate() {
-        let frame = encode_message(MSG_BLOB_UPDATE, hash, bytes);
-        write
-            .send(WsMessage::Binary(frame.into()))
-            .await
-            .context("send blob update from scanner")?;
-        if i % BURST_SIZE == BURST_SIZE - 1 {
-            tokio::task::yield_now().await;
-        }
+    // Only once what the index vouches for is persisted locally.
+    index.retain(|path| visited_rel.contains(path) || !in_scope(path));
+    if let Err(e) = index.save() {
+        warn!("persisting file index: {e:?}");
     }
+    debug!(skipped_unchanged, moved = moved.len(), targeted = only.is_some(), "scan walked vault");
 
+    // Stage the whole scan in the outbox before sending any of it: blobs
+    // first, then the manifest entries naming them, then content. If
+    // the connection drops mid-send, the next session sends it again.
+    let mut batch: Vec<Outgoing> = pending_blobs.into_iter().map(Outgoing::Blob).collect();
     if post_sv != pre_sv {
-        let update_bytes = {
-            let txn = manifest.doc().transact();
-            txn.encode_state_as_update_v1(&pre_sv)
-        };
-        let payload = encode_manifest_update(&update_bytes);
-        let frame = encode_message(MSG_MANIFEST_SYNC, MANIFEST_DOC_ID, &payload);
-        write
-            .send(WsMessage::Binary(frame.into()))
-            .await
-            .context("send manifest update from scanner")?;
-    }
-
-    for (i, (node_id, update)) in pending_content.iter().enumerate() {
-        let frame = encode_message(MSG_UPDATE, &content_doc_id(*node_id), update);
-        write
-            .send(WsMessage::Binary(frame.into()))
-            .await
-            .context("send content update from scanner")?;
-        if i % BURST_SIZE == BURST_SIZE - 1 {
-            tokio::task::yield_now().await;
-        }
+        batch.push(manifest_diff(manifest, &pre_sv));
     }
+    batch.extend(
+        pending_content
+            .into_iter()
+            .map(|(node_id, update)| Outgoing::Content {
+                doc_id: content_doc_id(node_id),
+                update,
+            }),
+    );
+    outbox.push(&batch).context("stage scanner changes")?;
+    send_outbox(write, outbox, blobs, chunked_blobs)
+        .await
+        .context("send scanner changes")?;
 
     if new_files + modified_files + new_binary + modified_binary + deleted_files > 0 {
         info!(
@@ -1010,10 +1991,229 @@ async fn scan_once(
 
     // Newly-created entries get a STEP_1 so we also hear concurrent
     // server-side edits that may already be in flight for that doc id.
-    subscribe_new_text_content(write, manifest, content, subscribed).await?;
+    subscribe_new_text_content(write, manifest, content, subscribed, &selection, batch_sync)
+        .await?;
     Ok(())
 }
 
+/// Text files at least this similar (see [`similarity`]) to a vanished
+/// one are taken to be it, moved and edited. Git's default.
+const MOVE_SIMILARITY: f64 = 0.5;
+
+/// Past this many new text files, or this many vanished ones, a scan
+/// skips the pairwise similarity search (one diff per pair) and lets
+/// the rest land as deletes plus creates, like git's
+/// `diff.renameLimit`. Inode and blob hash matches still apply.
+const RENAME_LIMIT: usize = 200;
+
+/// Files that moved since the last scan, as `(from, to)` manifest paths.
+///
+/// `gone` holds the projected files the walk expected but didn't find,
+/// of those this device had locally.
+/// A walked file at a path projection doesn't know is one of them if,
+/// in order of preference:
+///
+///   * the index last saw its inode, at the same size, at that path;
+///   * it's binary and hashes to that entry's blob;
+///   * it's text at least [`MOVE_SIMILARITY`] similar to that node's
+///     content, unless either side is over [`RENAME_LIMIT`].
+///
+/// Folding it in as a rename keeps its node, where a delete plus create
+/// would drop its history and orphan peers' concurrent edits.
+fn detect_moves<'g>(
+    folder: &Path,
+    walked: &[walkdir::DirEntry],
+    proj: &Projection,
+    gone: &HashMap<&'g str, &'g ProjectedEntry>,
+    index: &mut FileIndex,
+    content: &mut ContentStore,
+    names: &NameMap,
+) -> Vec<(String, String)> {
+    let mut moved = Vec::new();
+    if gone.is_empty() {
+        return moved;
+    }
+    let mut claimed: HashSet<&'g str> = HashSet::new();
+    let mut unpaired = Vec::new();
+    let by_inode = index.by_inode();
+    for dent in walked {
+        let Some(to) = walked_path(folder, dent, names) else {
+            continue;
+        };
+        if proj.by_path.contains_key(&to) {
+            continue;
+        }
+        let Some(stat) = dent.metadata().ok().and_then(|m| Stat::of(&m)) else {
+            continue;
+        };
+        let same_inode = by_inode
+            .get(&stat.inode)
+            .filter(|(_, seen)| seen.size == stat.size)
+            .and_then(|(from, _)| gone.get_key_value(*from));
+        match same_inode {
+            Some((&from, _)) if claimed.insert(from) => moved.push((from.to_string(), to)),
+            _ => unpaired.push((dent, to, stat)),
+        }
+    }
+
+    let is_text = |to: &str| {
+        let ext = Path::new(to)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        TEXT_EXTS.contains(&ext.as_str())
+    };
+    let new_texts = unpaired.iter().filter(|(_, to, _)| is_text(to)).count();
+    let old_texts = gone.values().filter(|e| e.kind == NodeKind::Text).count();
+    let compare_texts = new_texts <= RENAME_LIMIT && old_texts <= RENAME_LIMIT;
+    if !compare_texts && new_texts > 0 && old_texts > 0 {
+        warn!(
+            "{new_texts} new and {old_texts} vanished text files exceed the rename limit \
+             ({RENAME_LIMIT}); recording edited moves as deletes and creates"
+        );
+    }
+
+    for (dent, to, stat) in unpaired {
+        let candidates = gone.values().filter(|e| !claimed.contains(e.path.as_str()));
+        let from = if is_text(&to) {
+            if !compare_texts {
+                continue;
+            }
+            let Ok(body) = fs::read_to_string(dent.path()) else {
+                continue;
+            };
+            let mut best: Option<(f64, &&ProjectedEntry)> = None;
+            for entry in candidates.filter(|e| e.kind == NodeKind::Text) {
+                let Some(old) = content.load_text(entry.id) else {
+                    continue;
+                };
+                let score = similarity(&old, &body);
+                if score >= MOVE_SIMILARITY && best.is_none_or(|(b, _)| score > b) {
+                    best = Some((score, entry));
+                }
+            }
+            best.map(|(_, e)| e)
+        } else {
+            let mut candidates = candidates
+                .filter(|e| e.kind == NodeKind::Binary && e.size == stat.size)
+                .peekable();
+            if candidates.peek().is_none() {
+                continue;
+            }
+            let Ok((hash, _)) = fs::File::open(dent.path()).and_then(hash_reader) else {
+                continue;
+            };
+            let found = candidates.find(|e| e.blob_hash.as_deref() == Some(hash.as_str()));
+            // Spares the main walk hashing it again.
+            index.record(&to, stat, hash);
+            found
+        };
+        if let Some(entry) = from {
+            claimed.insert(entry.path.as_str());
+            moved.push((entry.path.clone(), to));
+        }
+    }
+    moved
+}
+
+/// How much of `a` and `b` a diff keeps, from 0.0 (nothing) to 1.0.
+fn similarity(a: &str, b: &str) -> f64 {
+    let total = a.len() + b.len();
+    if total == 0 {
+        return 1.0;
+    }
+    // Can't reach the threshold anyway; skip the diff.
+    if 2.0 * (a.len().min(b.len()) as f64) < MOVE_SIMILARITY * total as f64 {
+        return 0.0;
+    }
+    let kept: usize = dissimilar::diff(a, b)
+        .iter()
+        .map(|chunk| match chunk {
+            dissimilar::Chunk::Equal(s) => s.len(),
+            _ => 0,
+        })
+        .sum();
+    2.0 * kept as f64 / total as f64
+}
+
+/// Record `moved` in the manifest. Moves that carry everything under a
+/// directory now gone from disk to the same place under one new path
+/// become a single name / parent change on that directory's node.
+fn apply_moves(
+    folder: &Path,
+    manifest: &mut Manifest,
+    proj: &Projection,
+    moved: &[(String, String)],
+    names: &NameMap,
+) {
+    let targets: HashMap<&str, &str> = moved
+        .iter()
+        .map(|(from, to)| (from.as_str(), to.as_str()))
+        .collect();
+    // Directory pairs the moves could stand for, outermost first.
+    let mut dirs = std::collections::BTreeSet::new();
+    for (from, to) in moved {
+        let (mut from, mut to) = (from.as_str(), to.as_str());
+        while let (Some((from_dir, from_leaf)), Some((to_dir, to_leaf))) =
+            (from.rsplit_once('/'), to.rsplit_once('/'))
+        {
+            if from_leaf != to_leaf {
+                break;
+            }
+            dirs.insert((from_dir.matches('/').count(), from_dir, to_dir));
+            (from, to) = (from_dir, to_dir);
+        }
+    }
+    let under = |path: &str, dir: &str| {
+        path.strip_prefix(dir)
+            .is_some_and(|rest| rest.starts_with('/'))
+    };
+    let mut moved_dirs: Vec<&str> = Vec::new();
+    for (_, from_dir, to_dir) in dirs {
+        if moved_dirs.iter().any(|d| from_dir == *d || under(from_dir, d))
+            || folder.join(names.to_disk(from_dir)).symlink_metadata().is_ok()
+        {
+            continue;
+        }
+        let whole = proj
+            .by_path
+            .keys()
+            .filter(|p| under(p, from_dir))
+            .all(|p| {
+                targets
+                    .get(p.as_str())
+                    .is_some_and(|t| t.strip_prefix(to_dir) == p.strip_prefix(from_dir))
+            });
+        if !whole {
+            continue;
+        }
+        match crate::v1::ops::rename_directory(manifest, from_dir, to_dir) {
+            Ok(()) => {
+                debug!(from = %from_dir, to = %to_dir, "local directory move detected");
+                moved_dirs.push(from_dir);
+            }
+            Err(e) => debug!("rename_directory({from_dir:?}, {to_dir:?}) skipped: {e}"),
+        }
+    }
+    for (from, to) in moved {
+        if moved_dirs.iter().any(|d| under(from, d)) {
+            continue;
+        }
+        match crate::v1::ops::rename(manifest, from, to) {
+            Ok(()) => debug!(%from, %to, "local move detected"),
+            Err(e) => debug!("rename({from:?}, {to:?}) skipped: {e}"),
+        }
+    }
+}
+
+/// Manifest path of a walked file, if it's one the vault can hold.
+fn walked_path(folder: &Path, dent: &walkdir::DirEntry, names: &NameMap) -> Option<String> {
+    let rel = dent.path().strip_prefix(folder).ok()?;
+    let path = names.to_manifest(&rel.to_string_lossy().replace('\\', "/"));
+    (!is_unsafe_relative_path(&path)).then_some(path)
+}
+
 /// Outcome of scanning a single binary file on disk. Pure enough to
 /// unit-test against a fake manifest + temp-dir BlobStore.
 #[derive(Debug)]
@@ -1033,35 +2233,63 @@ enum BinaryScanOutcome {
     Skipped(&'static str),
 }
 
+/// Move a just-created file (at `abs`, manifest path `rel_path`) to
+/// wherever projection put its node. Used when the name collides with
+/// an equivalent spelling of an existing path: the node lands on a
+/// conflict name, and leaving the file where it is would make every
+/// later scan mint another node.
+fn move_to_projected_path(
+    folder: &Path,
+    abs: &Path,
+    manifest: &Manifest,
+    names: &NameMap,
+    rel_path: &str,
+    visited_rel: &mut HashSet<String>,
+) {
+    let Some(entry) = manifest.find_entry_by_path(rel_path) else {
+        return;
+    };
+    let Some(projected) = project(manifest).get_by_id(entry.id).map(|e| e.path.clone()) else {
+        return;
+    };
+    if projected == rel_path {
+        return;
+    }
+    match fs::rename(abs, folder.join(names.to_disk(&projected))) {
+        Ok(()) => {
+            info!(from = %rel_path, to = %projected, "name collides with an equivalent path; moved");
+            visited_rel.insert(projected);
+        }
+        Err(e) => warn!(path = %rel_path, "failed to move colliding file to {projected}: {e}"),
+    }
+}
+
 /// Core per-binary-file logic, factored out so tests can drive it
 /// without a `WsSink`. The caller is responsible for:
-///   * reading `bytes` off disk
-///   * enforcing `MAX_BLOB_SIZE`
-///   * issuing the `MSG_BLOB_UPDATE` frame when we return `Created` /
-///     `Rehashed`
+///   * opening the file on disk
+///   * enforcing the blob size limit
+///   * uploading the blob when we return `Created` / `Rehashed`
 ///
-/// This helper hashes the bytes, CAS-stashes them in `blobs` (idempotent),
-/// then reconciles against the current manifest projection:
+/// This helper streams `reader` into `blobs` (idempotent), hashing as it
+/// goes so the file is never held in memory, then reconciles against the
+/// current manifest projection:
 ///   * no existing entry at `rel_path` → `create_binary`, emit `Created`
 ///   * existing Binary entry with the same hash → `Unchanged`
 ///   * existing Binary entry with a different hash → `set_blob_hash`,
 ///     emit `Rehashed`
 ///   * existing Text/Directory entry at that path → `Skipped("kind")`
-fn process_binary_file(
+fn process_binary_file<R: Read>(
     rel_path: &str,
-    bytes: &[u8],
+    reader: R,
     proj: &Projection,
     manifest: &mut Manifest,
     blobs: &BlobStore,
 ) -> Result<BinaryScanOutcome> {
-    let hash = hash_hex(bytes);
-    let size = bytes.len() as u64;
-
     // Stash locally first — idempotent, and guarantees that if we
     // record the hash in the manifest we actually have the blob to
     // serve to any peer that asks.
-    blobs
-        .insert_bytes(bytes)
+    let (hash, size) = blobs
+        .insert_reader(reader)
         .with_context(|| format!("stashing blob for {}", rel_path))?;
 
     match proj.by_path.get(rel_path) {
@@ -1117,6 +2345,19 @@ impl ContentStore {
         Self::content_file(&self.content_dir, node_id).is_file()
     }
 
+    /// Every node with a persisted content subdoc.
+    fn persisted(&self) -> Vec<NodeId> {
+        let Ok(entries) = fs::read_dir(&self.content_dir) else {
+            return Vec::new();
+        };
+        entries
+            .filter_map(|e| {
+                let name = e.ok()?.file_name();
+                NodeId::parse_str(name.to_str()?.strip_suffix(".bin")?)
+            })
+            .collect()
+    }
+
     /// Loads the subdoc for `node_id` from disk if present, otherwise
     /// creates a fresh empty one. The root `text` Y.Text is eagerly
     /// materialised so later reads don't race on lazy creation.
@@ -1145,6 +2386,13 @@ impl ContentStore {
         Ok(txn.state_vector().encode_v1())
     }
 
+    /// [`content_digest`] of the local subdoc, for `MSG_SYNC_BATCH`.
+    fn digest(&mut self, node_id: NodeId) -> Result<[u8; 32]> {
+        self.ensure_loaded(node_id)?;
+        let doc = self.docs.get(&node_id).expect("inserted above");
+        Ok(content_digest(&doc.transact()))
+    }
+
     /// Encode the local subdoc state as an update relative to a peer's
     /// state vector. Used to reply to an incoming `MSG_SYNC_STEP_1` —
     /// the returned bytes contain whatever updates the peer is missing.
@@ -1178,6 +2426,12 @@ impl ContentStore {
         atomic_write(&path, &bytes)
     }
 
+    /// This node's text, loading its subdoc from disk if needed.
+    fn load_text(&mut self, node_id: NodeId) -> Option<String> {
+        self.ensure_loaded(node_id).ok()?;
+        self.current_text(node_id)
+    }
+
     fn current_text(&self, node_id: NodeId) -> Option<String> {
         let doc = self.docs.get(&node_id)?;
         let text = doc.get_or_insert_text("text");
@@ -1283,20 +2537,122 @@ fn handle_inbound_blob(doc_id: &str, payload: &[u8], blobs: &BlobStore) -> Resul
     Ok(())
 }
 
+/// Process an inbound `MSG_BLOB_CHUNK`. Pieces are appended to a staging
+/// file per hash in `partial`; a piece at offset 0 starts over, one that
+/// doesn't continue where the last left off aborts the transfer.
+/// Returns `Ok(true)` once the final piece has landed and the assembled
+/// blob verified against its hash and entered the store.
+fn handle_inbound_blob_chunk(
+    doc_id: &str,
+    payload: &[u8],
+    blobs: &BlobStore,
+    partial: &mut HashMap<String, BlobWriter>,
+) -> Result<bool> {
+    let (offset, total, bytes) = decode_blob_chunk(payload)
+        .ok_or_else(|| anyhow::anyhow!("malformed blob chunk for {}", doc_id))?;
+    if total > MAX_STREAMED_BLOB_SIZE {
+        anyhow::bail!(
+            "inbound blob {} is {} bytes, exceeds MAX_STREAMED_BLOB_SIZE {}",
+            doc_id,
+            total,
+            MAX_STREAMED_BLOB_SIZE
+        );
+    }
+    if offset == 0 {
+        partial.insert(doc_id.to_string(), blobs.writer(doc_id)?);
+    }
+    let Some(mut writer) = partial.remove(doc_id) else {
+        anyhow::bail!("blob chunk at offset {} for {} with no transfer in progress", offset, doc_id);
+    };
+    if writer.len() != offset {
+        anyhow::bail!(
+            "blob chunk for {} at offset {}, expected {}; transfer dropped",
+            doc_id,
+            offset,
+            writer.len()
+        );
+    }
+    writer.write_chunk(bytes)?;
+    if writer.len() < total {
+        partial.insert(doc_id.to_string(), writer);
+        return Ok(false);
+    }
+    writer
+        .finish()
+        .with_context(|| format!("verifying inbound blob {} ({} bytes)", doc_id, total))?;
+    debug!(blob_hash = doc_id, bytes = total, "stored inbound chunked blob");
+    Ok(true)
+}
+
+/// Upload one blob from the local store. Chunk-capable servers get it as
+/// consecutive `MSG_BLOB_CHUNK` frames read straight off disk, so memory
+/// stays at one chunk whatever the blob's size; 1.0 servers get the
+/// legacy single `MSG_BLOB_UPDATE`.
+async fn send_blob(
+    write: &mut WsSink,
+    blobs: &BlobStore,
+    hash: &str,
+    chunked: bool,
+    tag: Option<u64>,
+) -> Result<()> {
+    let mut file = blobs.open(hash)?;
+    let total = file.metadata()?.len();
+    if !chunked {
+        let mut bytes = Vec::with_capacity(total as usize);
+        file.read_to_end(&mut bytes)?;
+        let frame = tagged(encode_message(MSG_BLOB_UPDATE, hash, &bytes), tag);
+        write.send(WsMessage::Binary(frame.into())).await?;
+        return Ok(());
+    }
+    let mut buf = vec![0u8; BLOB_CHUNK_SIZE];
+    let mut offset = 0u64;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 && offset < total {
+            anyhow::bail!("blob {} shrank while sending ({} of {} bytes)", hash, offset, total);
+        }
+        let mut frame = encode_blob_chunk(hash, offset, total, &buf[..n]);
+        offset += n as u64;
+        // The server stores the blob on its last chunk.
+        if offset >= total {
+            frame = tagged(frame, tag);
+        }
+        write.send(WsMessage::Binary(frame.into())).await?;
+        if offset >= total {
+            return Ok(());
+        }
+    }
+}
+
+/// `frame` wrapped in a `MSG_TAGGED` under `tag`, or as is without one.
+fn tagged(frame: Vec<u8>, tag: Option<u64>) -> Vec<u8> {
+    let Some(tag) = tag else {
+        return frame;
+    };
+    let (msg_type, doc_id, payload) =
+        decode_message(&frame).expect("frames we encode decode");
+    encode_message(MSG_TAGGED, doc_id, &encode_tagged(tag, msg_type, payload))
+}
+
 /// For each live Binary entry in the manifest projection whose blob we
 /// don't yet have locally and haven't already requested this session,
-/// send a `MSG_BLOB_REQUEST`. The server replies with `MSG_BLOB_UPDATE`
-/// over the same connection.
+/// send a `MSG_BLOB_REQUEST`. The server replies with `MSG_BLOB_CHUNK`s
+/// (or a single `MSG_BLOB_UPDATE` from a 1.0 server) over the same
+/// connection.
 async fn request_missing_blobs(
     write: &mut WsSink,
     manifest: &Manifest,
     blobs: &BlobStore,
     requested: &mut HashSet<String>,
+    selection: &Selection,
 ) -> Result<()> {
     let proj = project(manifest);
     let mut sent = 0usize;
-    for entry in proj.by_path.values() {
-        if entry.kind != NodeKind::Binary {
+    for (path, entry) in &proj.by_path {
+        if entry.kind != NodeKind::Binary
+            || selection.excludes(path)
+            || !selection.fetches_up_front(entry.size)
+        {
             continue;
         }
         let Some(hash) = entry.blob_hash.as_deref() else {
@@ -1324,33 +2680,72 @@ async fn request_missing_blobs(
 /// For every live Text entry in the manifest projection not yet tracked
 /// in `subscribed`, send a content `MSG_SYNC_STEP_1`. The server replies
 /// with `MSG_SYNC_STEP_2` carrying any updates we're missing.
+///
+/// With `batched`, the entries go out in `MSG_SYNC_BATCH` frames
+/// instead, and the server stays silent about docs it already agrees
+/// with us on.
 async fn subscribe_new_text_content(
     write: &mut WsSink,
     manifest: &Manifest,
     content: &mut ContentStore,
     subscribed: &mut HashSet<NodeId>,
+    selection: &Selection,
+    batched: bool,
 ) -> Result<()> {
     let proj = project(manifest);
     let mut sent = 0usize;
-    for entry in proj.by_path.values() {
-        if entry.kind != NodeKind::Text || subscribed.contains(&entry.id) {
+    // (doc id, digest, state vector) awaiting the next batch frame.
+    let mut batch: Vec<(String, [u8; 32], Vec<u8>)> = Vec::new();
+    for (path, entry) in &proj.by_path {
+        if entry.kind != NodeKind::Text
+            || subscribed.contains(&entry.id)
+            || selection.excludes(path)
+        {
             continue;
         }
         let sv_bytes = content.state_vector_v1(entry.id)?;
-        let frame = encode_message(MSG_SYNC_STEP_1, &content_doc_id(entry.id), &sv_bytes);
-        write
-            .send(WsMessage::Binary(frame.into()))
-            .await
-            .context("send content STEP_1")?;
+        if batched {
+            let digest = content.digest(entry.id)?;
+            batch.push((content_doc_id(entry.id), digest, sv_bytes));
+            if batch.len() == SYNC_BATCH_MAX_DOCS {
+                send_sync_batch(write, &batch).await?;
+                batch.clear();
+            }
+        } else {
+            let frame = encode_message(MSG_SYNC_STEP_1, &content_doc_id(entry.id), &sv_bytes);
+            write
+                .send(WsMessage::Binary(frame.into()))
+                .await
+                .context("send content STEP_1")?;
+        }
         subscribed.insert(entry.id);
         sent += 1;
     }
+    if !batch.is_empty() {
+        send_sync_batch(write, &batch).await?;
+    }
     if sent > 0 {
         debug!("sent content STEP_1 for {} new text entries", sent);
     }
     Ok(())
 }
 
+async fn send_sync_batch(write: &mut WsSink, batch: &[(String, [u8; 32], Vec<u8>)]) -> Result<()> {
+    let entries: Vec<BatchEntry<'_>> = batch
+        .iter()
+        .map(|(doc_id, digest, sv)| BatchEntry {
+            doc_id,
+            digest: *digest,
+            state_vector: sv,
+        })
+        .collect();
+    let frame = encode_message(MSG_SYNC_BATCH, MANIFEST_DOC_ID, &encode_sync_batch(&entries));
+    write
+        .send(WsMessage::Binary(frame.into()))
+        .await
+        .context("send content sync batch")
+}
+
 /// Write the current Y.Text body of `node_id` through to the file at the
 /// path currently projected for that node. No-op (with a warning log) if
 /// the node has no projection path or the path fails the safety check.
@@ -1358,6 +2753,7 @@ fn flush_content_to_disk(
     folder: &Path,
     manifest: &Manifest,
     content: &ContentStore,
+    names: &NameMap,
     node_id: NodeId,
 ) -> Result<()> {
     let proj = project(manifest);
@@ -1365,14 +2761,17 @@ fn flush_content_to_disk(
         debug!("flush_content_to_disk: no projection entry for {:?}", node_id);
         return Ok(());
     };
-    if entry.kind != NodeKind::Text {
+    if entry.kind != NodeKind::Text
+        || Selection::load(folder).excludes(&entry.path)
+        || IgnoreList::load(folder).is_ignored(&names.to_disk(&entry.path), false)
+    {
         return Ok(());
     }
     if is_unsafe_relative_path(&entry.path) {
         warn!("unsafe projection path on content flush: {:?}", entry.path);
         return Ok(());
     }
-    let full = folder.join(&entry.path);
+    let full = folder.join(names.to_disk(&entry.path));
     if let Some(parent) = full.parent() {
         fs::create_dir_all(parent)
             .with_context(|| format!("mkdir -p {} for content flush", parent.display()))?;
@@ -1385,7 +2784,7 @@ fn flush_content_to_disk(
     // file via tmp + rename, which fires inotify CREATE/MOVED_TO
     // events. Those events go through `batch_wants_scan` (which
     // correctly classifies them as outside `.syncline/`) and trigger
-    // a full-vault `scan_once`. The scan finishes, we receive the
+    // a `scan_once`. The scan finishes, we receive the
     // next UPDATE, we rewrite the file, the watcher fires again — a
     // self-amplifying loop that pins the client at 100 % CPU during
     // and well after bootstrap on a vault with any meaningful churn.
@@ -1417,7 +2816,12 @@ fn flush_content_to_disk(
 /// manifest persist path and content-subdoc persistence. Creates parent
 /// directories as needed.
 fn atomic_write(path: &Path, bytes: &[u8]) -> Result<()> {
-    use std::io::Write;
+    atomic_write_from(path, bytes)
+}
+
+/// [`atomic_write`] fed from a reader, for bodies (blobs) that should be
+/// streamed rather than loaded into memory first.
+fn atomic_write_from<R: Read>(path: &Path, mut reader: R) -> Result<()> {
     if let Some(parent) = path.parent() {
         fs::create_dir_all(parent)
             .with_context(|| format!("mkdir -p {}", parent.display()))?;
@@ -1426,7 +2830,7 @@ fn atomic_write(path: &Path, bytes: &[u8]) -> Result<()> {
     {
         let mut f = fs::File::create(&tmp)
             .with_context(|| format!("create tmp {}", tmp.display()))?;
-        f.write_all(bytes)
+        std::io::copy(&mut reader, &mut f)
             .with_context(|| format!("write tmp {}", tmp.display()))?;
         // Intentionally no fsync. On a real-world bulk bootstrap (~1300
         // files into an empty vault) fsync-per-write was 6 ms/file ≈ 7s
@@ -1445,6 +2849,16 @@ fn atomic_write(path: &Path, bytes: &[u8]) -> Result<()> {
     Ok(())
 }
 
+/// The placeholder at `path`, if the file there is one. Only files small
+/// enough to be a stub are read.
+fn read_placeholder(path: &Path) -> Option<Placeholder> {
+    let meta = fs::metadata(path).ok()?;
+    if !meta.is_file() || meta.len() >= placeholder::MAX_LEN {
+        return None;
+    }
+    placeholder::parse(&fs::read(path).ok()?)
+}
+
 // ---------------------------------------------------------------------------
 // Projection → disk reconcile (create-only, Phase 3.3a subset)
 // ---------------------------------------------------------------------------
@@ -1460,8 +2874,10 @@ fn atomic_write(path: &Path, bytes: &[u8]) -> Result<()> {
 ///     request is fired separately by `request_missing_blobs`; the next
 ///     reconcile (triggered when the blob arrives) will materialize
 ///     the file.
-///   * Binary bytes-on-disk ≠ manifest hash → see 3.3e: LWW + conflict
-///     sibling.
+///   * Binary bytes-on-disk ≠ manifest hash → LWW: the remote bytes are
+///     written, the local ones are recorded as a new node that projects
+///     to a conflict name. Their blob hashes are returned so the caller
+///     can publish the copies (see `reconcile_and_publish`).
 ///   * Nodes that disappeared from the projection since the previous
 ///     reconcile (i.e. a remote delete) have their last-known disk path
 ///     removed. Same for renamed nodes — the old path is removed so the
@@ -1469,8 +2885,11 @@ fn atomic_write(path: &Path, bytes: &[u8]) -> Result<()> {
 ///   * Text files whose projection entry still exists are left untouched
 ///     (content is maintained by the subdoc sync path).
 ///
-/// `on_disk` tracks `NodeId → last materialised path` across reconcile
-/// invocations for this session. It starts empty; entries get added as
+/// Projected paths are written under their [`NameMap`] spelling, which
+/// is rebuilt here first so every new name gets a stable escape.
+///
+/// `on_disk` tracks `NodeId → last materialised disk path` across
+/// reconcile invocations for this session. It starts empty; entries get added as
 /// we materialise / observe files on disk, and removed when we delete
 /// stale paths.
 ///
@@ -1484,12 +2903,21 @@ fn atomic_write(path: &Path, bytes: &[u8]) -> Result<()> {
 /// round-trip.
 fn reconcile_projection_to_disk(
     folder: &Path,
-    manifest: &Manifest,
+    manifest: &mut Manifest,
     blobs: &BlobStore,
+    names: &mut NameMap,
     on_disk: &mut HashMap<NodeId, String>,
     content: Option<&ContentStore>,
-) -> Result<()> {
+) -> Result<Vec<String>> {
     let proj = project(manifest);
+    names.rebuild(proj.by_path.keys().map(String::as_str))?;
+    let selection = Selection::load(folder);
+    // Paths this vault's ignore files cover stay in the manifest but
+    // aren't synced here, in either direction.
+    let ignore = IgnoreList::load(folder);
+    let skipped = |path: &str| {
+        selection.excludes(path) || ignore.is_ignored(&names.to_disk(path), false)
+    };
 
     // Apply remote deletions / renames first: any NodeId we previously
     // materialised that is either gone from the projection or now lives
@@ -1502,7 +2930,7 @@ fn reconcile_projection_to_disk(
             let still_here = proj
                 .by_id
                 .get(id)
-                .map(|e| e.path == *old_path)
+                .map(|e| names.to_disk(&e.path) == *old_path)
                 .unwrap_or(false);
             if still_here {
                 None
@@ -1534,14 +2962,20 @@ fn reconcile_projection_to_disk(
     let mut created_text = 0usize;
     let mut created_binary = 0usize;
     let mut pending_binary = 0usize;
-    let mut conflicts_created = 0usize;
+    let mut placeholders = 0usize;
+    // Local blobs recorded as conflict copies; the caller uploads them.
+    let mut conflict_blobs: Vec<String> = Vec::new();
 
     for (path, entry) in &proj.by_path {
+        if skipped(path) {
+            continue;
+        }
         if is_unsafe_relative_path(path) {
             warn!("skipping unsafe projection path {:?}", path);
             continue;
         }
-        let full = folder.join(path);
+        let disk_path = names.to_disk(path);
+        let full = folder.join(&disk_path);
         if let Some(parent) = full.parent() {
             if !parent.exists() {
                 fs::create_dir_all(parent).with_context(|| {
@@ -1582,10 +3016,27 @@ fn reconcile_projection_to_disk(
                     debug!("binary {:?} has no blob_hash, skipping", path);
                     continue;
                 };
+                let lazy = !blobs.has(hash) && !selection.fetches_up_front(entry.size);
+                if let Some(stub) = read_placeholder(&full) {
+                    // Ours, not the user's: swap in the blob once we have
+                    // it, or restate which blob it now stands for.
+                    if blobs.has(hash) {
+                        let blob = blobs
+                            .open(hash)
+                            .with_context(|| format!("open blob {} for {:?}", hash, path))?;
+                        atomic_write_from(&full, blob).with_context(|| {
+                            format!("replace placeholder {} with blob {}", full.display(), hash)
+                        })?;
+                        created_binary += 1;
+                    } else if stub.hash != hash {
+                        atomic_write(&full, &placeholder::render(hash, entry.size))?;
+                    }
+                    continue;
+                }
                 if full.exists() {
-                    let local_bytes = fs::read(&full)
-                        .with_context(|| format!("read local {}", full.display()))?;
-                    let local_hash = hash_hex(&local_bytes);
+                    let (local_hash, _) = fs::File::open(&full)
+                        .and_then(hash_reader)
+                        .with_context(|| format!("hash local {}", full.display()))?;
                     if local_hash == hash {
                         continue;
                     }
@@ -1598,43 +3049,65 @@ fn reconcile_projection_to_disk(
                     }
                     // Disk diverged from manifest and we have the remote
                     // version. LWW: remote wins (we reach this branch
-                    // only after a remote manifest update landed). Save
-                    // local bytes as a conflict sibling so nothing gets
-                    // silently clobbered.
-                    blobs.insert_bytes(&local_bytes).with_context(|| {
-                        format!("stash conflict bytes for {:?}", path)
-                    })?;
-                    let actor_short = manifest.actor().short();
-                    let conflict_rel =
-                        conflict_sibling_path(path, &actor_short, &today_ymd());
+                    // only after a remote manifest update landed). The
+                    // local bytes become a node of their own at the same
+                    // path; being newer, it loses the collision and
+                    // projection gives it the vault's conflict name
+                    // (§6.4), the same on every peer.
+                    // The conflict copy is written from the stash, not the
+                    // live file, so it holds exactly the bytes we hashed.
+                    let (local_hash, local_size) = fs::File::open(&full)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|f| blobs.insert_reader(f))
+                        .with_context(|| format!("stash conflict bytes for {:?}", path))?;
+                    let copy_id = crate::v1::ops::create_binary_allowing_collision(
+                        manifest,
+                        path,
+                        &local_hash,
+                        local_size,
+                    )?;
+                    let Some(copy) = project(manifest).by_id.remove(&copy_id) else {
+                        anyhow::bail!("conflict copy of {path:?} did not project");
+                    };
+                    let conflict_rel = names.to_disk(&copy.path);
                     let conflict_full = folder.join(&conflict_rel);
-                    atomic_write(&conflict_full, &local_bytes).with_context(|| {
-                        format!("write conflict copy {}", conflict_full.display())
-                    })?;
-                    let remote_bytes = blobs.read(hash).with_context(|| {
-                        format!("read remote blob {} for {:?}", hash, path)
+                    atomic_write_from(&conflict_full, blobs.open(&local_hash)?).with_context(
+                        || format!("write conflict copy {}", conflict_full.display()),
+                    )?;
+                    let remote = blobs.open(hash).with_context(|| {
+                        format!("open remote blob {} for {:?}", hash, path)
                     })?;
-                    atomic_write(&full, &remote_bytes).with_context(|| {
+                    atomic_write_from(&full, remote).with_context(|| {
                         format!("overwrite with remote {}", full.display())
                     })?;
+                    let remote_device = manifest
+                        .get_entry(entry.id)
+                        .map(|e| device_label(manifest, e.effective_stamp().actor))
+                        .unwrap_or_default();
                     warn!(
                         path = %path,
-                        conflict_copy = %conflict_rel,
+                        conflict_copy = %copy.path,
                         local_hash = %local_hash,
                         remote_hash = %hash,
-                        "binary conflict: local bytes preserved, remote applied"
+                        "binary conflict: local bytes preserved, version edited on {remote_device} applied"
                     );
-                    conflicts_created += 1;
+                    conflict_blobs.push(local_hash);
+                    continue;
+                }
+                if lazy {
+                    atomic_write(&full, &placeholder::render(hash, entry.size))
+                        .with_context(|| format!("write placeholder {}", full.display()))?;
+                    placeholders += 1;
                     continue;
                 }
                 if !blobs.has(hash) {
                     pending_binary += 1;
                     continue;
                 }
-                let bytes = blobs
-                    .read(hash)
-                    .with_context(|| format!("read blob {} for {:?}", hash, path))?;
-                atomic_write(&full, &bytes).with_context(|| {
+                let blob = blobs
+                    .open(hash)
+                    .with_context(|| format!("open blob {} for {:?}", hash, path))?;
+                atomic_write_from(&full, blob).with_context(|| {
                     format!("materialize binary {} from blob {}", full.display(), hash)
                 })?;
                 created_binary += 1;
@@ -1650,14 +3123,19 @@ fn reconcile_projection_to_disk(
     // there) is a candidate for future stale-path cleanup. Pending
     // binaries (no blob yet) are NOT tracked — they don't exist on
     // disk, so nothing to clean.
+    let proj = if conflict_blobs.is_empty() {
+        proj
+    } else {
+        project(manifest)
+    };
     on_disk.clear();
     for (id, entry) in &proj.by_id {
-        if matches!(entry.kind, NodeKind::Directory) {
+        if matches!(entry.kind, NodeKind::Directory) || skipped(&entry.path) {
             continue;
         }
-        let full = folder.join(&entry.path);
-        if full.is_file() {
-            on_disk.insert(*id, entry.path.clone());
+        let disk_path = names.to_disk(&entry.path);
+        if folder.join(&disk_path).is_file() {
+            on_disk.insert(*id, disk_path);
         }
     }
 
@@ -1665,7 +3143,8 @@ fn reconcile_projection_to_disk(
         + created_text
         + created_binary
         + pending_binary
-        + conflicts_created
+        + placeholders
+        + conflict_blobs.len()
         + removed_stale
         > 0
     {
@@ -1674,68 +3153,153 @@ fn reconcile_projection_to_disk(
             created_text_placeholders = created_text,
             created_binary,
             pending_binary,
-            conflicts_created,
+            placeholders,
+            conflicts_created = conflict_blobs.len(),
             removed_stale,
             "reconciled projection → disk"
         );
     }
-    Ok(())
+    Ok(conflict_blobs)
 }
 
-// ---------------------------------------------------------------------------
-// Local-disk conflict handling (Phase 3.3e)
-// ---------------------------------------------------------------------------
-
-/// Build the conflict-sibling path for a vault-relative file path.
-///
-/// Shape: `<stem> (conflict <YYYY-MM-DD> <actor8>).<ext>` inside the same
-/// directory as the original. Extension-less files get no trailing dot.
-/// The suffix carries the actor id short form and the current day so
-/// multiple conflicts on the same file — whether from different peers or
-/// from a restart-induced re-detection — don't collide.
-fn conflict_sibling_path(path: &str, actor_short: &str, date_ymd: &str) -> String {
-    let last_slash = path.rfind('/').map(|i| i + 1).unwrap_or(0);
-    let dir_prefix = &path[..last_slash];
-    let last = &path[last_slash..];
-    let (stem, ext) = match last.rfind('.') {
-        // Hidden files like ".env" have no stem → keep the full name.
-        Some(dot) if dot > 0 => (&last[..dot], Some(&last[dot + 1..])),
-        _ => (last, None),
-    };
-    match ext {
-        Some(ext) => format!("{dir_prefix}{stem} (conflict {date_ymd} {actor_short}).{ext}"),
-        None => format!("{dir_prefix}{stem} (conflict {date_ymd} {actor_short})"),
+/// [`reconcile_projection_to_disk`], then publish the conflict copies it
+/// recorded: their blobs first, then the manifest entries naming them,
+/// so the server never holds an entry whose blob it can't serve.
+#[allow(clippy::too_many_arguments)]
+async fn reconcile_and_publish(
+    write: &mut WsSink,
+    folder: &Path,
+    syncline_dir: &Path,
+    manifest: &mut Manifest,
+    blobs: &BlobStore,
+    names: &mut NameMap,
+    on_disk: &mut HashMap<NodeId, String>,
+    content: &ContentStore,
+    outbox: &mut Outbox,
+    chunked_blobs: bool,
+) -> Result<()> {
+    let pre_sv = manifest.doc().transact().state_vector();
+    let conflict_blobs =
+        reconcile_projection_to_disk(folder, manifest, blobs, names, on_disk, Some(content))?;
+    if conflict_blobs.is_empty() {
+        return Ok(());
     }
+    save_manifest(syncline_dir, manifest)?;
+    let mut batch: Vec<Outgoing> = conflict_blobs.into_iter().map(Outgoing::Blob).collect();
+    batch.push(manifest_diff(manifest, &pre_sv));
+    outbox.push(&batch).context("stage conflict copies")?;
+    send_outbox(write, outbox, blobs, chunked_blobs)
+        .await
+        .context("send conflict copies")
 }
 
-/// `YYYY-MM-DD` (UTC) for embedding in conflict filenames. Falls back to
-/// the epoch if the clock is broken, which we accept — the suffix still
-/// serves its uniqueness purpose thanks to the actor id.
-fn today_ymd() -> String {
-    let secs = std::time::SystemTime::now()
+/// Refresh this actor's device record with `device` as its name and
+/// publish it. Runs once per session, so `last_seen` is the start of
+/// the latest session.
+async fn publish_device(
+    write: &mut WsSink,
+    manifest: &mut Manifest,
+    syncline_dir: &Path,
+    device: &str,
+    outbox: &mut Outbox,
+    blobs: &BlobStore,
+    chunked_blobs: bool,
+) -> Result<()> {
+    let pre_sv = manifest.doc().transact().state_vector();
+    let now = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
-        .map(|d| d.as_secs() as i64)
+        .map(|d| d.as_secs())
         .unwrap_or(0);
-    let days = secs.div_euclid(86_400);
-    let (y, m, d) = civil_from_days(days);
-    format!("{y:04}-{m:02}-{d:02}")
+    manifest.publish_device(device, std::env::consts::OS, env!("CARGO_PKG_VERSION"), now);
+    save_manifest(syncline_dir, manifest)?;
+    outbox.push(&[manifest_diff(manifest, &pre_sv)])?;
+    send_outbox(write, outbox, blobs, chunked_blobs).await
+}
+
+/// Give a vault nobody has written to yet the portable path policy, so
+/// case and Unicode variants are caught as collisions from the start.
+/// Existing vaults keep whatever they had — unset means exact. Returns
+/// the manifest update to send, if anything was seeded.
+fn seed_new_vault(manifest: &mut Manifest) -> Option<Outgoing> {
+    if !manifest.all_entries().is_empty() || !manifest.config_entries().is_empty() {
+        return None;
+    }
+    let pre_sv = manifest.doc().transact().state_vector();
+    manifest.set_config(PATH_EQUIVALENCE_KEY, &PathEquivalence::PORTABLE.to_string());
+    Some(manifest_diff(manifest, &pre_sv))
+}
+
+/// Everything `manifest` gained since `since`, as an outbox item.
+fn manifest_diff(manifest: &Manifest, since: &StateVector) -> Outgoing {
+    Outgoing::Manifest(manifest.doc().transact().encode_state_as_update_v1(since))
 }
 
-/// Howard Hinnant's `civil_from_days` — UNIX day number → (year, month, day).
-/// Exists so we don't have to pull in `chrono` or `time` just for a
-/// filename stamp. Valid for any Gregorian date in `i64` range.
-fn civil_from_days(days: i64) -> (i32, u32, u32) {
-    let z = days + 719_468;
-    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
-    let doe = (z - era * 146_097) as u64;
-    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
-    let y = yoe as i64 + era * 400;
-    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
-    let mp = (5 * doy + 2) / 153;
-    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
-    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
-    let y = if m <= 2 { y + 1 } else { y };
-    (y as i32, m, d)
+/// Send every batch waiting in the outbox and not yet in flight, oldest
+/// first. Each item is tagged if the server acknowledges updates, and
+/// its batch is dropped once all of them are acknowledged; otherwise
+/// once all of it is on the wire. A blob that has since left the local
+/// store is skipped: its manifest entry is gone or points elsewhere.
+async fn send_outbox(
+    write: &mut WsSink,
+    outbox: &mut Outbox,
+    blobs: &BlobStore,
+    chunked_blobs: bool,
+) -> Result<()> {
+    // Bulk-send in chunks with `tokio::task::yield_now()` between them.
+    // The naive tight loop starved the runtime: the WS pong-handler
+    // task and the broadcast-channel forward task both share this
+    // worker, and a sustained burst of `write.send().await` calls (no
+    // intervening yields, since `Sink::send` doesn't reliably yield
+    // when the sink's buffer has room) prevents them from running.
+    // Server-side, that lets the broadcast channel grow, the kernel
+    // recv window saturate, and the connection RST under load.
+    // Yielding every BURST_SIZE frames is enough to keep both tasks
+    // alive on the scanner-CLI workload (#60).
+    const BURST_SIZE: usize = 32;
+    let mut sent = 0usize;
+    for (seq, items) in outbox.unsent()? {
+        for item in &items {
+            if let Outgoing::Blob(hash) = item
+                && !blobs.has(hash)
+            {
+                warn!("outbox blob {hash} no longer in the local store; skipping");
+                continue;
+            }
+            let tag = outbox.tag(seq);
+            match item {
+                Outgoing::Blob(hash) => send_blob(write, blobs, hash, chunked_blobs, tag)
+                    .await
+                    .context("send blob update")?,
+                Outgoing::Manifest(update) => {
+                    let frame = tagged(
+                        encode_message(
+                            MSG_MANIFEST_SYNC,
+                            MANIFEST_DOC_ID,
+                            &encode_manifest_update(update),
+                        ),
+                        tag,
+                    );
+                    write
+                        .send(WsMessage::Binary(frame.into()))
+                        .await
+                        .context("send manifest update")?;
+                }
+                Outgoing::Content { doc_id, update } => {
+                    let frame = tagged(encode_message(MSG_UPDATE, doc_id, update), tag);
+                    write
+                        .send(WsMessage::Binary(frame.into()))
+                        .await
+                        .context("send content update")?;
+                }
+            }
+            sent += 1;
+            if sent.is_multiple_of(BURST_SIZE) {
+                tokio::task::yield_now().await;
+            }
+        }
+        outbox.sent(seq)?;
+    }
+    Ok(())
 }
 
 /// Pre-merge disk drift for a text node before applying an incoming remote
@@ -1752,6 +3316,7 @@ fn fold_disk_drift_into_content(
     folder: &Path,
     manifest: &Manifest,
     content: &mut ContentStore,
+    names: &NameMap,
     node_id: NodeId,
 ) -> Result<Option<Vec<u8>>> {
     let proj = project(manifest);
@@ -1764,7 +3329,7 @@ fn fold_disk_drift_into_content(
     if is_unsafe_relative_path(&entry.path) {
         return Ok(None);
     }
-    let full = folder.join(&entry.path);
+    let full = folder.join(names.to_disk(&entry.path));
     if !full.exists() {
         return Ok(None);
     }
@@ -1826,6 +3391,44 @@ fn batch_wants_scan(
         .any(|e| !path_is_inside_syncline(&e.path, syncline_dir))
 }
 
+/// The vault-relative disk paths a watcher batch touched, for a targeted
+/// `scan_once`. A path under another one in the batch is dropped, since
+/// walking the outer one reaches it.
+///
+/// `None` asks for a full scan instead: when an event can't be placed
+/// inside the vault (notify handed back a spelling of the root we don't
+/// recognise), names the root itself, or touches a `.synclineignore` —
+/// a changed ignore file can un-ignore files no event mentions.
+fn batch_scan_paths(
+    events: &[notify_debouncer_mini::DebouncedEvent],
+    folder: &Path,
+    syncline_dir: &Path,
+) -> Option<Vec<String>> {
+    let canonical = folder.canonicalize().ok();
+    let mut paths = std::collections::BTreeSet::new();
+    for event in events {
+        if path_is_inside_syncline(&event.path, syncline_dir) {
+            continue;
+        }
+        let rel = event
+            .path
+            .strip_prefix(folder)
+            .ok()
+            .or_else(|| event.path.strip_prefix(canonical.as_ref()?).ok())?;
+        if rel.as_os_str().is_empty()
+            || rel.file_name().is_some_and(|n| n == crate::ignore::IGNORE_FILE)
+        {
+            return None;
+        }
+        paths.insert(rel.to_string_lossy().replace('\\', "/"));
+    }
+    let covered = |p: &str| {
+        std::iter::successors(p.rsplit_once('/'), |(parent, _)| parent.rsplit_once('/'))
+            .any(|(parent, _)| paths.contains(parent))
+    };
+    Some(paths.iter().filter(|p| !covered(p)).cloned().collect())
+}
+
 fn path_is_inside_syncline(path: &Path, syncline_dir: &Path) -> bool {
     if path.starts_with(syncline_dir) {
         return true;
@@ -1860,6 +3463,32 @@ fn is_unsafe_relative_path(path: &str) -> bool {
 // Backoff + pretty-print
 // ---------------------------------------------------------------------------
 
+/// The server revoked this device (see `server/devices.rs`).
+/// Reconnecting can't help, so [`run_client`] gives up.
+#[derive(Debug)]
+struct DeviceRevoked;
+
+impl std::fmt::Display for DeviceRevoked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the server has revoked this device; ask its admin to reinstate it")
+    }
+}
+
+impl std::error::Error for DeviceRevoked {}
+
+/// The server refused our protocol version. Reconnecting can't help
+/// until one side is upgraded, so [`run_client`] gives up.
+#[derive(Debug)]
+struct IncompatibleServer(String);
+
+impl std::fmt::Display for IncompatibleServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}; upgrade syncline on this device or the server", self.0)
+    }
+}
+
+impl std::error::Error for IncompatibleServer {}
+
 fn backoff_ms(attempt: u32) -> u64 {
     let shifted = RECONNECT_BASE_MS.saturating_mul(1u64 << attempt.min(6));
     shifted.min(RECONNECT_CAP_MS)
@@ -1888,6 +3517,11 @@ fn banner(folder: &Path, url: &str) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::v1::hash::hash_hex;
+
+    fn posix_names() -> NameMap {
+        NameMap::new(NameRules::Posix)
+    }
 
     #[test]
     fn backoff_caps_at_reconnect_cap() {
@@ -1923,6 +3557,20 @@ mod tests {
         assert!(m.live_entries().is_empty());
     }
 
+    #[test]
+    fn feed_cursor_roundtrips_through_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(load_cursor(dir.path()), None);
+        let cursor = Cursor {
+            feed: uuid::Uuid::new_v4().to_string(),
+            seq: 1234,
+        };
+        save_cursor(dir.path(), &cursor).unwrap();
+        assert_eq!(load_cursor(dir.path()), Some(cursor));
+        fs::write(dir.path().join("feed_cursor"), "garbage").unwrap();
+        assert_eq!(load_cursor(dir.path()), None);
+    }
+
     #[test]
     fn reconcile_creates_dirs_and_text_placeholders_defers_binary_without_blob() {
         let dir = tempfile::tempdir().unwrap();
@@ -1936,7 +3584,15 @@ mod tests {
         // Binary present in manifest but blob is NOT in the local store.
         crate::v1::ops::create_binary(&mut m, "img/pic.png", "deadbeef", 1024).unwrap();
 
-        reconcile_projection_to_disk(folder, &m, &blobs, &mut HashMap::new(), None).unwrap();
+        reconcile_projection_to_disk(
+            folder,
+            &mut m,
+            &blobs,
+            &mut posix_names(),
+            &mut HashMap::new(),
+            None,
+        )
+        .unwrap();
 
         assert!(folder.join("top.md").exists(), "top.md should exist");
         assert_eq!(
@@ -1965,7 +3621,15 @@ mod tests {
         crate::v1::ops::create_binary(&mut m, "img/pic.png", &hash, bytes.len() as u64)
             .unwrap();
 
-        reconcile_projection_to_disk(folder, &m, &blobs, &mut HashMap::new(), None).unwrap();
+        reconcile_projection_to_disk(
+            folder,
+            &mut m,
+            &blobs,
+            &mut posix_names(),
+            &mut HashMap::new(),
+            None,
+        )
+        .unwrap();
 
         let on_disk = fs::read(folder.join("img/pic.png")).unwrap();
         assert_eq!(on_disk, bytes);
@@ -1984,7 +3648,15 @@ mod tests {
         let mut m = Manifest::new(actor);
         crate::v1::ops::create_text(&mut m, "diary.md", 0).unwrap();
 
-        reconcile_projection_to_disk(folder, &m, &blobs, &mut HashMap::new(), None).unwrap();
+        reconcile_projection_to_disk(
+            folder,
+            &mut m,
+            &blobs,
+            &mut posix_names(),
+            &mut HashMap::new(),
+            None,
+        )
+        .unwrap();
 
         let on_disk = fs::read(folder.join("diary.md")).unwrap();
         assert_eq!(on_disk, local_bytes, "reconcile clobbered local edits");
@@ -2007,7 +3679,15 @@ mod tests {
         let mut m = Manifest::new(ActorId::new());
         crate::v1::ops::create_binary(&mut m, "img.bin", "deadbeef", 123).unwrap();
 
-        reconcile_projection_to_disk(folder, &m, &blobs, &mut HashMap::new(), None).unwrap();
+        reconcile_projection_to_disk(
+            folder,
+            &mut m,
+            &blobs,
+            &mut posix_names(),
+            &mut HashMap::new(),
+            None,
+        )
+        .unwrap();
 
         let on_disk = fs::read(folder.join("img.bin")).unwrap();
         assert_eq!(on_disk, local_bytes, "must not touch disk without remote blob");
@@ -2091,11 +3771,34 @@ mod tests {
         let mut store = ContentStore::new(content_dir);
         store.apply_update(nid, &update_bytes).unwrap();
 
-        flush_content_to_disk(folder, &m, &store, nid).unwrap();
+        flush_content_to_disk(folder, &m, &store, &posix_names(), nid).unwrap();
         let written = fs::read_to_string(folder.join("notes/hi.md")).unwrap();
         assert_eq!(written, "Hello, world!");
     }
 
+    #[test]
+    fn windows_rules_escape_unrepresentable_names_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path();
+        let (_bs_tmp, blobs) = fresh_blob_store();
+        let mut store = ContentStore::new(folder.join(".syncline/content"));
+
+        let mut m = Manifest::new(ActorId::new());
+        let nid = crate::v1::ops::create_text(&mut m, "Q: notes/CON.md", 0).unwrap();
+        store.replace_text(nid, "body").unwrap();
+
+        let mut names = NameMap::new(NameRules::Windows);
+        let mut on_disk = HashMap::new();
+        reconcile_projection_to_disk(folder, &mut m, &blobs, &mut names, &mut on_disk, None)
+            .unwrap();
+        flush_content_to_disk(folder, &m, &store, &names, nid).unwrap();
+
+        let escaped = "Q： notes/COＮ.md";
+        assert_eq!(fs::read_to_string(folder.join(escaped)).unwrap(), "body");
+        assert_eq!(on_disk.get(&nid).map(String::as_str), Some(escaped));
+        assert_eq!(names.to_manifest(escaped), "Q: notes/CON.md");
+    }
+
     #[test]
     fn compute_minimal_edit_identical() {
         let (p, old_len, new_mid) = compute_minimal_edit("hello", "hello");
@@ -2180,7 +3883,7 @@ mod tests {
 
         // Node exists only in the caller's head, not in the manifest.
         let orphan = NodeId::new();
-        flush_content_to_disk(folder, &m, &store, orphan).unwrap();
+        flush_content_to_disk(folder, &m, &store, &posix_names(), orphan).unwrap();
         assert!(
             fs::read_dir(folder).unwrap().next().is_none(),
             "no files should be created for an unprojected node"
@@ -2202,7 +3905,7 @@ mod tests {
         let (_tmp, bs) = fresh_blob_store();
         let bytes = b"\x89PNG\r\n\x1a\npretend png";
 
-        let outcome = process_binary_file("img/pic.png", bytes, &proj, &mut m, &bs).unwrap();
+        let outcome = process_binary_file("img/pic.png", &bytes[..], &proj, &mut m, &bs).unwrap();
         let expected = hash_hex(bytes);
 
         match outcome {
@@ -2231,7 +3934,7 @@ mod tests {
         bs.insert_bytes(bytes).unwrap();
 
         let proj = project(&m);
-        let outcome = process_binary_file("a.png", bytes, &proj, &mut m, &bs).unwrap();
+        let outcome = process_binary_file("a.png", &bytes[..], &proj, &mut m, &bs).unwrap();
         assert!(matches!(outcome, BinaryScanOutcome::Unchanged));
     }
 
@@ -2247,7 +3950,7 @@ mod tests {
         let new = b"new png bytes, different length and content";
         let h_new = hash_hex(new);
         let proj = project(&m);
-        let outcome = process_binary_file("a.png", new, &proj, &mut m, &bs).unwrap();
+        let outcome = process_binary_file("a.png", &new[..], &proj, &mut m, &bs).unwrap();
 
         match outcome {
             BinaryScanOutcome::Rehashed { hash } => assert_eq!(hash, h_new),
@@ -2270,7 +3973,7 @@ mod tests {
         let (_tmp, bs) = fresh_blob_store();
         let proj = project(&m);
 
-        let outcome = process_binary_file("ambiguous", b"blob", &proj, &mut m, &bs).unwrap();
+        let outcome = process_binary_file("ambiguous", &b"blob"[..], &proj, &mut m, &bs).unwrap();
         match outcome {
             BinaryScanOutcome::Skipped(reason) => assert_eq!(reason, "kind_mismatch"),
             other => panic!("expected Skipped(kind_mismatch), got {other:?}"),
@@ -2311,61 +4014,51 @@ mod tests {
         assert!(!blobs.has(&h));
     }
 
-    // --- Phase 3.3e: conflict handling -------------------------------------
-
-    #[test]
-    fn conflict_sibling_path_with_extension() {
-        assert_eq!(
-            conflict_sibling_path("notes/hi.md", "abcd1234", "2026-04-21"),
-            "notes/hi (conflict 2026-04-21 abcd1234).md"
-        );
-    }
-
-    #[test]
-    fn conflict_sibling_path_no_extension() {
-        assert_eq!(
-            conflict_sibling_path("README", "abcd1234", "2026-04-21"),
-            "README (conflict 2026-04-21 abcd1234)"
-        );
-    }
-
     #[test]
-    fn conflict_sibling_path_nested() {
-        assert_eq!(
-            conflict_sibling_path("a/b/c/file.bin", "ffff0000", "2026-04-21"),
-            "a/b/c/file (conflict 2026-04-21 ffff0000).bin"
-        );
+    fn inbound_blob_chunks_assemble_into_store() {
+        let (_tmp, blobs) = fresh_blob_store();
+        let bytes: Vec<u8> = (0..10_000u32).map(|i| (i % 239) as u8).collect();
+        let h = hash_hex(&bytes);
+        let total = bytes.len() as u64;
+        let mut partial = HashMap::new();
+        let mut offset = 0usize;
+        for piece in bytes.chunks(4096) {
+            let frame = encode_blob_chunk(&h, offset as u64, total, piece);
+            let (_, doc_id, payload) = decode_message(&frame).unwrap();
+            offset += piece.len();
+            let done = handle_inbound_blob_chunk(doc_id, payload, &blobs, &mut partial).unwrap();
+            assert_eq!(done, offset == bytes.len());
+            assert_eq!(blobs.has(&h), done, "blob visible only once complete");
+        }
+        assert!(partial.is_empty());
+        assert_eq!(blobs.read(&h).unwrap(), bytes);
     }
 
     #[test]
-    fn conflict_sibling_path_hidden_file_has_no_stem_split() {
-        // `.env` is a single-segment name starting with a dot; treating
-        // the whole thing as the stem avoids producing ` (conflict …).env`
-        // without a leading stem.
-        let got = conflict_sibling_path(".env", "aaaa1111", "2026-04-21");
-        assert_eq!(got, ".env (conflict 2026-04-21 aaaa1111)");
-    }
+    fn inbound_blob_chunks_reject_gaps_and_bad_hashes() {
+        let (_tmp, blobs) = fresh_blob_store();
+        let bytes = b"0123456789";
+        let h = hash_hex(bytes);
+        let mut partial = HashMap::new();
+        let chunk = |off: usize, end: usize| encode_blob_chunk(&h, off as u64, 10, &bytes[off..end]);
+
+        let f = chunk(0, 4);
+        let (_, d, p) = decode_message(&f).unwrap();
+        assert!(!handle_inbound_blob_chunk(d, p, &blobs, &mut partial).unwrap());
+        let f = chunk(6, 10);
+        let (_, d, p) = decode_message(&f).unwrap();
+        assert!(handle_inbound_blob_chunk(d, p, &blobs, &mut partial).is_err());
+        assert!(partial.is_empty(), "a gap drops the transfer");
+        assert!(!blobs.has(&h));
 
-    #[test]
-    fn civil_from_days_known_dates() {
-        assert_eq!(civil_from_days(0), (1970, 1, 1));
-        assert_eq!(civil_from_days(1), (1970, 1, 2));
-        assert_eq!(civil_from_days(31), (1970, 2, 1));
-        // 2000-01-01 — well-known leap-century boundary.
-        assert_eq!(civil_from_days(10957), (2000, 1, 1));
-        // 2000-02-29 must decode as the real Feb 29 (not Mar 1).
-        assert_eq!(civil_from_days(11016), (2000, 2, 29));
-        // 2026-04-21 sanity check (today in the project timeline).
-        assert_eq!(civil_from_days(20564), (2026, 4, 21));
+        let wrong = hash_hex(b"something else");
+        let f = encode_blob_chunk(&wrong, 0, 10, bytes);
+        let (_, d, p) = decode_message(&f).unwrap();
+        assert!(handle_inbound_blob_chunk(d, p, &blobs, &mut partial).is_err());
+        assert!(!blobs.has(&wrong));
     }
 
-    #[test]
-    fn today_ymd_is_ten_chars_with_hyphens() {
-        let s = today_ymd();
-        assert_eq!(s.len(), 10);
-        assert_eq!(&s[4..5], "-");
-        assert_eq!(&s[7..8], "-");
-    }
+    // --- Phase 3.3e: conflict handling -------------------------------------
 
     #[test]
     fn reconcile_creates_binary_conflict_copy_when_disk_drifts() {
@@ -2389,7 +4082,15 @@ mod tests {
         )
         .unwrap();
 
-        reconcile_projection_to_disk(folder, &m, &blobs, &mut HashMap::new(), None).unwrap();
+        let uploads = reconcile_projection_to_disk(
+            folder,
+            &mut m,
+            &blobs,
+            &mut posix_names(),
+            &mut HashMap::new(),
+            None,
+        )
+        .unwrap();
 
         // Canonical path now holds the remote bytes.
         let on_disk = fs::read(folder.join("img.bin")).unwrap();
@@ -2402,11 +4103,18 @@ mod tests {
             .collect();
         let conflict_name = entries
             .iter()
-            .find(|n| n.contains("(conflict ") && n.ends_with(".bin"))
+            .find(|n| n.starts_with("img.conflict-") && n.ends_with(".bin"))
             .unwrap_or_else(|| panic!("no conflict sibling in {entries:?}"));
         let conflict_bytes = fs::read(folder.join(conflict_name)).unwrap();
         assert_eq!(conflict_bytes, local_bytes, "local bytes lost");
 
+        // The copy is a manifest node under the name projection gives it,
+        // and its blob is handed back for upload.
+        let copy = &project(&m).by_path[conflict_name.as_str()];
+        assert!(copy.is_conflict_copy);
+        assert_eq!(copy.blob_hash.as_deref(), Some(hash_hex(local_bytes).as_str()));
+        assert_eq!(uploads, vec![hash_hex(local_bytes)]);
+
         // Local bytes should also have been stashed in the CAS so the
         // user can never truly lose them even if they delete the sibling.
         assert!(blobs.has(&hash_hex(local_bytes)));
@@ -2425,7 +4133,15 @@ mod tests {
         let mut m = Manifest::new(ActorId::new());
         crate::v1::ops::create_binary(&mut m, "img.bin", &hash, bytes.len() as u64).unwrap();
 
-        reconcile_projection_to_disk(folder, &m, &blobs, &mut HashMap::new(), None).unwrap();
+        reconcile_projection_to_disk(
+            folder,
+            &mut m,
+            &blobs,
+            &mut posix_names(),
+            &mut HashMap::new(),
+            None,
+        )
+        .unwrap();
 
         let entries: Vec<String> = fs::read_dir(folder)
             .unwrap()
@@ -2453,7 +4169,7 @@ mod tests {
 
         let mut store = ContentStore::new(folder.join(".syncline/content"));
 
-        let delta = fold_disk_drift_into_content(folder, &m, &mut store, nid).unwrap();
+        let delta = fold_disk_drift_into_content(folder, &m, &mut store, &posix_names(), nid).unwrap();
         let delta = delta.expect("disk drift should produce a delta");
 
         // Applying the delta to a fresh peer converges to the disk text.
@@ -2483,7 +4199,7 @@ mod tests {
         // Seed CRDT with the same body.
         store.replace_text(nid, "same body").unwrap();
 
-        let out = fold_disk_drift_into_content(folder, &m, &mut store, nid).unwrap();
+        let out = fold_disk_drift_into_content(folder, &m, &mut store, &posix_names(), nid).unwrap();
         assert!(out.is_none(), "no delta expected when disk == CRDT");
     }
 
@@ -2497,7 +4213,7 @@ mod tests {
         let mut store = ContentStore::new(folder.join(".syncline/content"));
 
         // ghost.md was never materialised on disk → nothing to fold.
-        let out = fold_disk_drift_into_content(folder, &m, &mut store, nid).unwrap();
+        let out = fold_disk_drift_into_content(folder, &m, &mut store, &posix_names(), nid).unwrap();
         assert!(out.is_none());
     }
 
@@ -2555,6 +4271,185 @@ mod tests {
         assert!(batch_wants_scan(&batch2, rel_syncline));
     }
 
+    #[test]
+    fn batch_scan_paths_targets_outermost_vault_paths() {
+        let vault = Path::new("/vault");
+        let syncline = vault.join(".syncline");
+        let batch = vec![
+            debounced("/vault/notes/a.md"),
+            debounced("/vault/moved"),
+            debounced("/vault/moved/deep/b.md"),
+            debounced("/vault/.syncline/manifest.bin"),
+        ];
+        assert_eq!(
+            batch_scan_paths(&batch, vault, &syncline),
+            Some(vec!["moved".to_string(), "notes/a.md".to_string()])
+        );
+        assert_eq!(batch_scan_paths(&[], vault, &syncline), Some(vec![]));
+    }
+
+    #[test]
+    fn batch_scan_paths_falls_back_to_full_scan() {
+        let vault = Path::new("/vault");
+        let syncline = vault.join(".syncline");
+        for path in ["/vault", "/vault/notes/.synclineignore", "/elsewhere/a.md"] {
+            let batch = vec![debounced("/vault/a.md"), debounced(path)];
+            assert_eq!(batch_scan_paths(&batch, vault, &syncline), None, "{path}");
+        }
+    }
+
+    /// `detect_moves` over a full walk of `folder`, as `scan_once` runs it.
+    fn moves_in(
+        folder: &Path,
+        m: &Manifest,
+        index: &mut FileIndex,
+        content: &mut ContentStore,
+    ) -> Vec<(String, String)> {
+        let walked: Vec<_> = WalkDir::new(folder)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file() && !e.path().starts_with(folder.join(".syncline")))
+            .collect();
+        let proj = project(m);
+        let gone = proj
+            .by_path
+            .iter()
+            .filter(|(path, _)| !folder.join(path).exists())
+            .map(|(path, entry)| (path.as_str(), entry))
+            .collect();
+        let mut moved = detect_moves(folder, &walked, &proj, &gone, index, content, &posix_names());
+        moved.sort();
+        moved
+    }
+
+    #[test]
+    fn detect_moves_pairs_by_inode_blob_hash_and_similar_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path();
+        let syncline = folder.join(".syncline");
+        let mut content = ContentStore::new(syncline.join("content"));
+        let mut m = Manifest::new(ActorId::new());
+        let mut text = |m: &mut Manifest, path: &str, body: &str| {
+            let id = crate::v1::ops::create_text(m, path, body.len() as u64).unwrap();
+            content.replace_text(id, body).unwrap();
+            content.persist(id).unwrap();
+        };
+        text(&mut m, "a/old.md", "nothing alike");
+        text(&mut m, "notes/draft.md", "line one\nline two\nline three\n");
+        text(&mut m, "gone.md", "deleted for good");
+        let png = b"\x89PNG not really";
+        crate::v1::ops::create_binary(&mut m, "img/pic.png", &hash_hex(png), png.len() as u64)
+            .unwrap();
+
+        for d in ["b", "media", "notes"] {
+            fs::create_dir_all(folder.join(d)).unwrap();
+        }
+        fs::write(folder.join("b/new.md"), "rewritten from scratch").unwrap();
+        fs::write(folder.join("media/pic2.png"), png).unwrap();
+        fs::write(folder.join("notes/final.md"), "line one\nline two\nline 3\n").unwrap();
+        fs::write(folder.join("fresh.md"), "brand new").unwrap();
+        let mut index = FileIndex::load(&syncline);
+        let stat = Stat::of(&fs::metadata(folder.join("b/new.md")).unwrap()).unwrap();
+        index.record("a/old.md", stat, "h".into());
+
+        let mut expected = vec![
+            ("img/pic.png".to_string(), "media/pic2.png".to_string()),
+            ("notes/draft.md".to_string(), "notes/final.md".to_string()),
+        ];
+        if cfg!(unix) {
+            expected.insert(0, ("a/old.md".to_string(), "b/new.md".to_string()));
+        }
+        assert_eq!(moves_in(folder, &m, &mut index, &mut content), expected);
+
+        // Still on disk at its old path: a copy, not a move.
+        fs::create_dir_all(folder.join("a")).unwrap();
+        fs::write(folder.join("a/old.md"), "nothing alike").unwrap();
+        expected.retain(|(from, _)| from != "a/old.md");
+        assert_eq!(moves_in(folder, &m, &mut index, &mut content), expected);
+    }
+
+    #[test]
+    fn text_moves_past_the_rename_limit_are_not_diffed() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path();
+        let syncline = folder.join(".syncline");
+        let mut content = ContentStore::new(syncline.join("content"));
+        let mut m = Manifest::new(ActorId::new());
+        fs::create_dir_all(folder.join("new")).unwrap();
+        for i in 0..=RENAME_LIMIT {
+            let body = format!("note number {i}\n");
+            let id = crate::v1::ops::create_text(&mut m, &format!("old/{i}.md"), 0).unwrap();
+            content.replace_text(id, &body).unwrap();
+            content.persist(id).unwrap();
+            fs::write(folder.join(format!("new/{i}.md")), body).unwrap();
+        }
+        let mut index = FileIndex::load(&syncline);
+        assert!(moves_in(folder, &m, &mut index, &mut content).is_empty());
+
+        // Back under the limit, the same files pair up again.
+        fs::remove_file(folder.join("new/0.md")).unwrap();
+        fs::create_dir_all(folder.join("old")).unwrap();
+        fs::write(folder.join("old/0.md"), "note number 0\n").unwrap();
+        assert_eq!(moves_in(folder, &m, &mut index, &mut content).len(), RENAME_LIMIT);
+    }
+
+    #[test]
+    fn only_untouched_vaults_are_seeded_with_the_portable_policy() {
+        let mut fresh = Manifest::new(ActorId::new());
+        assert!(seed_new_vault(&mut fresh).is_some());
+        assert_eq!(PathEquivalence::of(&fresh), PathEquivalence::PORTABLE);
+        assert!(seed_new_vault(&mut fresh).is_none());
+
+        let mut existing = Manifest::new(ActorId::new());
+        existing.create_node("Todo.md", None, NodeKind::Text, None, 0);
+        assert!(seed_new_vault(&mut existing).is_none());
+        assert_eq!(PathEquivalence::of(&existing), PathEquivalence::EXACT);
+    }
+
+    #[test]
+    fn similarity_scores_shared_text() {
+        assert_eq!(similarity("", ""), 1.0);
+        assert_eq!(similarity("same", "same"), 1.0);
+        assert_eq!(similarity("abcd", "wxyz"), 0.0);
+        assert!(similarity("hello world", "hello there world") > MOVE_SIMILARITY);
+        assert_eq!(similarity("short", &"long ".repeat(100)), 0.0);
+    }
+
+    #[test]
+    fn apply_moves_collapses_a_whole_directory_into_one_move() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path();
+        let mut m = Manifest::new(ActorId::new());
+        let a = crate::v1::ops::create_text(&mut m, "src/notes/a.md", 0).unwrap();
+        let b = crate::v1::ops::create_text(&mut m, "src/notes/deep/b.md", 0).unwrap();
+        let c = crate::v1::ops::create_text(&mut m, "src/other.md", 0).unwrap();
+        let notes = m.get_entry(a).unwrap().parent;
+        let moved = vec![
+            ("src/notes/a.md".to_string(), "archive/notes/a.md".to_string()),
+            ("src/notes/deep/b.md".to_string(), "archive/notes/deep/b.md".to_string()),
+        ];
+        let before = project(&m);
+        apply_moves(folder, &mut m, &before, &moved, &posix_names());
+
+        let proj = project(&m);
+        assert_eq!(proj.by_id[&a].path, "archive/notes/a.md");
+        assert_eq!(proj.by_id[&b].path, "archive/notes/deep/b.md");
+        assert_eq!(proj.by_id[&c].path, "src/other.md");
+        // One parent change on the directory; the files weren't touched.
+        assert_eq!(m.get_entry(a).unwrap().parent, notes);
+        assert_eq!(m.get_entry(notes.unwrap()).unwrap().name, "notes");
+
+        // Half a directory moving is two file moves.
+        let x = crate::v1::ops::create_text(&mut m, "box/x.md", 0).unwrap();
+        crate::v1::ops::create_text(&mut m, "box/y.md", 0).unwrap();
+        let boxed = m.get_entry(x).unwrap().parent;
+        let moved = vec![("box/x.md".to_string(), "crate/x.md".to_string())];
+        let before = project(&m);
+        apply_moves(folder, &mut m, &before, &moved, &posix_names());
+        assert_eq!(project(&m).by_id[&x].path, "crate/x.md");
+        assert_ne!(m.get_entry(x).unwrap().parent, boxed);
+    }
+
     #[test]
     fn process_binary_is_idempotent_across_repeat_calls() {
         let mut m = Manifest::new(ActorId::new());
@@ -2562,14 +4457,14 @@ mod tests {
         let bytes = b"payload";
 
         let proj = project(&m);
-        let first = process_binary_file("x.bin", bytes, &proj, &mut m, &bs).unwrap();
+        let first = process_binary_file("x.bin", &bytes[..], &proj, &mut m, &bs).unwrap();
         assert!(matches!(first, BinaryScanOutcome::Created { .. }));
         let n_after_first = m.live_entries().len();
 
         // A second scan with the same bytes must be a no-op: no duplicate
         // manifest entry, no state change.
         let proj2 = project(&m);
-        let second = process_binary_file("x.bin", bytes, &proj2, &mut m, &bs).unwrap();
+        let second = process_binary_file("x.bin", &bytes[..], &proj2, &mut m, &bs).unwrap();
         assert!(matches!(second, BinaryScanOutcome::Unchanged));
         assert_eq!(m.live_entries().len(), n_after_first);
     }
@@ -1,28 +1,49 @@
 //! `.synclineignore` parser and matcher.
 //!
-//! A reduced subset of `.gitignore` semantics, sufficient for letting the
-//! user opt out of syncing device-specific files (e.g. Obsidian's
-//! `workspace.json`) while still syncing useful hidden config.
+//! Lets the user opt out of syncing device-specific files (e.g.
+//! Obsidian's `workspace.json`) while still syncing useful hidden config.
+//! Patterns follow `.gitignore` exactly, so rules copied from a
+//! repository's ignore file behave the same here:
 //!
-//! Supported syntax:
+//! - `# comment` and blank lines are skipped; trailing spaces are
+//!   dropped unless escaped with `\`.
+//! - `name` (no `/` except a trailing one) matches a file or directory
+//!   of that name at any depth.
+//! - A `/` at the start or in the middle anchors the pattern to the
+//!   vault root: `/todo.md`, `dir/file`.
+//! - A trailing `/` matches directories only.
+//! - `*` and `?` match within one path component; `[abc]`, `[a-z]` and
+//!   `[!abc]` match one character from (or not from) a class.
+//! - `**/x` matches `x` in any directory, `x/**` everything inside `x`,
+//!   and `a/**/b` zero or more directories between `a` and `b`.
+//! - `!pattern` re-includes what an earlier pattern excluded. The last
+//!   matching pattern wins, but nothing inside an excluded directory can
+//!   be re-included — as in git, the directory isn't looked into.
+//! - `\` escapes the next character, e.g. `\#notes.md`, `\!x`, `\*`.
 //!
-//! - `# comment` and blank lines are ignored.
-//! - `name`             matches any path component named `name`.
-//! - `name/`            matches a directory called `name` (and its contents).
-//! - `dir/file`         anchored path match relative to the vault root.
-//! - `dir/sub/`         anchored directory match (including everything inside).
-//! - `*` and `?`        glob wildcards within a single path component.
+//! Matching is case-sensitive.
 //!
-//! Not supported: `**` recursive globs, character classes (`[abc]`),
-//! negation (`!pattern`). The grammar is deliberately minimal — patterns
-//! that can't be expressed here belong in code.
+//! Besides the one at the vault root, a `.synclineignore` may sit in any
+//! directory. Its patterns are relative to that directory (`/x` anchors
+//! to it, not to the vault root), and they take precedence over the
+//! files above it, as with nested `.gitignore`s. Files inside an ignored
+//! directory are never read. Nested files are read lazily, the first
+//! time a path below their directory is checked, and cached for the
+//! life of the list, so a fresh [`IgnoreList::load`] picks up edits.
 //!
 //! `.syncline/` is **always** ignored, regardless of pattern file content.
 //! That rule is enforced by [`IgnoreList::is_ignored`] and cannot be
-//! overridden — Syncline's own metadata directory must never round-trip
-//! through the sync layer.
+//! overridden — not even by negation — since Syncline's own metadata
+//! directory must never round-trip through the sync layer.
 
+use std::collections::HashMap;
 use std::path::Path;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Name of the pattern file, at the vault root and in any directory.
+pub const IGNORE_FILE: &str = ".synclineignore";
 
 /// Built-in patterns layered under any user `.synclineignore`. Skips
 /// VCS metadata and device-specific Obsidian state that is rebuilt on
@@ -37,16 +58,46 @@ pub const DEFAULT_PATTERNS: &str = "\
 
 #[derive(Debug, Clone, Default)]
 pub struct IgnoreList {
+    /// Defaults plus the vault root's file.
     rules: Vec<Rule>,
+    /// Where nested pattern files are read from; `None` for lists built
+    /// from text only.
+    #[cfg(not(target_arch = "wasm32"))]
+    root: Option<PathBuf>,
+    /// Nested files read so far, by vault-relative directory. Shared by
+    /// clones, so a list handed to a walker fills one cache.
+    nested: Arc<Mutex<HashMap<String, Arc<[Rule]>>>>,
 }
 
 #[derive(Debug, Clone)]
 struct Rule {
-    parts: Vec<String>,
+    glob: Vec<Token>,
+    negated: bool,
     dir_only: bool,
+    /// Matched against the whole vault-relative path rather than just
+    /// the last component.
     anchored: bool,
 }
 
+/// One element of a compiled pattern.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(char),
+    /// `?`: any one character but `/`.
+    AnyChar,
+    /// `*`: any run of characters without `/`.
+    Star,
+    /// `**/`: zero or more whole directories.
+    AnyDirs,
+    /// Trailing `/**`'s `**`: one or more characters, `/` included.
+    Rest,
+    /// `[...]`: one character but `/`, in (or, negated, not in) the ranges.
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
 impl IgnoreList {
     pub fn empty() -> Self {
         Self::default()
@@ -63,40 +114,31 @@ impl IgnoreList {
     }
 
     pub fn extend_from_text(&mut self, text: &str) {
-        for raw in text.lines() {
-            let line = raw.trim();
-            if line.is_empty() || line.starts_with('#') {
-                continue;
-            }
-            let (body, dir_only) = match line.strip_suffix('/') {
-                Some(stripped) => (stripped, true),
-                None => (line, false),
-            };
-            let (body, leading_slash) = match body.strip_prefix('/') {
-                Some(stripped) => (stripped, true),
-                None => (body, false),
-            };
-            if body.is_empty() {
-                continue;
-            }
-            let parts: Vec<String> = body.split('/').map(str::to_owned).collect();
-            let anchored = leading_slash || parts.len() > 1;
-            self.rules.push(Rule { parts, dir_only, anchored });
-        }
+        self.rules.extend(text.lines().filter_map(Rule::parse));
     }
 
     /// Loads `.synclineignore` from `root` if it exists, layered on top
     /// of [`DEFAULT_PATTERNS`]. Missing/unreadable files yield the
     /// defaults-only list.
+    /// Nested `.synclineignore` files below `root` are consulted too.
     #[cfg(not(target_arch = "wasm32"))]
     pub fn load(root: &Path) -> Self {
         let mut list = Self::with_defaults();
-        if let Ok(text) = std::fs::read_to_string(root.join(".synclineignore")) {
+        if let Ok(text) = std::fs::read_to_string(root.join(IGNORE_FILE)) {
             list.extend_from_text(&text);
         }
+        list.root = Some(root.to_path_buf());
         list
     }
 
+    /// Adds the patterns of a `.synclineignore` in vault directory `dir`
+    /// (relative, `/`-separated) in place of reading it from disk.
+    pub fn add_nested(&mut self, dir: &str, text: &str) {
+        let rules: Arc<[Rule]> = text.lines().filter_map(Rule::parse).collect();
+        let dir = dir.trim_matches('/').to_string();
+        self.nested.lock().unwrap().insert(dir, rules);
+    }
+
     /// Returns true if the given vault-relative path should be excluded
     /// from sync. Path components are separated by `/` regardless of OS.
     /// `.syncline/` is hardcoded-ignored at any depth.
@@ -108,81 +150,269 @@ impl IgnoreList {
         if comps.contains(&".syncline") {
             return true;
         }
-        self.rules.iter().any(|r| r.matches(&comps, is_dir))
+        // An excluded directory hides everything below it, whatever
+        // later negations say about the contents.
+        let mut path = String::with_capacity(rel_path.len());
+        for (i, comp) in comps.iter().enumerate() {
+            if i > 0 {
+                path.push('/');
+            }
+            path.push_str(comp);
+            let last = i == comps.len() - 1;
+            if self.excludes(&path, comp, !last || is_dir) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// The verdict on `path`, ignoring its ancestors: the last matching
+    /// rule of the deepest pattern file that has one.
+    fn excludes(&self, path: &str, name: &str, is_dir: bool) -> bool {
+        let mut dir = path;
+        while let Some((parent, _)) = dir.rsplit_once('/') {
+            dir = parent;
+            let rel = &path[dir.len() + 1..];
+            let rules = self.nested_rules(dir);
+            if let Some(rule) = rules.iter().rev().find(|r| r.matches(rel, name, is_dir)) {
+                return !rule.negated;
+            }
+        }
+        self.rules
+            .iter()
+            .rev()
+            .find(|r| r.matches(path, name, is_dir))
+            .is_some_and(|r| !r.negated)
+    }
+
+    /// The rules of the pattern file in `dir`, read on first use.
+    fn nested_rules(&self, dir: &str) -> Arc<[Rule]> {
+        if let Some(rules) = self.nested.lock().unwrap().get(dir) {
+            return rules.clone();
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        let rules: Arc<[Rule]> = match &self.root {
+            Some(root) => std::fs::read_to_string(root.join(dir).join(IGNORE_FILE))
+                .map(|text| text.lines().filter_map(Rule::parse).collect())
+                .unwrap_or_default(),
+            None => Arc::default(),
+        };
+        #[cfg(target_arch = "wasm32")]
+        let rules: Arc<[Rule]> = Arc::default();
+        self.nested
+            .lock()
+            .unwrap()
+            .insert(dir.to_string(), rules.clone());
+        rules
     }
 }
 
 impl Rule {
-    fn matches(&self, comps: &[&str], is_dir: bool) -> bool {
-        if self.anchored {
-            if self.parts.len() > comps.len() {
-                return false;
+    /// Compiles one line of an ignore file; `None` for blanks and
+    /// comments.
+    fn parse(raw: &str) -> Option<Self> {
+        let mut line = raw.strip_suffix('\r').unwrap_or(raw);
+        if line.starts_with('#') {
+            return None;
+        }
+        // Trailing spaces go unless the last one is escaped.
+        while let Some(rest) = line.strip_suffix(' ') {
+            if rest.ends_with('\\') && !rest.ends_with("\\\\") {
+                break;
             }
-            for (pat, c) in self.parts.iter().zip(comps.iter()) {
-                if !glob_match(pat, c) {
-                    return false;
+            line = rest;
+        }
+        let (line, negated) = match line.strip_prefix('!') {
+            Some(rest) => (rest, true),
+            None => (line, false),
+        };
+        let (line, dir_only) = match line.strip_suffix('/') {
+            Some(rest) => (rest, true),
+            None => (line, false),
+        };
+        if line.is_empty() {
+            return None;
+        }
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        Some(Rule {
+            glob: compile(line),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, path: &str, name: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let text: Vec<char> = if self.anchored { path } else { name }.chars().collect();
+        glob_matches(&self.glob, &text)
+    }
+}
+
+/// Turns a pattern (already stripped of `!`, the trailing `/` and a
+/// leading `/`) into tokens.
+fn compile(pattern: &str) -> Vec<Token> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                out.push(Token::Literal(chars[i + 1]));
+                i += 2;
+            }
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                // `**` is special only as a whole path component.
+                let starts_component = i == 0 || chars[i - 1] == '/';
+                let mut end = i;
+                while chars.get(end) == Some(&'*') {
+                    end += 1;
                 }
+                if starts_component && chars.get(end) == Some(&'/') {
+                    out.push(Token::AnyDirs);
+                    i = end + 1;
+                } else if starts_component && end == chars.len() && i > 0 {
+                    out.push(Token::Rest);
+                    i = end;
+                } else {
+                    out.push(Token::Star);
+                    i = end;
+                }
+            }
+            '*' => {
+                out.push(Token::Star);
+                i += 1;
             }
-            if self.parts.len() < comps.len() {
-                // Path extends beyond pattern → only a directory pattern
-                // matches descendants.
-                return self.dir_only;
+            '?' => {
+                out.push(Token::AnyChar);
+                i += 1;
             }
-            // Exact-length match: a dir-only pattern still matches the
-            // directory itself, but not a file with the same path.
-            !self.dir_only || is_dir
-        } else {
-            let pat = &self.parts[0];
-            let last = comps.len() - 1;
-            for (i, c) in comps.iter().enumerate() {
-                if !glob_match(pat, c) {
-                    continue;
+            '[' => match compile_class(&chars[i + 1..]) {
+                Some((token, used)) => {
+                    out.push(token);
+                    i += 1 + used;
                 }
-                if !self.dir_only || i < last || is_dir {
-                    return true;
+                None => {
+                    out.push(Token::Literal('['));
+                    i += 1;
                 }
+            },
+            c => {
+                out.push(Token::Literal(c));
+                i += 1;
             }
-            false
         }
     }
+    out
 }
 
-/// Per-component glob: `?` matches one byte, `*` matches any run of
-/// bytes. No path-separator semantics — patterns are split on `/` first.
-fn glob_match(pattern: &str, text: &str) -> bool {
-    let p = pattern.as_bytes();
-    let t = text.as_bytes();
-
-    // Iterative algorithm with a single fallback point — handles `*`
-    // without recursion blow-up and is plenty for our pattern shape.
-    let (mut pi, mut ti) = (0usize, 0usize);
-    let (mut star, mut tstar) = (None::<usize>, 0usize);
-    while ti < t.len() {
-        if pi < p.len() && (p[pi] == t[ti] || p[pi] == b'?') {
-            pi += 1;
-            ti += 1;
-        } else if pi < p.len() && p[pi] == b'*' {
-            star = Some(pi);
-            tstar = ti;
-            pi += 1;
-        } else if let Some(s) = star {
-            pi = s + 1;
-            tstar += 1;
-            ti = tstar;
-        } else {
-            return false;
+/// Parses a character class from just after its `[`. Returns the token
+/// and how many characters it used, closing `]` included; `None` if the
+/// class is never closed, in which case the `[` is literal.
+fn compile_class(chars: &[char]) -> Option<(Token, usize)> {
+    let mut i = 0;
+    let negated = matches!(chars.first(), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let mut c = *chars.get(i)?;
+        if c == ']' && !first {
+            return Some((Token::Class { negated, ranges }, i + 1));
         }
+        first = false;
+        if c == '\\' {
+            i += 1;
+            c = *chars.get(i)?;
+        }
+        i += 1;
+        let mut hi = c;
+        if chars.get(i) == Some(&'-') && chars.get(i + 1).is_some_and(|&n| n != ']') {
+            hi = chars[i + 1];
+            if hi == '\\' {
+                hi = *chars.get(i + 2)?;
+                i += 1;
+            }
+            i += 2;
+        }
+        ranges.push((c, hi));
     }
-    while pi < p.len() && p[pi] == b'*' {
-        pi += 1;
+}
+
+/// Whole-string match of `text` against compiled `glob`. Memoised over
+/// (token, position) pairs, so `*`-heavy patterns stay polynomial.
+fn glob_matches(glob: &[Token], text: &[char]) -> bool {
+    let width = text.len() + 1;
+    let mut seen = vec![false; (glob.len() + 1) * width];
+    let mut stack = vec![(0usize, 0usize)];
+    while let Some((g, t)) = stack.pop() {
+        let cell = g * width + t;
+        if seen[cell] {
+            continue;
+        }
+        seen[cell] = true;
+        let Some(token) = glob.get(g) else {
+            if t == text.len() {
+                return true;
+            }
+            continue;
+        };
+        let next = text.get(t).copied();
+        match token {
+            Token::Literal(c) => {
+                if next == Some(*c) {
+                    stack.push((g + 1, t + 1));
+                }
+            }
+            Token::AnyChar => {
+                if next.is_some_and(|c| c != '/') {
+                    stack.push((g + 1, t + 1));
+                }
+            }
+            Token::Class { negated, ranges } => {
+                if let Some(c) = next.filter(|&c| c != '/')
+                    && ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+                {
+                    stack.push((g + 1, t + 1));
+                }
+            }
+            Token::Star => {
+                stack.push((g + 1, t));
+                if next.is_some_and(|c| c != '/') {
+                    stack.push((g, t + 1));
+                }
+            }
+            Token::AnyDirs => {
+                stack.push((g + 1, t));
+                for (i, &c) in text.iter().enumerate().skip(t) {
+                    if c == '/' {
+                        stack.push((g + 1, i + 1));
+                    }
+                }
+            }
+            Token::Rest => {
+                if t < text.len() {
+                    stack.push((g + 1, text.len()));
+                }
+            }
+        }
     }
-    pi == p.len()
+    false
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn glob_match(pattern: &str, text: &str) -> bool {
+        glob_matches(&compile(pattern), &text.chars().collect::<Vec<_>>())
+    }
+
     #[test]
     fn glob_basics() {
         assert!(glob_match("foo", "foo"));
@@ -193,6 +423,14 @@ mod tests {
         assert!(!glob_match("file?.md", "file12.md"));
         assert!(glob_match("*", ""));
         assert!(glob_match("a*b*c", "azzzbzzc"));
+        assert!(!glob_match("*", "a/b"));
+        assert!(glob_match("[a-c]x", "bx"));
+        assert!(!glob_match("[!a-c]x", "bx"));
+        assert!(glob_match("[]]", "]"));
+        assert!(glob_match("[a-]", "-"));
+        assert!(glob_match("a[", "a["));
+        assert!(glob_match("\\*", "*"));
+        assert!(!glob_match("\\*", "x"));
     }
 
     #[test]
@@ -269,11 +507,116 @@ mod tests {
     }
 
     #[test]
-    fn syncline_rule_cannot_be_overridden_via_unsupported_negation() {
-        // We don't support `!` negation; lines starting with `!` parse
-        // as patterns whose first component begins with `!`. Either way,
-        // the hardcoded `.syncline` rule is unreachable.
-        let l = IgnoreList::from_text("!.syncline/\n");
+    fn nested_files_are_relative_to_their_directory() {
+        let mut l = IgnoreList::from_text("*.tmp\n");
+        l.add_nested("projects/site", "/dist/\nnotes/*.md\n!keep.tmp\n");
+        assert!(l.is_ignored("projects/site/dist/app.js", false));
+        assert!(!l.is_ignored("projects/dist/app.js", false));
+        assert!(!l.is_ignored("projects/site/sub/dist/app.js", false));
+        assert!(l.is_ignored("projects/site/notes/a.md", false));
+        assert!(!l.is_ignored("notes/a.md", false));
+        // Deeper files win over the root's.
+        assert!(l.is_ignored("projects/site/x.tmp", false));
+        assert!(!l.is_ignored("projects/site/keep.tmp", false));
+        assert!(l.is_ignored("keep.tmp", false));
+    }
+
+    #[test]
+    fn load_reads_nested_files_but_not_inside_ignored_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+        std::fs::create_dir_all(root.join("gone")).unwrap();
+        std::fs::write(root.join(IGNORE_FILE), "gone/\n").unwrap();
+        std::fs::write(root.join("a").join(IGNORE_FILE), "*.log\n").unwrap();
+        std::fs::write(root.join("a/b").join(IGNORE_FILE), "!keep.log\n").unwrap();
+        std::fs::write(root.join("gone").join(IGNORE_FILE), "!*\n").unwrap();
+        let l = IgnoreList::load(root);
+        assert!(l.is_ignored("a/x.log", false));
+        assert!(l.is_ignored("a/b/x.log", false));
+        assert!(!l.is_ignored("a/b/keep.log", false));
+        assert!(!l.is_ignored("x.log", false));
+        assert!(l.is_ignored("gone/file.md", false));
+
+        // A fresh load sees edits; the old list keeps what it read.
+        std::fs::write(root.join("a").join(IGNORE_FILE), "").unwrap();
+        assert!(l.is_ignored("a/x.log", false));
+        assert!(!IgnoreList::load(root).is_ignored("a/x.log", false));
+    }
+
+    #[test]
+    fn syncline_rule_cannot_be_overridden_by_negation() {
+        let l = IgnoreList::from_text("!.syncline/\n!.syncline/manifest.bin\n");
         assert!(l.is_ignored(".syncline/manifest.bin", false));
     }
+
+    /// Cases checked against `git check-ignore` (git 2.x) with the same
+    /// patterns in a `.gitignore` at the repository root.
+    #[test]
+    fn matches_git_check_ignore() {
+        // (patterns, path, is_dir, ignored)
+        let cases: &[(&str, &str, bool, bool)] = &[
+            // Negation: last match wins.
+            ("*.log\n!important.log\n", "debug.log", false, true),
+            ("*.log\n!important.log\n", "important.log", false, false),
+            ("*.log\n!important.log\n", "logs/important.log", false, false),
+            ("!important.log\n*.log\n", "important.log", false, true),
+            // No re-including inside an excluded directory.
+            ("build/\n!build/keep.txt\n", "build/keep.txt", false, true),
+            ("build/*\n!build/keep.txt\n", "build/keep.txt", false, false),
+            ("build/*\n!build/keep.txt\n", "build/other.txt", false, true),
+            ("/*\n!/notes/\n", "notes/a.md", false, false),
+            ("/*\n!/notes/\n", "todo.md", false, true),
+            // `**` forms.
+            ("**/build/\n", "build/x.o", false, true),
+            ("**/build/\n", "a/b/build/x.o", false, true),
+            ("**/build/\n", "a/build", false, false),
+            ("**/foo\n", "foo", false, true),
+            ("**/foo\n", "x/y/foo", false, true),
+            ("**/foo/bar\n", "x/foo/bar", false, true),
+            ("abc/**\n", "abc/x/y", false, true),
+            ("abc/**\n", "abc", true, false),
+            ("abc/**\n", "x/abc/y", false, false),
+            ("a/**/b\n", "a/b", false, true),
+            ("a/**/b\n", "a/x/y/b", false, true),
+            ("a/**/b\n", "a/xb", false, false),
+            ("a**b\n", "axxb", false, true),
+            ("a**b\n", "a/b", false, false),
+            // Anchoring.
+            ("doc/frotz\n", "doc/frotz", false, true),
+            ("doc/frotz\n", "a/doc/frotz", false, false),
+            ("doc/frotz/\n", "doc/frotz/x", false, true),
+            ("frotz/\n", "a/frotz/x", false, true),
+            ("frotz/\n", "frotz", false, false),
+            ("/*.c\n", "cat-file.c", false, true),
+            ("/*.c\n", "mozilla-sha1/sha1.c", false, false),
+            ("foo/*\n", "foo/test.json", false, true),
+            ("foo/*\n", "foo/bar/hello.c", false, true),
+            ("foo/*\n", "foo", true, false),
+            ("dir/file\n", "dir/file/inner", false, true),
+            // Character classes.
+            ("*.[oa]\n", "x.o", false, true),
+            ("*.[oa]\n", "x.c", false, false),
+            ("file[0-9].md\n", "file7.md", false, true),
+            ("file[!0-9].md\n", "file7.md", false, false),
+            ("file[!0-9].md\n", "fileA.md", false, true),
+            // Escapes and whitespace.
+            ("\\#hash.md\n", "#hash.md", false, true),
+            ("\\!bang.md\n", "!bang.md", false, true),
+            ("trail.md   \n", "trail.md", false, true),
+            ("space\\ \n", "space ", false, true),
+            ("space\\ \n", "space", false, false),
+            ("a\\*b\n", "a*b", false, true),
+            ("a\\*b\n", "axb", false, false),
+            // Case-sensitive, like git on Linux.
+            ("Readme.md\n", "README.md", false, false),
+        ];
+        for &(patterns, path, is_dir, ignored) in cases {
+            assert_eq!(
+                IgnoreList::from_text(patterns).is_ignored(path, is_dir),
+                ignored,
+                "patterns {patterns:?}, path {path:?}"
+            );
+        }
+    }
 }
@@ -1,5 +1,5 @@
 use clap::builder::styling::{AnsiColor, Effects, Styles};
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
 fn cli_styles() -> Styles {
@@ -31,9 +31,11 @@ enum Commands {
         #[arg(short, long, default_value = "3030")]
         port: u16,
 
-        /// Database path or connection string
-        #[arg(short, long, default_value = "syncline.db")]
-        db_path: String,
+        #[command(flatten)]
+        store: StoreArgs,
+
+        #[command(flatten)]
+        limits: LimitArgs,
 
         /// Log level (error, warn, info, debug, trace)
         #[arg(short, long, default_value = "info")]
@@ -89,6 +91,264 @@ enum Commands {
         #[arg(long)]
         log_file: Option<PathBuf>,
     },
+    /// Show or change vault-wide settings. Settings live in the synced
+    /// manifest, so a change reaches every peer on the next sync. Stop
+    /// `syncline sync` for this folder before changing one.
+    ///
+    /// Settings: `path_equivalence` — which spellings of a path count as
+    /// the same file (`exact`, `nfc`, `casefold`, `nfc+casefold`; new
+    /// vaults start as `nfc+casefold`, unset means `exact`). `conflict_name` — how conflict copies
+    /// are named, from `{stem}`, `{ext}`, `{device}`, `{actor}`,
+    /// `{lamport}` and `{node}` (default
+    /// `{stem}.conflict-{actor}-{lamport}-{node}{ext}`).
+    Config {
+        /// Setting to show or change. Omit to list all settings.
+        key: Option<String>,
+
+        /// New value for the setting.
+        value: Option<String>,
+
+        /// Remove the setting, restoring its default.
+        #[arg(long, conflicts_with = "value", requires = "key")]
+        unset: bool,
+
+        /// Vault folder.
+        #[arg(short, long, default_value = ".")]
+        folder: PathBuf,
+
+        /// Log level (error, warn, info, debug, trace)
+        #[arg(long, default_value = "warn")]
+        log_level: String,
+
+        /// Optional file to redirect logs to
+        #[arg(long)]
+        log_file: Option<PathBuf>,
+    },
+    /// List files whose names can't be stored as-is on every platform
+    /// (Windows reserved characters and device names, trailing dots…).
+    /// They still sync, under an escaped name on the affected platform.
+    /// Exits 0 when every name is portable, 1 otherwise.
+    CheckNames {
+        /// Vault folder.
+        #[arg(short, long, default_value = ".")]
+        folder: PathBuf,
+
+        /// Log level (error, warn, info, debug, trace)
+        #[arg(long, default_value = "warn")]
+        log_level: String,
+
+        /// Optional file to redirect logs to
+        #[arg(long)]
+        log_file: Option<PathBuf>,
+    },
+    /// Show how many local changes the server hasn't yet confirmed
+    /// storing. Changes are staged in .syncline/outbox/ before they're
+    /// sent and survive a crash or lost connection until they are.
+    Status {
+        /// Vault folder.
+        #[arg(short, long, default_value = ".")]
+        folder: PathBuf,
+
+        /// Log level (error, warn, info, debug, trace)
+        #[arg(long, default_value = "warn")]
+        log_level: String,
+
+        /// Optional file to redirect logs to
+        #[arg(long)]
+        log_file: Option<PathBuf>,
+    },
+    /// List the devices that have synced this vault, or retire one.
+    /// Device records live in the synced manifest; stop `syncline sync`
+    /// for this folder before retiring one.
+    Devices {
+        /// Mark a device retired, by name or by a prefix of its actor id.
+        #[arg(long, value_name = "DEVICE")]
+        retire: Option<String>,
+
+        /// Vault folder.
+        #[arg(short, long, default_value = ".")]
+        folder: PathBuf,
+
+        /// Log level (error, warn, info, debug, trace)
+        #[arg(long, default_value = "warn")]
+        log_level: String,
+
+        /// Optional file to redirect logs to
+        #[arg(long)]
+        log_file: Option<PathBuf>,
+    },
+    /// Choose what this device keeps on disk. Excluded folders stay in
+    /// the vault and keep syncing between other devices; this one just
+    /// doesn't download or write them. Large binaries can be left on the
+    /// server until fetched. With no flags, shows the current choice.
+    Selective {
+        /// Stop keeping a folder on this device. Local copies that match
+        /// the synced version are removed; edited files are kept.
+        #[arg(long, value_name = "FOLDER", conflicts_with = "include")]
+        exclude: Option<String>,
+
+        /// Keep an excluded folder on this device again.
+        #[arg(long, value_name = "FOLDER")]
+        include: Option<String>,
+
+        /// Leave binaries larger than this many bytes on the server until
+        /// they're fetched (`syncline fetch`); until then each is a small
+        /// placeholder file. 0 makes every binary on-demand.
+        #[arg(long, value_name = "BYTES", conflicts_with = "fetch_all")]
+        fetch_limit: Option<u64>,
+
+        /// Fetch every binary up front again (the default).
+        #[arg(long)]
+        fetch_all: bool,
+
+        /// Vault folder.
+        #[arg(short, long, default_value = ".")]
+        folder: PathBuf,
+
+        /// Filesystem naming rules the folder syncs with (see `sync`).
+        #[arg(long, default_value = "native")]
+        filename_rules: syncline::v1::names::NameRules,
+
+        /// Log level (error, warn, info, debug, trace)
+        #[arg(long, default_value = "warn")]
+        log_level: String,
+
+        /// Optional file to redirect logs to
+        #[arg(long)]
+        log_file: Option<PathBuf>,
+    },
+    /// Download binaries this device fetches on demand (see `selective
+    /// --fetch-limit`), replacing their placeholders.
+    Fetch {
+        /// File or folder to fetch, relative to the vault.
+        path: String,
+
+        /// Vault folder.
+        #[arg(short, long, default_value = ".")]
+        folder: PathBuf,
+
+        /// URL of the Syncline server.
+        #[arg(
+            short,
+            long,
+            default_value = "ws://127.0.0.1:3030/sync",
+            env = "SYNCLINE_URL"
+        )]
+        url: String,
+
+        /// Give up if the blobs haven't all arrived after this long.
+        #[arg(short = 't', long, default_value_t = 300)]
+        timeout_secs: u64,
+
+        /// Filesystem naming rules the folder syncs with (see `sync`).
+        #[arg(long, default_value = "native")]
+        filename_rules: syncline::v1::names::NameRules,
+
+        /// Log level (error, warn, info, debug, trace)
+        #[arg(long, default_value = "warn")]
+        log_level: String,
+
+        /// Optional file to redirect logs to
+        #[arg(long)]
+        log_file: Option<PathBuf>,
+    },
+    /// List the devices a server has seen, or revoke one. A revoked
+    /// device can't connect and manifest writes it made after the
+    /// revocation are dropped; other devices sharing its token are not
+    /// affected. A token with a secret can be revoked on its own. Takes
+    /// effect on a running server without a restart.
+    ServerDevices {
+        /// Revoke a device, by actor id or a unique prefix of one.
+        #[arg(
+            long,
+            value_name = "ACTOR",
+            conflicts_with_all = ["reinstate", "revoke_token", "reinstate_token"]
+        )]
+        revoke: Option<String>,
+
+        /// Lift a revocation, by actor id or a unique prefix of one.
+        #[arg(
+            long,
+            value_name = "ACTOR",
+            conflicts_with_all = ["revoke_token", "reinstate_token"]
+        )]
+        reinstate: Option<String>,
+
+        /// Refuse every connection presenting this token's secret.
+        #[arg(long, value_name = "TOKEN", conflicts_with = "reinstate_token")]
+        revoke_token: Option<String>,
+
+        /// Lift a token's revocation.
+        #[arg(long, value_name = "TOKEN")]
+        reinstate_token: Option<String>,
+
+        #[command(flatten)]
+        store: StoreArgs,
+
+        /// Log level (error, warn, info, debug, trace)
+        #[arg(long, default_value = "warn")]
+        log_level: String,
+
+        /// Optional file to redirect logs to
+        #[arg(long)]
+        log_file: Option<PathBuf>,
+    },
+    /// List the tokens that need a secret to connect, or issue or
+    /// remove one. A client presents it as `?token=<token>:<secret>`.
+    /// Access rules only apply to tokens with a secret.
+    ServerTokens {
+        /// Give a token a new secret, printing the credential once
+        #[arg(long, value_name = "TOKEN", conflicts_with = "remove")]
+        issue: Option<String>,
+
+        /// Drop a token's secret, making it a plain label again
+        #[arg(long, value_name = "TOKEN")]
+        remove: Option<String>,
+
+        #[command(flatten)]
+        store: StoreArgs,
+
+        /// Log level (error, warn, info, debug, trace)
+        #[arg(long, default_value = "warn")]
+        log_level: String,
+
+        /// Optional file to redirect logs to
+        #[arg(long)]
+        log_file: Option<PathBuf>,
+    },
+    /// Show or change which tokens may read and write which folders.
+    /// With no options, lists the rules. Rules may only name tokens
+    /// with a secret (see `server-tokens`). Tokens a rule keeps from
+    /// writing somewhere sync only what they may read; a change reaches
+    /// a token when it next connects.
+    ServerAcl {
+        /// Folder the rule covers, e.g. "HR" or "Finance/Payroll"
+        #[arg(long, value_name = "FOLDER")]
+        folder: Option<String>,
+
+        /// Tokens that may read the folder (comma-separated)
+        #[arg(long, value_delimiter = ',', requires = "folder")]
+        read: Vec<String>,
+
+        /// Tokens that may read and write the folder (comma-separated)
+        #[arg(long, value_delimiter = ',', requires = "folder")]
+        write: Vec<String>,
+
+        /// Drop the rule for --folder, opening it to every token again
+        #[arg(long, requires = "folder", conflicts_with_all = ["read", "write"])]
+        remove: bool,
+
+        #[command(flatten)]
+        store: StoreArgs,
+
+        /// Log level (error, warn, info, debug, trace)
+        #[arg(long, default_value = "warn")]
+        log_level: String,
+
+        /// Optional file to redirect logs to
+        #[arg(long)]
+        log_file: Option<PathBuf>,
+    },
     /// Start the Syncline Client to sync a folder
     Sync {
         /// Folder to watch and sync
@@ -104,11 +364,18 @@ enum Commands {
         )]
         url: String,
 
-        /// Client name used in conflict filenames (e.g. "laptop", "work-mac").
-        /// Defaults to hostname + short unique ID, persisted in .syncline/client_id.
+        /// Name this device publishes to the vault (e.g. "laptop", "work-mac"),
+        /// shown in `syncline devices` and `{device}` conflict names.
+        /// Defaults to the hostname; remembered in .syncline/client_id.
         #[arg(short = 'n', long)]
         name: Option<String>,
 
+        /// Filesystem naming rules for this folder: `native`, `posix` or
+        /// `windows`. Names the rules can't represent are escaped on disk.
+        /// Use `windows` for FAT/exFAT volumes mounted on Linux or macOS.
+        #[arg(long, default_value = "native")]
+        filename_rules: syncline::v1::names::NameRules,
+
         /// Log level (error, warn, info, debug, trace)
         #[arg(long, default_value = "info")]
         log_level: String,
@@ -119,6 +386,89 @@ enum Commands {
     },
 }
 
+/// Where the server keeps its state.
+#[derive(clap::Args, Debug)]
+struct StoreArgs {
+    /// Database path or connection string. With `--storage fs`
+    /// this is the storage directory instead.
+    #[arg(short, long, default_value = "syncline.db")]
+    db_path: String,
+
+    /// Storage backend for updates, blobs and server metadata.
+    #[arg(long, value_enum, default_value_t = StorageBackend::Sqlite)]
+    storage: StorageBackend,
+
+    /// Directory for blob bytes with the SQLite backend. Defaults to
+    /// `<db-path>.blobs`; existing inline blobs are moved there on
+    /// startup. Connection strings (`sqlite:…`) without this flag
+    /// keep blobs inside the database.
+    #[arg(long)]
+    blob_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    s3: S3Args,
+}
+
+/// Optional S3-compatible object store for blobs. Setting an endpoint
+/// moves blob storage there; updates and metadata stay in `--storage`.
+#[derive(clap::Args, Debug)]
+struct S3Args {
+    /// S3-compatible endpoint for blobs, e.g. `http://127.0.0.1:9000`.
+    #[arg(long, env = "SYNCLINE_S3_ENDPOINT")]
+    s3_endpoint: Option<String>,
+
+    /// Bucket to store blobs in. Must already exist.
+    #[arg(long, env = "SYNCLINE_S3_BUCKET", default_value = "syncline")]
+    s3_bucket: String,
+
+    /// Signing region.
+    #[arg(long, env = "SYNCLINE_S3_REGION", default_value = "us-east-1")]
+    s3_region: String,
+
+    /// Key prefix inside the bucket, e.g. `vault-a/`.
+    #[arg(long, env = "SYNCLINE_S3_PREFIX", default_value = "")]
+    s3_prefix: String,
+
+    /// Access key id.
+    #[arg(long, env = "AWS_ACCESS_KEY_ID", default_value = "")]
+    s3_access_key: String,
+
+    /// Secret access key.
+    #[arg(long, env = "AWS_SECRET_ACCESS_KEY", default_value = "", hide_env_values = true)]
+    s3_secret_key: String,
+}
+
+/// Rate limits and quotas. Rates apply per connection and per token
+/// (the `token` query parameter of the sync URL); unset means unlimited.
+#[derive(clap::Args, Debug)]
+struct LimitArgs {
+    /// Inbound frames per second before a client is throttled.
+    #[arg(long, env = "SYNCLINE_MAX_FRAMES_PER_SEC")]
+    max_frames_per_sec: Option<u32>,
+
+    /// Inbound bytes per second before a client is throttled.
+    #[arg(long, env = "SYNCLINE_MAX_BYTES_PER_SEC")]
+    max_bytes_per_sec: Option<u64>,
+
+    /// Total stored bytes (updates and blobs) the server accepts.
+    #[arg(long, env = "SYNCLINE_MAX_VAULT_BYTES")]
+    max_vault_bytes: Option<u64>,
+
+    /// Blob bytes each token may upload.
+    #[arg(long, env = "SYNCLINE_MAX_BLOB_BYTES_PER_TOKEN")]
+    max_blob_bytes_per_token: Option<u64>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum StorageBackend {
+    /// Single SQLite database file (default).
+    Sqlite,
+    /// Plain directory tree; blobs are stored as individual files.
+    Fs,
+    /// Volatile in-process storage. Everything is lost on exit.
+    Memory,
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
@@ -139,6 +489,51 @@ async fn main() -> anyhow::Result<()> {
             log_file,
             ..
         } => (log_level, log_file),
+        Commands::Config {
+            log_level,
+            log_file,
+            ..
+        } => (log_level, log_file),
+        Commands::CheckNames {
+            log_level,
+            log_file,
+            ..
+        } => (log_level, log_file),
+        Commands::Status {
+            log_level,
+            log_file,
+            ..
+        } => (log_level, log_file),
+        Commands::Devices {
+            log_level,
+            log_file,
+            ..
+        } => (log_level, log_file),
+        Commands::Selective {
+            log_level,
+            log_file,
+            ..
+        } => (log_level, log_file),
+        Commands::Fetch {
+            log_level,
+            log_file,
+            ..
+        } => (log_level, log_file),
+        Commands::ServerDevices {
+            log_level,
+            log_file,
+            ..
+        } => (log_level, log_file),
+        Commands::ServerTokens {
+            log_level,
+            log_file,
+            ..
+        } => (log_level, log_file),
+        Commands::ServerAcl {
+            log_level,
+            log_file,
+            ..
+        } => (log_level, log_file),
         Commands::Sync {
             log_level,
             log_file,
@@ -172,21 +567,30 @@ async fn main() -> anyhow::Result<()> {
     }
 
     match cli.command {
-        Commands::Server { port, db_path, .. } => {
+        Commands::Server {
+            port,
+            store,
+            limits,
+            ..
+        } => {
             use colored::Colorize;
             tracing::info!("{} Starting Syncline server...", "🚀".green());
             tracing::info!("{} Port: {}", "🔌".blue(), port);
-            tracing::info!("{} Database: {}", "💾".cyan(), db_path);
+            tracing::info!(
+                "{} Database: {} ({:?})",
+                "💾".cyan(),
+                store.db_path,
+                store.storage
+            );
 
-            // Convert db_path to sqlite connection string
-            let connection_string = if db_path.starts_with("sqlite:") {
-                db_path.clone()
-            } else {
-                format!("sqlite://{}?mode=rwc", db_path)
+            let db = open_storage(store).await?;
+            let limits = syncline::server::limits::Limits {
+                frames_per_sec: limits.max_frames_per_sec,
+                bytes_per_sec: limits.max_bytes_per_sec,
+                max_vault_bytes: limits.max_vault_bytes,
+                max_blob_bytes_per_token: limits.max_blob_bytes_per_token,
             };
-
-            let db = syncline::server::db::Db::new(&connection_string).await?;
-            syncline::server::server::run_server(db, port).await?;
+            syncline::server::server::run_server_with_storage(db, port, limits).await?;
         }
         Commands::Migrate { folder, .. } => {
             use colored::Colorize;
@@ -241,10 +645,327 @@ async fn main() -> anyhow::Result<()> {
                 std::process::exit(1);
             }
         }
-        Commands::Sync { folder, url, name, .. } => {
-            syncline::client_v1::run_client(folder, url, name).await?;
+        Commands::Config {
+            key,
+            value,
+            unset,
+            folder,
+            ..
+        } => {
+            let entries = syncline::client_v1::run_config(
+                &folder,
+                key.as_deref(),
+                value.as_deref(),
+                unset,
+            )?;
+            for (k, v) in entries {
+                println!("{k} = {v}");
+            }
+        }
+        Commands::CheckNames { folder, .. } => {
+            let report = syncline::client_v1::run_check_names(&folder)?;
+            for u in &report {
+                println!(
+                    "{}: {}: {:?} {}",
+                    u.path,
+                    u.rules.as_str(),
+                    u.segment,
+                    u.problem
+                );
+            }
+            if !report.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        Commands::Status { folder, .. } => {
+            match syncline::client_v1::run_status(&folder)? {
+                0 => println!("all changes saved to server"),
+                1 => println!("1 change waiting to upload"),
+                n => println!("{n} changes waiting to upload"),
+            }
+        }
+        Commands::Devices { retire, folder, .. } => {
+            let devices = syncline::client_v1::run_devices(&folder, retire.as_deref())?;
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            for d in devices {
+                println!(
+                    "{:<20} {}  {:<8} {:<8} last seen {}{}",
+                    d.name,
+                    d.actor.short(),
+                    d.platform,
+                    d.version,
+                    ago(now.saturating_sub(d.last_seen)),
+                    if d.retired { "  (retired)" } else { "" }
+                );
+            }
+        }
+        Commands::Selective {
+            exclude,
+            include,
+            fetch_limit,
+            fetch_all,
+            folder,
+            filename_rules,
+            ..
+        } => {
+            let limit = if fetch_all { Some(None) } else { fetch_limit.map(Some) };
+            let report = syncline::client_v1::run_selective(
+                &folder,
+                exclude.as_deref(),
+                include.as_deref(),
+                limit,
+                filename_rules,
+            )?;
+            if exclude.is_some() {
+                println!("removed {} local files", report.evicted);
+                for path in &report.kept {
+                    println!("kept {path} (modified locally)");
+                }
+            }
+            for f in &report.excluded {
+                println!("excluded: {f}");
+            }
+            if let Some(limit) = report.fetch_limit {
+                println!("binaries over {limit} bytes fetched on demand");
+            }
+        }
+        Commands::Fetch {
+            path,
+            folder,
+            url,
+            timeout_secs,
+            filename_rules,
+            ..
+        } => {
+            let timeout = std::time::Duration::from_secs(timeout_secs);
+            let written =
+                syncline::client_v1::run_fetch(folder, url, &path, filename_rules, timeout)
+                    .await?;
+            for p in written {
+                println!("fetched {p}");
+            }
+        }
+        Commands::ServerDevices {
+            revoke,
+            reinstate,
+            revoke_token,
+            reinstate_token,
+            store,
+            ..
+        } => {
+            use syncline::server::devices;
+            let db = open_storage(store).await?;
+            let listed = devices::list_devices(db.as_ref()).await?;
+            if let Some(sel) = revoke {
+                let actor = resolve_actor(&listed, &sel)?;
+                let r = devices::revoke(db.as_ref(), actor).await?;
+                println!("revoked {} at lamport {}", actor, r.revoked_at);
+            } else if let Some(sel) = reinstate {
+                let actor = resolve_actor(&listed, &sel)?;
+                if !devices::reinstate(db.as_ref(), actor).await? {
+                    anyhow::bail!("{actor} is not revoked");
+                }
+                println!("reinstated {actor}");
+            } else if let Some(token) = revoke_token {
+                devices::revoke_token(db.as_ref(), &token).await?;
+                println!("revoked token {token}");
+            } else if let Some(token) = reinstate_token {
+                if !devices::reinstate_token(db.as_ref(), &token).await? {
+                    anyhow::bail!("token {token} is not revoked");
+                }
+                println!("reinstated token {token}");
+            } else {
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                for d in listed {
+                    let seen = match &d.seen {
+                        Some(s) => format!(
+                            "token {:<16} last seen {}",
+                            s.token,
+                            ago(now.saturating_sub(s.last_seen))
+                        ),
+                        None => "never connected".to_string(),
+                    };
+                    let revoked = match &d.revoked {
+                        Some(r) => format!("  (revoked at lamport {})", r.revoked_at),
+                        None => String::new(),
+                    };
+                    println!("{}  {}{}", d.actor, seen, revoked);
+                }
+                for token in devices::revoked_tokens(db.as_ref()).await? {
+                    println!("token {token}  (revoked)");
+                }
+            }
+        }
+        Commands::ServerTokens {
+            issue,
+            remove,
+            store,
+            ..
+        } => {
+            use syncline::server::tokens;
+            let db = open_storage(store).await?;
+            if let Some(token) = issue {
+                let credential = tokens::issue(db.as_ref(), &token).await?;
+                println!("issued a secret for {token}; connect with ?token={credential}");
+            } else if let Some(token) = remove {
+                if !tokens::remove(db.as_ref(), &token).await? {
+                    anyhow::bail!("{token} has no secret");
+                }
+                println!("removed the secret for {token}");
+            } else {
+                for token in tokens::list(db.as_ref()).await? {
+                    println!("{token}");
+                }
+            }
+        }
+        Commands::ServerAcl {
+            folder,
+            read,
+            write,
+            remove,
+            store,
+            ..
+        } => {
+            use syncline::server::acl;
+            let db = open_storage(store).await?;
+            if let Some(folder) = folder {
+                let Some(folder) = syncline::v1::selective::normalize_folder(&folder) else {
+                    anyhow::bail!("{folder:?} is not a folder inside the vault");
+                };
+                if remove {
+                    if !acl::remove_rule(db.as_ref(), &folder).await? {
+                        anyhow::bail!("no rule for {folder}");
+                    }
+                    println!("removed rule for {folder}");
+                } else {
+                    acl::set_rule(db.as_ref(), acl::Rule { folder: folder.clone(), read, write })
+                        .await?;
+                    println!("set rule for {folder}");
+                }
+            } else {
+                for r in acl::list_rules(db.as_ref()).await? {
+                    println!(
+                        "{}  read: {}  write: {}",
+                        r.folder,
+                        if r.read.is_empty() { "-".to_string() } else { r.read.join(",") },
+                        if r.write.is_empty() { "-".to_string() } else { r.write.join(",") },
+                    );
+                }
+            }
+        }
+        Commands::Sync {
+            folder,
+            url,
+            name,
+            filename_rules,
+            ..
+        } => {
+            syncline::client_v1::run_client(folder, url, name, filename_rules).await?;
         }
     }
 
     Ok(())
 }
+
+/// The actor `sel` names: a full actor id, or a prefix matching exactly
+/// one device the server knows.
+fn resolve_actor(
+    known: &[syncline::server::devices::ServerDevice],
+    sel: &str,
+) -> anyhow::Result<syncline::v1::ActorId> {
+    if let Some(actor) = syncline::v1::ActorId::parse_str(sel) {
+        return Ok(actor);
+    }
+    let matches: Vec<_> = known
+        .iter()
+        .filter(|d| d.actor.to_string_hyphenated().starts_with(sel))
+        .collect();
+    match matches.as_slice() {
+        [d] => Ok(d.actor),
+        [] => anyhow::bail!("no device with an actor id starting {sel:?}"),
+        many => anyhow::bail!("{sel:?} matches {} devices; give more of the id", many.len()),
+    }
+}
+
+/// `secs` as a rough "… ago" for humans.
+fn ago(secs: u64) -> String {
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3_600 => format!("{}m ago", secs / 60),
+        3_600..86_400 => format!("{}h ago", secs / 3_600),
+        _ => format!("{}d ago", secs / 86_400),
+    }
+}
+
+/// Open the server's storage as configured by `store`.
+async fn open_storage(
+    store: StoreArgs,
+) -> anyhow::Result<std::sync::Arc<dyn syncline::server::storage::Storage>> {
+    use colored::Colorize;
+    use std::sync::Arc;
+    use syncline::server::storage::Storage;
+    let StoreArgs {
+        db_path,
+        storage,
+        blob_dir,
+        s3,
+    } = store;
+    let db: Arc<dyn Storage> = match storage {
+        StorageBackend::Sqlite => {
+            // Convert db_path to sqlite connection string
+            let is_conn_string = db_path.starts_with("sqlite:");
+            let connection_string = if is_conn_string {
+                db_path.clone()
+            } else {
+                format!("sqlite://{}?mode=rwc", db_path)
+            };
+            // With S3 in front, nothing new lands in the database; it
+            // keeps serving the blobs it had from the blob directory it
+            // recorded when they moved out.
+            let blob_dir = blob_dir.or_else(|| {
+                (!is_conn_string && s3.s3_endpoint.is_none())
+                    .then(|| PathBuf::from(format!("{}.blobs", db_path)))
+            });
+            match blob_dir {
+                Some(dir) => {
+                    tracing::info!("{} Blobs: {}", "📁".cyan(), dir.display());
+                    Arc::new(
+                        syncline::server::db::Db::with_blob_dir(&connection_string, dir).await?,
+                    )
+                }
+                None => Arc::new(syncline::server::db::Db::new(&connection_string).await?),
+            }
+        }
+        StorageBackend::Fs => Arc::new(syncline::server::fs_storage::FsStorage::open(&db_path)?),
+        StorageBackend::Memory => Arc::new(syncline::server::memory_storage::MemoryStorage::new()),
+    };
+    Ok(match s3.s3_endpoint {
+        Some(endpoint) => {
+            tracing::info!("{} Blobs: s3 {} bucket {}", "☁️".cyan(), endpoint, s3.s3_bucket);
+            // Chunked uploads are staged next to the database.
+            let staging = match db_path.strip_prefix("sqlite:") {
+                Some(_) => std::env::temp_dir().join("syncline-s3-staging"),
+                None => PathBuf::from(format!("{}.uploads", db_path)),
+            };
+            Arc::new(syncline::server::s3_storage::S3Storage::with_staging_dir(
+                db,
+                syncline::server::s3_storage::S3Config {
+                    endpoint,
+                    bucket: s3.s3_bucket,
+                    region: s3.s3_region,
+                    access_key: s3.s3_access_key,
+                    secret_key: s3.s3_secret_key,
+                    prefix: s3.s3_prefix,
+                },
+                staging,
+            )?)
+        }
+        None => db,
+    })
+}
@@ -25,6 +25,68 @@ pub const MSG_MANIFEST_SYNC: u8 = 0x20;
 /// v1: convergence heartbeat — SHA-256 over the projected namespace
 /// (§4.4.1). Mismatch triggers a full manifest SyncStep1.
 pub const MSG_MANIFEST_VERIFY: u8 = 0x21;
+/// v1.1: one piece of a blob too large (or too precious to buffer) to
+/// send as a single [`MSG_BLOB_UPDATE`]. `doc_id` is the blob's hex
+/// hash; the payload is `[u64 BE offset][u64 BE total][bytes]` — see
+/// [`encode_blob_chunk`]. Pieces arrive in order; the receiver appends
+/// them to a staging file and verifies the hash once `offset + len`
+/// reaches `total`. Only sent to peers that advertised minor >= 1.
+pub const MSG_BLOB_CHUNK: u8 = 0x22;
+/// v1.2: names the actor behind a connection. `doc_id` is
+/// [`MANIFEST_DOC_ID`]; the payload is the client's `ActorId`,
+/// hyphenated UTF-8. Sent right after the version handshake to servers
+/// at minor >= 2. A server that has revoked the actor closes the socket
+/// with [`DEVICE_REVOKED_REASON`].
+pub const MSG_HELLO: u8 = 0x23;
+/// v1.3: many content `MSG_SYNC_STEP_1`s in one frame. `doc_id` is
+/// [`MANIFEST_DOC_ID`]; the payload lists `(doc_id, digest, state
+/// vector)` triples — see [`crate::v1::sync::encode_sync_batch`]. The
+/// server subscribes the connection to every listed doc but answers
+/// (with the usual per-doc STEP_2 + STEP_1) only those whose digest
+/// differs from its own. Only sent to servers at minor >= 3.
+pub const MSG_SYNC_BATCH: u8 = 0x24;
+/// v1.4: where the client left off in the server's change feed. `doc_id`
+/// is [`MANIFEST_DOC_ID`]. The client sends its cursor (see
+/// [`crate::v1::sync::encode_changes_request`]); the server subscribes
+/// the connection to every content doc it may read and answers with the
+/// docs changed since the cursor, or with "unknown" if it can't tell
+/// ([`crate::v1::sync::encode_changes_reply`]). Only sent to servers at
+/// minor >= 4.
+pub const MSG_CHANGES: u8 = 0x25;
+/// v1.5: an update the sender wants acknowledged once it is stored.
+/// `doc_id` is the wrapped frame's; the payload is `[seq u64 BE][msg_type
+/// u8][payload]` — see [`crate::v1::sync::encode_tagged`]. Wraps
+/// content updates, manifest updates, `MSG_BLOB_UPDATE` and the last
+/// `MSG_BLOB_CHUNK` of a blob. Only sent to servers at minor >= 5.
+pub const MSG_TAGGED: u8 = 0x26;
+/// v1.5: the server's answer to a [`MSG_TAGGED`] frame, sent once the
+/// update it wrapped has been committed to storage or refused. `doc_id`
+/// echoes the tagged frame's; the payload is `[seq u64 BE][status u8]`
+/// with one of [`ACK_SAVED`] / [`ACK_FAILED`] / [`ACK_REFUSED`].
+pub const MSG_ACK: u8 = 0x27;
+/// v1.6: something went wrong with a frame, or with the connection.
+/// `doc_id` names the doc or blob concerned ([`MANIFEST_DOC_ID`] for the
+/// manifest or the connection itself); the payload is `[code u16
+/// BE][message utf8]` with one of the `ERR_*` codes — see
+/// [`crate::v1::sync::encode_error`]. Only sent to clients at minor >= 6,
+/// except for [`ERR_VERSION`], which goes out before the server knows
+/// the client's version and is followed by a close.
+pub const MSG_ERROR: u8 = 0x28;
+/// [`CAP_COMPRESSION`]: a frame whose payload went out deflated.
+/// `doc_id` is the wrapped frame's; the payload is `[msg_type
+/// u8][raw deflate stream]` — see [`crate::v1::sync::compress_frame`].
+/// Wraps [`MSG_SYNC_STEP_2`], [`MSG_MANIFEST_SYNC`], [`MSG_BLOB_UPDATE`]
+/// and [`MSG_BLOB_CHUNK`] payloads of at least [`COMPRESS_MIN_SIZE`]
+/// bytes that deflate well; may itself be wrapped in [`MSG_TAGGED`].
+pub const MSG_COMPRESSED: u8 = 0x29;
+/// v1.7: the features its sender supports, as a bitmap of `CAP_*`
+/// bits — see [`crate::v1::sync::encode_caps`]. `doc_id` is
+/// [`MANIFEST_DOC_ID`]. A client sends it right behind its
+/// [`MSG_VERSION`]; a server answers with its own right behind its
+/// version echo, to clients at minor >= 7 only. Each side then uses the
+/// features both support. A peer below minor 7 is taken to support
+/// exactly what its minor version introduced.
+pub const MSG_CAPS: u8 = 0xF1;
 /// v1: protocol version handshake. Must be the first frame on a v1
 /// session. Payload is `[u8 major][u8 minor]`.
 pub const MSG_VERSION: u8 = 0xF0;
@@ -38,23 +100,144 @@ pub const MANIFEST_STEP_1: u8 = 0;
 pub const MANIFEST_STEP_2: u8 = 1;
 pub const MANIFEST_UPDATE: u8 = 2;
 
+/// [`MSG_ACK`] status: the update is stored.
+pub const ACK_SAVED: u8 = 0;
+/// [`MSG_ACK`] status: storing the update failed; sending it again may
+/// work.
+pub const ACK_FAILED: u8 = 1;
+/// [`MSG_ACK`] status: the server will never store this update (too
+/// large, outside the token's access, malformed, by a revoked device).
+pub const ACK_REFUSED: u8 = 2;
+
+/// [`MSG_ERROR`] code: the handshake was missing, malformed, or named
+/// an incompatible major version. The server closes the connection.
+pub const ERR_VERSION: u16 = 1;
+/// [`MSG_ERROR`] code: a blob was refused for exceeding the size limit.
+pub const ERR_BLOB_TOO_LARGE: u16 = 2;
+/// [`MSG_ERROR`] code: a requested blob isn't on the server (yet).
+pub const ERR_BLOB_NOT_FOUND: u16 = 3;
+/// [`MSG_ERROR`] code: the server's storage failed reading or writing
+/// the doc or blob; trying again later may work.
+pub const ERR_STORAGE: u16 = 4;
+/// [`MSG_ERROR`] code: a compressed frame came from a peer that didn't
+/// negotiate compression, or didn't inflate; send it uncompressed.
+pub const ERR_COMPRESSION: u16 = 5;
+
 /// Current v1 protocol version.
 pub const V1_PROTOCOL_MAJOR: u8 = 1;
-pub const V1_PROTOCOL_MINOR: u8 = 0;
+pub const V1_PROTOCOL_MINOR: u8 = 7;
+/// First minor version that understands [`MSG_BLOB_CHUNK`].
+pub const V1_MINOR_BLOB_CHUNKS: u8 = 1;
+/// First minor version that understands [`MSG_HELLO`].
+pub const V1_MINOR_HELLO: u8 = 2;
+/// First minor version that understands [`MSG_SYNC_BATCH`].
+pub const V1_MINOR_SYNC_BATCH: u8 = 3;
+/// First minor version that understands [`MSG_CHANGES`].
+pub const V1_MINOR_CHANGES: u8 = 4;
+/// First minor version that understands [`MSG_TAGGED`].
+pub const V1_MINOR_ACKS: u8 = 5;
+/// First minor version that understands [`MSG_ERROR`].
+pub const V1_MINOR_ERRORS: u8 = 6;
+/// First minor version that sends [`MSG_CAPS`]. Features added after it
+/// get a `CAP_*` bit and no minor version of their own.
+pub const V1_MINOR_CAPS: u8 = 7;
+
+/// [`MSG_CAPS`] bit: sends and receives [`MSG_BLOB_CHUNK`].
+pub const CAP_BLOB_CHUNKS: u64 = 1 << 0;
+/// [`MSG_CAPS`] bit: names its actor with [`MSG_HELLO`].
+pub const CAP_HELLO: u64 = 1 << 1;
+/// [`MSG_CAPS`] bit: subscribes with [`MSG_SYNC_BATCH`].
+pub const CAP_SYNC_BATCH: u64 = 1 << 2;
+/// [`MSG_CAPS`] bit: resumes from the change feed with [`MSG_CHANGES`].
+pub const CAP_CHANGES: u64 = 1 << 3;
+/// [`MSG_CAPS`] bit: [`MSG_TAGGED`] updates and their [`MSG_ACK`]s.
+pub const CAP_ACKS: u64 = 1 << 4;
+/// [`MSG_CAPS`] bit: [`MSG_ERROR`] frames.
+pub const CAP_ERRORS: u64 = 1 << 5;
+/// [`MSG_CAPS`] bit: reads [`MSG_COMPRESSED`] frames.
+pub const CAP_COMPRESSION: u64 = 1 << 6;
+/// [`MSG_CAPS`] bit: honours the vault's `path_equivalence` setting.
+/// Clients only seed a new vault with a folding policy on a server
+/// that has it.
+pub const CAP_PATH_EQUIVALENCE: u64 = 1 << 7;
+
+/// Close-frame reason a server gives a revoked device. Clients stop
+/// reconnecting when they see it.
+pub const DEVICE_REVOKED_REASON: &str = "device revoked";
 
-/// Maximum blob size in bytes (50 MB).
+/// Maximum blob size in bytes (50 MB) for a single-frame
+/// [`MSG_BLOB_UPDATE`], which both ends hold in memory whole.
 pub const MAX_BLOB_SIZE: usize = 50 * 1024 * 1024;
 
+/// Maximum blob size in bytes (4 GiB) for a chunked transfer. Neither
+/// end ever buffers more than one [`BLOB_CHUNK_SIZE`] piece, so this is
+/// a sanity bound rather than a memory budget.
+pub const MAX_STREAMED_BLOB_SIZE: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Payload bytes per [`MSG_BLOB_CHUNK`] frame.
+pub const BLOB_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Smallest payload worth deflating. Below this the saving is a few
+/// dozen bytes at most and not worth the receiver's inflate.
+pub const COMPRESS_MIN_SIZE: usize = 512;
+
+/// Largest payload a [`MSG_COMPRESSED`] frame may inflate to, the same
+/// as the WebSocket message limit on an uncompressed frame. Anything
+/// bigger is dropped as malformed rather than buffered. Blobs and
+/// chunks are held to their own, smaller sizes.
+pub const MAX_INFLATED_SIZE: usize = 64 * 1024 * 1024;
+
+/// Docs listed per [`MSG_SYNC_BATCH`] frame. Keeps a 20k-note vault's
+/// reconnect to a few dozen frames of ~50 KB rather than one huge one.
+pub const SYNC_BATCH_MAX_DOCS: usize = 512;
+
+/// Header bytes in front of a [`MSG_BLOB_CHUNK`] payload's data.
+pub const BLOB_CHUNK_HEADER_LEN: usize = 16;
+
 pub fn encode_message(msg_type: u8, doc_id: &str, payload: &[u8]) -> Vec<u8> {
+    let mut msg = encode_message_header(msg_type, doc_id, payload.len());
+    msg.extend_from_slice(payload);
+    msg
+}
+
+/// Frame header only, with room reserved for `payload_len` more bytes.
+/// Lets a sender append a payload piecewise (e.g. straight from a blob
+/// stream) without first materialising it in a separate buffer.
+pub fn encode_message_header(msg_type: u8, doc_id: &str, payload_len: usize) -> Vec<u8> {
     let doc_id_bytes = doc_id.as_bytes();
-    let mut msg = Vec::with_capacity(1 + 2 + doc_id_bytes.len() + payload.len());
+    let mut msg = Vec::with_capacity(1 + 2 + doc_id_bytes.len() + payload_len);
     msg.push(msg_type);
     msg.extend_from_slice(&(doc_id_bytes.len() as u16).to_be_bytes());
     msg.extend_from_slice(doc_id_bytes);
-    msg.extend_from_slice(payload);
     msg
 }
 
+/// Full [`MSG_BLOB_CHUNK`] frame carrying `bytes` at `offset` of a blob
+/// that is `total` bytes long.
+pub fn encode_blob_chunk(hash: &str, offset: u64, total: u64, bytes: &[u8]) -> Vec<u8> {
+    let mut msg =
+        encode_message_header(MSG_BLOB_CHUNK, hash, BLOB_CHUNK_HEADER_LEN + bytes.len());
+    msg.extend_from_slice(&offset.to_be_bytes());
+    msg.extend_from_slice(&total.to_be_bytes());
+    msg.extend_from_slice(bytes);
+    msg
+}
+
+/// Split a [`MSG_BLOB_CHUNK`] payload into `(offset, total, bytes)`.
+/// `None` if the header is truncated or the piece overruns `total`.
+pub fn decode_blob_chunk(payload: &[u8]) -> Option<(u64, u64, &[u8])> {
+    if payload.len() < BLOB_CHUNK_HEADER_LEN {
+        return None;
+    }
+    let offset = u64::from_be_bytes(payload[0..8].try_into().ok()?);
+    let total = u64::from_be_bytes(payload[8..16].try_into().ok()?);
+    let bytes = &payload[BLOB_CHUNK_HEADER_LEN..];
+    if offset.checked_add(bytes.len() as u64)? > total {
+        return None;
+    }
+    Some((offset, total, bytes))
+}
+
 pub fn decode_message(data: &[u8]) -> Option<(u8, &str, &[u8])> {
     if data.len() < 3 {
         return None;
@@ -121,6 +304,26 @@ mod tests {
         assert_eq!(payload, blob_data.as_slice());
     }
 
+    #[test]
+    fn test_encode_decode_blob_chunk() {
+        let frame = encode_blob_chunk("abcd", 4, 10, b"efgh");
+        let (msg_type, doc_id, payload) = decode_message(&frame).unwrap();
+        assert_eq!(msg_type, MSG_BLOB_CHUNK);
+        assert_eq!(doc_id, "abcd");
+        assert_eq!(decode_blob_chunk(payload), Some((4, 10, &b"efgh"[..])));
+    }
+
+    #[test]
+    fn test_decode_blob_chunk_rejects_overrun_and_truncation() {
+        let frame = encode_blob_chunk("abcd", 8, 10, b"efgh");
+        let (_, _, payload) = decode_message(&frame).unwrap();
+        assert!(decode_blob_chunk(payload).is_none());
+        assert!(decode_blob_chunk(&[0u8; 15]).is_none());
+        let frame = encode_blob_chunk("abcd", u64::MAX, u64::MAX, b"x");
+        let (_, _, payload) = decode_message(&frame).unwrap();
+        assert!(decode_blob_chunk(payload).is_none());
+    }
+
     #[test]
     fn test_encode_decode_blob_request() {
         // 32-byte SHA256 hash as payload
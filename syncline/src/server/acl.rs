@@ -0,0 +1,540 @@
+//! Per-folder access control for shared vaults.
+//!
+//! An admin binds folders to account tokens with [`set_rule`]: a rule
+//! names the tokens that may read the folder's subtree and the tokens
+//! that may also write it. A rule may only name tokens that have a
+//! secret ([`tokens`]), and its grants go only to connections that
+//! presented that secret; a client that merely claims a token's label
+//! is treated like any unnamed token. The most specific rule covering a path
+//! decides; paths no rule covers are open to every token. Rules live in
+//! server meta as JSON and are read when a connection is accepted, so a
+//! change reaches a token on its next connection. Paths are matched
+//! under the vault's [`PathEquivalence`], so where `hr/x.md` and
+//! `HR/x.md` are one file the rule for `HR` covers both spellings.
+//!
+//! A token that can write everywhere is a **full replica** and syncs
+//! the shared manifest exactly as before. Every other token is a
+//! **partial replica**: it syncs a per-token *view* manifest instead —
+//! a separate Y.Doc, persisted under `view:<token>`, into which the
+//! server mirrors the raw fields of the nodes the token may read (plus
+//! the directories above them, so paths still resolve), the vault
+//! config and the device records. Node ids, names and stamps are copied
+//! verbatim, so the view projects every visible file to the same path,
+//! id and blob as the full manifest does.
+//!
+//! Writes a partial replica makes to its view are copied back into the
+//! shared manifest node by node with [`merge_view`], but only where the
+//! token may write both the node's old and new path. The server
+//! re-stamps what it copies with its own lamport, so a partial replica
+//! can't forge a stamp to win a collision, and takes back only the
+//! device record of the actor the connection identified as. A refused edit to
+//! an existing node is undone in the view on the next [`refresh_view`];
+//! a refused new node stays in the view only, so the client keeps its
+//! file and it syncs if the token is later granted write access.
+//!
+//! `MSG_MANIFEST_VERIFY` from a partial replica is answered against its
+//! view, so `projection_hash` still proves that client and server agree
+//! on everything the client is allowed to have.
+
+use crate::server::storage::Storage;
+use crate::server::tokens;
+use crate::v1::ids::{ActorId, NodeId};
+use crate::v1::manifest::{Manifest, NodeEntry, build_path_ignoring_tombstones};
+use crate::v1::projection::PathEquivalence;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+const ACL_KEY: &str = "acl";
+
+/// Doc-id prefix of the persisted view manifests of partial replicas.
+pub(crate) const VIEW_DOC_PREFIX: &str = "view:";
+
+/// Doc id a token's view manifest is stored and broadcast under.
+pub(crate) fn view_doc_id(token: &str) -> String {
+    format!("{VIEW_DOC_PREFIX}{token}")
+}
+
+/// Who may read and write one folder's subtree.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rule {
+    /// Vault-relative folder path, without leading or trailing `/`.
+    pub folder: String,
+    /// Tokens that may read the subtree. Writers may read it too.
+    pub read: Vec<String>,
+    /// Tokens that may read and write the subtree.
+    pub write: Vec<String>,
+}
+
+/// Every rule, ordered by folder.
+pub async fn list_rules(db: &dyn Storage) -> Result<Vec<Rule>> {
+    match db.get_meta(ACL_KEY).await? {
+        Some(json) => serde_json::from_str(&json).context("parsing meta acl"),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Add `rule`, replacing any rule for the same folder. Every token it
+/// names must have a secret (see [`tokens`]): a rule naming a plain
+/// label would grant that folder to whoever claims the label.
+pub async fn set_rule(db: &dyn Storage, rule: Rule) -> Result<()> {
+    let verified = tokens::list(db).await?;
+    if let Some(t) = rule.read.iter().chain(&rule.write).find(|t| !verified.contains(t)) {
+        anyhow::bail!("token {t:?} has no secret; issue one with `syncline server-tokens --issue {t}`");
+    }
+    let mut rules = list_rules(db).await?;
+    rules.retain(|r| r.folder != rule.folder);
+    rules.push(rule);
+    rules.sort_by(|a, b| a.folder.cmp(&b.folder));
+    db.set_meta(ACL_KEY, &serde_json::to_string(&rules)?).await
+}
+
+/// Drop the rule for `folder`. Returns `false` if there was none.
+pub async fn remove_rule(db: &dyn Storage, folder: &str) -> Result<bool> {
+    let mut rules = list_rules(db).await?;
+    let before = rules.len();
+    rules.retain(|r| r.folder != folder);
+    if rules.len() == before {
+        return Ok(false);
+    }
+    db.set_meta(ACL_KEY, &serde_json::to_string(&rules)?)
+        .await?;
+    Ok(true)
+}
+
+/// What one token may do, under the rules loaded for its connection.
+#[derive(Clone, Debug)]
+pub(crate) struct Access {
+    token: String,
+    /// Whether the connection proved it holds the token's secret. Rules
+    /// grant nothing to a token that didn't.
+    verified: bool,
+    rules: Vec<Rule>,
+}
+
+impl Access {
+    pub(crate) fn new(token: &str, verified: bool, rules: Vec<Rule>) -> Self {
+        Self {
+            token: token.to_string(),
+            verified,
+            rules,
+        }
+    }
+
+    pub(crate) fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// True if no rule keeps this token from writing anywhere.
+    pub(crate) fn is_full(&self) -> bool {
+        self.rules.iter().all(|r| self.named(&r.write))
+    }
+
+    /// Whether the token may read `path`, compared under `eq` — the
+    /// shared manifest's [`PathEquivalence::of`].
+    pub(crate) fn can_read(&self, eq: PathEquivalence, path: &str) -> bool {
+        self.rule_for(eq, path)
+            .is_none_or(|r| self.named(&r.read) || self.named(&r.write))
+    }
+
+    /// Whether the token may write `path`, compared under `eq`.
+    pub(crate) fn can_write(&self, eq: PathEquivalence, path: &str) -> bool {
+        self.rule_for(eq, path)
+            .is_none_or(|r| self.named(&r.write))
+    }
+
+    fn named(&self, tokens: &[String]) -> bool {
+        self.verified && tokens.contains(&self.token)
+    }
+
+    fn rule_for(&self, eq: PathEquivalence, path: &str) -> Option<&Rule> {
+        let path = eq.key(path);
+        self.rules
+            .iter()
+            .filter(|r| covers(&eq.key(&r.folder), &path))
+            .max_by_key(|r| r.folder.len())
+    }
+}
+
+/// Whether `folder` is `path` or one of its ancestors. Both are
+/// [`PathEquivalence::key`]s, which fold segment by segment.
+fn covers(folder: &str, path: &str) -> bool {
+    path.strip_prefix(folder)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Raw path of node `id` in `manifest`, tombstones included. Walks the
+/// parent chain only, so it is cheap enough to check per frame.
+pub(crate) fn node_path(manifest: &Manifest, id: NodeId) -> Option<String> {
+    let mut entry = manifest.get_entry(id)?;
+    let mut segments = vec![entry.name.clone()];
+    for _ in 0..1024 {
+        let Some(parent) = entry.parent else {
+            segments.reverse();
+            return Some(segments.join("/"));
+        };
+        entry = manifest.get_entry(parent)?;
+        segments.push(entry.name.clone());
+    }
+    None
+}
+
+/// Nodes of `all` that `access` may see: the readable ones and the
+/// directories above them.
+fn visible(
+    all: &HashMap<NodeId, NodeEntry>,
+    eq: PathEquivalence,
+    access: &Access,
+) -> HashSet<NodeId> {
+    let mut out = HashSet::new();
+    for entry in all.values() {
+        let readable =
+            build_path_ignoring_tombstones(entry, all).is_some_and(|p| access.can_read(eq, &p));
+        if !readable {
+            continue;
+        }
+        let mut cursor = Some(entry.id);
+        while let Some(id) = cursor {
+            if !out.insert(id) {
+                break;
+            }
+            cursor = all.get(&id).and_then(|e| e.parent);
+        }
+    }
+    out
+}
+
+/// Bring `view` in line with what `access` may see of `main`. Nodes
+/// `main` doesn't have — new nodes [`merge_view`] refused — are left
+/// alone. Returns whether `view` changed.
+pub(crate) fn refresh_view(main: &Manifest, view: &mut Manifest, access: &Access) -> bool {
+    let visible = visible(&main.all_entries(), PathEquivalence::of(main), access);
+    let in_view = view.raw_nodes();
+    let mut changed = false;
+    for (key, fields) in main.raw_nodes() {
+        let shown = NodeId::parse_str(&key).is_some_and(|id| visible.contains(&id));
+        if shown {
+            if in_view.get(&key) != Some(&fields) {
+                changed |= view.put_raw_node(&key, &fields);
+            }
+        } else if in_view.contains_key(&key) {
+            changed |= view.remove_raw_node(&key);
+        }
+    }
+
+    let config: HashMap<String, String> = main.config_entries().into_iter().collect();
+    let view_config: HashMap<String, String> = view.config_entries().into_iter().collect();
+    for key in view_config.keys() {
+        if !config.contains_key(key) {
+            view.remove_config(key);
+            changed = true;
+        }
+    }
+    for (key, value) in &config {
+        if view_config.get(key) != Some(value) {
+            view.set_config(key, value);
+            changed = true;
+        }
+    }
+
+    let devices = view.raw_devices();
+    for (key, fields) in main.raw_devices() {
+        if devices.get(&key) != Some(&fields) {
+            changed |= view.put_raw_device(&key, &fields);
+        }
+    }
+    changed
+}
+
+/// Copy the nodes a partial replica wrote to `view` into `main`,
+/// re-stamped, where `access` lets it write the node's path both before
+/// and after the edit. Of the device records only `actor`'s — the one
+/// the connection named in its hello — is copied; config edits never
+/// are. Returns the nodes whose edits were refused.
+pub(crate) fn merge_view(
+    view: &Manifest,
+    main: &mut Manifest,
+    access: &Access,
+    actor: Option<ActorId>,
+) -> Vec<NodeId> {
+    let main_all = main.all_entries();
+    let view_all = view.all_entries();
+    let main_nodes = main.raw_nodes();
+    let eq = PathEquivalence::of(main);
+    let writable = |all: &HashMap<NodeId, NodeEntry>, id: NodeId| {
+        all.get(&id)
+            .and_then(|e| build_path_ignoring_tombstones(e, all))
+            .is_some_and(|p| access.can_write(eq, &p))
+    };
+    let mut refused = Vec::new();
+    for (key, fields) in view.raw_nodes() {
+        if main_nodes.get(&key) == Some(&fields) {
+            continue;
+        }
+        let Some(id) = NodeId::parse_str(&key) else {
+            continue;
+        };
+        let allowed =
+            writable(&view_all, id) && (!main_all.contains_key(&id) || writable(&main_all, id));
+        if allowed {
+            main.put_raw_node_restamped(&key, &fields);
+        } else {
+            refused.push(id);
+        }
+    }
+
+    if let Some(actor) = actor {
+        let key = actor.to_string_hyphenated();
+        if let Some(fields) = view.raw_devices().get(&key)
+            && main.raw_devices().get(&key) != Some(fields)
+        {
+            main.put_raw_device(&key, fields);
+        }
+    }
+    refused
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::memory_storage::MemoryStorage;
+    use crate::v1::ids::Lamport;
+    use crate::v1::manifest::NodeKind;
+    use crate::v1::projection::{PATH_EQUIVALENCE_KEY, project};
+    use crate::v1::sync::projection_hash;
+
+    fn rule(folder: &str, read: &[&str], write: &[&str]) -> Rule {
+        Rule {
+            folder: folder.to_string(),
+            read: read.iter().map(|s| s.to_string()).collect(),
+            write: write.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    /// A vault with `notes.md`, `HR/salaries.md` and `HR/Public/handbook.md`.
+    fn vault() -> (Manifest, NodeId, NodeId) {
+        let mut m = Manifest::new(ActorId::new());
+        m.create_node("notes.md", None, NodeKind::Text, None, 1);
+        let hr = m.create_node("HR", None, NodeKind::Directory, None, 0);
+        let salaries = m.create_node("salaries.md", Some(hr), NodeKind::Text, None, 2);
+        let public = m.create_node("Public", Some(hr), NodeKind::Directory, None, 0);
+        m.create_node("handbook.md", Some(public), NodeKind::Text, None, 3);
+        (m, hr, salaries)
+    }
+
+    fn paths(m: &Manifest) -> Vec<String> {
+        let mut p: Vec<String> = project(m).by_path.keys().map(|p| p.to_string()).collect();
+        p.sort();
+        p
+    }
+
+    #[test]
+    fn most_specific_rule_decides() {
+        let rules = vec![
+            rule("HR", &[], &["hr"]),
+            rule("HR/Public", &["staff"], &["hr"]),
+        ];
+        let staff = Access::new("staff", true, rules.clone());
+        let eq = PathEquivalence::EXACT;
+        assert!(staff.can_read(eq, "notes.md") && staff.can_write(eq, "notes.md"));
+        assert!(!staff.can_read(eq, "HR") && !staff.can_read(eq, "HR/salaries.md"));
+        assert!(staff.can_read(eq, "HR/Public/handbook.md"));
+        assert!(!staff.can_write(eq, "HR/Public/handbook.md"));
+        assert!(staff.can_read(eq, "HRX/a.md"));
+        assert!(!staff.is_full());
+        assert!(Access::new("hr", true, rules.clone()).is_full());
+
+        // Claiming a token without its secret earns none of its grants.
+        let claimed = Access::new("hr", false, rules);
+        assert!(!claimed.is_full());
+        assert!(!claimed.can_read(eq, "HR/salaries.md"));
+        assert!(claimed.can_write(eq, "notes.md"));
+    }
+
+    #[test]
+    fn rules_cover_every_equivalent_spelling() {
+        let staff = Access::new("staff", true, vec![rule("HR", &[], &["hr"])]);
+        let eq = PathEquivalence::PORTABLE;
+        assert!(!staff.can_write(eq, "hr/x.md") && !staff.can_read(eq, "Hr"));
+        assert!(staff.can_write(eq, "hrx/x.md"));
+        assert!(staff.can_write(PathEquivalence::EXACT, "hr/x.md"));
+    }
+
+    #[test]
+    fn merge_refuses_new_nodes_under_another_spelling_of_a_locked_folder() {
+        let (mut main, _, _) = vault();
+        main.set_config(PATH_EQUIVALENCE_KEY, &PathEquivalence::PORTABLE.to_string());
+        let access = Access::new("staff", true, vec![rule("HR", &[], &["hr"])]);
+        let mut view = Manifest::new(ActorId::new());
+        refresh_view(&main, &mut view, &access);
+        let mut client = Manifest::new(ActorId::new());
+        client.apply_update(&view.encode_state_as_update()).unwrap();
+
+        // The client can't see `HR`, so it creates `hr/` — which the
+        // projection folds onto `HR/`.
+        let dir = client.create_node("hr", None, NodeKind::Directory, None, 0);
+        let file = client.create_node("x.md", Some(dir), NodeKind::Text, None, 0);
+        view.apply_update(&client.encode_state_as_update()).unwrap();
+
+        let mut refused = merge_view(&view, &mut main, &access, Some(client.actor()));
+        refused.sort();
+        let mut expected = vec![dir, file];
+        expected.sort();
+        assert_eq!(refused, expected);
+        assert!(main.get_entry(file).is_none());
+    }
+
+    #[test]
+    fn view_holds_only_what_the_token_may_read() {
+        let (main, _, salaries) = vault();
+        let access = Access::new(
+            "staff",
+            true,
+            vec![
+                rule("HR", &[], &["hr"]),
+                rule("HR/Public", &["staff"], &["hr"]),
+            ],
+        );
+        let mut view = Manifest::new(ActorId::new());
+        assert!(refresh_view(&main, &mut view, &access));
+        assert!(!refresh_view(&main, &mut view, &access));
+        assert_eq!(paths(&view), vec!["HR/Public/handbook.md", "notes.md"]);
+        assert!(view.get_entry(salaries).is_none());
+
+        // The view projects its nodes exactly as the full manifest does.
+        let full = project(&main);
+        for (path, entry) in project(&view).by_path.iter() {
+            assert_eq!(full.by_path.get(path).map(|e| e.id), Some(entry.id));
+        }
+
+        // A client holding the view verifies against it.
+        let mut client = Manifest::new(ActorId::new());
+        client.apply_update(&view.encode_state_as_update()).unwrap();
+        assert_eq!(projection_hash(&client), projection_hash(&view));
+    }
+
+    #[test]
+    fn moving_a_node_out_of_reach_removes_it_from_the_view() {
+        let (mut main, hr, _) = vault();
+        let access = Access::new("staff", true, vec![rule("HR", &[], &["hr"])]);
+        let mut view = Manifest::new(ActorId::new());
+        refresh_view(&main, &mut view, &access);
+        let notes = main.find_entry_by_path("notes.md").unwrap().id;
+        assert!(view.get_entry(notes).is_some());
+
+        main.set_parent(notes, Some(hr));
+        assert!(refresh_view(&main, &mut view, &access));
+        assert!(view.get_entry(notes).is_none());
+        assert!(paths(&view).is_empty());
+    }
+
+    #[test]
+    fn merge_copies_allowed_writes_and_refuses_the_rest() {
+        let (mut main, _, _) = vault();
+        let access = Access::new(
+            "staff",
+            true,
+            vec![
+                rule("HR", &[], &["hr"]),
+                rule("HR/Public", &["staff"], &["hr"]),
+            ],
+        );
+        let mut view = Manifest::new(ActorId::new());
+        refresh_view(&main, &mut view, &access);
+        let mut client = Manifest::new(ActorId::new());
+        client.apply_update(&view.encode_state_as_update()).unwrap();
+
+        let notes = client.find_entry_by_path("notes.md").unwrap().id;
+        let handbook = client
+            .find_entry_by_path("HR/Public/handbook.md")
+            .unwrap()
+            .id;
+        let public = client.get_entry(handbook).unwrap().parent;
+        client.set_name(notes, "ideas.md");
+        client.set_name(handbook, "leaked.md");
+        let sneaky = client.create_node("new.md", public, NodeKind::Text, None, 0);
+        let fresh = client.create_node("todo.md", None, NodeKind::Text, None, 0);
+        view.apply_update(&client.encode_state_as_update()).unwrap();
+
+        let mut refused = merge_view(&view, &mut main, &access, Some(client.actor()));
+        refused.sort();
+        let mut expected = vec![handbook, sneaky];
+        expected.sort();
+        assert_eq!(refused, expected);
+        assert_eq!(main.get_entry(notes).unwrap().name, "ideas.md");
+        assert!(main.get_entry(fresh).is_some());
+        assert!(main.get_entry(sneaky).is_none());
+        assert_eq!(main.get_entry(handbook).unwrap().name, "handbook.md");
+
+        // The refused rename is undone in the view; the refused file
+        // stays in the view only.
+        refresh_view(&main, &mut view, &access);
+        assert_eq!(view.get_entry(handbook).unwrap().name, "handbook.md");
+        assert!(view.get_entry(sneaky).is_some());
+    }
+
+    #[test]
+    fn merge_restamps_edits_and_takes_only_the_callers_device() {
+        let (mut main, _, _) = vault();
+        let notes = main.find_entry_by_path("notes.md").unwrap().id;
+        let access = Access::new("staff", true, vec![rule("HR", &[], &["hr"])]);
+        let mut view = Manifest::new(ActorId::new());
+        refresh_view(&main, &mut view, &access);
+        let mut client = Manifest::new(ActorId::new());
+        client.apply_update(&view.encode_state_as_update()).unwrap();
+
+        // A new node claiming to predate everything, and a rename
+        // claiming to outrun every later write.
+        let mut fields = client.raw_nodes();
+        let early = NodeId::new().to_string_hyphenated();
+        let mut forged = fields[&notes.to_string_hyphenated()].clone();
+        forged.insert("name".into(), "first.md".into());
+        forged.insert("created_at".into(), yrs::Any::from(0i64));
+        client.put_raw_node(&early, &forged);
+        let renamed = fields.get_mut(&notes.to_string_hyphenated()).unwrap();
+        renamed.insert("name".into(), "ideas.md".into());
+        renamed.insert("mod_lamp".into(), yrs::Any::from(1_000_000i64));
+        renamed.insert("mod_actor".into(), client.actor().to_string_hyphenated().into());
+        client.put_raw_node(&notes.to_string_hyphenated(), renamed);
+
+        let other = ActorId::new();
+        client.publish_device("mine", "linux", "1", 0);
+        let mut impostor = Manifest::new(other);
+        impostor.publish_device("stolen", "linux", "1", 0);
+        client.apply_update(&impostor.encode_state_as_update()).unwrap();
+        view.apply_update(&client.encode_state_as_update()).unwrap();
+
+        let before = main.lamport();
+        assert!(merge_view(&view, &mut main, &access, Some(client.actor())).is_empty());
+        let new = main.get_entry(NodeId::parse_str(&early).unwrap()).unwrap();
+        assert!(new.created_at > before);
+        let edited = main.get_entry(notes).unwrap();
+        assert_eq!(edited.name, "ideas.md");
+        assert!(edited.modify_stamp.unwrap().lamport < Lamport(1_000_000));
+
+        let names = main.device_names();
+        assert_eq!(names.get(&client.actor()).map(String::as_str), Some("mine"));
+        assert!(!names.contains_key(&other));
+    }
+
+    #[tokio::test]
+    async fn rules_roundtrip_through_meta() {
+        let db = MemoryStorage::new();
+        assert!(list_rules(&db).await.unwrap().is_empty());
+        assert!(set_rule(&db, rule("HR", &["a"], &["b"])).await.is_err(), "no secrets yet");
+        for t in ["a", "b", "c"] {
+            tokens::issue(&db, t).await.unwrap();
+        }
+        set_rule(&db, rule("HR", &["a"], &["b"])).await.unwrap();
+        set_rule(&db, rule("Finance", &[], &["c"])).await.unwrap();
+        set_rule(&db, rule("HR", &[], &["b"])).await.unwrap();
+        let rules = list_rules(&db).await.unwrap();
+        assert_eq!(
+            rules,
+            vec![rule("Finance", &[], &["c"]), rule("HR", &[], &["b"])]
+        );
+        assert!(remove_rule(&db, "HR").await.unwrap());
+        assert!(!remove_rule(&db, "HR").await.unwrap());
+        assert_eq!(list_rules(&db).await.unwrap().len(), 1);
+    }
+}
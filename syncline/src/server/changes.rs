@@ -0,0 +1,88 @@
+//! The server's change feed.
+//!
+//! Every stored update stamps its doc with a sequence number (see
+//! [`Storage::changes_since`]). A reconnecting client at minor >= 4
+//! sends the cursor it left off at in a [`MSG_CHANGES`] frame and hears
+//! back only the docs touched since, instead of re-checking the whole
+//! vault. The cursor names the feed it belongs to — a random id kept in
+//! server meta — so a cursor from another server, or from before the
+//! store was wiped, is answered with "check everything" rather than
+//! trusted.
+//!
+//! A client on the feed also stops subscribing to content docs one by
+//! one: every stored content update is published here, and the
+//! connection forwards whichever ones its token may read.
+//!
+//! [`MSG_CHANGES`]: crate::protocol::MSG_CHANGES
+
+use crate::server::storage::Storage;
+use anyhow::Result;
+use tokio::sync::broadcast;
+
+/// Meta key holding the feed id.
+const FEED_ID_KEY: &str = "change_feed_id";
+
+/// Content updates buffered per subscriber. A connection that falls
+/// further behind is sent a fresh change list instead.
+const FEED_CAP: usize = 1024;
+
+/// One stored content update, as broadcast to connections on the feed.
+#[derive(Clone, Debug)]
+pub struct ContentChange {
+    pub doc_id: String,
+    /// The `MSG_UPDATE` frame to forward.
+    pub frame: Vec<u8>,
+    /// Connection the update came from; not echoed back to it.
+    pub conn: uuid::Uuid,
+}
+
+pub struct ChangeFeed {
+    id: String,
+    tx: broadcast::Sender<ContentChange>,
+}
+
+impl ChangeFeed {
+    /// The feed of `db`, naming it on first use.
+    pub async fn load(db: &dyn Storage) -> Result<Self> {
+        let id = match db.get_meta(FEED_ID_KEY).await? {
+            Some(id) => id,
+            None => {
+                let id = uuid::Uuid::new_v4().to_string();
+                db.set_meta(FEED_ID_KEY, &id).await?;
+                id
+            }
+        };
+        Ok(Self {
+            id,
+            tx: broadcast::channel(FEED_CAP).0,
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn publish(&self, change: ContentChange) {
+        let _ = self.tx.send(change);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ContentChange> {
+        self.tx.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::memory_storage::MemoryStorage;
+
+    #[tokio::test]
+    async fn feed_id_is_stable_per_store() {
+        let db = MemoryStorage::new();
+        let first = ChangeFeed::load(&db).await.unwrap();
+        let again = ChangeFeed::load(&db).await.unwrap();
+        assert_eq!(first.id(), again.id());
+        let other = ChangeFeed::load(&MemoryStorage::new()).await.unwrap();
+        assert_ne!(first.id(), other.id());
+    }
+}
@@ -1,15 +1,73 @@
-use anyhow::Result;
+//! SQLite backend for [`Storage`] — the server's default persistence.
+//!
+//! Tables:
+//!
+//! - `updates`        — append-only yrs update log, one row per update
+//! - `blobs`          — content-addressed binary payloads stored inline
+//! - `blob_files`     — hash + size of blobs kept in the external store
+//! - `meta`           — key/value pairs (schema version, actor id, blob dir)
+//! - `updates_v0_bak` — archived rows, created on first [`Storage::archive_docs`]
+//!
+//! ## External blob store
+//!
+//! [`Db::with_blob_dir`] keeps blob bytes out of the database entirely:
+//! payloads go into a sharded [`BlobStore`] directory and SQLite only
+//! records `(hash, size, created_at)` in `blob_files`. On open, any rows
+//! still sitting in the inline `blobs` table are moved out one at a time
+//! (write file → index row → delete inline row, so a crash at any point
+//! leaves the blob readable) and the database is vacuumed. Rows whose
+//! hash is malformed or doesn't match their bytes stay inline and keep
+//! being served from there.
+//!
+//! The directory is recorded in `meta`, and [`Db::new`] reopens it, so
+//! a database whose blobs moved out keeps serving them however it is
+//! opened later (behind S3, or from a connection string). A database
+//! with `blob_files` rows but no recorded directory refuses to open
+//! rather than report those blobs as missing.
+
+use super::storage::{BlobReader, BlobUpload, Changes, Storage, file_blob_reader};
+use crate::protocol::{MAX_BLOB_SIZE, MAX_STREAMED_BLOB_SIZE};
+use crate::v1::blob_store::BlobStore;
+use crate::v1::hash_hex;
+use anyhow::{Result, bail};
+use async_trait::async_trait;
 use sqlx::{Executor, Pool, Row, Sqlite, sqlite::SqlitePool};
-use yrs::updates::decoder::Decode;
-use yrs::{Doc, ReadTxn, StateVector, Transact, Update};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const BLOB_DIR_KEY: &str = "blob_dir";
 
 #[derive(Clone)]
 pub struct Db {
     pool: Pool<Sqlite>,
+    /// External CAS for blob bytes. `None` keeps the legacy behaviour of
+    /// storing payloads inline in the `blobs` table.
+    blob_store: Option<Arc<BlobStore>>,
 }
 
 impl Db {
+    /// Open the database, with blob bytes in the directory it recorded
+    /// if they were ever moved out (see [`Db::with_blob_dir`]).
     pub async fn new(connection_string: &str) -> Result<Self> {
+        let mut db = Self::open(connection_string).await?;
+        match db.get_meta(BLOB_DIR_KEY).await? {
+            Some(dir) => db.blob_store = Some(Arc::new(BlobStore::new(PathBuf::from(dir)))),
+            None => {
+                let (files,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM blob_files")
+                    .fetch_one(&db.pool)
+                    .await?;
+                if files > 0 {
+                    bail!(
+                        "{files} blobs are stored outside the database but it doesn't record \
+                         where; open it with --blob-dir pointing at them"
+                    );
+                }
+            }
+        }
+        Ok(db)
+    }
+
+    async fn open(connection_string: &str) -> Result<Self> {
         let pool = SqlitePool::connect(connection_string).await?;
 
         let mut conn = pool.acquire().await?;
@@ -38,17 +96,129 @@ impl Db {
         )
         .await?;
 
-        Ok(Self { pool })
+        conn.execute(
+            r#"
+            CREATE TABLE IF NOT EXISTS blob_files (
+                hash TEXT PRIMARY KEY,
+                size INTEGER NOT NULL,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+            "#,
+        )
+        .await?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS meta (key TEXT PRIMARY KEY, value TEXT NOT NULL)",
+        )
+        .await?;
+
+        Ok(Self {
+            pool,
+            blob_store: None,
+        })
+    }
+
+    /// Open the database with blob bytes stored under `blob_dir` instead
+    /// of inside SQLite, and record it for later opens. Existing inline
+    /// blobs are migrated out before this returns.
+    pub async fn with_blob_dir(connection_string: &str, blob_dir: PathBuf) -> Result<Self> {
+        let mut db = Self::open(connection_string).await?;
+        let blob_dir = std::path::absolute(&blob_dir)?;
+        let recorded = blob_dir.to_string_lossy();
+        match db.get_meta(BLOB_DIR_KEY).await? {
+            Some(dir) if dir == recorded => {}
+            previous => {
+                if let Some(dir) = previous {
+                    tracing::warn!("blob directory moved from {} to {}", dir, recorded);
+                }
+                db.set_meta(BLOB_DIR_KEY, &recorded).await?;
+            }
+        }
+        db.blob_store = Some(Arc::new(BlobStore::new(blob_dir)));
+        let moved = db.externalize_inline_blobs().await?;
+        if moved > 0 {
+            tracing::info!("moved {} inline blobs out of the database", moved);
+        }
+        Ok(db)
+    }
+
+    /// Move every inline `blobs` row with a valid, matching hash into the
+    /// external store. Returns the number of rows moved. Rows are loaded
+    /// one at a time so memory stays bounded by the largest single blob.
+    async fn externalize_inline_blobs(&self) -> Result<usize> {
+        let Some(store) = self.blob_store.clone() else {
+            return Ok(0);
+        };
+        let hashes: Vec<String> = sqlx::query("SELECT hash FROM blobs ORDER BY hash ASC")
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|r| r.get::<String, _>(0))
+            .collect();
+
+        let mut moved = 0usize;
+        for hash in hashes {
+            let Some(row) = sqlx::query("SELECT data FROM blobs WHERE hash = ?")
+                .bind(&hash)
+                .fetch_optional(&self.pool)
+                .await?
+            else {
+                continue;
+            };
+            let data: Vec<u8> = row.get(0);
+            if hash_hex(&data) != hash {
+                tracing::warn!("keeping blob {} inline: stored bytes don't match its hash", hash);
+                continue;
+            }
+            let size = data.len() as i64;
+            let store = store.clone();
+            let h = hash.clone();
+            tokio::task::spawn_blocking(move || store.insert_verified(&h, &data)).await??;
+
+            let mut tx = self.pool.begin().await?;
+            sqlx::query("INSERT OR IGNORE INTO blob_files (hash, size) VALUES (?, ?)")
+                .bind(&hash)
+                .bind(size)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("DELETE FROM blobs WHERE hash = ?")
+                .bind(&hash)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            moved += 1;
+        }
+
+        if moved > 0 {
+            // Give the freed pages back to the filesystem — shrinking the
+            // database file is the whole point of moving blobs out.
+            sqlx::query("VACUUM").execute(&self.pool).await?;
+        }
+        Ok(moved)
     }
 
-    /// Raw connection pool — used by server-side migration code that
-    /// needs to run ad-hoc DDL / schema queries the typed helpers here
-    /// don't expose.
+    async fn has_external_blob(&self, hash: &str) -> Result<bool> {
+        if self.blob_store.is_none() {
+            return Ok(false);
+        }
+        let row: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM blob_files WHERE hash = ?")
+            .bind(hash)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.0 > 0)
+    }
+
+    /// Raw connection pool — used by tests that need to inspect tables
+    /// the [`Storage`] trait deliberately doesn't expose.
+    #[cfg(test)]
     pub(crate) fn pool(&self) -> &Pool<Sqlite> {
         &self.pool
     }
+}
 
-    pub async fn save_update(&self, doc_id: &str, update: &[u8]) -> Result<()> {
+#[async_trait]
+impl Storage for Db {
+    async fn save_update(&self, doc_id: &str, update: &[u8]) -> Result<()> {
         sqlx::query("INSERT INTO updates (doc_id, update_data) VALUES (?, ?)")
             .bind(doc_id)
             .bind(update)
@@ -57,7 +227,7 @@ impl Db {
         Ok(())
     }
 
-    pub async fn load_doc_updates(&self, doc_id: &str) -> Result<Vec<Vec<u8>>> {
+    async fn load_doc_updates(&self, doc_id: &str) -> Result<Vec<Vec<u8>>> {
         let rows = sqlx::query("SELECT update_data FROM updates WHERE doc_id = ? ORDER BY id ASC")
             .bind(doc_id)
             .fetch_all(&self.pool)
@@ -71,7 +241,14 @@ impl Db {
         Ok(updates)
     }
 
-    pub async fn count_docs(&self) -> Result<i64> {
+    async fn list_doc_ids(&self) -> Result<Vec<String>> {
+        let rows = sqlx::query("SELECT DISTINCT doc_id FROM updates ORDER BY doc_id ASC")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows.into_iter().map(|r| r.get::<String, _>(0)).collect())
+    }
+
+    async fn count_docs(&self) -> Result<i64> {
         let row: (i64,) = sqlx::query_as(
             "SELECT COUNT(DISTINCT doc_id) FROM updates WHERE doc_id != '__index__'",
         )
@@ -80,82 +257,91 @@ impl Db {
         Ok(row.0)
     }
 
-    /// Encode the current state vector for a doc as v1 bytes.
-    ///
-    /// Used by the server to advertise what it has so the client can
-    /// reply with the inverse diff (its updates the server is missing).
-    /// Without this the sync handshake is one-way: a client whose subdoc
-    /// has updates we never observed has no way to push them back after
-    /// reconnect.
-    pub async fn get_doc_state_vector(&self, doc_id: &str) -> Result<Vec<u8>> {
-        use yrs::Doc;
-        use yrs::ReadTxn;
-        use yrs::Transact;
-        use yrs::Update;
-        use yrs::updates::decoder::Decode;
-        use yrs::updates::encoder::Encode;
-
-        let all_updates = self.load_doc_updates(doc_id).await?;
-        tokio::task::spawn_blocking(move || {
-            let doc = Doc::new();
-            if !all_updates.is_empty() {
-                let mut txn = doc.transact_mut();
-                for update_data in all_updates {
-                    if let Ok(u) = Update::decode_v1(&update_data) {
-                        txn.apply_update(u);
-                    }
-                }
-            }
-            let txn = doc.transact();
-            Ok(txn.state_vector().encode_v1())
-        })
-        .await
-        .unwrap()
-    }
-
-    pub async fn get_all_updates_since(
-        &self,
-        doc_id: &str,
-        since_sv: &StateVector,
-    ) -> Result<Vec<u8>> {
-        // In a real implementation, we might optimize this query based on vector clock.
-        // For this PoC, we load all updates, merge them into a single update relative to the SV.
-        // Or simply return all raw updates and let the client merge.
-        // The most robust way for the server:
-        // 1. Load full history for doc into a temporary Yrs doc.
-        // 2. Compute the difference update based on `since_sv`.
-
-        let all_updates = self.load_doc_updates(doc_id).await?;
-        let since_sv = since_sv.clone();
-
-        tokio::task::spawn_blocking(move || {
-            if all_updates.is_empty() {
-                let doc = Doc::new();
-                let txn = doc.transact();
-                return Ok(txn.encode_state_as_update_v1(&since_sv));
-            }
+    async fn save_snapshot(&self, doc_id: &str, snapshot: &[u8]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM updates WHERE doc_id = ?")
+            .bind(doc_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("INSERT INTO updates (doc_id, update_data) VALUES (?, ?)")
+            .bind(doc_id)
+            .bind(snapshot)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
 
-            let doc = Doc::new();
-            {
-                let mut txn = doc.transact_mut();
-                for update_data in all_updates {
-                    if let Ok(u) = Update::decode_v1(&update_data) {
-                        txn.apply_update(u);
-                    }
-                }
+    async fn archive_docs(&self, doc_ids: &[String]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS updates_v0_bak ( \
+                 id INTEGER PRIMARY KEY AUTOINCREMENT, \
+                 doc_id TEXT NOT NULL, \
+                 update_data BLOB NOT NULL \
+             )",
+        )
+        .execute(&mut *tx)
+        .await?;
+        for doc_id in doc_ids {
+            sqlx::query(
+                "INSERT INTO updates_v0_bak (doc_id, update_data) \
+                 SELECT doc_id, update_data FROM updates WHERE doc_id = ? ORDER BY id ASC",
+            )
+            .bind(doc_id)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query("DELETE FROM updates WHERE doc_id = ?")
+                .bind(doc_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
 
-                let update_to_sync = txn.encode_state_as_update_v1(&since_sv);
-                Ok(update_to_sync)
-            }
+    /// The `updates` rowid doubles as the change sequence: AUTOINCREMENT
+    /// never reuses a number, and `sqlite_sequence` remembers the highest
+    /// one even after those rows are snapshotted or archived away.
+    async fn changes_since(&self, since: u64) -> Result<Changes> {
+        let mut tx = self.pool.begin().await?;
+        let head: (i64,) = sqlx::query_as(
+            "SELECT COALESCE((SELECT seq FROM sqlite_sequence WHERE name = 'updates'), 0)",
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        let rows = sqlx::query(
+            "SELECT DISTINCT doc_id FROM updates WHERE id > ? AND id <= ? ORDER BY doc_id ASC",
+        )
+        .bind(since.min(i64::MAX as u64) as i64)
+        .bind(head.0)
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(Changes {
+            head: head.0 as u64,
+            docs: rows.into_iter().map(|r| r.get::<String, _>(0)).collect(),
         })
-        .await
-        .unwrap()
     }
 
-    /// Store a binary blob by its SHA256 hash. Content-addressable: if the hash
-    /// already exists the insert is silently ignored (deduplication).
-    pub async fn save_blob(&self, hash: &str, data: &[u8]) -> Result<()> {
+    async fn save_blob(&self, hash: &str, data: &[u8]) -> Result<()> {
         let size = data.len() as i64;
+        if let Some(store) = self.blob_store.clone() {
+            if self.has_blob(hash).await? {
+                return Ok(());
+            }
+            // File first, index row second: a crash in between leaves an
+            // orphan file that the next upload of the same hash adopts.
+            let h = hash.to_string();
+            let bytes = data.to_vec();
+            tokio::task::spawn_blocking(move || store.insert_verified(&h, &bytes)).await??;
+            sqlx::query("INSERT OR IGNORE INTO blob_files (hash, size) VALUES (?, ?)")
+                .bind(hash)
+                .bind(size)
+                .execute(&self.pool)
+                .await?;
+            return Ok(());
+        }
         sqlx::query("INSERT OR IGNORE INTO blobs (hash, data, size) VALUES (?, ?, ?)")
             .bind(hash)
             .bind(data)
@@ -165,8 +351,13 @@ impl Db {
         Ok(())
     }
 
-    /// Load a binary blob by its SHA256 hash. Returns None if not found.
-    pub async fn load_blob(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+    async fn load_blob(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        if self.has_external_blob(hash).await? {
+            let store = self.blob_store.clone().expect("checked by has_external_blob");
+            let h = hash.to_string();
+            let data = tokio::task::spawn_blocking(move || store.read(&h)).await??;
+            return Ok(Some(data));
+        }
         let row = sqlx::query("SELECT data FROM blobs WHERE hash = ?")
             .bind(hash)
             .fetch_optional(&self.pool)
@@ -174,8 +365,56 @@ impl Db {
         Ok(row.map(|r| r.get(0)))
     }
 
-    /// Check whether a blob with the given hash exists.
-    pub async fn has_blob(&self, hash: &str) -> Result<bool> {
+    async fn open_blob(&self, hash: &str) -> Result<Option<BlobReader>> {
+        if self.has_external_blob(hash).await? {
+            let store = self.blob_store.as_ref().expect("checked by has_external_blob");
+            return file_blob_reader(store.path_for(hash)).await.map(Some);
+        }
+        Ok(self.load_blob(hash).await?.map(|data| BlobReader {
+            size: data.len() as u64,
+            chunks: Box::pin(futures_util::stream::once(async move { Ok(data.into()) })),
+        }))
+    }
+
+    fn max_upload_size(&self) -> u64 {
+        match self.blob_store {
+            Some(_) => MAX_STREAMED_BLOB_SIZE,
+            None => MAX_BLOB_SIZE as u64,
+        }
+    }
+
+    async fn begin_blob(&self, hash: &str, total: u64) -> Result<BlobUpload> {
+        if total > self.max_upload_size() {
+            bail!("blob {hash} is {total} bytes, over the upload limit");
+        }
+        match &self.blob_store {
+            Some(store) => Ok(BlobUpload::staged(hash, total, store.writer(hash)?)),
+            None => Ok(BlobUpload::buffered(hash, total)),
+        }
+    }
+
+    async fn finish_blob(&self, upload: BlobUpload) -> Result<()> {
+        let hash = upload.hash().to_string();
+        let size = upload.total() as i64;
+        if self.blob_store.is_none() {
+            let data = upload.into_bytes()?;
+            return self.save_blob(&hash, &data).await;
+        }
+        // Same ordering as `save_blob`: file first, index row second.
+        let writer = upload.into_writer()?;
+        tokio::task::spawn_blocking(move || writer.finish()).await??;
+        sqlx::query("INSERT OR IGNORE INTO blob_files (hash, size) VALUES (?, ?)")
+            .bind(&hash)
+            .bind(size)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn has_blob(&self, hash: &str) -> Result<bool> {
+        if self.has_external_blob(hash).await? {
+            return Ok(true);
+        }
         let row: (i64,) =
             sqlx::query_as("SELECT COUNT(*) FROM blobs WHERE hash = ?")
                 .bind(hash)
@@ -183,12 +422,39 @@ impl Db {
                 .await?;
         Ok(row.0 > 0)
     }
+
+    async fn get_meta(&self, key: &str) -> Result<Option<String>> {
+        let row = sqlx::query("SELECT value FROM meta WHERE key = ?")
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|r| r.get::<String, _>(0)))
+    }
+
+    async fn set_meta(&self, key: &str, value: &str) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO meta (key, value) VALUES (?, ?) \
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        )
+        .bind(key)
+        .bind(value)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use yrs::{GetString, Text};
+    use crate::server::storage::conformance;
+    use yrs::updates::decoder::Decode;
+    use yrs::{Doc, GetString, ReadTxn, StateVector, Text, Transact, Update};
+
+    #[tokio::test]
+    async fn sqlite_backend_conformance() {
+        conformance::run_all(|| async { Db::new("sqlite::memory:").await.unwrap() }).await;
+    }
 
     #[tokio::test]
     async fn test_db_operations() {
@@ -305,4 +571,102 @@ mod tests {
         assert!(db.load_blob("nonexistent").await.unwrap().is_none());
         assert!(!db.has_blob("nonexistent").await.unwrap());
     }
+
+    async fn count_rows(db: &Db, table: &str) -> i64 {
+        let row: (i64,) = sqlx::query_as(&format!("SELECT COUNT(*) FROM {}", table))
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+        row.0
+    }
+
+    #[tokio::test]
+    async fn external_blob_store_conformance() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let counter = std::sync::atomic::AtomicUsize::new(0);
+        conformance::run_all(|| {
+            let n = counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let dir = tmp.path().join(n.to_string());
+            async move { Db::with_blob_dir("sqlite::memory:", dir).await.unwrap() }
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn external_blob_bytes_stay_out_of_sqlite() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let db = Db::with_blob_dir("sqlite::memory:", tmp.path().join("blobs"))
+            .await
+            .unwrap();
+        let data = b"attachment bytes".to_vec();
+        let hash = hash_hex(&data);
+        db.save_blob(&hash, &data).await.unwrap();
+
+        assert_eq!(count_rows(&db, "blobs").await, 0);
+        assert_eq!(count_rows(&db, "blob_files").await, 1);
+        assert!(BlobStore::new(tmp.path().join("blobs")).has(&hash));
+        assert_eq!(db.load_blob(&hash).await.unwrap().unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn external_store_rejects_hash_mismatch() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let db = Db::with_blob_dir("sqlite::memory:", tmp.path().join("blobs"))
+            .await
+            .unwrap();
+        let wrong = "a".repeat(64);
+        assert!(db.save_blob(&wrong, b"payload").await.is_err());
+        assert!(!db.has_blob(&wrong).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn opening_with_blob_dir_migrates_inline_rows() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let conn = format!("sqlite://{}?mode=rwc", tmp.path().join("s.db").display());
+        let good = b"legacy inline blob".to_vec();
+        let good_hash = hash_hex(&good);
+        {
+            let db = Db::new(&conn).await.unwrap();
+            db.save_blob(&good_hash, &good).await.unwrap();
+            // Pre-hash-verification uploads could carry any key.
+            db.save_blob("legacy-key", b"odd").await.unwrap();
+            db.pool().close().await;
+        }
+
+        let db = Db::with_blob_dir(&conn, tmp.path().join("blobs")).await.unwrap();
+        assert_eq!(count_rows(&db, "blobs").await, 1, "malformed row stays inline");
+        assert_eq!(count_rows(&db, "blob_files").await, 1);
+        assert_eq!(db.load_blob(&good_hash).await.unwrap().unwrap(), good);
+        assert_eq!(db.load_blob("legacy-key").await.unwrap().unwrap(), b"odd");
+
+        // Reopening is a no-op.
+        assert_eq!(db.externalize_inline_blobs().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn plain_open_reuses_the_recorded_blob_dir() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let conn = format!("sqlite://{}?mode=rwc", tmp.path().join("s.db").display());
+        let data = b"attachment bytes".to_vec();
+        let hash = hash_hex(&data);
+        {
+            let db = Db::with_blob_dir(&conn, tmp.path().join("blobs")).await.unwrap();
+            db.save_blob(&hash, &data).await.unwrap();
+            db.pool().close().await;
+        }
+
+        let db = Db::new(&conn).await.unwrap();
+        assert_eq!(db.load_blob(&hash).await.unwrap(), Some(data));
+
+        // Without the record the blobs would read as missing, so the
+        // database refuses to open instead.
+        sqlx::query("DELETE FROM meta WHERE key = ?")
+            .bind(BLOB_DIR_KEY)
+            .execute(db.pool())
+            .await
+            .unwrap();
+        db.pool().close().await;
+        assert!(Db::new(&conn).await.is_err());
+        assert!(Db::with_blob_dir(&conn, tmp.path().join("blobs")).await.is_ok());
+    }
 }
@@ -0,0 +1,408 @@
+//! Server-side device registry and revocation.
+//!
+//! Clients at protocol minor >= 2 name their actor in a [`MSG_HELLO`]
+//! frame right after the version handshake; any other frame before it
+//! closes the connection. The server records every actor it hears
+//! from, with the account token it connected under, so an admin can
+//! find a lost laptop and revoke it:
+//!
+//! - a revoked actor's `MSG_HELLO` is answered with a policy-violation
+//!   close frame carrying [`DEVICE_REVOKED_REASON`];
+//! - manifest updates that would add a stamp by the actor newer than
+//!   its **revocation point** — the highest Lamport it had written when
+//!   it was revoked — are dropped whole, whoever relays them. A stale
+//!   replica resurfacing months later therefore can't resurrect deleted
+//!   files or undo renames, while its earlier history stays valid.
+//!
+//! Revoking a device leaves its token alone: a token may be shared by a
+//! whole team, and a client picks its own anyway. An admin can revoke a
+//! token on its own with [`revoke_token`], which also shuts out clients
+//! too old to send a hello, but only a token with a secret
+//! ([`tokens`](crate::server::tokens)): the refusal applies to
+//! connections that presented it, so nobody can dodge it by picking
+//! another label.
+//!
+//! The lists live in server meta as JSON: `devices` is written only by
+//! the server, `revoked_devices` and `revoked_tokens` only by the admin
+//! functions below, so
+//! an admin command run against the store of a live server never races
+//! the server's own bookkeeping. The server re-reads the revocations on
+//! every handshake and hello, so a revoked device is turned away on its
+//! next connection without a restart. The per-frame check of manifest
+//! updates uses that copy for up to [`REVOCATIONS_MAX_AGE`].
+//!
+//! [`MSG_HELLO`]: crate::protocol::MSG_HELLO
+//! [`DEVICE_REVOKED_REASON`]: crate::protocol::DEVICE_REVOKED_REASON
+
+use crate::server::server::hydrate_manifest;
+use crate::server::storage::Storage;
+use crate::server::tokens;
+use crate::v1::ids::{ActorId, Lamport};
+use crate::v1::manifest::Manifest;
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex as AsyncMutex;
+
+const DEVICES_KEY: &str = "devices";
+const REVOKED_KEY: &str = "revoked_devices";
+const REVOKED_TOKENS_KEY: &str = "revoked_tokens";
+
+/// How long manifest checks trust the server's copy of the revocations.
+/// Admin commands write them straight to the store, possibly from
+/// another process, so the copy can't be invalidated from there.
+pub(crate) const REVOCATIONS_MAX_AGE: Duration = Duration::from_secs(2);
+
+/// An actor the server has heard from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SeenDevice {
+    pub actor: ActorId,
+    /// Token of the actor's latest connection.
+    pub token: String,
+    /// Unix seconds.
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+/// A revoked actor.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Revocation {
+    pub actor: ActorId,
+    /// Highest Lamport the actor had stamped when it was revoked.
+    pub revoked_at: Lamport,
+    /// Unix seconds.
+    pub when: u64,
+}
+
+/// One row of [`list_devices`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerDevice {
+    pub actor: ActorId,
+    pub seen: Option<SeenDevice>,
+    pub revoked: Option<Revocation>,
+}
+
+/// Records connecting actors for a running server.
+pub(crate) struct Registry {
+    db: Arc<dyn Storage>,
+    write: AsyncMutex<()>,
+    /// The revocations and when they were read.
+    revoked: Mutex<Option<(Instant, Arc<Vec<Revocation>>)>>,
+}
+
+impl Registry {
+    pub(crate) fn new(db: Arc<dyn Storage>) -> Self {
+        Self {
+            db,
+            write: AsyncMutex::new(()),
+            revoked: Mutex::new(None),
+        }
+    }
+
+    /// The current revocations. Re-read from the store if `fresh` or the
+    /// cached copy has expired.
+    async fn revocations(&self, fresh: bool) -> Arc<Vec<Revocation>> {
+        if !fresh
+            && let Some((read_at, revoked)) = &*self.revoked.lock().unwrap()
+            && read_at.elapsed() < REVOCATIONS_MAX_AGE
+        {
+            return revoked.clone();
+        }
+        let revoked = match load_revocations(self.db.as_ref()).await {
+            Ok(r) => Arc::new(r),
+            Err(e) => {
+                tracing::warn!("load revoked devices: {}", e);
+                return Arc::default();
+            }
+        };
+        *self.revoked.lock().unwrap() = Some((Instant::now(), revoked.clone()));
+        revoked
+    }
+
+    /// The revocation that shuts out `actor`, if any.
+    pub(crate) async fn refused(&self, actor: ActorId) -> Option<Revocation> {
+        self.revocations(true)
+            .await
+            .iter()
+            .find(|r| r.actor == actor)
+            .cloned()
+    }
+
+    /// Whether an admin revoked `token`. Only meaningful for a
+    /// connection that presented the token's secret.
+    pub(crate) async fn token_refused(&self, token: &str) -> bool {
+        match revoked_tokens(self.db.as_ref()).await {
+            Ok(revoked) => revoked.iter().any(|t| t == token),
+            Err(e) => {
+                tracing::warn!("load revoked tokens: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Note that `actor` connected with `token` just now.
+    pub(crate) async fn seen(&self, actor: ActorId, token: &str) {
+        let _guard = self.write.lock().await;
+        let result = async {
+            let mut devices: Vec<SeenDevice> = load_json(self.db.as_ref(), DEVICES_KEY).await?;
+            let now = unix_now();
+            match devices.iter_mut().find(|d| d.actor == actor) {
+                Some(d) => {
+                    d.token = token.to_string();
+                    d.last_seen = now;
+                }
+                None => devices.push(SeenDevice {
+                    actor,
+                    token: token.to_string(),
+                    first_seen: now,
+                    last_seen: now,
+                }),
+            }
+            store_json(self.db.as_ref(), DEVICES_KEY, &devices).await
+        }
+        .await;
+        if let Err(e) = result {
+            tracing::warn!("record device {}: {}", actor, e);
+        }
+    }
+
+    /// The revoked actor, if any, whose stamps past its revocation point
+    /// applying `update` to `manifest` would introduce.
+    pub(crate) async fn offending_actor(&self, manifest: &Manifest, update: &[u8]) -> Option<ActorId> {
+        let revoked = self.revocations(false).await;
+        if revoked.is_empty() {
+            return None;
+        }
+        offending_actor(manifest, update, &revoked)
+    }
+}
+
+fn offending_actor(manifest: &Manifest, update: &[u8], revoked: &[Revocation]) -> Option<ActorId> {
+    // Every stamp write carries its actor's id as a plain string, so an
+    // update that never mentions a revoked actor can't add one of its
+    // stamps. Only the rare update that does pays for a trial merge
+    // into a copy of the manifest.
+    let named: Vec<&Revocation> = revoked
+        .iter()
+        .filter(|r| mentions(update, r.actor.to_string_hyphenated().as_bytes()))
+        .collect();
+    if named.is_empty() {
+        return None;
+    }
+    let mut scratch = Manifest::from_update(
+        manifest.actor(),
+        manifest.lamport(),
+        &manifest.encode_state_as_update(),
+    )
+    .ok()?;
+    // A malformed update is rejected by the normal apply path.
+    scratch.apply_update(update).ok()?;
+    named
+        .into_iter()
+        .find(|r| scratch.max_stamp_by(r.actor) > Some(r.revoked_at))
+        .map(|r| r.actor)
+}
+
+fn mentions(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+/// Every actor the server has seen or revoked, ordered by actor id.
+pub async fn list_devices(db: &dyn Storage) -> Result<Vec<ServerDevice>> {
+    let seen: Vec<SeenDevice> = load_json(db, DEVICES_KEY).await?;
+    let revoked = load_revocations(db).await?;
+    let mut out: Vec<ServerDevice> = seen
+        .into_iter()
+        .map(|s| ServerDevice {
+            actor: s.actor,
+            revoked: revoked.iter().find(|r| r.actor == s.actor).cloned(),
+            seen: Some(s),
+        })
+        .collect();
+    for r in revoked {
+        if !out.iter().any(|d| d.actor == r.actor) {
+            out.push(ServerDevice {
+                actor: r.actor,
+                seen: None,
+                revoked: Some(r),
+            });
+        }
+    }
+    out.sort_by_key(|d| d.actor);
+    Ok(out)
+}
+
+/// Revoke `actor`: from now on its hellos and any manifest update
+/// carrying its newer stamps are refused. Revoking an already revoked
+/// actor keeps the original revocation point.
+pub async fn revoke(db: &dyn Storage, actor: ActorId) -> Result<Revocation> {
+    let mut revoked = load_revocations(db).await?;
+    if let Some(r) = revoked.iter().find(|r| r.actor == actor) {
+        return Ok(r.clone());
+    }
+    let manifest = hydrate_manifest(db, ActorId::new()).await?;
+    let revocation = Revocation {
+        actor,
+        revoked_at: manifest.max_stamp_by(actor).unwrap_or(Lamport::ZERO),
+        when: unix_now(),
+    };
+    revoked.push(revocation.clone());
+    store_json(db, REVOKED_KEY, &revoked).await?;
+    Ok(revocation)
+}
+
+/// Lift `actor`'s revocation. Returns `false` if it wasn't revoked.
+pub async fn reinstate(db: &dyn Storage, actor: ActorId) -> Result<bool> {
+    let mut revoked = load_revocations(db).await?;
+    let before = revoked.len();
+    revoked.retain(|r| r.actor != actor);
+    if revoked.len() == before {
+        return Ok(false);
+    }
+    store_json(db, REVOKED_KEY, &revoked).await?;
+    Ok(true)
+}
+
+/// Revoke `token`: from now on every connection presenting it is
+/// closed like a revoked device's. The token must have a secret.
+pub async fn revoke_token(db: &dyn Storage, token: &str) -> Result<()> {
+    if !tokens::list(db).await?.iter().any(|t| t == token) {
+        bail!(
+            "token {token:?} has no secret, so anyone can connect under another label; \
+             issue one with `syncline server-tokens --issue {token}` first"
+        );
+    }
+    let mut revoked = revoked_tokens(db).await?;
+    if !revoked.iter().any(|t| t == token) {
+        revoked.push(token.to_string());
+        store_json(db, REVOKED_TOKENS_KEY, &revoked).await?;
+    }
+    Ok(())
+}
+
+/// Lift `token`'s revocation. Returns `false` if it wasn't revoked.
+pub async fn reinstate_token(db: &dyn Storage, token: &str) -> Result<bool> {
+    let mut revoked = revoked_tokens(db).await?;
+    let before = revoked.len();
+    revoked.retain(|t| t != token);
+    if revoked.len() == before {
+        return Ok(false);
+    }
+    store_json(db, REVOKED_TOKENS_KEY, &revoked).await?;
+    Ok(true)
+}
+
+/// Every revoked token, in the order they were revoked.
+pub async fn revoked_tokens(db: &dyn Storage) -> Result<Vec<String>> {
+    load_json(db, REVOKED_TOKENS_KEY).await
+}
+
+async fn load_revocations(db: &dyn Storage) -> Result<Vec<Revocation>> {
+    load_json(db, REVOKED_KEY).await
+}
+
+async fn load_json<T: serde::de::DeserializeOwned + Default>(db: &dyn Storage, key: &str) -> Result<T> {
+    match db.get_meta(key).await? {
+        Some(json) => serde_json::from_str(&json).with_context(|| format!("parsing meta {key}")),
+        None => Ok(T::default()),
+    }
+}
+
+async fn store_json<T: Serialize>(db: &dyn Storage, key: &str, value: &T) -> Result<()> {
+    db.set_meta(key, &serde_json::to_string(value)?).await
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::MANIFEST_DOC_ID;
+    use crate::server::memory_storage::MemoryStorage;
+    use crate::v1::manifest::NodeKind;
+    use yrs::{ReadTxn, Transact};
+
+    #[tokio::test]
+    async fn revoke_records_the_point_and_spares_the_token() {
+        let db: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let mut laptop = Manifest::new(ActorId::new());
+        let id = laptop.create_node("a.md", None, NodeKind::Text, None, 0);
+        laptop.record_modify(id);
+        db.save_update(MANIFEST_DOC_ID, &laptop.encode_state_as_update())
+            .await
+            .unwrap();
+        let registry = Registry::new(db.clone());
+        registry.seen(laptop.actor(), "laptop-token").await;
+
+        let r = revoke(db.as_ref(), laptop.actor()).await.unwrap();
+        assert_eq!(r.revoked_at, Lamport(2));
+
+        assert!(registry.refused(laptop.actor()).await.is_some());
+        assert!(registry.refused(ActorId::new()).await.is_none());
+        // Other devices on the same token keep connecting.
+        assert!(!registry.token_refused("laptop-token").await);
+
+        let listed = list_devices(db.as_ref()).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert!(listed[0].seen.is_some() && listed[0].revoked.is_some());
+
+        assert!(reinstate(db.as_ref(), laptop.actor()).await.unwrap());
+        assert!(!reinstate(db.as_ref(), laptop.actor()).await.unwrap());
+        assert!(registry.refused(laptop.actor()).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn only_tokens_with_a_secret_can_be_revoked() {
+        let db: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let registry = Registry::new(db.clone());
+        assert!(revoke_token(db.as_ref(), "team").await.is_err());
+
+        tokens::issue(db.as_ref(), "team").await.unwrap();
+        revoke_token(db.as_ref(), "team").await.unwrap();
+        revoke_token(db.as_ref(), "team").await.unwrap();
+        assert_eq!(revoked_tokens(db.as_ref()).await.unwrap(), vec!["team"]);
+        assert!(registry.token_refused("team").await);
+        assert!(!registry.token_refused("other").await);
+
+        assert!(reinstate_token(db.as_ref(), "team").await.unwrap());
+        assert!(!reinstate_token(db.as_ref(), "team").await.unwrap());
+        assert!(!registry.token_refused("team").await);
+    }
+
+    #[test]
+    fn only_stamps_past_the_revocation_point_offend() {
+        let mut laptop = Manifest::new(ActorId::new());
+        let id = laptop.create_node("a.md", None, NodeKind::Text, None, 0);
+        let mut server = Manifest::new(ActorId::new());
+        server.apply_update(&laptop.encode_state_as_update()).unwrap();
+        let revoked = [Revocation {
+            actor: laptop.actor(),
+            revoked_at: Lamport(1),
+            when: 0,
+        }];
+
+        // Replaying history the server already has is fine.
+        assert_eq!(offending_actor(&server, &laptop.encode_state_as_update(), &revoked), None);
+
+        // Offline work after the revocation point is not.
+        let before = laptop.doc().transact().state_vector();
+        laptop.delete(id);
+        let diff = laptop.doc().transact().encode_state_as_update_v1(&before);
+        assert_eq!(offending_actor(&server, &diff, &revoked), Some(laptop.actor()));
+
+        // Nor is another peer's write, untouched.
+        let mut phone = Manifest::new(ActorId::new());
+        phone.apply_update(&server.encode_state_as_update()).unwrap();
+        let before = phone.doc().transact().state_vector();
+        phone.set_name(id, "b.md");
+        let diff = phone.doc().transact().encode_state_as_update_v1(&before);
+        assert_eq!(offending_actor(&server, &diff, &revoked), None);
+        assert!(!mentions(&diff, laptop.actor().to_string_hyphenated().as_bytes()));
+    }
+}
@@ -0,0 +1,98 @@
+//! Cached content digests for batched subscribes.
+//!
+//! A [`MSG_SYNC_BATCH`] frame lists each doc with the client's
+//! [`content_digest`]; the server answers only docs whose digest
+//! differs from its own. Computing a digest replays the doc's whole
+//! history, so the server remembers each one until the next update to
+//! that doc is stored. A reconnecting client whose vault hasn't changed
+//! then costs a map lookup per doc.
+//!
+//! A digest computed while an update to the same doc is being stored
+//! may already be stale by the time it's ready; [`Digests::get`] only
+//! caches it if no doc was invalidated meanwhile.
+//!
+//! [`MSG_SYNC_BATCH`]: crate::protocol::MSG_SYNC_BATCH
+//! [`content_digest`]: crate::v1::sync::content_digest
+
+use crate::server::storage::Storage;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Default)]
+pub struct Digests {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    by_doc: HashMap<String, [u8; 32]>,
+    /// Bumped by every [`Digests::invalidate`].
+    epoch: u64,
+}
+
+impl Digests {
+    /// `doc_id`'s digest, computed from `db` unless cached.
+    pub async fn get(&self, db: &dyn Storage, doc_id: &str) -> Result<[u8; 32]> {
+        let epoch = {
+            let inner = self.inner.lock().unwrap();
+            if let Some(digest) = inner.by_doc.get(doc_id) {
+                return Ok(*digest);
+            }
+            inner.epoch
+        };
+        let digest = db.get_doc_digest(doc_id).await?;
+        let mut inner = self.inner.lock().unwrap();
+        if inner.epoch == epoch {
+            inner.by_doc.insert(doc_id.to_string(), digest);
+        }
+        Ok(digest)
+    }
+
+    /// Forget `doc_id`'s digest; call after storing an update to it.
+    pub fn invalidate(&self, doc_id: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.by_doc.remove(doc_id);
+        inner.epoch += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::memory_storage::MemoryStorage;
+    use yrs::{Doc, ReadTxn, Text, Transact};
+
+    fn text_update(doc: &Doc, insert: &str) -> Vec<u8> {
+        let text = doc.get_or_insert_text("text");
+        let mut txn = doc.transact_mut();
+        let len = text.len(&txn);
+        text.insert(&mut txn, len, insert);
+        drop(txn);
+        doc.transact()
+            .encode_state_as_update_v1(&yrs::StateVector::default())
+    }
+
+    #[tokio::test]
+    async fn caches_until_invalidated() {
+        let db = MemoryStorage::new();
+        let digests = Digests::default();
+        let doc = Doc::new();
+        db.save_update("content:a", &text_update(&doc, "one"))
+            .await
+            .unwrap();
+        let first = digests.get(&db, "content:a").await.unwrap();
+        assert_eq!(first, crate::v1::sync::content_digest(&doc.transact()));
+
+        // Stored behind the cache's back: still the old digest.
+        db.save_update("content:a", &text_update(&doc, " two"))
+            .await
+            .unwrap();
+        assert_eq!(digests.get(&db, "content:a").await.unwrap(), first);
+
+        digests.invalidate("content:a");
+        let second = digests.get(&db, "content:a").await.unwrap();
+        assert_ne!(second, first);
+        assert_eq!(second, crate::v1::sync::content_digest(&doc.transact()));
+    }
+}
@@ -0,0 +1,530 @@
+//! Plain-filesystem [`Storage`] backend.
+//!
+//! Layout under the root directory:
+//!
+//! ```text
+//! <root>/
+//!   meta.json                  key/value meta, rewritten atomically
+//!   changes.log                change sequence stamps, see below
+//!   updates/<hex doc_id>.log   append-only update log per doc
+//!   archive/<hex doc_id>.log   logs moved aside by `archive_docs`
+//!   blobs/ab/cd/abcd…          content-addressed blobs (see `BlobStore`)
+//! ```
+//!
+//! Doc ids are hex-encoded in file names so arbitrary ids (`content:…`,
+//! `__manifest__`, v0 UUIDs) are safe on every filesystem. A log file is
+//! a sequence of `[u32 LE length][update bytes]` records; a torn final
+//! record left by a crash mid-append is ignored on read, and cut off
+//! before the first append to that log after the restart, so later
+//! records don't end up inside it. A failed append is cut off at once.
+//!
+//! `changes.log` uses the same record framing; each record is
+//! `[u64 LE seq][doc_id]`, appended (before the update itself, so a
+//! crash can only leave a stamp too many) on every log write. The
+//! latest stamp per doc is kept in memory and the file is rewritten
+//! compactly on open once superseded stamps dominate it.
+//!
+//! Every blob is an ordinary file, so rsync/restic-style backups and
+//! replication only copy what changed instead of one huge database file.
+
+use super::storage::{BlobReader, BlobUpload, Changes, Storage, file_blob_reader};
+use crate::protocol::MAX_STREAMED_BLOB_SIZE;
+use crate::v1::blob_store::BlobStore;
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const LOG_EXT: &str = "log";
+
+/// Superseded stamps `changes.log` may hold before it's compacted.
+const CHANGES_SLACK: usize = 1024;
+
+/// Directory-backed storage. Clones share the same root and write lock.
+#[derive(Clone)]
+pub struct FsStorage {
+    inner: Arc<FsInner>,
+}
+
+struct FsInner {
+    root: PathBuf,
+    blobs: BlobStore,
+    /// Serialises every filesystem mutation. Reads take it too so a
+    /// snapshot rename can't interleave with a half-read log.
+    lock: Mutex<()>,
+    /// In-memory copy of `changes.log`. Only touched under `lock`.
+    feed: Mutex<Feed>,
+    /// Logs checked for a torn tail since open. Only touched under `lock`.
+    appended: Mutex<HashSet<PathBuf>>,
+}
+
+#[derive(Default)]
+struct Feed {
+    seq: u64,
+    stamps: HashMap<String, u64>,
+}
+
+impl FsStorage {
+    /// Open (creating if needed) a storage directory at `root`.
+    pub fn open(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        for sub in ["updates", "archive", "blobs"] {
+            let dir = root.join(sub);
+            fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+        }
+        let blobs = BlobStore::new(root.join("blobs"));
+        let inner = FsInner {
+            root,
+            blobs,
+            lock: Mutex::new(()),
+            feed: Mutex::new(Feed::default()),
+            appended: Mutex::new(HashSet::new()),
+        };
+        inner.load_feed()?;
+        Ok(Self {
+            inner: Arc::new(inner),
+        })
+    }
+
+    /// Root directory on disk.
+    pub fn root(&self) -> &Path {
+        &self.inner.root
+    }
+
+    /// Run `f` on the blocking pool while holding the write lock.
+    async fn with_lock<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&FsInner) -> Result<T> + Send + 'static,
+    {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || {
+            let _guard = inner.lock.lock().unwrap();
+            f(&inner)
+        })
+        .await?
+    }
+}
+
+impl FsInner {
+    fn log_path(&self, area: &str, doc_id: &str) -> PathBuf {
+        self.root
+            .join(area)
+            .join(format!("{}.{}", encode_doc_id(doc_id), LOG_EXT))
+    }
+
+    fn changes_path(&self) -> PathBuf {
+        self.root.join("changes.log")
+    }
+
+    /// Rebuild the feed from `changes.log`, dropping stamps of docs that
+    /// are gone and compacting the file if that saves enough.
+    fn load_feed(&self) -> Result<()> {
+        let records = read_log(&self.changes_path())?;
+        let mut feed = Feed::default();
+        for record in &records {
+            let Some((seq, doc_id)) = record.split_first_chunk::<8>() else {
+                continue;
+            };
+            let seq = u64::from_le_bytes(*seq);
+            feed.seq = feed.seq.max(seq);
+            if let Ok(doc_id) = std::str::from_utf8(doc_id) {
+                feed.stamps.insert(doc_id.to_string(), seq);
+            }
+        }
+        feed.stamps
+            .retain(|doc_id, _| self.log_path("updates", doc_id).is_file());
+        if records.len() > feed.stamps.len() + CHANGES_SLACK {
+            let mut stamps: Vec<_> = feed.stamps.iter().collect();
+            stamps.sort_by_key(|(_, seq)| **seq);
+            // Keep the head stamp even if its doc is gone, so the
+            // sequence never goes backwards.
+            let mut bytes = encode_record(&encode_stamp(feed.seq, ""));
+            for (doc_id, seq) in stamps {
+                bytes.extend(encode_record(&encode_stamp(*seq, doc_id)));
+            }
+            write_atomic(&self.changes_path(), &bytes)?;
+        }
+        *self.feed.lock().unwrap() = feed;
+        Ok(())
+    }
+
+    /// Append one record to the log at `path`. The first append to a
+    /// log since open cuts off a torn record left at its end; an append
+    /// that fails is cut off again.
+    fn append_record(&self, path: &Path, record: &[u8]) -> Result<()> {
+        let mut f = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        let mut len = f.metadata()?.len();
+        if self.appended.lock().unwrap().insert(path.to_path_buf()) {
+            let whole = parse_log(&fs::read(path)?).1 as u64;
+            if whole < len {
+                tracing::warn!("cutting torn trailing record off {}", path.display());
+                f.set_len(whole)?;
+                len = whole;
+            }
+        }
+        let written = f.write_all(&encode_record(record)).and_then(|()| f.sync_data());
+        if let Err(e) = written {
+            let _ = f.set_len(len);
+            return Err(e).with_context(|| format!("appending to {}", path.display()));
+        }
+        Ok(())
+    }
+
+    /// Stamp `doc_id` with the next change sequence number.
+    fn stamp(&self, doc_id: &str) -> Result<()> {
+        let mut feed = self.feed.lock().unwrap();
+        let seq = feed.seq + 1;
+        self.append_record(&self.changes_path(), &encode_stamp(seq, doc_id))?;
+        feed.seq = seq;
+        feed.stamps.insert(doc_id.to_string(), seq);
+        Ok(())
+    }
+
+    fn meta_path(&self) -> PathBuf {
+        self.root.join("meta.json")
+    }
+
+    fn read_meta(&self) -> Result<BTreeMap<String, String>> {
+        match fs::read(self.meta_path()) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes).context("parsing meta.json")?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e).context("reading meta.json"),
+        }
+    }
+
+    fn write_meta(&self, meta: &BTreeMap<String, String>) -> Result<()> {
+        let bytes = serde_json::to_vec_pretty(meta)?;
+        write_atomic(&self.meta_path(), &bytes)
+    }
+}
+
+#[async_trait]
+impl Storage for FsStorage {
+    async fn save_update(&self, doc_id: &str, update: &[u8]) -> Result<()> {
+        let doc_id = doc_id.to_string();
+        let update = update.to_vec();
+        self.with_lock(move |inner| {
+            inner.stamp(&doc_id)?;
+            inner.append_record(&inner.log_path("updates", &doc_id), &update)
+        })
+        .await
+    }
+
+    async fn load_doc_updates(&self, doc_id: &str) -> Result<Vec<Vec<u8>>> {
+        let doc_id = doc_id.to_string();
+        self.with_lock(move |inner| read_log(&inner.log_path("updates", &doc_id)))
+            .await
+    }
+
+    async fn list_doc_ids(&self) -> Result<Vec<String>> {
+        self.with_lock(|inner| {
+            let mut ids = Vec::new();
+            for entry in fs::read_dir(inner.root.join("updates"))? {
+                let path = entry?.path();
+                if path.extension().and_then(|e| e.to_str()) != Some(LOG_EXT) {
+                    continue;
+                }
+                let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+                if let Some(id) = decode_doc_id(stem) {
+                    ids.push(id);
+                }
+            }
+            ids.sort();
+            Ok(ids)
+        })
+        .await
+    }
+
+    async fn save_snapshot(&self, doc_id: &str, snapshot: &[u8]) -> Result<()> {
+        let doc_id = doc_id.to_string();
+        let record = encode_record(snapshot);
+        self.with_lock(move |inner| {
+            inner.stamp(&doc_id)?;
+            write_atomic(&inner.log_path("updates", &doc_id), &record)
+        })
+        .await
+    }
+
+    async fn archive_docs(&self, doc_ids: &[String]) -> Result<()> {
+        let doc_ids = doc_ids.to_vec();
+        self.with_lock(move |inner| {
+            for doc_id in doc_ids {
+                inner.feed.lock().unwrap().stamps.remove(&doc_id);
+                let live = inner.log_path("updates", &doc_id);
+                let Ok(bytes) = fs::read(&live) else {
+                    continue;
+                };
+                let dest = inner.log_path("archive", &doc_id);
+                let mut f = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&dest)
+                    .with_context(|| format!("opening {}", dest.display()))?;
+                f.write_all(&bytes)?;
+                f.sync_data()?;
+                fs::remove_file(&live)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn changes_since(&self, since: u64) -> Result<Changes> {
+        self.with_lock(move |inner| {
+            let feed = inner.feed.lock().unwrap();
+            let mut docs: Vec<String> = feed
+                .stamps
+                .iter()
+                .filter(|(_, seq)| **seq > since)
+                .map(|(doc_id, _)| doc_id.clone())
+                .collect();
+            docs.sort();
+            Ok(Changes {
+                head: feed.seq,
+                docs,
+            })
+        })
+        .await
+    }
+
+    async fn save_blob(&self, hash: &str, data: &[u8]) -> Result<()> {
+        let hash = hash.to_string();
+        let data = data.to_vec();
+        self.with_lock(move |inner| inner.blobs.insert_verified(&hash, &data))
+            .await
+    }
+
+    async fn load_blob(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        let hash = hash.to_string();
+        self.with_lock(move |inner| {
+            if !inner.blobs.has(&hash) {
+                return Ok(None);
+            }
+            inner.blobs.read(&hash).map(Some)
+        })
+        .await
+    }
+
+    async fn has_blob(&self, hash: &str) -> Result<bool> {
+        let hash = hash.to_string();
+        self.with_lock(move |inner| Ok(inner.blobs.has(&hash))).await
+    }
+
+    async fn open_blob(&self, hash: &str) -> Result<Option<BlobReader>> {
+        // Blob files are immutable once renamed into place, so reading
+        // them outside the lock is safe.
+        if !self.inner.blobs.has(hash) {
+            return Ok(None);
+        }
+        file_blob_reader(self.inner.blobs.path_for(hash)).await.map(Some)
+    }
+
+    fn max_upload_size(&self) -> u64 {
+        MAX_STREAMED_BLOB_SIZE
+    }
+
+    async fn begin_blob(&self, hash: &str, total: u64) -> Result<BlobUpload> {
+        if total > self.max_upload_size() {
+            bail!("blob {hash} is {total} bytes, over the upload limit");
+        }
+        let writer = self.inner.blobs.writer(hash)?;
+        Ok(BlobUpload::staged(hash, total, writer))
+    }
+
+    async fn finish_blob(&self, upload: BlobUpload) -> Result<()> {
+        let writer = upload.into_writer()?;
+        self.with_lock(move |_| writer.finish()).await
+    }
+
+    async fn get_meta(&self, key: &str) -> Result<Option<String>> {
+        let key = key.to_string();
+        self.with_lock(move |inner| Ok(inner.read_meta()?.remove(&key)))
+            .await
+    }
+
+    async fn set_meta(&self, key: &str, value: &str) -> Result<()> {
+        let key = key.to_string();
+        let value = value.to_string();
+        self.with_lock(move |inner| {
+            let mut meta = inner.read_meta()?;
+            meta.insert(key, value);
+            inner.write_meta(&meta)
+        })
+        .await
+    }
+}
+
+fn encode_doc_id(doc_id: &str) -> String {
+    doc_id.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_doc_id(hex: &str) -> Option<String> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes: Option<Vec<u8>> = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect();
+    String::from_utf8(bytes?).ok()
+}
+
+fn encode_record(update: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(4 + update.len());
+    out.extend_from_slice(&(update.len() as u32).to_le_bytes());
+    out.extend_from_slice(update);
+    out
+}
+
+fn read_log(path: &Path) -> Result<Vec<Vec<u8>>> {
+    let bytes = match fs::read(path) {
+        Ok(b) => b,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+    let (out, whole) = parse_log(&bytes);
+    if whole < bytes.len() {
+        tracing::warn!("ignoring torn trailing record in {}", path.display());
+    }
+    Ok(out)
+}
+
+/// The whole records in a log, and the length of the prefix they fill.
+fn parse_log(bytes: &[u8]) -> (Vec<Vec<u8>>, usize) {
+    let mut out = Vec::new();
+    let mut pos = 0usize;
+    while pos + 4 <= bytes.len() {
+        let len = u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+        let start = pos + 4;
+        let Some(end) = start.checked_add(len).filter(|&e| e <= bytes.len()) else {
+            break;
+        };
+        out.push(bytes[start..end].to_vec());
+        pos = end;
+    }
+    (out, pos)
+}
+
+fn encode_stamp(seq: u64, doc_id: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(8 + doc_id.len());
+    out.extend_from_slice(&seq.to_le_bytes());
+    out.extend_from_slice(doc_id.as_bytes());
+    out
+}
+
+fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    {
+        let mut f = fs::File::create(&tmp).with_context(|| format!("creating {}", tmp.display()))?;
+        f.write_all(bytes)?;
+        f.sync_data()?;
+    }
+    fs::rename(&tmp, path)
+        .with_context(|| format!("renaming {} -> {}", tmp.display(), path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::storage::conformance;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn fs_backend_conformance() {
+        let tmp = TempDir::new().unwrap();
+        let counter = std::sync::atomic::AtomicUsize::new(0);
+        conformance::run_all(|| {
+            let n = counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let root = tmp.path().join(n.to_string());
+            async move { FsStorage::open(root).unwrap() }
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn reopen_sees_persisted_state() {
+        let tmp = TempDir::new().unwrap();
+        {
+            let s = FsStorage::open(tmp.path()).unwrap();
+            s.save_update("__manifest__", &[1, 2]).await.unwrap();
+            s.set_meta("db_version", "1").await.unwrap();
+        }
+        let s = FsStorage::open(tmp.path()).unwrap();
+        assert_eq!(s.load_doc_updates("__manifest__").await.unwrap(), vec![vec![1, 2]]);
+        assert_eq!(s.get_meta("db_version").await.unwrap().as_deref(), Some("1"));
+    }
+
+    #[tokio::test]
+    async fn change_feed_survives_reopen_and_compaction() {
+        let tmp = TempDir::new().unwrap();
+        let head = {
+            let s = FsStorage::open(tmp.path()).unwrap();
+            for _ in 0..CHANGES_SLACK + 10 {
+                s.save_update("busy", &[1]).await.unwrap();
+            }
+            s.save_update("quiet", &[2]).await.unwrap();
+            s.save_update("gone", &[3]).await.unwrap();
+            s.archive_docs(&["gone".to_string()]).await.unwrap();
+            s.changes_since(0).await.unwrap().head
+        };
+        let s = FsStorage::open(tmp.path()).unwrap();
+        let all = s.changes_since(0).await.unwrap();
+        assert_eq!(all.head, head);
+        assert_eq!(all.docs, vec!["busy".to_string(), "quiet".to_string()]);
+        assert_eq!(
+            s.changes_since(head - 2).await.unwrap().docs,
+            vec!["quiet".to_string()]
+        );
+        assert!(fs::metadata(s.inner.changes_path()).unwrap().len() < 200);
+        s.save_update("busy", &[4]).await.unwrap();
+        assert_eq!(s.changes_since(head).await.unwrap().head, head + 1);
+    }
+
+    #[tokio::test]
+    async fn torn_trailing_record_is_ignored() {
+        let tmp = TempDir::new().unwrap();
+        let s = FsStorage::open(tmp.path()).unwrap();
+        s.save_update("doc", &[9, 9, 9]).await.unwrap();
+        drop(s);
+        // Length prefix promising 100 bytes, followed by only two, as a
+        // crash mid-append leaves it — in the log and the change feed.
+        let s = FsStorage::open(tmp.path()).unwrap();
+        for path in [s.inner.log_path("updates", "doc"), s.inner.changes_path()] {
+            let mut f = fs::OpenOptions::new().append(true).open(&path).unwrap();
+            f.write_all(&100u32.to_le_bytes()).unwrap();
+            f.write_all(&[1, 2]).unwrap();
+        }
+        assert_eq!(s.load_doc_updates("doc").await.unwrap(), vec![vec![9, 9, 9]]);
+
+        // Later writes land after the last whole record, not inside the
+        // torn one, and survive a reopen.
+        s.save_update("doc", &[7]).await.unwrap();
+        s.save_update("other", &[8]).await.unwrap();
+        let head = s.changes_since(0).await.unwrap().head;
+        drop(s);
+        let s = FsStorage::open(tmp.path()).unwrap();
+        assert_eq!(s.load_doc_updates("doc").await.unwrap(), vec![vec![9, 9, 9], vec![7]]);
+        let changes = s.changes_since(0).await.unwrap();
+        assert_eq!(changes.head, head);
+        assert_eq!(changes.docs, vec!["doc", "other"]);
+    }
+
+    #[test]
+    fn doc_id_encoding_roundtrips() {
+        for id in ["__manifest__", "content:019dc69a-1234", "Ünïcode/ä"] {
+            assert_eq!(decode_doc_id(&encode_doc_id(id)).as_deref(), Some(id));
+        }
+        assert!(decode_doc_id("abc").is_none());
+    }
+}
@@ -0,0 +1,362 @@
+//! Rate limits and storage quotas for [`handle_socket`].
+//!
+//! Every connection carries an account token — the `token` query
+//! parameter of the `/sync` URL or an `Authorization: Bearer` header,
+//! falling back to [`ANONYMOUS_TOKEN`]. The token is an accounting
+//! label the client picks, not a credential: it groups a device's
+//! connections so reconnecting under the same token doesn't reset its
+//! limits. A client that switches to a fresh token does start with
+//! fresh per-token buckets and blob quota; only the per-connection rates
+//! and the vault quota still hold it. A token only becomes binding
+//! once an admin gives it a secret (see [`tokens`]).
+//!
+//! [`tokens`]: crate::server::tokens
+//!
+//! - **Rates** (frames/s, bytes/s) are token buckets applied per
+//!   connection *and* per token. Going over throttles the reader: we
+//!   stop pulling frames off the socket until the bucket refills, which
+//!   pushes back on the client through TCP flow control.
+//! - **Quotas** (vault bytes, blob bytes per token) are hard caps. A
+//!   write that would cross one is refused and the connection is closed
+//!   with a policy-violation close frame naming the quota.
+//!
+//! Usage counters live in server meta (`vault_bytes`,
+//! `blob_bytes:<token>`) and are flushed when a connection ends or a
+//! blob is stored. They count bytes accepted, so compaction does not
+//! shrink them; blobs stored before accounting existed are not counted.
+//!
+//! [`handle_socket`]: crate::server::server
+
+use crate::server::storage::Storage;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex as AsyncMutex;
+
+/// Token used by connections that don't present one.
+pub const ANONYMOUS_TOKEN: &str = "anonymous";
+
+/// Configured limits. `None` means unlimited; the default is no limits.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Frames per second, per connection and per token.
+    pub frames_per_sec: Option<u32>,
+    /// Inbound bytes per second, per connection and per token.
+    pub bytes_per_sec: Option<u64>,
+    /// Total bytes of updates and blobs the server will accept.
+    pub max_vault_bytes: Option<u64>,
+    /// Blob bytes a single token may upload.
+    pub max_blob_bytes_per_token: Option<u64>,
+}
+
+impl Limits {
+    pub fn is_unlimited(&self) -> bool {
+        *self == Limits::default()
+    }
+}
+
+impl fmt::Display for Limits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn show(f: &mut fmt::Formatter<'_>, name: &str, v: Option<u64>) -> fmt::Result {
+            match v {
+                Some(v) => write!(f, "{name}={v}"),
+                None => write!(f, "{name}=unlimited"),
+            }
+        }
+        show(f, "frames/s", self.frames_per_sec.map(u64::from))?;
+        f.write_str(", ")?;
+        show(f, "bytes/s", self.bytes_per_sec)?;
+        f.write_str(", ")?;
+        show(f, "vault bytes", self.max_vault_bytes)?;
+        f.write_str(", ")?;
+        show(f, "blob bytes/token", self.max_blob_bytes_per_token)
+    }
+}
+
+/// A write refused because it would cross a quota. The `Display` text is
+/// what the client sees as the close reason.
+#[derive(Debug, PartialEq, Eq)]
+pub enum QuotaExceeded {
+    Vault { used: u64, limit: u64 },
+    Blob { used: u64, limit: u64 },
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuotaExceeded::Vault { used, limit } => {
+                write!(f, "vault quota exceeded ({used} of {limit} bytes used)")
+            }
+            QuotaExceeded::Blob { used, limit } => {
+                write!(f, "blob quota exceeded ({used} of {limit} bytes used)")
+            }
+        }
+    }
+}
+
+/// Classic token bucket holding up to one second of budget. Takes may
+/// overdraw it; the debt is paid back as a delay so a single frame
+/// larger than the per-second budget still gets through, just slowly.
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    level: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn new(rate: f64, now: Instant) -> Self {
+        Self {
+            rate,
+            level: rate,
+            last: now,
+        }
+    }
+
+    fn take(&mut self, n: f64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.level = (self.level + elapsed * self.rate).min(self.rate) - n;
+        if self.level >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.level / self.rate)
+        }
+    }
+}
+
+/// Frame and byte buckets for one connection or one token.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    frames: Option<Bucket>,
+    bytes: Option<Bucket>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limits: &Limits, now: Instant) -> Self {
+        Self {
+            frames: limits
+                .frames_per_sec
+                .filter(|r| *r > 0)
+                .map(|r| Bucket::new(f64::from(r), now)),
+            bytes: limits
+                .bytes_per_sec
+                .filter(|r| *r > 0)
+                .map(|r| Bucket::new(r as f64, now)),
+        }
+    }
+
+    /// Charge one frame of `len` bytes; returns how long the caller must
+    /// wait before handling it.
+    pub(crate) fn charge(&mut self, len: usize, now: Instant) -> Duration {
+        let frames = self.frames.as_mut().map_or(Duration::ZERO, |b| b.take(1.0, now));
+        let bytes = self
+            .bytes
+            .as_mut()
+            .map_or(Duration::ZERO, |b| b.take(len as f64, now));
+        frames.max(bytes)
+    }
+}
+
+#[derive(Default)]
+struct Usage {
+    vault_bytes: u64,
+    blob_bytes: HashMap<String, u64>,
+}
+
+/// Server-wide limit state shared by every connection.
+pub(crate) struct Quotas {
+    pub(crate) limits: Limits,
+    db: Arc<dyn Storage>,
+    usage: AsyncMutex<Usage>,
+    token_rates: Mutex<HashMap<String, Arc<Mutex<RateLimiter>>>>,
+}
+
+impl Quotas {
+    /// Load the persisted usage counters. The first start with
+    /// accounting seeds `vault_bytes` from the stored update logs.
+    pub(crate) async fn load(db: Arc<dyn Storage>, limits: Limits) -> anyhow::Result<Self> {
+        let vault_bytes = match db.get_meta("vault_bytes").await? {
+            Some(v) => v.parse().unwrap_or(0),
+            None => {
+                let mut total = 0u64;
+                for doc_id in db.list_doc_ids().await? {
+                    for u in db.load_doc_updates(&doc_id).await? {
+                        total += u.len() as u64;
+                    }
+                }
+                db.set_meta("vault_bytes", &total.to_string()).await?;
+                total
+            }
+        };
+        Ok(Self {
+            limits,
+            db,
+            usage: AsyncMutex::new(Usage {
+                vault_bytes,
+                blob_bytes: HashMap::new(),
+            }),
+            token_rates: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The shared rate limiter for `token`, created on first use.
+    pub(crate) fn token_rate(&self, token: &str) -> Arc<Mutex<RateLimiter>> {
+        self.token_rates
+            .lock()
+            .unwrap()
+            .entry(token.to_string())
+            .or_insert_with(|| Arc::new(Mutex::new(RateLimiter::new(&self.limits, Instant::now()))))
+            .clone()
+    }
+
+    /// Current (vault bytes, blob bytes of `token`).
+    pub(crate) async fn usage(&self, token: &str) -> (u64, u64) {
+        let mut usage = self.usage.lock().await;
+        let blob = self.blob_bytes(&mut usage, token).await;
+        (usage.vault_bytes, blob)
+    }
+
+    /// Refuse a write of `len` bytes (a blob if `blob`) that would cross
+    /// a quota. Does not record anything.
+    pub(crate) async fn check(&self, token: &str, len: u64, blob: bool) -> Result<(), QuotaExceeded> {
+        let mut usage = self.usage.lock().await;
+        if let Some(limit) = self.limits.max_vault_bytes
+            && usage.vault_bytes.saturating_add(len) > limit
+        {
+            return Err(QuotaExceeded::Vault {
+                used: usage.vault_bytes,
+                limit,
+            });
+        }
+        if let (true, Some(limit)) = (blob, self.limits.max_blob_bytes_per_token) {
+            let used = self.blob_bytes(&mut usage, token).await;
+            if used.saturating_add(len) > limit {
+                return Err(QuotaExceeded::Blob { used, limit });
+            }
+        }
+        Ok(())
+    }
+
+    /// Count `len` stored bytes against the vault and, for blobs, the
+    /// token. Blob usage is flushed right away; it changes rarely.
+    pub(crate) async fn record(&self, token: &str, len: u64, blob: bool) {
+        if len == 0 {
+            return;
+        }
+        let mut usage = self.usage.lock().await;
+        usage.vault_bytes += len;
+        if blob {
+            let used = self.blob_bytes(&mut usage, token).await + len;
+            usage.blob_bytes.insert(token.to_string(), used);
+            if let Err(e) = self.db.set_meta(&blob_key(token), &used.to_string()).await {
+                tracing::warn!("persist blob usage for {}: {}", token, e);
+            }
+        }
+    }
+
+    /// Write the vault counter back to meta.
+    pub(crate) async fn flush(&self) {
+        let vault_bytes = self.usage.lock().await.vault_bytes;
+        if let Err(e) = self.db.set_meta("vault_bytes", &vault_bytes.to_string()).await {
+            tracing::warn!("persist vault usage: {}", e);
+        }
+    }
+
+    async fn blob_bytes(&self, usage: &mut Usage, token: &str) -> u64 {
+        if let Some(v) = usage.blob_bytes.get(token) {
+            return *v;
+        }
+        let v = match self.db.get_meta(&blob_key(token)).await {
+            Ok(v) => v.and_then(|v| v.parse().ok()).unwrap_or(0),
+            Err(e) => {
+                tracing::warn!("load blob usage for {}: {}", token, e);
+                0
+            }
+        };
+        usage.blob_bytes.insert(token.to_string(), v);
+        v
+    }
+}
+
+fn blob_key(token: &str) -> String {
+    format!("blob_bytes:{token}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::memory_storage::MemoryStorage;
+
+    #[test]
+    fn bucket_allows_burst_then_delays() {
+        let t0 = Instant::now();
+        let limits = Limits {
+            frames_per_sec: Some(10),
+            ..Limits::default()
+        };
+        let mut rl = RateLimiter::new(&limits, t0);
+        for _ in 0..10 {
+            assert_eq!(rl.charge(1, t0), Duration::ZERO);
+        }
+        let wait = rl.charge(1, t0);
+        assert!(wait > Duration::from_millis(90) && wait <= Duration::from_millis(100));
+        // A second later the bucket has refilled.
+        assert_eq!(rl.charge(1, t0 + Duration::from_secs(2)), Duration::ZERO);
+    }
+
+    #[test]
+    fn oversized_frame_overdraws_byte_bucket() {
+        let t0 = Instant::now();
+        let limits = Limits {
+            bytes_per_sec: Some(1000),
+            ..Limits::default()
+        };
+        let mut rl = RateLimiter::new(&limits, t0);
+        // 3000 bytes against a 1000 byte/s bucket: 2 s of debt.
+        let wait = rl.charge(3000, t0);
+        assert_eq!(wait, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn unlimited_never_delays() {
+        let t0 = Instant::now();
+        let mut rl = RateLimiter::new(&Limits::default(), t0);
+        for _ in 0..10_000 {
+            assert_eq!(rl.charge(1 << 20, t0), Duration::ZERO);
+        }
+    }
+
+    #[tokio::test]
+    async fn quotas_refuse_and_persist() {
+        let db: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        db.save_update("content:a", &[0u8; 40]).await.unwrap();
+        let limits = Limits {
+            max_vault_bytes: Some(100),
+            max_blob_bytes_per_token: Some(30),
+            ..Limits::default()
+        };
+        let q = Quotas::load(db.clone(), limits.clone()).await.unwrap();
+        // Seeded from the existing update log.
+        assert_eq!(q.usage("laptop").await, (40, 0));
+
+        assert!(q.check("laptop", 20, true).await.is_ok());
+        q.record("laptop", 20, true).await;
+        assert_eq!(
+            q.check("laptop", 20, true).await,
+            Err(QuotaExceeded::Blob { used: 20, limit: 30 })
+        );
+        // Another token still has its own blob budget...
+        assert!(q.check("phone", 20, true).await.is_ok());
+        // ...but shares the vault.
+        assert_eq!(
+            q.check("phone", 50, false).await,
+            Err(QuotaExceeded::Vault { used: 60, limit: 100 })
+        );
+
+        q.flush().await;
+        let q = Quotas::load(db, limits).await.unwrap();
+        assert_eq!(q.usage("laptop").await, (60, 20));
+    }
+}
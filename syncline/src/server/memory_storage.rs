@@ -0,0 +1,150 @@
+//! In-process [`Storage`] backend.
+//!
+//! Holds everything in `BTreeMap`s behind a single mutex. Nothing
+//! survives a restart, which is exactly what the server tests want:
+//! no SQLite connection setup, no temp files, deterministic ordering.
+
+use super::storage::{Changes, Storage};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+struct Inner {
+    updates: BTreeMap<String, Vec<Vec<u8>>>,
+    archived: BTreeMap<String, Vec<Vec<u8>>>,
+    blobs: HashMap<String, Vec<u8>>,
+    meta: HashMap<String, String>,
+    /// Last change sequence number handed out.
+    seq: u64,
+    /// Each live doc's latest change sequence number.
+    stamps: HashMap<String, u64>,
+}
+
+impl Inner {
+    fn stamp(&mut self, doc_id: &str) {
+        self.seq += 1;
+        self.stamps.insert(doc_id.to_string(), self.seq);
+    }
+}
+
+/// Volatile storage. Clones share the same underlying maps.
+#[derive(Clone, Default)]
+pub struct MemoryStorage {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn save_update(&self, doc_id: &str, update: &[u8]) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .updates
+            .entry(doc_id.to_string())
+            .or_default()
+            .push(update.to_vec());
+        inner.stamp(doc_id);
+        Ok(())
+    }
+
+    async fn load_doc_updates(&self, doc_id: &str) -> Result<Vec<Vec<u8>>> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner.updates.get(doc_id).cloned().unwrap_or_default())
+    }
+
+    async fn list_doc_ids(&self) -> Result<Vec<String>> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner.updates.keys().cloned().collect())
+    }
+
+    async fn save_snapshot(&self, doc_id: &str, snapshot: &[u8]) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .updates
+            .insert(doc_id.to_string(), vec![snapshot.to_vec()]);
+        inner.stamp(doc_id);
+        Ok(())
+    }
+
+    async fn archive_docs(&self, doc_ids: &[String]) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        for doc_id in doc_ids {
+            inner.stamps.remove(doc_id);
+            if let Some(rows) = inner.updates.remove(doc_id) {
+                inner.archived.entry(doc_id.clone()).or_default().extend(rows);
+            }
+        }
+        Ok(())
+    }
+
+    async fn changes_since(&self, since: u64) -> Result<Changes> {
+        let inner = self.inner.lock().unwrap();
+        let mut docs: Vec<String> = inner
+            .stamps
+            .iter()
+            .filter(|(_, seq)| **seq > since)
+            .map(|(doc_id, _)| doc_id.clone())
+            .collect();
+        docs.sort();
+        Ok(Changes {
+            head: inner.seq,
+            docs,
+        })
+    }
+
+    async fn save_blob(&self, hash: &str, data: &[u8]) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .blobs
+            .entry(hash.to_string())
+            .or_insert_with(|| data.to_vec());
+        Ok(())
+    }
+
+    async fn load_blob(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner.blobs.get(hash).cloned())
+    }
+
+    async fn has_blob(&self, hash: &str) -> Result<bool> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner.blobs.contains_key(hash))
+    }
+
+    async fn get_meta(&self, key: &str) -> Result<Option<String>> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner.meta.get(key).cloned())
+    }
+
+    async fn set_meta(&self, key: &str, value: &str) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.meta.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::storage::conformance;
+
+    #[tokio::test]
+    async fn memory_backend_conformance() {
+        conformance::run_all(|| async { MemoryStorage::new() }).await;
+    }
+
+    #[tokio::test]
+    async fn clones_share_state() {
+        let a = MemoryStorage::new();
+        let b = a.clone();
+        a.save_update("doc", &[1]).await.unwrap();
+        assert_eq!(b.load_doc_updates("doc").await.unwrap(), vec![vec![1]]);
+    }
+}
@@ -7,41 +7,46 @@
 //! - `content:<node_hex>`  — per-text-file content subdoc state update
 //! - (nothing else)        — v0 per-path docs are not used in v1
 //!
-//! Binary payloads remain in the `blobs` table unchanged (content-
+//! Binary payloads stay wherever the backend keeps blobs (content-
 //! addressable storage is protocol-agnostic).
 //!
-//! A small `meta` table records the schema version and the server's
+//! A small meta store records the schema version and the server's
 //! persistent actor id so restarts don't keep minting fresh actor ids
 //! and re-migrating data.
 //!
+//! Everything goes through the [`Storage`] trait, so the same migration
+//! runs against whichever backend the server was started with.
+//!
 //! Migration algorithm:
 //!
-//! 1. Ensure the `meta` table exists.
-//! 2. If `meta.db_version == 1`, return early (`already_migrated`).
-//! 3. Stream every distinct v0 `doc_id` (skipping `__index__`), merge
+//! 1. If `meta.db_version == 1`, return early (`already_migrated`).
+//! 2. Stream every distinct v0 `doc_id` (skipping `__index__`), merge
 //!    its updates into a transient Y.Doc, and extract
 //!    `meta.path` / `meta.type` / `meta.blob_hash` + `content` Y.Text.
-//! 4. Build a fresh v1 [`Manifest`], minting one leaf per file and the
+//! 3. Build a fresh v1 [`Manifest`], minting one leaf per file and the
 //!    chain of directory nodes above it.
-//! 5. For every text file, write a content subdoc under
+//! 4. For every text file, write a content subdoc under
 //!    `content:<node_hex>` seeded from the v0 body.
-//! 6. Save the manifest as a single row under `__manifest__`.
-//! 7. Archive v0 rows into `updates_v0_bak` (same schema) so the
-//!    migration is reversible.
-//! 8. Set `meta.db_version = 1` and `meta.actor_id = <uuid>`.
+//! 5. Save the manifest as a single row under `__manifest__`.
+//! 6. Archive v0 rows via [`Storage::archive_docs`] so the migration
+//!    is reversible.
+//! 7. Set `meta.db_version = 1` and `meta.actor_id = <uuid>`.
 //!
-//! The whole thing runs inside a single SQL transaction so a crashed
-//! run leaves the DB in its pre-migration state.
+//! Storage writes aren't transactional, so instead every step is safe
+//! to repeat after a crash: node ids are derived from the v0 doc id
+//! (directories from their path), so a re-run writes the same manifest
+//! entries again rather than duplicating them, and a content subdoc
+//! that already has rows is left alone. `db_version` is set last.
 
 use anyhow::{Context, Result};
-use sqlx::Row;
 use std::collections::HashMap;
 use yrs::{
     Any, Doc, GetString, Map, MapRef, Out, ReadTxn, Text, Transact, Update,
     updates::decoder::Decode,
 };
 
-use crate::server::db::Db;
+use crate::protocol::MANIFEST_DOC_ID;
+use crate::server::storage::{INDEX_DOC_ID, Storage};
 use crate::v1::ids::{ActorId, NodeId};
 use crate::v1::manifest::{Manifest, NodeKind};
 
@@ -59,10 +64,8 @@ pub struct ServerMigrationReport {
 /// Inspect the `meta` table and migrate the DB in-place if it's still
 /// on the v0 schema. Idempotent: running it on a v1 DB is a cheap
 /// no-op.
-pub async fn migrate_server_db(db: &Db) -> Result<ServerMigrationReport> {
-    ensure_meta_table(db).await?;
-
-    if let Some("1") = get_meta(db, "db_version").await?.as_deref() {
+pub async fn migrate_server_db(db: &dyn Storage) -> Result<ServerMigrationReport> {
+    if let Some("1") = db.get_meta("db_version").await?.as_deref() {
         let actor = actor_id_from_meta(db).await?;
         return Ok(ServerMigrationReport {
             already_migrated: true,
@@ -111,7 +114,9 @@ pub async fn migrate_server_db(db: &Db) -> Result<ServerMigrationReport> {
             NodeKind::Text => snap.content.as_ref().map(|s| s.len()).unwrap_or(0) as u64,
             _ => 0,
         };
-        let node_id = manifest.create_node(
+        let node_id = migrated_id(&doc_id);
+        manifest.create_node_with_id(
+            node_id,
             &leaf,
             parent,
             snap.kind,
@@ -132,28 +137,31 @@ pub async fn migrate_server_db(db: &Db) -> Result<ServerMigrationReport> {
         }
     }
 
-    // Persist: manifest + each content subdoc + archive v0 rows.
-    // All writes happen through the existing Db helpers; we then flip
-    // the schema version marker once the data is on disk. A crash
-    // before the version flip re-migrates on next startup and is safe
-    // because the content doc_ids don't clash with v0 doc_ids (UUID
-    // vs `content:<hex>` prefix).
+    // Persist: manifest + each content subdoc + archive v0 rows, then
+    // flip the schema version marker once the data is on disk. A crash
+    // before the flip re-migrates on next startup, which is safe: the
+    // manifest entries come out under the same ids, content doc_ids
+    // don't clash with v0 doc_ids (UUID vs `content:<hex>` prefix), and
+    // subdocs a previous run wrote are skipped.
     let manifest_bytes = manifest.encode_state_as_update();
-    db.save_update("__manifest__", &manifest_bytes)
+    db.save_update(MANIFEST_DOC_ID, &manifest_bytes)
         .await
         .context("saving manifest update")?;
 
     let text_docs = text_contents.len();
     for (node_id, body) in text_contents {
-        let subdoc_bytes = encode_content_subdoc(&body);
         let key = format!("content:{}", node_id.to_string_hyphenated());
+        if !db.load_doc_updates(&key).await?.is_empty() {
+            continue;
+        }
+        let subdoc_bytes = encode_content_subdoc(&body);
         db.save_update(&key, &subdoc_bytes)
             .await
             .with_context(|| format!("saving content subdoc {}", key))?;
     }
 
     archive_v0_rows(db).await.context("archiving v0 rows")?;
-    set_meta(db, "db_version", "1").await?;
+    db.set_meta("db_version", "1").await?;
 
     Ok(ServerMigrationReport {
         already_migrated: false,
@@ -167,92 +175,46 @@ pub async fn migrate_server_db(db: &Db) -> Result<ServerMigrationReport> {
 
 /// Look up the server's persistent actor id from the `meta` table,
 /// creating one on first call.
-async fn actor_id_from_meta(db: &Db) -> Result<ActorId> {
-    if let Some(s) = get_meta(db, "actor_id").await? {
+async fn actor_id_from_meta(db: &dyn Storage) -> Result<ActorId> {
+    if let Some(s) = db.get_meta("actor_id").await? {
         if let Some(a) = ActorId::parse_str(&s) {
             return Ok(a);
         }
     }
     let a = ActorId::new();
-    set_meta(db, "actor_id", &a.to_string_hyphenated()).await?;
+    db.set_meta("actor_id", &a.to_string_hyphenated()).await?;
     Ok(a)
 }
 
 // ---------------------------------------------------------------------------
-// SQL helpers — all private to this module.
+// Doc-id selection helpers.
 // ---------------------------------------------------------------------------
 
-async fn ensure_meta_table(db: &Db) -> Result<()> {
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS meta (key TEXT PRIMARY KEY, value TEXT NOT NULL)",
-    )
-    .execute(db.pool())
-    .await?;
-    Ok(())
-}
-
-async fn get_meta(db: &Db, key: &str) -> Result<Option<String>> {
-    let row = sqlx::query("SELECT value FROM meta WHERE key = ?")
-        .bind(key)
-        .fetch_optional(db.pool())
-        .await?;
-    Ok(row.map(|r| r.get::<String, _>(0)))
+/// True for rows that belong to the v1 layout and must survive migration.
+fn is_v1_doc_id(doc_id: &str) -> bool {
+    doc_id == MANIFEST_DOC_ID
+        || doc_id.starts_with("content:")
+        || doc_id.starts_with(crate::server::acl::VIEW_DOC_PREFIX)
 }
 
-async fn set_meta(db: &Db, key: &str, value: &str) -> Result<()> {
-    sqlx::query(
-        "INSERT INTO meta (key, value) VALUES (?, ?) \
-         ON CONFLICT(key) DO UPDATE SET value = excluded.value",
-    )
-    .bind(key)
-    .bind(value)
-    .execute(db.pool())
-    .await?;
-    Ok(())
+async fn list_v0_doc_ids(db: &dyn Storage) -> Result<Vec<String>> {
+    Ok(db
+        .list_doc_ids()
+        .await?
+        .into_iter()
+        .filter(|id| id != INDEX_DOC_ID && !is_v1_doc_id(id))
+        .collect())
 }
 
-async fn list_v0_doc_ids(db: &Db) -> Result<Vec<String>> {
-    let rows = sqlx::query(
-        "SELECT DISTINCT doc_id FROM updates \
-         WHERE doc_id != '__index__' \
-           AND doc_id != '__manifest__' \
-           AND doc_id NOT LIKE 'content:%' \
-         ORDER BY doc_id ASC",
-    )
-    .fetch_all(db.pool())
-    .await?;
-    Ok(rows.into_iter().map(|r| r.get::<String, _>(0)).collect())
-}
-
-async fn archive_v0_rows(db: &Db) -> Result<()> {
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS updates_v0_bak ( \
-             id INTEGER PRIMARY KEY AUTOINCREMENT, \
-             doc_id TEXT NOT NULL, \
-             update_data BLOB NOT NULL \
-         )",
-    )
-    .execute(db.pool())
-    .await?;
-
-    sqlx::query(
-        "INSERT INTO updates_v0_bak (doc_id, update_data) \
-         SELECT doc_id, update_data FROM updates \
-         WHERE doc_id != '__manifest__' \
-           AND doc_id NOT LIKE 'content:%'",
-    )
-    .execute(db.pool())
-    .await?;
-
-    sqlx::query(
-        "DELETE FROM updates \
-         WHERE doc_id != '__manifest__' \
-           AND doc_id NOT LIKE 'content:%'",
-    )
-    .execute(db.pool())
-    .await?;
-
-    Ok(())
+/// Archive every non-v1 row, including the v0 `__index__` doc.
+async fn archive_v0_rows(db: &dyn Storage) -> Result<()> {
+    let doc_ids: Vec<String> = db
+        .list_doc_ids()
+        .await?
+        .into_iter()
+        .filter(|id| !is_v1_doc_id(id))
+        .collect();
+    db.archive_docs(&doc_ids).await
 }
 
 // ---------------------------------------------------------------------------
@@ -341,7 +303,8 @@ fn ensure_parent_chain(
         let id = if let Some(id) = dir_nodes.get(&prefix) {
             *id
         } else {
-            let id = manifest.create_node(seg, parent, NodeKind::Directory, None, 0);
+            let id = migrated_id(&format!("dir:{prefix}"));
+            manifest.create_node_with_id(id, seg, parent, NodeKind::Directory, None, 0);
             dir_nodes.insert(prefix.clone(), id);
             id
         };
@@ -350,6 +313,16 @@ fn ensure_parent_chain(
     parent
 }
 
+/// Node id for the v0 doc (or `dir:<path>`) `key`, the same on every
+/// run so a repeated migration doesn't mint duplicates.
+fn migrated_id(key: &str) -> NodeId {
+    use sha2::{Digest, Sha256};
+    let digest = Sha256::digest(format!("syncline-v0:{key}").as_bytes());
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    NodeId::from_uuid(uuid::Builder::from_custom_bytes(bytes).into_uuid())
+}
+
 fn leaf_segment(rel: &str) -> &str {
     rel.rsplit('/').next().unwrap_or(rel)
 }
@@ -368,6 +341,9 @@ fn encode_content_subdoc(body: &str) -> Vec<u8> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::server::db::Db;
+    use crate::server::memory_storage::MemoryStorage;
+    use sqlx::Row;
     use yrs::{Doc, Map, Text, Transact};
 
     /// Build a v0-shaped Y.Doc (meta.path, meta.type, content Y.Text)
@@ -409,10 +385,10 @@ mod tests {
         assert_eq!(r.text_docs, 0);
         assert_eq!(r.binary_docs, 0);
 
-        let version = get_meta(&db, "db_version").await.unwrap();
+        let version = db.get_meta("db_version").await.unwrap();
         assert_eq!(version.as_deref(), Some("1"));
 
-        let actor = get_meta(&db, "actor_id").await.unwrap().unwrap();
+        let actor = db.get_meta("actor_id").await.unwrap().unwrap();
         assert!(ActorId::parse_str(&actor).is_some());
     }
 
@@ -514,4 +490,55 @@ mod tests {
         let mu = db.load_doc_updates("__manifest__").await.unwrap();
         assert_eq!(mu.len(), 2, "pre-existing manifest row preserved, new one appended");
     }
+
+    #[tokio::test]
+    async fn rerun_after_a_crash_before_the_version_flip_converges() {
+        let db = MemoryStorage::new();
+        let v0 = make_v0_text_doc("notes/one.md", "hello");
+        db.save_update("doc-uuid-a", &v0).await.unwrap();
+        migrate_server_db(&db).await.unwrap();
+
+        // Crash after the writes but before archiving and the flip.
+        db.save_update("doc-uuid-a", &v0).await.unwrap();
+        db.set_meta("db_version", "0").await.unwrap();
+        let r = migrate_server_db(&db).await.unwrap();
+        assert!(!r.already_migrated);
+
+        let m = crate::server::server::hydrate_manifest(&db, r.actor_id)
+            .await
+            .unwrap();
+        let mut paths: Vec<String> = crate::v1::projection::project(&m)
+            .by_path
+            .keys()
+            .map(|p| p.to_string())
+            .collect();
+        paths.sort();
+        assert_eq!(paths, vec!["notes/one.md"]);
+        assert_eq!(m.all_entries().len(), 2, "one file and its directory");
+
+        let id = m.find_entry_by_path("notes/one.md").unwrap().id;
+        let content = db
+            .load_doc_updates(&format!("content:{}", id.to_string_hyphenated()))
+            .await
+            .unwrap();
+        assert_eq!(content.len(), 1, "subdoc written once");
+    }
+
+    #[tokio::test]
+    async fn migrates_through_non_sqlite_backend() {
+        let store = MemoryStorage::new();
+        store
+            .save_update("doc-uuid-a", &make_v0_text_doc("a/b.md", "hi"))
+            .await
+            .unwrap();
+        store.save_update("__index__", b"garbage").await.unwrap();
+
+        let r = migrate_server_db(&store).await.unwrap();
+        assert_eq!(r.text_docs, 1);
+
+        let ids = store.list_doc_ids().await.unwrap();
+        assert!(ids.iter().all(|id| is_v1_doc_id(id)), "{:?}", ids);
+        assert_eq!(ids.len(), 2, "manifest + one content subdoc: {:?}", ids);
+        assert!(migrate_server_db(&store).await.unwrap().already_migrated);
+    }
 }
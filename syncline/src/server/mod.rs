@@ -1,3 +1,13 @@
+pub mod acl;
+pub mod changes;
 pub mod db;
+pub mod devices;
+pub mod digests;
+pub mod fs_storage;
+pub mod limits;
+pub mod memory_storage;
 pub mod migration;
+pub mod s3_storage;
 pub mod server;
+pub mod storage;
+pub mod tokens;
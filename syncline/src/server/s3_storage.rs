@@ -0,0 +1,704 @@
+//! S3-compatible object storage for server blobs.
+//!
+//! [`S3Storage`] wraps any other [`Storage`] backend and redirects only
+//! the blob half of the trait to an object store — update logs, snapshots
+//! and meta stay where they were (typically SQLite). Attachments can then
+//! live in MinIO, Garage, AWS S3 or anything else that speaks the S3 REST
+//! API, while the database holds nothing but the manifest and text
+//! content.
+//!
+//! Objects are stored at `<prefix><hash>` using path-style addressing
+//! (`<endpoint>/<bucket>/<key>`), which every self-hosted S3 clone
+//! supports. Requests are signed with AWS Signature V4. Because a blob's
+//! key *is* its SHA-256, the signed `x-amz-content-sha256` header on a PUT
+//! doubles as an integrity check: the object store itself rejects a body
+//! that doesn't hash to the key.
+//!
+//! Reads are streamed ([`Storage::open_blob`]) so serving a large blob
+//! never holds more than one copy in memory. Chunked uploads are staged
+//! in a local directory as they arrive and streamed from there into a
+//! single PUT, so they are bounded by [`MAX_STREAMED_BLOB_SIZE`] rather
+//! than by memory. A blob missing from the bucket is looked up in the
+//! inner backend as well, so attachments uploaded before the switch to
+//! S3 stay readable.
+
+use super::storage::{BlobReader, BlobUpload, Changes, Storage, file_blob_reader};
+use crate::protocol::{BLOB_CHUNK_SIZE, MAX_STREAMED_BLOB_SIZE};
+use crate::v1::blob_store::BlobStore;
+use crate::v1::hash_hex;
+use anyhow::{Context, Result, anyhow, bail};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::TryStreamExt;
+use futures_util::stream::{self, BoxStream};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+use url::Url;
+
+/// SHA-256 of the empty string, the payload hash for body-less requests.
+const EMPTY_PAYLOAD_SHA256: &str =
+    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// Connection settings for an S3-compatible endpoint.
+#[derive(Clone, Debug)]
+pub struct S3Config {
+    /// Base URL, e.g. `http://127.0.0.1:9000` for a local MinIO.
+    pub endpoint: String,
+    pub bucket: String,
+    /// Signing region. Self-hosted stores usually accept anything;
+    /// `us-east-1` is the conventional default.
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Optional key prefix, e.g. `syncline/blobs/`.
+    pub prefix: String,
+}
+
+/// Minimal S3 client covering the three calls the blob store needs:
+/// HEAD, GET and PUT of a single object.
+#[derive(Clone)]
+pub struct S3BlobStore {
+    http: reqwest::Client,
+    endpoint: Url,
+    config: Arc<S3Config>,
+}
+
+impl S3BlobStore {
+    pub fn new(config: S3Config) -> Result<Self> {
+        // Same provider the WebSocket client installs; reqwest picks up
+        // the process default.
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let endpoint = Url::parse(&config.endpoint)
+            .with_context(|| format!("invalid S3 endpoint {}", config.endpoint))?;
+        if endpoint.host_str().is_none() {
+            bail!("S3 endpoint has no host: {}", config.endpoint);
+        }
+        let http = reqwest::Client::builder()
+            .build()
+            .context("building S3 HTTP client")?;
+        Ok(Self {
+            http,
+            endpoint,
+            config: Arc::new(config),
+        })
+    }
+
+    fn object_path(&self, hash: &str) -> String {
+        let base = self.endpoint.path().trim_end_matches('/');
+        format!(
+            "{}/{}/{}",
+            base,
+            uri_encode(&self.config.bucket, false),
+            uri_encode(&format!("{}{}", self.config.prefix, hash), false)
+        )
+    }
+
+    fn host_header(&self) -> String {
+        let host = self.endpoint.host_str().unwrap_or_default();
+        match self.endpoint.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        }
+    }
+
+    fn request(
+        &self,
+        method: reqwest::Method,
+        hash: &str,
+        payload_sha256: &str,
+    ) -> reqwest::RequestBuilder {
+        let path = self.object_path(hash);
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+        let signed = sign_v4(
+            &SigningParams {
+                method: method.as_str(),
+                path: &path,
+                host: &self.host_header(),
+                payload_sha256,
+                region: &self.config.region,
+                access_key: &self.config.access_key,
+                secret_key: &self.config.secret_key,
+            },
+            SystemTime::now(),
+        );
+        self.http
+            .request(method, url)
+            .header("x-amz-date", signed.amz_date)
+            .header("x-amz-content-sha256", payload_sha256)
+            .header("authorization", signed.authorization)
+    }
+
+    pub async fn head(&self, hash: &str) -> Result<bool> {
+        let resp = self
+            .request(reqwest::Method::HEAD, hash, EMPTY_PAYLOAD_SHA256)
+            .send()
+            .await
+            .context("S3 HEAD")?;
+        match resp.status() {
+            s if s.is_success() => Ok(true),
+            reqwest::StatusCode::NOT_FOUND => Ok(false),
+            s => bail!("S3 HEAD {} failed: {}", hash, s),
+        }
+    }
+
+    /// Upload `size` bytes from `chunks` as the object `hash`. The body is
+    /// streamed as it is produced; the store checks it against the signed
+    /// SHA-256, so a body that doesn't match the key is rejected.
+    pub async fn put(
+        &self,
+        hash: &str,
+        size: u64,
+        chunks: BoxStream<'static, Result<Bytes>>,
+    ) -> Result<()> {
+        let resp = self
+            .request(reqwest::Method::PUT, hash, hash)
+            .header(reqwest::header::CONTENT_LENGTH, size)
+            .body(reqwest::Body::wrap_stream(chunks))
+            .send()
+            .await
+            .context("S3 PUT")?;
+        if !resp.status().is_success() {
+            bail!("S3 PUT {} failed: {}", hash, resp.status());
+        }
+        Ok(())
+    }
+
+    pub async fn get(&self, hash: &str) -> Result<Option<BlobReader>> {
+        let resp = self
+            .request(reqwest::Method::GET, hash, EMPTY_PAYLOAD_SHA256)
+            .send()
+            .await
+            .context("S3 GET")?;
+        match resp.status() {
+            s if s.is_success() => {
+                let size = resp.content_length().unwrap_or(0);
+                let chunks = resp.bytes_stream().map_err(|e| anyhow!("S3 GET body: {}", e));
+                Ok(Some(BlobReader {
+                    size,
+                    chunks: Box::pin(chunks),
+                }))
+            }
+            reqwest::StatusCode::NOT_FOUND => Ok(None),
+            s => bail!("S3 GET {} failed: {}", hash, s),
+        }
+    }
+}
+
+/// [`Storage`] that keeps blobs in an S3 bucket and delegates everything
+/// else to `inner`.
+#[derive(Clone)]
+pub struct S3Storage {
+    inner: Arc<dyn Storage>,
+    blobs: S3BlobStore,
+    /// Where chunked uploads are staged until they are sent.
+    staging: BlobStore,
+}
+
+impl S3Storage {
+    /// Stage chunked uploads under the system temp directory.
+    pub fn new(inner: Arc<dyn Storage>, config: S3Config) -> Result<Self> {
+        let dir = std::env::temp_dir().join("syncline-s3-staging");
+        Self::with_staging_dir(inner, config, dir)
+    }
+
+    /// Stage chunked uploads under `dir`, which needs room for the
+    /// uploads in flight.
+    pub fn with_staging_dir(
+        inner: Arc<dyn Storage>,
+        config: S3Config,
+        dir: PathBuf,
+    ) -> Result<Self> {
+        Ok(Self {
+            inner,
+            blobs: S3BlobStore::new(config)?,
+            staging: BlobStore::new(dir),
+        })
+    }
+}
+
+impl S3Storage {
+    async fn put_bytes(&self, hash: &str, data: Bytes) -> Result<()> {
+        // First write wins, as with every other backend.
+        if self.blobs.head(hash).await? {
+            return Ok(());
+        }
+        let size = data.len() as u64;
+        let pieces = (0..data.len())
+            .step_by(BLOB_CHUNK_SIZE)
+            .map(move |at| Ok(data.slice(at..(at + BLOB_CHUNK_SIZE).min(data.len()))))
+            .collect::<Vec<_>>();
+        self.blobs.put(hash, size, Box::pin(stream::iter(pieces))).await
+    }
+
+    /// Send the staged, hash-verified blob `hash`.
+    async fn put_staged(&self, hash: &str) -> Result<()> {
+        if self.blobs.head(hash).await? {
+            return Ok(());
+        }
+        let reader = file_blob_reader(self.staging.path_for(hash)).await?;
+        self.blobs.put(hash, reader.size, reader.chunks).await
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn save_update(&self, doc_id: &str, update: &[u8]) -> Result<()> {
+        self.inner.save_update(doc_id, update).await
+    }
+
+    async fn load_doc_updates(&self, doc_id: &str) -> Result<Vec<Vec<u8>>> {
+        self.inner.load_doc_updates(doc_id).await
+    }
+
+    async fn list_doc_ids(&self) -> Result<Vec<String>> {
+        self.inner.list_doc_ids().await
+    }
+
+    async fn count_docs(&self) -> Result<i64> {
+        self.inner.count_docs().await
+    }
+
+    async fn save_snapshot(&self, doc_id: &str, snapshot: &[u8]) -> Result<()> {
+        self.inner.save_snapshot(doc_id, snapshot).await
+    }
+
+    async fn archive_docs(&self, doc_ids: &[String]) -> Result<()> {
+        self.inner.archive_docs(doc_ids).await
+    }
+
+    async fn changes_since(&self, since: u64) -> Result<Changes> {
+        self.inner.changes_since(since).await
+    }
+
+    async fn save_blob(&self, hash: &str, data: &[u8]) -> Result<()> {
+        let actual = hash_hex(data);
+        if actual != hash {
+            bail!("blob hash mismatch: expected {}, computed {}", hash, actual);
+        }
+        self.put_bytes(hash, Bytes::copy_from_slice(data)).await
+    }
+
+    fn max_upload_size(&self) -> u64 {
+        MAX_STREAMED_BLOB_SIZE
+    }
+
+    async fn begin_blob(&self, hash: &str, total: u64) -> Result<BlobUpload> {
+        if total > self.max_upload_size() {
+            bail!("blob {hash} is {total} bytes, over the upload limit");
+        }
+        Ok(BlobUpload::staged(hash, total, self.staging.writer(hash)?))
+    }
+
+    async fn finish_blob(&self, upload: BlobUpload) -> Result<()> {
+        let hash = upload.hash().to_string();
+        let writer = upload.into_writer()?;
+        tokio::task::spawn_blocking(move || writer.finish()).await??;
+        let sent = self.put_staged(&hash).await;
+        if let Err(e) = self.staging.remove(&hash) {
+            tracing::warn!("removing staged blob {}: {}", hash, e);
+        }
+        sent
+    }
+
+    async fn load_blob(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        let Some(reader) = self.blobs.get(hash).await? else {
+            return self.inner.load_blob(hash).await;
+        };
+        let mut data = Vec::with_capacity(reader.size as usize);
+        let mut chunks = reader.chunks;
+        while let Some(chunk) = chunks.try_next().await? {
+            data.extend_from_slice(&chunk);
+        }
+        Ok(Some(data))
+    }
+
+    async fn has_blob(&self, hash: &str) -> Result<bool> {
+        Ok(self.blobs.head(hash).await? || self.inner.has_blob(hash).await?)
+    }
+
+    async fn open_blob(&self, hash: &str) -> Result<Option<BlobReader>> {
+        match self.blobs.get(hash).await? {
+            Some(reader) => Ok(Some(reader)),
+            None => self.inner.open_blob(hash).await,
+        }
+    }
+
+    async fn get_meta(&self, key: &str) -> Result<Option<String>> {
+        self.inner.get_meta(key).await
+    }
+
+    async fn set_meta(&self, key: &str, value: &str) -> Result<()> {
+        self.inner.set_meta(key, value).await
+    }
+}
+
+// ---------------------------------------------------------------------------
+// AWS Signature Version 4 — just enough for single-object requests with
+// no query string and the three signed headers we always send.
+// ---------------------------------------------------------------------------
+
+struct SigningParams<'a> {
+    method: &'a str,
+    /// Already URI-encoded absolute path.
+    path: &'a str,
+    host: &'a str,
+    payload_sha256: &'a str,
+    region: &'a str,
+    access_key: &'a str,
+    secret_key: &'a str,
+}
+
+struct SignedHeaders {
+    amz_date: String,
+    authorization: String,
+}
+
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+fn sign_v4(p: &SigningParams<'_>, now: SystemTime) -> SignedHeaders {
+    let (amz_date, date) = amz_timestamps(now);
+    let canonical_request = format!(
+        "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+        p.method, p.path, p.host, p.payload_sha256, amz_date, SIGNED_HEADERS, p.payload_sha256
+    );
+    let scope = format!("{}/{}/s3/aws4_request", date, p.region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+    let key = signing_key(p.secret_key, &date, p.region, "s3");
+    let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+    SignedHeaders {
+        authorization: format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            p.access_key, scope, SIGNED_HEADERS, signature
+        ),
+        amz_date,
+    }
+}
+
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let k_date = hmac_sha256(format!("AWS4{}", secret).as_bytes(), date.as_bytes());
+    let k_region = hmac_sha256(&k_date, region.as_bytes());
+    let k_service = hmac_sha256(&k_region, service.as_bytes());
+    hmac_sha256(&k_service, b"aws4_request")
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// `(YYYYMMDD'T'HHMMSS'Z', YYYYMMDD)` in UTC.
+fn amz_timestamps(now: SystemTime) -> (String, String) {
+    let secs = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (y, m, d) = civil_from_days((secs / 86_400) as i64);
+    let rem = secs % 86_400;
+    let date = format!("{:04}{:02}{:02}", y, m, d);
+    let stamp = format!(
+        "{}T{:02}{:02}{:02}Z",
+        date,
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60
+    );
+    (stamp, date)
+}
+
+/// Days since 1970-01-01 → proleptic Gregorian (year, month, day).
+/// Howard Hinnant's `civil_from_days`.
+fn civil_from_days(z: i64) -> (i64, u32, u32) {
+    let z = z + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m, d)
+}
+
+/// RFC 3986 percent-encoding as SigV4 expects: unreserved characters
+/// pass through, `/` too unless `encode_slash`.
+fn uri_encode(s: &str, encode_slash: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            b'/' if !encode_slash => out.push('/'),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::memory_storage::MemoryStorage;
+    use crate::server::storage::conformance;
+    use axum::Router;
+    use axum::body::Bytes as AxumBytes;
+    use axum::extract::{Path as AxumPath, State};
+    use axum::http::{HeaderMap, Method, StatusCode};
+    use axum::routing::any;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    const ACCESS: &str = "minioadmin";
+    const SECRET: &str = "minio-secret";
+
+    type Objects = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// In-process stand-in for an S3 endpoint: path-style PUT/GET/HEAD
+    /// on `/<bucket>/<key>`, with SigV4 verification using the same
+    /// credentials the client was configured with.
+    async fn spawn_mock_s3() -> (String, Objects) {
+        let objects: Objects = Arc::default();
+        let app = Router::new()
+            .route("/*path", any(mock_handler))
+            .with_state(objects.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        (format!("http://{}", addr), objects)
+    }
+
+    async fn mock_handler(
+        State(objects): State<Objects>,
+        AxumPath(path): AxumPath<String>,
+        method: Method,
+        headers: HeaderMap,
+        body: AxumBytes,
+    ) -> (StatusCode, Vec<u8>) {
+        let h = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let payload_sha = h("x-amz-content-sha256");
+        let amz_date = h("x-amz-date");
+        let region = "us-east-1";
+        let expected = sign_v4(
+            &SigningParams {
+                method: method.as_str(),
+                path: &format!("/{}", path),
+                host: &h("host"),
+                payload_sha256: &payload_sha,
+                region,
+                access_key: ACCESS,
+                secret_key: SECRET,
+            },
+            parse_amz_date(&amz_date),
+        );
+        if expected.authorization != h("authorization") {
+            return (StatusCode::FORBIDDEN, Vec::new());
+        }
+        let mut objects = objects.lock().unwrap();
+        match method {
+            Method::PUT => {
+                if hash_hex(&body) != payload_sha {
+                    return (StatusCode::BAD_REQUEST, Vec::new());
+                }
+                objects.insert(path, body.to_vec());
+                (StatusCode::OK, Vec::new())
+            }
+            Method::GET => match objects.get(&path) {
+                Some(data) => (StatusCode::OK, data.clone()),
+                None => (StatusCode::NOT_FOUND, Vec::new()),
+            },
+            Method::HEAD => match objects.contains_key(&path) {
+                true => (StatusCode::OK, Vec::new()),
+                false => (StatusCode::NOT_FOUND, Vec::new()),
+            },
+            _ => (StatusCode::METHOD_NOT_ALLOWED, Vec::new()),
+        }
+    }
+
+    /// Inverse of `amz_timestamps` for the mock's signature check.
+    fn parse_amz_date(s: &str) -> SystemTime {
+        let n = |r: std::ops::Range<usize>| s.get(r).and_then(|x| x.parse::<i64>().ok()).unwrap_or(0);
+        let (y, m, d) = (n(0..4), n(4..6), n(6..8));
+        let (hh, mm, ss) = (n(9..11), n(11..13), n(13..15));
+        // days_from_civil, inverse of civil_from_days.
+        let y = if m <= 2 { y - 1 } else { y };
+        let era = y.div_euclid(400);
+        let yoe = y - era * 400;
+        let mp = if m > 2 { m - 3 } else { m + 9 };
+        let doy = (153 * mp + 2) / 5 + d - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+        let secs = days * 86_400 + hh * 3600 + mm * 60 + ss;
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs as u64)
+    }
+
+    fn config(endpoint: &str) -> S3Config {
+        S3Config {
+            endpoint: endpoint.to_string(),
+            bucket: "vault".to_string(),
+            region: "us-east-1".to_string(),
+            access_key: ACCESS.to_string(),
+            secret_key: SECRET.to_string(),
+            prefix: "blobs/".to_string(),
+        }
+    }
+
+    #[test]
+    fn signing_key_matches_aws_reference_vector() {
+        // From the AWS SigV4 documentation ("Deriving the signing key").
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex(&key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+
+    #[test]
+    fn timestamps_are_utc_basic_format() {
+        // 2013-05-24T00:00:00Z, the date used in the AWS S3 examples.
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1_369_353_600);
+        assert_eq!(
+            amz_timestamps(t),
+            ("20130524T000000Z".to_string(), "20130524".to_string())
+        );
+        assert_eq!(parse_amz_date("20130524T000000Z"), t);
+    }
+
+    #[test]
+    fn uri_encode_keeps_unreserved_and_slashes() {
+        assert_eq!(uri_encode("a/b c~", false), "a/b%20c~");
+        assert_eq!(uri_encode("a/b", true), "a%2Fb");
+    }
+
+    #[tokio::test]
+    async fn s3_backend_conformance_against_mock() {
+        let (endpoint, _objects) = spawn_mock_s3().await;
+        conformance::run_all(|| {
+            // Fresh bucket prefix per run so state doesn't leak between checks.
+            let mut cfg = config(&endpoint);
+            cfg.prefix = format!("{}/", uuid::Uuid::new_v4());
+            async move { S3Storage::new(Arc::new(MemoryStorage::new()), cfg).unwrap() }
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn blobs_land_in_bucket_not_inner_storage() {
+        let (endpoint, objects) = spawn_mock_s3().await;
+        let inner = MemoryStorage::new();
+        let s = S3Storage::new(Arc::new(inner.clone()), config(&endpoint)).unwrap();
+
+        let data = b"attachment".to_vec();
+        let hash = hash_hex(&data);
+        s.save_blob(&hash, &data).await.unwrap();
+
+        let key = format!("vault/blobs/{}", hash);
+        assert_eq!(objects.lock().unwrap().get(&key), Some(&data));
+        assert!(!inner.has_blob(&hash).await.unwrap());
+        assert_eq!(s.load_blob(&hash).await.unwrap(), Some(data));
+    }
+
+    #[tokio::test]
+    async fn chunked_uploads_stream_from_the_staging_dir() {
+        let (endpoint, objects) = spawn_mock_s3().await;
+        let staging = tempfile::TempDir::new().unwrap();
+        let s = S3Storage::with_staging_dir(
+            Arc::new(MemoryStorage::new()),
+            config(&endpoint),
+            staging.path().to_path_buf(),
+        )
+        .unwrap();
+        assert!(s.max_upload_size() > crate::protocol::MAX_BLOB_SIZE as u64);
+
+        let data: Vec<u8> = (0..3 * BLOB_CHUNK_SIZE as u32 / 2).map(|i| i as u8).collect();
+        let hash = hash_hex(&data);
+        let mut upload = s.begin_blob(&hash, data.len() as u64).await.unwrap();
+        for piece in data.chunks(BLOB_CHUNK_SIZE) {
+            upload.write(piece).unwrap();
+        }
+        s.finish_blob(upload).await.unwrap();
+
+        let key = format!("vault/blobs/{}", hash);
+        assert_eq!(objects.lock().unwrap().get(&key), Some(&data));
+        let left: Vec<_> = walkdir::WalkDir::new(staging.path())
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_file())
+            .collect();
+        assert!(left.is_empty(), "staging dir not cleaned up: {left:?}");
+    }
+
+    #[tokio::test]
+    async fn blobs_predating_s3_are_served_from_inner_storage() {
+        let (endpoint, _objects) = spawn_mock_s3().await;
+        let inner = MemoryStorage::new();
+        let data = b"uploaded before S3".to_vec();
+        let hash = hash_hex(&data);
+        inner.save_blob(&hash, &data).await.unwrap();
+
+        let s = S3Storage::new(Arc::new(inner), config(&endpoint)).unwrap();
+        assert!(s.has_blob(&hash).await.unwrap());
+        assert_eq!(s.load_blob(&hash).await.unwrap(), Some(data));
+    }
+
+    #[tokio::test]
+    async fn wrong_credentials_surface_as_errors() {
+        let (endpoint, _objects) = spawn_mock_s3().await;
+        let mut cfg = config(&endpoint);
+        cfg.secret_key = "not-the-secret".to_string();
+        let s = S3Storage::new(Arc::new(MemoryStorage::new()), cfg).unwrap();
+        assert!(s.has_blob(&"0".repeat(64)).await.is_err());
+    }
+
+    /// Runs against a real MinIO when `SYNCLINE_TEST_S3_ENDPOINT` is set,
+    /// e.g. `docker run -p 9000:9000 minio/minio server /data` plus a
+    /// bucket named by `SYNCLINE_TEST_S3_BUCKET`.
+    #[tokio::test]
+    #[ignore]
+    async fn s3_backend_conformance_against_minio() {
+        let Ok(endpoint) = std::env::var("SYNCLINE_TEST_S3_ENDPOINT") else {
+            return;
+        };
+        let env = |k: &str, d: &str| std::env::var(k).unwrap_or_else(|_| d.to_string());
+        conformance::run_all(|| {
+            let cfg = S3Config {
+                endpoint: endpoint.clone(),
+                bucket: env("SYNCLINE_TEST_S3_BUCKET", "syncline-test"),
+                region: env("SYNCLINE_TEST_S3_REGION", "us-east-1"),
+                access_key: env("SYNCLINE_TEST_S3_ACCESS_KEY", "minioadmin"),
+                secret_key: env("SYNCLINE_TEST_S3_SECRET_KEY", "minioadmin"),
+                prefix: format!("{}/", uuid::Uuid::new_v4()),
+            };
+            async move { S3Storage::new(Arc::new(MemoryStorage::new()), cfg).unwrap() }
+        })
+        .await;
+    }
+}
@@ -4,47 +4,111 @@
 //! the DB on startup) plus a set of per-doc broadcast channels. Clients
 //! speak the v1 wire protocol (see `protocol.rs`):
 //!
-//! - first frame must be [`MSG_VERSION`] with major/minor = 1/0
+//! - first frame must be [`MSG_VERSION`] with major = 1
 //! - manifest sync is driven through [`MSG_MANIFEST_SYNC`] /
 //!   [`MSG_MANIFEST_VERIFY`] frames targeting [`MANIFEST_DOC_ID`]
 //! - text content subdocs sync through standard
 //!   [`MSG_SYNC_STEP_1`] / [`MSG_SYNC_STEP_2`] / [`MSG_UPDATE`] frames
 //!   with `doc_id = "content:<node-hex>"`
-//! - binaries continue through [`MSG_BLOB_UPDATE`] / [`MSG_BLOB_REQUEST`]
+//! - binaries continue through [`MSG_BLOB_UPDATE`] / [`MSG_BLOB_REQUEST`];
+//!   peers at minor >= 1 also stream them as [`MSG_BLOB_CHUNK`] pieces,
+//!   and get chunked replies to their requests
+//!
+//! Each connection is metered against the server's [`Limits`] (see
+//! `limits.rs`): inbound frames are throttled to the configured rates
+//! and writes past a storage quota close the socket with the reason.
+//!
+//! From minor 7 each side lists its features in a [`MSG_CAPS`] frame
+//! behind its version, and a connection uses only the ones both ends
+//! support; older clients are taken to support what their minor
+//! version introduced.
+//!
+//! With [`CAP_COMPRESSION`] agreed, the send task deflates sync
+//! replies, manifest frames and blob data that shrink enough into
+//! [`MSG_COMPRESSED`] frames, and the message loop inflates the client's
+//! before handling them like any other frame.
+//!
+//! Clients at minor >= 3 subscribe to many content docs per
+//! [`MSG_SYNC_BATCH`] frame and hear back only about docs whose digest
+//! differs from the server's (see `digests.rs`). Clients at minor >= 4
+//! resume from a cursor in the server's change feed with
+//! [`MSG_CHANGES`] and then receive every content update they may read
+//! without subscribing doc by doc (see `changes.rs`).
+//!
+//! Clients at minor >= 5 wrap the updates they want confirmed in
+//! [`MSG_TAGGED`], and hear a [`MSG_ACK`] once each is stored or
+//! refused. Clients at minor >= 6 are also told why with a
+//! [`MSG_ERROR`]: a blob too large or not found, a storage failure. A
+//! bad handshake gets one whatever the client's version, then a close.
+//!
+//! Clients name their actor with [`MSG_HELLO`] before anything else;
+//! revoked devices are turned away and their late manifest writes
+//! dropped (see `devices.rs`).
+//!
+//! Tokens that per-folder rules keep from writing somewhere are partial
+//! replicas: they sync a per-token view of the manifest instead of the
+//! manifest itself, and only the content and blobs of nodes they may
+//! read (see `acl.rs`).
 //!
 //! A v0 client that speaks a pre-manifest protocol will either fail the
 //! version handshake (if it sends no MSG_VERSION) or send messages that
 //! don't match a known v1 type — both paths close the connection with
-//! an explanatory log line. We do not ship a bespoke "upgrade required"
-//! frame because v0 clients would interpret any unknown payload as
-//! garbage anyway; closing the socket is the least ambiguous signal.
+//! an explanatory log line. The close is preceded by an [`ERR_VERSION`]
+//! error frame, which a v0 client reads as garbage but a v1 client from
+//! another major version can show to its user.
 
 use crate::protocol::{
-    MANIFEST_DOC_ID, MSG_BLOB_REQUEST, MSG_BLOB_UPDATE, MSG_MANIFEST_SYNC,
-    MSG_MANIFEST_VERIFY, MSG_SYNC_STEP_1, MSG_SYNC_STEP_2, MSG_UPDATE, MSG_VERSION,
-    V1_PROTOCOL_MAJOR, V1_PROTOCOL_MINOR, decode_message, encode_message,
+    ACK_FAILED, ACK_REFUSED, ACK_SAVED, BLOB_CHUNK_SIZE, CAP_BLOB_CHUNKS, CAP_COMPRESSION,
+    CAP_ERRORS, CAP_HELLO, DEVICE_REVOKED_REASON, ERR_BLOB_NOT_FOUND, ERR_BLOB_TOO_LARGE,
+    ERR_COMPRESSION, ERR_STORAGE, ERR_VERSION, MANIFEST_DOC_ID, MAX_BLOB_SIZE, MSG_ACK,
+    MSG_BLOB_CHUNK, MSG_BLOB_REQUEST, MSG_BLOB_UPDATE, MSG_CAPS, MSG_CHANGES, MSG_COMPRESSED,
+    MSG_ERROR, MSG_HELLO, MSG_MANIFEST_SYNC, MSG_MANIFEST_VERIFY, MSG_SYNC_BATCH, MSG_SYNC_STEP_1,
+    MSG_SYNC_STEP_2, MSG_TAGGED, MSG_UPDATE, MSG_VERSION, V1_MINOR_CAPS, V1_PROTOCOL_MAJOR,
+    V1_PROTOCOL_MINOR, decode_blob_chunk, decode_message, encode_blob_chunk, encode_message,
+    encode_message_header,
+};
+use crate::server::acl::{
+    self, Access, VIEW_DOC_PREFIX, merge_view, node_path, refresh_view, view_doc_id,
 };
-use crate::server::db::Db;
+use crate::server::changes::{ChangeFeed, ContentChange};
+use crate::server::devices::Registry;
+use crate::server::digests::Digests;
+use crate::server::limits::{ANONYMOUS_TOKEN, Limits, Quotas, RateLimiter};
 use crate::server::migration::migrate_server_db;
-use crate::v1::ids::Lamport;
+use crate::server::storage::{BlobUpload, Storage};
+use crate::server::tokens::{self, Authentication};
+use crate::v1::ids::{ActorId, Lamport, NodeId};
 use crate::v1::manifest::Manifest;
+use crate::v1::projection::PathEquivalence;
 use crate::v1::sync::{
-    decode_version_handshake, encode_version_handshake, handle_manifest_payload,
-    handle_verify_payload, manifest_step1_payload, split_manifest_payload,
+    Caps, ChangesReply, Cursor, compress_frame, decode_caps, decode_changes_request,
+    decode_compressed, decode_hello, decode_sync_batch, decode_tagged, decode_version_handshake,
+    encode_ack, encode_caps, encode_changes_reply, encode_error, encode_version_handshake,
+    handle_manifest_payload, handle_verify_payload, manifest_step1_payload,
+    split_manifest_payload,
 };
 use axum::{
     Router,
     extract::{
-        State, WebSocketUpgrade,
-        ws::{Message, WebSocket},
+        Query, State, WebSocketUpgrade,
+        ws::{CloseFrame, Message, WebSocket, close_code},
     },
+    http::{HeaderMap, StatusCode},
     response::IntoResponse,
     routing::get,
 };
 use futures_util::{SinkExt, StreamExt};
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
-use tokio::sync::{Mutex as AsyncMutex, RwLock, broadcast, mpsc};
-use yrs::{StateVector, updates::decoder::Decode};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{Mutex as AsyncMutex, RwLock, broadcast, mpsc, oneshot};
+use yrs::{ReadTxn, StateVector, Transact, updates::decoder::Decode};
 
 type ChannelMap = Arc<RwLock<HashMap<String, broadcast::Sender<(Vec<u8>, uuid::Uuid)>>>>;
 
@@ -57,20 +121,65 @@ type ChannelMap = Arc<RwLock<HashMap<String, broadcast::Sender<(Vec<u8>, uuid::U
 // subscriber, which catches up immediately, so a small cap is plenty.
 const PER_DOC_BROADCAST_CAP: usize = 64;
 
+// Outgoing blob chunks go through their own bounded queue so a slow
+// reader applies backpressure to the blob stream instead of letting a
+// multi-GB reply pile up in the unbounded control-frame queue.
+const BLOB_SEND_WINDOW: usize = 4;
+
+// Chunked uploads a single connection may have in flight. Backends that
+// buffer uploads in memory hold up to this many partial blobs at once.
+const MAX_PENDING_UPLOADS: usize = 8;
+
+// How long a connection must go unthrottled before the next throttle
+// is logged at warn again, so a flooding client yields one line per
+// episode rather than one per frame.
+const THROTTLE_LOG_QUIET: Duration = Duration::from_secs(10);
+
 #[derive(Clone)]
 struct AppState {
-    db: Db,
+    /// Persistence backend — SQLite, filesystem or in-memory depending
+    /// on how the server was started.
+    db: Arc<dyn Storage>,
     channels: ChannelMap,
     /// The server's authoritative manifest. Locked for mutation while
     /// applying an incoming MANIFEST_STEP_2/UPDATE; held only briefly
     /// for STEP_1 responses (state-vector read + update encoding).
     manifest: Arc<AsyncMutex<Manifest>>,
+    /// Configured limits plus the usage they are checked against.
+    quotas: Arc<Quotas>,
+    /// Actors seen and revoked.
+    devices: Arc<Registry>,
+    /// Views of the partial replicas connected since startup, by token.
+    /// Lock after `manifest` when both are needed.
+    views: Arc<AsyncMutex<HashMap<String, View>>>,
+    /// Content digests for answering batched subscribes.
+    digests: Arc<Digests>,
+    /// Every stored content update, for connections resuming from a
+    /// change-feed cursor.
+    feed: Arc<ChangeFeed>,
+}
+
+/// A partial replica's view of the manifest and the access it was last
+/// refreshed under.
+struct View {
+    manifest: Manifest,
+    access: Access,
 }
 
-pub async fn run_server(db: Db, port: u16) -> anyhow::Result<()> {
+pub async fn run_server<S: Storage + 'static>(db: S, port: u16) -> anyhow::Result<()> {
+    run_server_with_storage(Arc::new(db), port, Limits::default()).await
+}
+
+/// [`run_server`] for callers that already hold a type-erased backend
+/// (e.g. chosen at runtime from a CLI flag) and may configure limits.
+pub async fn run_server_with_storage(
+    db: Arc<dyn Storage>,
+    port: u16,
+    limits: Limits,
+) -> anyhow::Result<()> {
     // Phase 3.2: migrate the DB if it's still v0 — idempotent if
     // already migrated.
-    let report = migrate_server_db(&db).await?;
+    let report = migrate_server_db(db.as_ref()).await?;
     if !report.already_migrated {
         tracing::info!(
             "Migrated server DB to v1: {} text + {} binary + {} skipped (actor {})",
@@ -90,12 +199,23 @@ pub async fn run_server(db: Db, port: u16) -> anyhow::Result<()> {
     }
 
     // Hydrate the in-memory manifest from DB rows.
-    let manifest = hydrate_manifest(&db, report.actor_id).await?;
+    let manifest = hydrate_manifest(db.as_ref(), report.actor_id).await?;
+
+    let quotas = Quotas::load(db.clone(), limits).await?;
+    let feed = ChangeFeed::load(db.as_ref()).await?;
+    if !quotas.limits.is_unlimited() {
+        tracing::info!("Limits: {}", quotas.limits);
+    }
 
     let state = AppState {
+        devices: Arc::new(Registry::new(db.clone())),
         db,
         channels: Arc::new(RwLock::new(HashMap::new())),
         manifest: Arc::new(AsyncMutex::new(manifest)),
+        quotas: Arc::new(quotas),
+        views: Arc::new(AsyncMutex::new(HashMap::new())),
+        digests: Arc::new(Digests::default()),
+        feed: Arc::new(feed),
     };
 
     let app = Router::new()
@@ -111,8 +231,21 @@ pub async fn run_server(db: Db, port: u16) -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn hydrate_manifest(db: &Db, actor: crate::v1::ids::ActorId) -> anyhow::Result<Manifest> {
-    let updates = db.load_doc_updates(MANIFEST_DOC_ID).await.unwrap_or_default();
+pub(crate) async fn hydrate_manifest(
+    db: &dyn Storage,
+    actor: ActorId,
+) -> anyhow::Result<Manifest> {
+    hydrate_manifest_doc(db, MANIFEST_DOC_ID, actor).await
+}
+
+/// [`hydrate_manifest`] for a manifest stored under another doc id,
+/// such as a partial replica's view.
+async fn hydrate_manifest_doc(
+    db: &dyn Storage,
+    doc_id: &str,
+    actor: ActorId,
+) -> anyhow::Result<Manifest> {
+    let updates = db.load_doc_updates(doc_id).await.unwrap_or_default();
     if updates.is_empty() {
         return Ok(Manifest::new(actor));
     }
@@ -128,18 +261,86 @@ async fn hydrate_manifest(db: &Db, actor: crate::v1::ids::ActorId) -> anyhow::Re
     Ok(m)
 }
 
-async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state))
+async fn ws_handler(
+    ws: WebSocketUpgrade,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> impl IntoResponse {
+    let (token, secret) = connection_token(&query, &headers);
+    let verified = match tokens::authenticate(state.db.as_ref(), &token, secret.as_deref()).await {
+        Ok(Authentication::Verified) => true,
+        Ok(Authentication::Label) => false,
+        Ok(Authentication::Rejected) => {
+            tracing::warn!(token, "refusing connection: wrong or missing token secret");
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+        Err(e) => {
+            tracing::error!("load token secrets: {}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    ws.on_upgrade(move |socket| handle_socket(socket, state, token, verified))
+}
+
+/// The account token a connection is metered under, and the secret it
+/// presented for it: `?token=` on the URL, else an
+/// `Authorization: Bearer` header, else [`ANONYMOUS_TOKEN`]. Either
+/// holds `<token>` or `<token>:<secret>`.
+fn connection_token(
+    query: &HashMap<String, String>,
+    headers: &HeaderMap,
+) -> (String, Option<String>) {
+    let credential = query
+        .get("token")
+        .map(String::as_str)
+        .or_else(|| {
+            headers
+                .get(axum::http::header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "))
+        })
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .unwrap_or(ANONYMOUS_TOKEN);
+    match credential.split_once(':') {
+        Some((token, secret)) => (token.to_string(), Some(secret.to_string())),
+        None => (credential.to_string(), None),
+    }
 }
 
-async fn handle_socket(socket: WebSocket, state: AppState) {
+/// Serve one upgraded connection. `verified` is whether it proved it
+/// holds `token`'s secret.
+async fn handle_socket(socket: WebSocket, state: AppState, token: String, verified: bool) {
     let connection_id = uuid::Uuid::new_v4();
     let (mut sender, mut receiver) = socket.split();
 
     let (tx_socket, mut rx_socket) = mpsc::unbounded_channel::<Vec<u8>>();
+    let (tx_blob, mut rx_blob) = mpsc::channel::<Vec<u8>>(BLOB_SEND_WINDOW);
+    let (tx_close, mut rx_close) = oneshot::channel::<CloseFrame<'static>>();
+    // Set once the handshake shows the client reads MSG_COMPRESSED.
+    let compress = Arc::new(AtomicBool::new(false));
+    let compress_out = compress.clone();
 
-    let send_task = tokio::spawn(async move {
-        while let Some(data) = rx_socket.recv().await {
+    let mut send_task = tokio::spawn(async move {
+        loop {
+            // Control frames first: a long blob reply must not delay
+            // manifest or content traffic behind it.
+            let data = tokio::select! {
+                biased;
+                Ok(frame) = &mut rx_close => {
+                    let _ = sender.send(Message::Close(Some(frame))).await;
+                    break;
+                }
+                Some(data) = rx_socket.recv() => data,
+                Some(data) = rx_blob.recv() => data,
+                else => break,
+            };
+            let data = if compress_out.load(Ordering::Relaxed) {
+                compress_frame(&data).unwrap_or(data)
+            } else {
+                data
+            };
             if sender.send(Message::Binary(data)).await.is_err() {
                 break;
             }
@@ -155,18 +356,18 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
         // -----------------------------------------------------------------
         let first = match receiver.next().await {
             Some(Ok(Message::Binary(data))) => data,
-            Some(Ok(Message::Close(_))) => return,
+            Some(Ok(Message::Close(_))) => return None,
             _ => {
                 tracing::warn!(
                     conn = %connection_id,
                     "closing: first frame was not a binary WebSocket message"
                 );
-                return;
+                return None;
             }
         };
         let Some((msg_type, doc_id, payload)) = decode_message(&first) else {
             tracing::warn!(conn = %connection_id, "closing: malformed first frame");
-            return;
+            return Some(handshake_refused(&tx_out, "malformed first frame".into()));
         };
         if msg_type != MSG_VERSION {
             tracing::warn!(
@@ -175,11 +376,14 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
                 doc_id,
                 "closing: first frame must be MSG_VERSION (client speaks v0 or wrong protocol)"
             );
-            return;
+            return Some(handshake_refused(
+                &tx_out,
+                "first frame must be MSG_VERSION".into(),
+            ));
         }
         let Some((major, minor)) = decode_version_handshake(payload) else {
             tracing::warn!(conn = %connection_id, "closing: malformed version handshake");
-            return;
+            return Some(handshake_refused(&tx_out, "malformed version handshake".into()));
         };
         if major != V1_PROTOCOL_MAJOR {
             tracing::warn!(
@@ -189,52 +393,271 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
                 V1_PROTOCOL_MAJOR,
                 V1_PROTOCOL_MINOR
             );
-            return;
+            return Some(handshake_refused(
+                &tx_out,
+                format!(
+                    "protocol {major}.{minor} is incompatible with server {V1_PROTOCOL_MAJOR}.{V1_PROTOCOL_MINOR}"
+                ),
+            ));
         }
+        // From 1.7 the client's capabilities follow its version.
+        let peer_caps = if minor >= V1_MINOR_CAPS {
+            let caps = match receiver.next().await {
+                Some(Ok(Message::Binary(data))) => decode_message(&data)
+                    .filter(|(t, _, _)| *t == MSG_CAPS)
+                    .and_then(|(_, _, payload)| decode_caps(payload)),
+                _ => None,
+            };
+            let Some(caps) = caps else {
+                tracing::warn!(conn = %connection_id, "closing: MSG_CAPS must follow MSG_VERSION");
+                return Some(handshake_refused(
+                    &tx_out,
+                    "MSG_CAPS must follow MSG_VERSION".into(),
+                ));
+            };
+            caps
+        } else {
+            Caps::of_minor(minor)
+        };
+        let caps = Caps::ALL.intersect(peer_caps);
         // Echo our version back so the client can confirm the server
-        // is v1 too.
+        // is v1 too, and tell a client that can read them what we
+        // support.
         let _ = tx_out.send(encode_message(
             MSG_VERSION,
             MANIFEST_DOC_ID,
             &encode_version_handshake(),
         ));
-        tracing::info!(conn = %connection_id, "v1 handshake OK (peer {}.{})", major, minor);
+        if minor >= V1_MINOR_CAPS {
+            let _ = tx_out.send(encode_message(MSG_CAPS, MANIFEST_DOC_ID, &encode_caps(Caps::ALL)));
+        }
+        let devices = state_for_recv.devices.clone();
+        if verified && devices.token_refused(&token).await {
+            tracing::warn!(conn = %connection_id, token, "closing: token revoked");
+            return Some(revoked_close());
+        }
+        let access = match acl::list_rules(state_for_recv.db.as_ref()).await {
+            Ok(rules) => Access::new(&token, verified, rules),
+            Err(e) => {
+                tracing::error!(conn = %connection_id, "closing: load access rules: {}", e);
+                return None;
+            }
+        };
+        // Full replicas skip every access check below.
+        let partial = (!access.is_full()).then_some(access);
+        if partial.is_some() {
+            tracing::info!(conn = %connection_id, token, "partial replica: syncing its view");
+        }
+        let quotas = state_for_recv.quotas.clone();
+        let (vault_used, blob_used) = quotas.usage(&token).await;
+        tracing::info!(
+            conn = %connection_id,
+            token,
+            vault_bytes = vault_used,
+            blob_bytes = blob_used,
+            caps = format!("{:#x}", caps.0),
+            "v1 handshake OK (peer {}.{})",
+            major,
+            minor
+        );
+        let peer_chunks_blobs = caps.has(CAP_BLOB_CHUNKS);
+        compress.store(caps.has(CAP_COMPRESSION), Ordering::Relaxed);
+        let errors = Errors(caps.has(CAP_ERRORS).then(|| tx_out.clone()));
+        let mut uploads: HashMap<String, BlobUpload> = HashMap::new();
+        let mut meter = Meter::new(&quotas.limits, quotas.token_rate(&token));
+        // Set once the connection is on the change feed; content docs
+        // then need no subscription of their own.
+        let mut on_feed = false;
+        // The actor the peer named in its hello, if it sent one.
+        let mut actor = None;
 
         // -----------------------------------------------------------------
         // Step 2 — message loop.
         // -----------------------------------------------------------------
+        let mut close = None;
         while let Some(Ok(msg)) = receiver.next().await {
             let data = match msg {
                 Message::Binary(b) => b,
                 Message::Close(_) => break,
                 _ => continue,
             };
+            meter.throttle(connection_id, &token, data.len()).await;
             let Some((msg_type, doc_id, payload)) = decode_message(&data) else {
                 tracing::debug!(conn = %connection_id, "skipping malformed frame");
                 continue;
             };
-            match msg_type {
-                MSG_MANIFEST_SYNC if doc_id == MANIFEST_DOC_ID => {
-                    handle_manifest_sync(
+            // A tagged update is handled like the frame it wraps, then
+            // acknowledged.
+            let (tag, msg_type, payload) = if msg_type == MSG_TAGGED {
+                let Some((seq, msg_type, payload)) = decode_tagged(payload) else {
+                    tracing::debug!(conn = %connection_id, "skipping malformed tagged frame");
+                    continue;
+                };
+                (Some(seq), msg_type, payload)
+            } else {
+                (None, msg_type, payload)
+            };
+            // So is a compressed one, once inflated, if the peer said it
+            // would send them. Inflated bytes are metered like wire ones.
+            // Any other is answered, so the peer can send it uncompressed.
+            let inflated;
+            let (msg_type, payload) = if msg_type == MSG_COMPRESSED {
+                if !caps.has(CAP_COMPRESSION) {
+                    tracing::debug!(conn = %connection_id, "refusing unnegotiated compressed frame");
+                    compression_refused(&tx_out, &errors, doc_id, tag, "not negotiated");
+                    continue;
+                }
+                let Some((msg_type, bytes)) = decode_compressed(payload) else {
+                    tracing::debug!(conn = %connection_id, "refusing malformed compressed frame");
+                    compression_refused(&tx_out, &errors, doc_id, tag, "malformed");
+                    continue;
+                };
+                let extra = bytes.len().saturating_sub(payload.len());
+                meter.charge(connection_id, &token, extra).await;
+                inflated = bytes;
+                (msg_type, &inflated[..])
+            } else {
+                (msg_type, payload)
+            };
+            if msg_type == MSG_HELLO && doc_id == MANIFEST_DOC_ID {
+                let Some(hello_actor) = decode_hello(payload) else {
+                    tracing::debug!(conn = %connection_id, "skipping malformed hello");
+                    continue;
+                };
+                if devices.refused(hello_actor).await.is_some() {
+                    tracing::warn!(
+                        conn = %connection_id,
+                        token,
+                        actor = %hello_actor,
+                        "closing: device revoked"
+                    );
+                    close = Some(revoked_close());
+                    break;
+                }
+                devices.seen(hello_actor, &token).await;
+                tracing::info!(conn = %connection_id, actor = %hello_actor, "device identified");
+                actor = Some(hello_actor);
+                continue;
+            }
+            // A peer that can name its actor must do so before anything
+            // else, so its revocation is checked before its first write.
+            if actor.is_none() && caps.has(CAP_HELLO) {
+                tracing::warn!(conn = %connection_id, msg_type, "closing: frame before MSG_HELLO");
+                close = Some(CloseFrame {
+                    code: close_code::POLICY,
+                    reason: "MSG_HELLO must precede other frames".into(),
+                });
+                break;
+            }
+            // A whole blob is hashed once, for the quota check and the
+            // store; a chunked one is named by its hash.
+            let whole_blob_hash =
+                (msg_type == MSG_BLOB_UPDATE).then(|| crate::v1::hash_hex(payload));
+            let blob_hash = whole_blob_hash.as_deref().unwrap_or(doc_id);
+            let charge = match quota_charge(msg_type, doc_id, payload, &uploads) {
+                // Re-sending a blob the server already holds stores nothing.
+                Some((_, true)) if blob_stored(&state_for_recv, blob_hash).await => None,
+                charge => charge,
+            };
+            if let Some((len, blob)) = charge
+                && let Err(e) = quotas.check(&token, len, blob).await
+            {
+                tracing::warn!(conn = %connection_id, token, "closing: {}", e);
+                close = Some(CloseFrame {
+                    code: close_code::POLICY,
+                    reason: e.to_string().into(),
+                });
+                break;
+            }
+            let stored = match msg_type {
+                MSG_MANIFEST_SYNC if doc_id == MANIFEST_DOC_ID => match &partial {
+                    Some(access) => {
+                        handle_view_sync(
+                            &state_for_recv,
+                            connection_id,
+                            &tx_out,
+                            &errors,
+                            payload,
+                            access,
+                            actor,
+                        )
+                        .await
+                    }
+                    None => {
+                        handle_manifest_sync(
+                            &state_for_recv,
+                            connection_id,
+                            &tx_out,
+                            &errors,
+                            payload,
+                        )
+                        .await
+                    }
+                },
+                MSG_MANIFEST_VERIFY if doc_id == MANIFEST_DOC_ID => {
+                    handle_manifest_verify(&state_for_recv, &tx_out, payload, partial.as_ref())
+                        .await;
+                    Ok(0)
+                }
+                MSG_SYNC_BATCH if doc_id == MANIFEST_DOC_ID => {
+                    handle_sync_batch(
                         &state_for_recv,
                         connection_id,
                         &tx_out,
                         payload,
+                        partial.as_ref(),
+                        !on_feed,
                     )
                     .await;
+                    Ok(0)
                 }
-                MSG_MANIFEST_VERIFY if doc_id == MANIFEST_DOC_ID => {
-                    handle_manifest_verify(&state_for_recv, &tx_out, payload).await;
+                MSG_CHANGES if doc_id == MANIFEST_DOC_ID => {
+                    handle_changes(
+                        &state_for_recv,
+                        connection_id,
+                        &tx_out,
+                        payload,
+                        partial.as_ref(),
+                        &mut on_feed,
+                    )
+                    .await;
+                    Ok(0)
+                }
+                MSG_SYNC_STEP_1 | MSG_SYNC_STEP_2 | MSG_UPDATE
+                    if doc_id.starts_with("content:")
+                        && !content_allowed(
+                            &state_for_recv,
+                            partial.as_ref(),
+                            doc_id,
+                            msg_type != MSG_SYNC_STEP_1,
+                        )
+                        .await =>
+                {
+                    tracing::debug!(
+                        conn = %connection_id,
+                        doc_id,
+                        "refusing content sync outside the token's access"
+                    );
+                    Err(Unsaved::Refused)
                 }
                 MSG_SYNC_STEP_1 if doc_id.starts_with("content:") => {
+                    let gate = partial.as_ref().and_then(|access| {
+                        Some(ReadGate {
+                            manifest: state_for_recv.manifest.clone(),
+                            access: access.clone(),
+                            node: content_node(doc_id)?,
+                        })
+                    });
                     handle_content_step1(
                         &state_for_recv,
                         connection_id,
                         &tx_out,
                         doc_id,
                         payload,
+                        (!on_feed).then_some(gate),
                     )
                     .await;
+                    Ok(0)
                 }
                 MSG_SYNC_STEP_2 | MSG_UPDATE if doc_id.starts_with("content:") => {
                     // Persist the raw yrs update and broadcast. STEP_2
@@ -243,22 +666,50 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
                     handle_content_update(
                         &state_for_recv,
                         connection_id,
+                        &errors,
                         doc_id,
                         payload,
                     )
-                    .await;
+                    .await
                 }
                 MSG_BLOB_UPDATE => {
                     handle_blob_update(
                         &state_for_recv,
                         connection_id,
+                        &errors,
                         doc_id,
+                        blob_hash,
                         payload,
                     )
-                    .await;
+                    .await
+                }
+                MSG_BLOB_CHUNK => {
+                    handle_blob_chunk(
+                        &state_for_recv,
+                        connection_id,
+                        &errors,
+                        &mut uploads,
+                        doc_id,
+                        payload,
+                    )
+                    .await
+                }
+                MSG_BLOB_REQUEST
+                    if !blob_allowed(&state_for_recv, partial.as_ref(), payload).await =>
+                {
+                    tracing::debug!(
+                        conn = %connection_id,
+                        "refusing blob request outside the token's access"
+                    );
+                    Ok(0)
+                }
+                MSG_BLOB_REQUEST if peer_chunks_blobs => {
+                    handle_blob_request_chunked(&state_for_recv, &tx_blob, &errors, payload);
+                    Ok(0)
                 }
                 MSG_BLOB_REQUEST => {
-                    handle_blob_request(&state_for_recv, &tx_out, doc_id, payload).await;
+                    handle_blob_request(&state_for_recv, &tx_out, &errors, doc_id, payload).await;
+                    Ok(0)
                 }
                 other => {
                     tracing::debug!(
@@ -267,36 +718,248 @@ async fn handle_socket(socket: WebSocket, state: AppState) {
                         doc_id,
                         "ignoring frame with unexpected msg_type / doc_id"
                     );
+                    Err(Unsaved::Refused)
                 }
+            };
+            if let Some(seq) = tag {
+                let status = match stored {
+                    Ok(_) => ACK_SAVED,
+                    Err(Unsaved::Failed) => ACK_FAILED,
+                    Err(Unsaved::Refused) => ACK_REFUSED,
+                };
+                let _ = tx_out.send(encode_message(MSG_ACK, doc_id, &encode_ack(seq, status)));
+            }
+            if let Ok(stored) = stored
+                && stored > 0
+            {
+                let blob = matches!(msg_type, MSG_BLOB_UPDATE | MSG_BLOB_CHUNK);
+                quotas.record(&token, stored, blob).await;
             }
         }
-        tracing::debug!(conn = %connection_id, "receive loop ended");
+        quotas.flush().await;
+        let (vault_used, blob_used) = quotas.usage(&token).await;
+        tracing::info!(
+            conn = %connection_id,
+            token,
+            frames = meter.frames,
+            bytes = meter.bytes,
+            throttled_ms = meter.throttled.as_millis() as u64,
+            vault_bytes = vault_used,
+            blob_bytes = blob_used,
+            "connection closed"
+        );
+        close
     });
 
-    recv_task.await.ok();
-    send_task.abort();
+    match recv_task.await {
+        Ok(Some(frame)) => {
+            // Let the send task flush what's queued, then say why we're
+            // hanging up.
+            let _ = tx_close.send(frame);
+            if tokio::time::timeout(Duration::from_secs(1), &mut send_task)
+                .await
+                .is_err()
+            {
+                send_task.abort();
+            }
+        }
+        _ => send_task.abort(),
+    }
+}
+
+/// Why a frame that writes to the vault stored nothing, as reported in
+/// its [`MSG_ACK`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Unsaved {
+    /// Storage failed; the same frame may succeed later.
+    Failed,
+    /// The frame will never be stored: malformed, too large, or not the
+    /// sender's to write.
+    Refused,
+}
+
+/// Sends a connection's [`MSG_ERROR`] frames. Mute for clients without
+/// [`CAP_ERRORS`], which wouldn't know what to make of them.
+#[derive(Clone)]
+struct Errors(Option<mpsc::UnboundedSender<Vec<u8>>>);
+
+impl Errors {
+    fn send(&self, code: u16, doc_id: &str, message: &str) {
+        if let Some(tx) = &self.0 {
+            let _ = tx.send(encode_message(MSG_ERROR, doc_id, &encode_error(code, message)));
+        }
+    }
+}
+
+/// Tell a client a compressed frame was dropped, and fail its tag so
+/// the update is sent again, uncompressed.
+fn compression_refused(
+    tx_out: &mpsc::UnboundedSender<Vec<u8>>,
+    errors: &Errors,
+    doc_id: &str,
+    tag: Option<u64>,
+    message: &str,
+) {
+    errors.send(ERR_COMPRESSION, doc_id, message);
+    if let Some(seq) = tag {
+        let ack = encode_ack(seq, ACK_FAILED);
+        let _ = tx_out.send(encode_message(MSG_ACK, doc_id, &ack));
+    }
+}
+
+/// Tell a client why its handshake was refused, and close.
+fn handshake_refused(
+    tx_out: &mpsc::UnboundedSender<Vec<u8>>,
+    message: String,
+) -> CloseFrame<'static> {
+    let _ = tx_out.send(encode_message(
+        MSG_ERROR,
+        MANIFEST_DOC_ID,
+        &encode_error(ERR_VERSION, &message),
+    ));
+    CloseFrame {
+        code: close_code::PROTOCOL,
+        reason: message.into(),
+    }
+}
+
+fn revoked_close() -> CloseFrame<'static> {
+    CloseFrame {
+        code: close_code::POLICY,
+        reason: DEVICE_REVOKED_REASON.into(),
+    }
+}
+
+/// Throttling state for one connection: its own rate limiter plus the
+/// one shared by every connection of the same token.
+struct Meter {
+    conn: RateLimiter,
+    token: Arc<std::sync::Mutex<RateLimiter>>,
+    frames: u64,
+    bytes: u64,
+    throttled: Duration,
+    last_throttle: Option<Instant>,
+}
+
+impl Meter {
+    fn new(limits: &Limits, token: Arc<std::sync::Mutex<RateLimiter>>) -> Self {
+        Self {
+            conn: RateLimiter::new(limits, Instant::now()),
+            token,
+            frames: 0,
+            bytes: 0,
+            throttled: Duration::ZERO,
+            last_throttle: None,
+        }
+    }
+
+    /// Charge one inbound frame and sleep off any rate-limit debt.
+    /// While we sleep nothing is read from the socket, so the client
+    /// is slowed down by TCP backpressure.
+    async fn throttle(&mut self, conn: uuid::Uuid, token: &str, len: usize) {
+        self.frames += 1;
+        self.charge(conn, token, len).await;
+    }
+
+    /// Charge `len` more bytes of the current frame, such as what it
+    /// inflated to, and sleep off any debt.
+    async fn charge(&mut self, conn: uuid::Uuid, token: &str, len: usize) {
+        self.bytes += len as u64;
+        let now = Instant::now();
+        let wait = self
+            .conn
+            .charge(len, now)
+            .max(self.token.lock().unwrap().charge(len, now));
+        if wait.is_zero() {
+            return;
+        }
+        let quiet = self
+            .last_throttle
+            .is_none_or(|t| now.duration_since(t) >= THROTTLE_LOG_QUIET);
+        if quiet {
+            tracing::warn!(
+                conn = %conn,
+                token,
+                frames = self.frames,
+                bytes = self.bytes,
+                "rate limit hit; throttling for {:?}",
+                wait
+            );
+        }
+        self.last_throttle = Some(now);
+        self.throttled += wait;
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Bytes a frame will add to storage if accepted, and whether they are
+/// blob bytes. `None` for frames that never persist. Chunked uploads are
+/// charged their full size up front, on the chunk at offset 0.
+fn quota_charge(
+    msg_type: u8,
+    doc_id: &str,
+    payload: &[u8],
+    uploads: &HashMap<String, BlobUpload>,
+) -> Option<(u64, bool)> {
+    match msg_type {
+        MSG_MANIFEST_SYNC if doc_id == MANIFEST_DOC_ID => {
+            let (sub_type, _) = split_manifest_payload(payload)?;
+            (sub_type == crate::protocol::MANIFEST_STEP_2
+                || sub_type == crate::protocol::MANIFEST_UPDATE)
+                .then(|| (payload.len() as u64 - 1, false))
+        }
+        MSG_SYNC_STEP_2 | MSG_UPDATE if doc_id.starts_with("content:") => {
+            Some((payload.len() as u64, false))
+        }
+        MSG_BLOB_UPDATE => Some((payload.len() as u64, true)),
+        MSG_BLOB_CHUNK => match decode_blob_chunk(payload)? {
+            (0, total, _) if !uploads.contains_key(doc_id) => Some((total, true)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Whether the blob a `MSG_BLOB_UPDATE` or `MSG_BLOB_CHUNK` carries is
+/// stored already. A whole blob is looked up by the hash of its bytes,
+/// not the doc id the client put on it; a chunked one by the hash its
+/// upload is verified against.
+async fn blob_stored(state: &AppState, hash: &str) -> bool {
+    state.db.has_blob(hash).await.unwrap_or(false)
 }
 
 // ---------------------------------------------------------------------------
 // Manifest handlers
 // ---------------------------------------------------------------------------
 
+/// Returns the number of bytes persisted.
 async fn handle_manifest_sync(
     state: &AppState,
     conn: uuid::Uuid,
     tx_out: &mpsc::UnboundedSender<Vec<u8>>,
+    errors: &Errors,
     payload: &[u8],
-) {
+) -> Result<u64, Unsaved> {
     let Some((sub_type, _inner)) = split_manifest_payload(payload) else {
-        return;
+        return Err(Unsaved::Refused);
     };
 
     // Ensure there's a broadcast channel for the manifest, and that
     // this connection is subscribed. Subscribe-on-first-frame keeps
     // the channel alive for every connected v1 client.
-    ensure_subscribed(state, MANIFEST_DOC_ID.to_string(), conn, tx_out).await;
+    ensure_subscribed(state, MANIFEST_DOC_ID.to_string(), conn, tx_out, None).await;
 
     let mut manifest = state.manifest.lock().await;
+    if (sub_type == crate::protocol::MANIFEST_STEP_2 || sub_type == crate::protocol::MANIFEST_UPDATE)
+        && let Some(actor) = state.devices.offending_actor(&manifest, &payload[1..]).await
+    {
+        tracing::warn!(
+            conn = %conn,
+            %actor,
+            "dropping manifest update with writes by a revoked device"
+        );
+        return Err(Unsaved::Refused);
+    }
     match handle_manifest_payload(&mut manifest, payload) {
         Ok(Some(response_payload)) => {
             // STEP_1 arrived — respond with STEP_2 to just this client.
@@ -326,23 +989,177 @@ async fn handle_manifest_sync(
                 let inner = &payload[1..];
                 if let Err(e) = state.db.save_update(MANIFEST_DOC_ID, inner).await {
                     tracing::error!("persist manifest update failed: {}", e);
-                    return;
-                }
-                // Rebroadcast as MANIFEST_UPDATE (STEP_2 is peer-
-                // directed; UPDATE is the broadcast form).
-                let mut rebroadcast = Vec::with_capacity(1 + inner.len());
-                rebroadcast.push(MANIFEST_UPDATE);
-                rebroadcast.extend_from_slice(inner);
-                let framed =
-                    encode_message(MSG_MANIFEST_SYNC, MANIFEST_DOC_ID, &rebroadcast);
-                if let Some(tx) = state.channels.read().await.get(MANIFEST_DOC_ID) {
-                    let _ = tx.send((framed, conn));
+                    errors.send(ERR_STORAGE, MANIFEST_DOC_ID, "could not store manifest update");
+                    return Err(Unsaved::Failed);
                 }
+                broadcast_manifest_update(state, MANIFEST_DOC_ID, inner, conn).await;
+                refresh_views(state, &manifest, &mut *state.views.lock().await).await;
+                return Ok(inner.len() as u64);
             }
         }
         Err(e) => {
             tracing::warn!("manifest sync payload rejected: {}", e);
+            return Err(Unsaved::Refused);
+        }
+    }
+    Ok(0)
+}
+
+/// Rebroadcast a manifest update on `channel` as MANIFEST_UPDATE
+/// (STEP_2 is peer-directed; UPDATE is the broadcast form). View
+/// channels carry it under [`MANIFEST_DOC_ID`] too: to the client, its
+/// view *is* the manifest.
+async fn broadcast_manifest_update(state: &AppState, channel: &str, update: &[u8], conn: uuid::Uuid) {
+    let mut rebroadcast = Vec::with_capacity(1 + update.len());
+    rebroadcast.push(crate::protocol::MANIFEST_UPDATE);
+    rebroadcast.extend_from_slice(update);
+    let framed = encode_message(MSG_MANIFEST_SYNC, MANIFEST_DOC_ID, &rebroadcast);
+    if let Some(tx) = state.channels.read().await.get(channel) {
+        let _ = tx.send((framed, conn));
+    }
+}
+
+/// [`handle_manifest_sync`] for a partial replica. The client syncs
+/// against its token's view; whatever it writes there that its access
+/// allows is merged into the shared manifest. Returns the number of
+/// bytes persisted.
+async fn handle_view_sync(
+    state: &AppState,
+    conn: uuid::Uuid,
+    tx_out: &mpsc::UnboundedSender<Vec<u8>>,
+    errors: &Errors,
+    payload: &[u8],
+    access: &Access,
+    actor: Option<ActorId>,
+) -> Result<u64, Unsaved> {
+    use crate::protocol::{MANIFEST_STEP_1, MANIFEST_STEP_2, MANIFEST_UPDATE};
+    let Some((sub_type, inner)) = split_manifest_payload(payload) else {
+        return Err(Unsaved::Refused);
+    };
+    let view_id = view_doc_id(access.token());
+    ensure_subscribed(state, view_id.clone(), conn, tx_out, None).await;
+
+    let mut manifest = state.manifest.lock().await;
+    let mut views = state.views.lock().await;
+    let view = match open_view(state, &mut views, access).await {
+        Ok(view) => view,
+        Err(e) => {
+            tracing::error!(conn = %conn, "load view of {}: {}", access.token(), e);
+            errors.send(ERR_STORAGE, MANIFEST_DOC_ID, "could not load manifest");
+            return Err(Unsaved::Failed);
         }
+    };
+    // A STEP_1 is answered from the refreshed view anyway.
+    let told = if sub_type == MANIFEST_STEP_1 { conn } else { uuid::Uuid::nil() };
+    publish_view(state, &manifest, view, told).await;
+
+    if sub_type == MANIFEST_STEP_1 {
+        match handle_manifest_payload(&mut view.manifest, payload) {
+            Ok(Some(response_payload)) => {
+                let frame = encode_message(MSG_MANIFEST_SYNC, MANIFEST_DOC_ID, &response_payload);
+                let _ = tx_out.send(frame);
+                let our_step1 = manifest_step1_payload(&view.manifest);
+                let frame = encode_message(MSG_MANIFEST_SYNC, MANIFEST_DOC_ID, &our_step1);
+                let _ = tx_out.send(frame);
+            }
+            Ok(None) => {}
+            Err(e) => tracing::warn!("manifest sync payload rejected: {}", e),
+        }
+        return Ok(0);
+    }
+    if sub_type != MANIFEST_STEP_2 && sub_type != MANIFEST_UPDATE {
+        tracing::warn!("manifest sync payload rejected: unknown sub-type {:#x}", sub_type);
+        return Err(Unsaved::Refused);
+    }
+    if let Some(actor) = state.devices.offending_actor(&view.manifest, inner).await {
+        tracing::warn!(
+            conn = %conn,
+            %actor,
+            "dropping manifest update with writes by a revoked device"
+        );
+        return Err(Unsaved::Refused);
+    }
+    if let Err(e) = view.manifest.apply_update(inner) {
+        tracing::warn!("manifest sync payload rejected: {}", e);
+        return Err(Unsaved::Refused);
+    }
+    if let Err(e) = state.db.save_update(&view_id, inner).await {
+        tracing::error!("persist view update failed: {}", e);
+        errors.send(ERR_STORAGE, MANIFEST_DOC_ID, "could not store manifest update");
+        return Err(Unsaved::Failed);
+    }
+    broadcast_manifest_update(state, &view_id, inner, conn).await;
+
+    let before = manifest.doc().transact().state_vector();
+    let refused = merge_view(&view.manifest, &mut manifest, &view.access, actor);
+    if !refused.is_empty() {
+        // Refused new nodes stay in the view and are refused again on
+        // every later update, so this would be noisy at warn.
+        tracing::debug!(
+            conn = %conn,
+            token = access.token(),
+            nodes = refused.len(),
+            "refusing manifest writes outside the token's access"
+        );
+    }
+    let merged = manifest.doc().transact().encode_state_as_update_v1(&before);
+    if is_noop_update(&merged) {
+        // Undo whatever was refused in the client's view.
+        publish_view(state, &manifest, view, uuid::Uuid::nil()).await;
+        return Ok(inner.len() as u64);
+    }
+    if let Err(e) = state.db.save_update(MANIFEST_DOC_ID, &merged).await {
+        tracing::error!("persist manifest update failed: {}", e);
+        return Ok(inner.len() as u64);
+    }
+    broadcast_manifest_update(state, MANIFEST_DOC_ID, &merged, conn).await;
+    refresh_views(state, &manifest, &mut views).await;
+    Ok((inner.len() + merged.len()) as u64)
+}
+
+/// The token's view, loaded from storage on first use. Takes on
+/// `access`: the newest connection's rules win.
+async fn open_view<'a>(
+    state: &AppState,
+    views: &'a mut HashMap<String, View>,
+    access: &Access,
+) -> anyhow::Result<&'a mut View> {
+    let token = access.token();
+    if !views.contains_key(token) {
+        let manifest = hydrate_manifest_doc(state.db.as_ref(), &view_doc_id(token), ActorId::new()).await?;
+        views.insert(
+            token.to_string(),
+            View {
+                manifest,
+                access: access.clone(),
+            },
+        );
+    }
+    let view = views.get_mut(token).expect("view just loaded");
+    view.access = access.clone();
+    Ok(view)
+}
+
+/// Refresh `view` from the shared manifest, then persist the change and
+/// broadcast it to every connection of the view's token except `told`.
+async fn publish_view(state: &AppState, manifest: &Manifest, view: &mut View, told: uuid::Uuid) {
+    let before = view.manifest.doc().transact().state_vector();
+    if !refresh_view(manifest, &mut view.manifest, &view.access) {
+        return;
+    }
+    let update = view.manifest.doc().transact().encode_state_as_update_v1(&before);
+    let view_id = view_doc_id(view.access.token());
+    if let Err(e) = state.db.save_update(&view_id, &update).await {
+        tracing::error!("persist view update failed: {}", e);
+        return;
+    }
+    broadcast_manifest_update(state, &view_id, &update, told).await;
+}
+
+/// [`publish_view`] every loaded view after the shared manifest changed.
+async fn refresh_views(state: &AppState, manifest: &Manifest, views: &mut HashMap<String, View>) {
+    for view in views.values_mut() {
+        publish_view(state, manifest, view, uuid::Uuid::nil()).await;
     }
 }
 
@@ -350,9 +1167,27 @@ async fn handle_manifest_verify(
     state: &AppState,
     tx_out: &mpsc::UnboundedSender<Vec<u8>>,
     payload: &[u8],
+    partial: Option<&Access>,
 ) {
     let manifest = state.manifest.lock().await;
-    match handle_verify_payload(&manifest, payload) {
+    // A partial replica holds its view, so that is what it must match.
+    let result = match partial {
+        None => handle_verify_payload(&manifest, payload),
+        Some(access) => {
+            let mut views = state.views.lock().await;
+            match open_view(state, &mut views, access).await {
+                Ok(view) => {
+                    publish_view(state, &manifest, view, uuid::Uuid::nil()).await;
+                    handle_verify_payload(&view.manifest, payload)
+                }
+                Err(e) => {
+                    tracing::error!("load view of {}: {}", access.token(), e);
+                    return;
+                }
+            }
+        }
+    };
+    match result {
         Ok(Some(step1_payload)) => {
             let frame = encode_message(MSG_MANIFEST_SYNC, MANIFEST_DOC_ID, &step1_payload);
             let _ = tx_out.send(frame);
@@ -363,28 +1198,286 @@ async fn handle_manifest_verify(
         Err(e) => {
             tracing::warn!("verify payload rejected: {}", e);
         }
-    }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Content subdoc handlers
+// ---------------------------------------------------------------------------
+
+fn content_node(doc_id: &str) -> Option<NodeId> {
+    NodeId::parse_str(doc_id.strip_prefix("content:")?)
+}
+
+/// Whether a partial replica may read (or `write`) content doc `doc_id`.
+/// Always true for full replicas.
+async fn content_allowed(state: &AppState, partial: Option<&Access>, doc_id: &str, write: bool) -> bool {
+    let Some(access) = partial else {
+        return true;
+    };
+    let Some(node) = content_node(doc_id) else {
+        return false;
+    };
+    let manifest = state.manifest.lock().await;
+    let eq = PathEquivalence::of(&manifest);
+    node_path(&manifest, node).is_some_and(|p| {
+        if write {
+            access.can_write(eq, &p)
+        } else {
+            access.can_read(eq, &p)
+        }
+    })
+}
+
+/// Whether a partial replica may fetch the blob whose hash is `payload`:
+/// only if some node in its view refers to it. Always true for full
+/// replicas.
+async fn blob_allowed(state: &AppState, partial: Option<&Access>, payload: &[u8]) -> bool {
+    let Some(access) = partial else {
+        return true;
+    };
+    let Ok(hash) = std::str::from_utf8(payload) else {
+        return false;
+    };
+    let views = state.views.lock().await;
+    views.get(access.token()).is_some_and(|view| {
+        view.manifest
+            .all_entries()
+            .values()
+            .any(|e| e.blob_hash.as_deref() == Some(hash))
+    })
+}
+
+/// Stops a partial replica's content subscription from forwarding
+/// updates once the node has moved somewhere its token can't read.
+struct ReadGate {
+    manifest: Arc<AsyncMutex<Manifest>>,
+    access: Access,
+    node: NodeId,
+}
+
+impl ReadGate {
+    async fn open(&self) -> bool {
+        let manifest = self.manifest.lock().await;
+        let eq = PathEquivalence::of(&manifest);
+        node_path(&manifest, self.node).is_some_and(|p| self.access.can_read(eq, &p))
+    }
+}
+
+/// `subscribe` is `None` for connections on the change feed, which
+/// already receive the doc's updates; otherwise it holds the read gate
+/// (if any) for the doc's own subscription.
+async fn handle_content_step1(
+    state: &AppState,
+    conn: uuid::Uuid,
+    tx_out: &mpsc::UnboundedSender<Vec<u8>>,
+    doc_id: &str,
+    payload: &[u8],
+    subscribe: Option<Option<ReadGate>>,
+) {
+    if let Some(gate) = subscribe {
+        ensure_subscribed(state, doc_id.to_string(), conn, tx_out, gate).await;
+    }
+
+    let Ok(sv) = StateVector::decode_v1(payload) else {
+        return;
+    };
+    answer_step1(state, tx_out, doc_id, &sv).await;
+}
+
+/// Subscribe the connection (unless `subscribe` is false because it is
+/// on the change feed) to every doc listed in a `MSG_SYNC_BATCH` it may
+/// read, then answer, as [`handle_content_step1`] would, only those
+/// whose digest differs from ours.
+async fn handle_sync_batch(
+    state: &AppState,
+    conn: uuid::Uuid,
+    tx_out: &mpsc::UnboundedSender<Vec<u8>>,
+    payload: &[u8],
+    partial: Option<&Access>,
+    subscribe: bool,
+) {
+    let Some(entries) = decode_sync_batch(payload) else {
+        tracing::debug!(conn = %conn, "skipping malformed sync batch");
+        return;
+    };
+    let mut answered = 0usize;
+    for entry in &entries {
+        let doc_id = entry.doc_id;
+        if !doc_id.starts_with("content:")
+            || !content_allowed(state, partial, doc_id, false).await
+        {
+            tracing::debug!(conn = %conn, doc_id, "refusing batched subscribe");
+            continue;
+        }
+        if subscribe {
+            let gate = partial.and_then(|access| {
+                Some(ReadGate {
+                    manifest: state.manifest.clone(),
+                    access: access.clone(),
+                    node: content_node(doc_id)?,
+                })
+            });
+            ensure_subscribed(state, doc_id.to_string(), conn, tx_out, gate).await;
+        }
+
+        match state.digests.get(state.db.as_ref(), doc_id).await {
+            Ok(digest) if digest == entry.digest => continue,
+            Ok(_) => {}
+            Err(e) => tracing::error!("digest for {}: {}", doc_id, e),
+        }
+        let Ok(sv) = StateVector::decode_v1(entry.state_vector) else {
+            continue;
+        };
+        answer_step1(state, tx_out, doc_id, &sv).await;
+        answered += 1;
+    }
+    tracing::debug!(conn = %conn, docs = entries.len(), answered, "sync batch");
+}
+
+/// Answer a client's change-feed cursor with the content docs it may
+/// read that changed since. The first request also puts the connection
+/// on the feed, before the answer is computed so no update falls
+/// between the two.
+async fn handle_changes(
+    state: &AppState,
+    conn: uuid::Uuid,
+    tx_out: &mpsc::UnboundedSender<Vec<u8>>,
+    payload: &[u8],
+    partial: Option<&Access>,
+    on_feed: &mut bool,
+) {
+    let Some(cursor) = decode_changes_request(payload) else {
+        tracing::debug!(conn = %conn, "skipping malformed change-feed request");
+        return;
+    };
+    let rx = (!*on_feed).then(|| state.feed.subscribe());
+    let reply = match changes_reply(state, &cursor, partial).await {
+        Ok(reply) => reply,
+        Err(e) => {
+            tracing::error!("read change feed: {}", e);
+            return;
+        }
+    };
+    tracing::debug!(
+        conn = %conn,
+        since = cursor.seq,
+        head = reply.cursor.seq,
+        changed = reply.docs.as_ref().map(|d| d.len()),
+        "change feed"
+    );
+    if let Some(rx) = rx {
+        forward_feed(state, conn, tx_out, partial.cloned(), rx, reply.cursor.seq);
+        *on_feed = true;
+    }
+    let frame = encode_message(MSG_CHANGES, MANIFEST_DOC_ID, &encode_changes_reply(&reply));
+    let _ = tx_out.send(frame);
+}
+
+/// The content docs changed since `cursor` that `partial` may read, or
+/// `None` if the cursor isn't one of ours.
+async fn changes_reply(
+    state: &AppState,
+    cursor: &Cursor,
+    partial: Option<&Access>,
+) -> anyhow::Result<ChangesReply> {
+    let feed = state.feed.id();
+    let ours = cursor.feed == feed && cursor.seq > 0;
+    let changes = state
+        .db
+        .changes_since(if ours { cursor.seq } else { u64::MAX })
+        .await?;
+    // A cursor past the head means the store was rolled back; what the
+    // client saw since then may have been lost or renumbered.
+    let docs = if ours && cursor.seq <= changes.head {
+        let mut docs = Vec::new();
+        for doc_id in changes.docs {
+            if doc_id.starts_with("content:")
+                && content_allowed(state, partial, &doc_id, false).await
+            {
+                docs.push(doc_id);
+            }
+        }
+        Some(docs)
+    } else {
+        None
+    };
+    Ok(ChangesReply {
+        cursor: Cursor {
+            feed: feed.to_string(),
+            seq: changes.head,
+        },
+        docs,
+        pushed: false,
+    })
+}
+
+/// Forward every content update on the feed that the connection may
+/// read. If it falls behind, send it the docs changed since `since`
+/// instead of the updates it missed.
+fn forward_feed(
+    state: &AppState,
+    conn: uuid::Uuid,
+    tx_out: &mpsc::UnboundedSender<Vec<u8>>,
+    partial: Option<Access>,
+    mut rx: broadcast::Receiver<ContentChange>,
+    mut since: u64,
+) {
+    let state = state.clone();
+    let tx_fwd = tx_out.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = tx_fwd.closed() => break,
+                res = rx.recv() => match res {
+                    Ok(change) => {
+                        if change.conn == conn
+                            || !content_allowed(&state, partial.as_ref(), &change.doc_id, false).await
+                        {
+                            continue;
+                        }
+                        if tx_fwd.send(change.frame).is_err() {
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        tracing::warn!(conn = %conn, "change feed lagged by {}: resending change list", n);
+                        let cursor = Cursor {
+                            feed: state.feed.id().to_string(),
+                            seq: since,
+                        };
+                        match changes_reply(&state, &cursor, partial.as_ref()).await {
+                            Ok(mut reply) => {
+                                reply.pushed = true;
+                                since = reply.cursor.seq;
+                                let frame = encode_message(
+                                    MSG_CHANGES,
+                                    MANIFEST_DOC_ID,
+                                    &encode_changes_reply(&reply),
+                                );
+                                if tx_fwd.send(frame).is_err() {
+                                    break;
+                                }
+                            }
+                            Err(e) => tracing::error!("read change feed: {}", e),
+                        }
+                    }
+                }
+            }
+        }
+    });
 }
 
-// ---------------------------------------------------------------------------
-// Content subdoc handlers
-// ---------------------------------------------------------------------------
-
-async fn handle_content_step1(
+/// Reply to a content SyncStep1 carrying `sv`.
+async fn answer_step1(
     state: &AppState,
-    conn: uuid::Uuid,
     tx_out: &mpsc::UnboundedSender<Vec<u8>>,
     doc_id: &str,
-    payload: &[u8],
+    sv: &StateVector,
 ) {
-    ensure_subscribed(state, doc_id.to_string(), conn, tx_out).await;
-
-    let Ok(sv) = StateVector::decode_v1(payload) else {
-        return;
-    };
-
     // 1) Reply with what we have past the client's SV (existing behaviour).
-    match state.db.get_all_updates_since(doc_id, &sv).await {
+    match state.db.get_all_updates_since(doc_id, sv).await {
         Ok(update) if !update.is_empty() => {
             let frame = encode_message(MSG_SYNC_STEP_2, doc_id, &update);
             let _ = tx_out.send(frame);
@@ -425,12 +1518,14 @@ fn is_noop_update(payload: &[u8]) -> bool {
     }
 }
 
+/// Returns the number of bytes persisted.
 async fn handle_content_update(
     state: &AppState,
     conn: uuid::Uuid,
+    errors: &Errors,
     doc_id: &str,
     payload: &[u8],
-) {
+) -> Result<u64, Unsaved> {
     // Skip empty STEP_2 replies (typically those that come back from a
     // client whose state vector matched ours after the handshake — the
     // client has nothing to add). Without this the bidirectional handshake
@@ -438,52 +1533,229 @@ async fn handle_content_update(
     // the content to disk on every peer and shadow-resurrecting freshly
     // deleted files before scan_once can register them as gone.
     if is_noop_update(payload) {
-        return;
+        return Ok(0);
     }
     if let Err(e) = state.db.save_update(doc_id, payload).await {
         tracing::error!("persist content update for {}: {}", doc_id, e);
-        return;
+        errors.send(ERR_STORAGE, doc_id, "could not store content update");
+        return Err(Unsaved::Failed);
     }
+    state.digests.invalidate(doc_id);
     // Broadcast as MSG_UPDATE so late-arriving peers don't misread
     // a STEP_2 (which by convention is peer-directed, not broadcast).
     let frame = encode_message(MSG_UPDATE, doc_id, payload);
+    state.feed.publish(ContentChange {
+        doc_id: doc_id.to_string(),
+        frame: frame.clone(),
+        conn,
+    });
     let mut channels = state.channels.write().await;
     let tx = channels
         .entry(doc_id.to_string())
         .or_insert_with(|| broadcast::channel(PER_DOC_BROADCAST_CAP).0);
     let _ = tx.send((frame, conn));
+    Ok(payload.len() as u64)
 }
 
+/// Store `payload`, whose SHA-256 is `hash`. Returns the number of
+/// bytes newly stored; 0 if the blob was known.
 async fn handle_blob_update(
     state: &AppState,
     conn: uuid::Uuid,
+    errors: &Errors,
     doc_id: &str,
+    hash: &str,
     payload: &[u8],
-) {
-    use sha2::{Digest, Sha256};
-    if payload.len() > crate::protocol::MAX_BLOB_SIZE {
+) -> Result<u64, Unsaved> {
+    if payload.len() > MAX_BLOB_SIZE {
         tracing::warn!(
             "Rejected blob for {} — {} bytes exceeds {} byte limit",
             doc_id,
             payload.len(),
-            crate::protocol::MAX_BLOB_SIZE
+            MAX_BLOB_SIZE
         );
-        return;
+        errors.send(
+            ERR_BLOB_TOO_LARGE,
+            doc_id,
+            &format!("blob exceeds the {} byte limit", MAX_BLOB_SIZE),
+        );
+        return Err(Unsaved::Refused);
     }
-    let hash = format!("{:x}", Sha256::digest(payload));
-    if let Err(e) = state.db.save_blob(&hash, payload).await {
+    let known = state.db.has_blob(hash).await.unwrap_or(false);
+    if let Err(e) = state.db.save_blob(hash, payload).await {
         tracing::error!("save blob: {}", e);
-        return;
+        errors.send(ERR_STORAGE, doc_id, "could not store blob");
+        return Err(Unsaved::Failed);
     }
     let frame = encode_message(MSG_BLOB_UPDATE, doc_id, payload);
     if let Some(tx) = state.channels.read().await.get(doc_id) {
         let _ = tx.send((frame, conn));
     }
+    Ok(if known { 0 } else { payload.len() as u64 })
+}
+
+/// One `MSG_BLOB_CHUNK` from a client. Chunks must arrive in order; a
+/// chunk at offset 0 (re)starts the upload, anything out of sequence
+/// drops it. The blob is verified and stored once the last byte lands;
+/// returns the number of bytes that added to storage.
+async fn handle_blob_chunk(
+    state: &AppState,
+    conn: uuid::Uuid,
+    errors: &Errors,
+    uploads: &mut HashMap<String, BlobUpload>,
+    hash: &str,
+    payload: &[u8],
+) -> Result<u64, Unsaved> {
+    let Some((offset, total, bytes)) = decode_blob_chunk(payload) else {
+        tracing::debug!(conn = %conn, hash, "skipping malformed blob chunk");
+        return Err(Unsaved::Refused);
+    };
+    let limit = state.db.max_upload_size();
+    if total > limit {
+        tracing::warn!(
+            conn = %conn,
+            "Rejected blob {} — {} bytes exceeds {} byte limit",
+            hash,
+            total,
+            limit
+        );
+        errors.send(ERR_BLOB_TOO_LARGE, hash, &format!("blob exceeds the {limit} byte limit"));
+        return Err(Unsaved::Refused);
+    }
+    if offset == 0 {
+        if !uploads.contains_key(hash) && uploads.len() >= MAX_PENDING_UPLOADS {
+            tracing::warn!(conn = %conn, hash, "too many concurrent blob uploads; dropping");
+            return Err(Unsaved::Failed);
+        }
+        match state.db.begin_blob(hash, total).await {
+            Ok(upload) => {
+                uploads.insert(hash.to_string(), upload);
+            }
+            Err(e) => {
+                tracing::warn!(conn = %conn, "begin blob {}: {}", hash, e);
+                errors.send(ERR_STORAGE, hash, "could not stage blob upload");
+                uploads.remove(hash);
+                return Err(Unsaved::Failed);
+            }
+        }
+    }
+    let Some(mut upload) = uploads.remove(hash) else {
+        tracing::debug!(conn = %conn, hash, offset, "blob chunk without an upload in progress");
+        return Err(Unsaved::Failed);
+    };
+    if upload.received() != offset || upload.total() != total {
+        tracing::warn!(
+            conn = %conn,
+            hash,
+            offset,
+            expected = upload.received(),
+            "out-of-sequence blob chunk; discarding partial upload"
+        );
+        return Err(Unsaved::Failed);
+    }
+    let chunk = bytes.to_vec();
+    let upload = match tokio::task::spawn_blocking(move || upload.write(&chunk).map(|_| upload))
+        .await
+    {
+        Ok(Ok(upload)) => upload,
+        Ok(Err(e)) => {
+            tracing::warn!(conn = %conn, "write blob chunk {}: {}", hash, e);
+            errors.send(ERR_STORAGE, hash, "could not stage blob upload");
+            return Err(Unsaved::Failed);
+        }
+        Err(e) => {
+            tracing::error!(conn = %conn, "blob chunk writer panicked: {}", e);
+            return Err(Unsaved::Failed);
+        }
+    };
+    if !upload.is_complete() {
+        uploads.insert(hash.to_string(), upload);
+        return Ok(0);
+    }
+    let known = state.db.has_blob(hash).await.unwrap_or(false);
+    match state.db.finish_blob(upload).await {
+        Ok(()) => {
+            tracing::debug!(conn = %conn, hash, bytes = total, "stored chunked blob");
+            Ok(if known { 0 } else { total })
+        }
+        Err(e) => {
+            tracing::warn!(conn = %conn, "finish blob {}: {:#}", hash, e);
+            Err(Unsaved::Failed)
+        }
+    }
+}
+
+/// Answer a `MSG_BLOB_REQUEST` from a chunk-capable peer. Runs on its own
+/// task and feeds `tx_blob` one [`BLOB_CHUNK_SIZE`] frame at a time, so
+/// at most [`BLOB_SEND_WINDOW`] chunks of the blob are ever in memory.
+fn handle_blob_request_chunked(
+    state: &AppState,
+    tx_blob: &mpsc::Sender<Vec<u8>>,
+    errors: &Errors,
+    payload: &[u8],
+) {
+    let Ok(hash) = std::str::from_utf8(payload) else {
+        return;
+    };
+    let hash = hash.to_string();
+    let db = state.db.clone();
+    let tx_blob = tx_blob.clone();
+    let errors = errors.clone();
+    tokio::spawn(async move {
+        let reader = match db.open_blob(&hash).await {
+            Ok(Some(reader)) => reader,
+            Ok(None) => {
+                tracing::warn!("blob not found: {}", hash);
+                errors.send(ERR_BLOB_NOT_FOUND, &hash, "blob not on the server");
+                return;
+            }
+            Err(e) => {
+                tracing::error!("load blob: {}", e);
+                errors.send(ERR_STORAGE, &hash, "could not load blob");
+                return;
+            }
+        };
+        let total = reader.size;
+        let mut chunks = reader.chunks;
+        let mut offset = 0u64;
+        let mut pending: Vec<u8> = Vec::with_capacity(BLOB_CHUNK_SIZE);
+        // Re-slice whatever the backend yields into fixed-size frames.
+        loop {
+            let next = match chunks.next().await {
+                Some(Ok(bytes)) => Some(bytes),
+                Some(Err(e)) => {
+                    tracing::error!("read blob {}: {}", hash, e);
+                    errors.send(ERR_STORAGE, &hash, "could not load blob");
+                    return;
+                }
+                None => None,
+            };
+            if let Some(bytes) = &next {
+                pending.extend_from_slice(bytes);
+            }
+            while pending.len() >= BLOB_CHUNK_SIZE || (next.is_none() && !pending.is_empty()) {
+                let take = pending.len().min(BLOB_CHUNK_SIZE);
+                let frame = encode_blob_chunk(&hash, offset, total, &pending[..take]);
+                offset += take as u64;
+                pending.drain(..take);
+                if tx_blob.send(frame).await.is_err() {
+                    return;
+                }
+            }
+            if next.is_none() {
+                break;
+            }
+        }
+        if total == 0 {
+            let _ = tx_blob.send(encode_blob_chunk(&hash, 0, 0, &[])).await;
+        }
+    });
 }
 
 async fn handle_blob_request(
     state: &AppState,
     tx_out: &mpsc::UnboundedSender<Vec<u8>>,
+    errors: &Errors,
     doc_id: &str,
     payload: &[u8],
 ) {
@@ -491,9 +1763,36 @@ async fn handle_blob_request(
         Ok(s) => s,
         Err(_) => return,
     };
-    match state.db.load_blob(hash).await {
-        Ok(Some(data)) => {
-            let frame = encode_message(MSG_BLOB_UPDATE, doc_id, &data);
+    // Stream the blob straight into the outgoing frame rather than
+    // loading it into memory first and copying it again to frame it.
+    match state.db.open_blob(hash).await {
+        // Peers without chunking take the blob as one frame, which they
+        // (and we) only hold in memory up to MAX_BLOB_SIZE.
+        Ok(Some(reader)) if reader.size > MAX_BLOB_SIZE as u64 => {
+            tracing::warn!(
+                "blob {} is {} bytes, too large for a peer without chunked transfer",
+                hash,
+                reader.size
+            );
+            errors.send(
+                ERR_BLOB_TOO_LARGE,
+                hash,
+                &format!("blob exceeds the {MAX_BLOB_SIZE} byte single-frame limit"),
+            );
+        }
+        Ok(Some(reader)) => {
+            let mut frame =
+                encode_message_header(MSG_BLOB_UPDATE, doc_id, reader.size as usize);
+            let mut chunks = reader.chunks;
+            while let Some(chunk) = chunks.next().await {
+                match chunk {
+                    Ok(bytes) => frame.extend_from_slice(&bytes),
+                    Err(e) => {
+                        tracing::error!("read blob {}: {}", hash, e);
+                        return;
+                    }
+                }
+            }
             let _ = tx_out.send(frame);
         }
         Ok(None) => {
@@ -513,6 +1812,7 @@ async fn ensure_subscribed(
     doc_id: String,
     conn: uuid::Uuid,
     tx_out: &mpsc::UnboundedSender<Vec<u8>>,
+    gate: Option<ReadGate>,
 ) {
     let rx = {
         let mut channels = state.channels.write().await;
@@ -539,6 +1839,9 @@ async fn ensure_subscribed(
                         if sender_id == conn {
                             continue;
                         }
+                        if let Some(gate) = &gate && !gate.open().await {
+                            continue;
+                        }
                         if tx_fwd.send(framed).is_err() {
                             break;
                         }
@@ -551,14 +1854,18 @@ async fn ensure_subscribed(
                             "broadcast lagged by {} for {}: doing full catch-up",
                             n, doc_id
                         );
-                        let is_manifest = doc_id == MANIFEST_DOC_ID;
+                        if let Some(gate) = &gate && !gate.open().await {
+                            continue;
+                        }
+                        let is_manifest =
+                            doc_id == MANIFEST_DOC_ID || doc_id.starts_with(VIEW_DOC_PREFIX);
                         match db.get_all_updates_since(&doc_id, &StateVector::default()).await {
                             Ok(update) if !update.is_empty() => {
                                 let frame = if is_manifest {
                                     let mut p = Vec::with_capacity(1 + update.len());
                                     p.push(crate::protocol::MANIFEST_UPDATE);
                                     p.extend_from_slice(&update);
-                                    encode_message(MSG_MANIFEST_SYNC, &doc_id, &p)
+                                    encode_message(MSG_MANIFEST_SYNC, MANIFEST_DOC_ID, &p)
                                 } else {
                                     encode_message(MSG_SYNC_STEP_2, &doc_id, &update)
                                 };
@@ -583,22 +1890,38 @@ async fn ensure_subscribed(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::server::memory_storage::MemoryStorage;
     use crate::v1::ids::ActorId;
-    use crate::v1::sync::{encode_manifest_update, manifest_step1_payload};
+    use crate::v1::sync::{
+        decode_error, encode_hello, encode_manifest_update, manifest_step1_payload,
+    };
     use std::time::Duration;
     use tokio::net::TcpListener;
     use tokio_tungstenite::connect_async;
     use tokio_tungstenite::tungstenite::Message as TungsteniteMessage;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
     use yrs::updates::encoder::Encode;
     use yrs::{ReadTxn, Transact};
 
     async fn setup_test_server() -> (u16, AppState) {
-        let db = Db::new("sqlite::memory:").await.unwrap();
+        setup_test_server_with_limits(Limits::default()).await
+    }
+
+    async fn setup_test_server_with_limits(limits: Limits) -> (u16, AppState) {
+        let db = MemoryStorage::new();
         let _ = migrate_server_db(&db).await.unwrap();
+        let db: Arc<dyn Storage> = Arc::new(db);
+        let quotas = Quotas::load(db.clone(), limits).await.unwrap();
+        let feed = ChangeFeed::load(db.as_ref()).await.unwrap();
         let state = AppState {
+            devices: Arc::new(Registry::new(db.clone())),
             db,
             channels: Arc::new(RwLock::new(HashMap::new())),
             manifest: Arc::new(AsyncMutex::new(Manifest::new(ActorId::new()))),
+            quotas: Arc::new(quotas),
+            views: Arc::new(AsyncMutex::new(HashMap::new())),
+            digests: Arc::new(Digests::default()),
+            feed: Arc::new(feed),
         };
         let app = Router::new()
             .route("/sync", get(ws_handler))
@@ -633,6 +1956,29 @@ mod tests {
         }
     }
 
+    /// Finish the version handshake offering every capability.
+    async fn handshake(
+        ws: &mut tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+    ) {
+        handshake_as(ws, ActorId::new()).await;
+    }
+
+    /// Version and capability exchange, then a hello naming `actor`.
+    async fn handshake_as(
+        ws: &mut tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+        actor: ActorId,
+    ) {
+        send_bin(ws, encode_message(MSG_VERSION, MANIFEST_DOC_ID, &encode_version_handshake())).await;
+        send_bin(ws, encode_message(MSG_CAPS, MANIFEST_DOC_ID, &encode_caps(Caps::ALL))).await;
+        let _ = recv_bin(ws).await; // version echo
+        let _ = recv_bin(ws).await; // server caps
+        send_bin(ws, encode_message(MSG_HELLO, MANIFEST_DOC_ID, &encode_hello(actor))).await;
+    }
+
     #[tokio::test]
     async fn handshake_accepts_v1_and_echoes_version() {
         let (port, _) = setup_test_server().await;
@@ -645,6 +1991,7 @@ mod tests {
             &encode_version_handshake(),
         );
         send_bin(&mut ws, frame).await;
+        send_bin(&mut ws, encode_message(MSG_CAPS, MANIFEST_DOC_ID, &encode_caps(Caps::ALL))).await;
 
         let echo = recv_bin(&mut ws).await;
         let (t, d, p) = decode_message(&echo).unwrap();
@@ -652,6 +1999,43 @@ mod tests {
         assert_eq!(d, MANIFEST_DOC_ID);
         let (major, minor) = decode_version_handshake(p).unwrap();
         assert_eq!((major, minor), (V1_PROTOCOL_MAJOR, V1_PROTOCOL_MINOR));
+        let caps = recv_bin(&mut ws).await;
+        let (t, _, p) = decode_message(&caps).unwrap();
+        assert_eq!(t, MSG_CAPS);
+        assert_eq!(decode_caps(p), Some(Caps::ALL));
+    }
+
+    #[tokio::test]
+    async fn features_are_limited_to_the_capabilities_both_sides_offer() {
+        let (port, _) = setup_test_server().await;
+        let url = format!("ws://127.0.0.1:{}/sync", port);
+        let hs = encode_message(MSG_VERSION, MANIFEST_DOC_ID, &encode_version_handshake());
+
+        // A current client that leaves out CAP_ERRORS is never sent a
+        // MSG_ERROR, even though its minor version knows the frame.
+        let (mut ws, _) = connect_async(&url).await.unwrap();
+        let offered = Caps(Caps::ALL.0 & !CAP_ERRORS);
+        send_bin(&mut ws, hs.clone()).await;
+        send_bin(&mut ws, encode_message(MSG_CAPS, MANIFEST_DOC_ID, &encode_caps(offered))).await;
+        let _ = recv_bin(&mut ws).await;
+        let _ = recv_bin(&mut ws).await;
+        send_bin(&mut ws, encode_message(MSG_HELLO, MANIFEST_DOC_ID, &encode_hello(ActorId::new())))
+            .await;
+        let hash = "cd".repeat(32);
+        send_bin(&mut ws, encode_message(MSG_BLOB_REQUEST, &hash, hash.as_bytes())).await;
+        let step1 = manifest_step1_payload(&Manifest::new(ActorId::new()));
+        send_bin(&mut ws, encode_message(MSG_MANIFEST_SYNC, MANIFEST_DOC_ID, &step1)).await;
+        let reply = recv_bin(&mut ws).await;
+        assert_eq!(decode_message(&reply).unwrap().0, MSG_MANIFEST_SYNC);
+
+        // A 1.7 client that skips MSG_CAPS is refused.
+        let (mut ws, _) = connect_async(&url).await.unwrap();
+        send_bin(&mut ws, hs).await;
+        send_bin(&mut ws, encode_message(MSG_MANIFEST_SYNC, MANIFEST_DOC_ID, &step1)).await;
+        let reply = recv_bin(&mut ws).await;
+        let (t, _, p) = decode_message(&reply).unwrap();
+        assert_eq!(t, MSG_ERROR);
+        assert_eq!(decode_error(p).unwrap().0, ERR_VERSION);
     }
 
     #[tokio::test]
@@ -661,12 +2045,17 @@ mod tests {
         let (mut ws, _) = connect_async(url).await.unwrap();
 
         // v0 would send MSG_SYNC_STEP_1 immediately. The v1 server
-        // closes the connection instead of replying.
+        // says why in a MSG_ERROR and closes the connection.
         let sv = StateVector::default();
         use yrs::updates::encoder::Encode;
         let frame = encode_message(MSG_SYNC_STEP_1, "some.md", &sv.encode_v1());
         send_bin(&mut ws, frame).await;
 
+        let error = recv_bin(&mut ws).await;
+        let (t, _, p) = decode_message(&error).unwrap();
+        assert_eq!(t, MSG_ERROR);
+        assert_eq!(decode_error(p).unwrap().0, ERR_VERSION);
+
         // Either the next recv yields Close/None or the stream drops.
         let outcome =
             tokio::time::timeout(Duration::from_secs(2), ws.next()).await;
@@ -686,16 +2075,7 @@ mod tests {
         let (mut ws, _) = connect_async(url).await.unwrap();
 
         // Version handshake.
-        send_bin(
-            &mut ws,
-            encode_message(
-                MSG_VERSION,
-                MANIFEST_DOC_ID,
-                &encode_version_handshake(),
-            ),
-        )
-        .await;
-        let _ = recv_bin(&mut ws).await; // echo
+        handshake(&mut ws).await;
 
         // Client's STEP_1 from a fresh (empty) manifest.
         let client_manifest = Manifest::new(ActorId::new());
@@ -724,15 +2104,8 @@ mod tests {
         // Two clients both finish the version handshake.
         let (mut a, _) = connect_async(&url).await.unwrap();
         let (mut b, _) = connect_async(&url).await.unwrap();
-        let hs = encode_message(
-            MSG_VERSION,
-            MANIFEST_DOC_ID,
-            &encode_version_handshake(),
-        );
-        send_bin(&mut a, hs.clone()).await;
-        send_bin(&mut b, hs).await;
-        let _ = recv_bin(&mut a).await;
-        let _ = recv_bin(&mut b).await;
+        handshake(&mut a).await;
+        handshake(&mut b).await;
 
         // Both subscribe via STEP_1 so they're registered in the
         // manifest broadcast channel. The server replies with STEP_2
@@ -787,7 +2160,8 @@ mod tests {
     async fn v1_blob_upload_then_request_roundtrip() {
         // Pins the 3.3c contract: a v1 client uploads a blob addressed
         // by its own hex hash, and a later MSG_BLOB_REQUEST for that hash
-        // returns the original bytes.
+        // returns the original bytes. A 1.0 peer gets the single-frame
+        // reply it understands.
         let (port, state) = setup_test_server().await;
         let url = format!("ws://127.0.0.1:{}/sync", port);
         let (mut ws, _) = connect_async(url).await.unwrap();
@@ -795,11 +2169,7 @@ mod tests {
         // Handshake.
         send_bin(
             &mut ws,
-            encode_message(
-                MSG_VERSION,
-                MANIFEST_DOC_ID,
-                &encode_version_handshake(),
-            ),
+            encode_message(MSG_VERSION, MANIFEST_DOC_ID, &[V1_PROTOCOL_MAJOR, 0]),
         )
         .await;
         let _ = recv_bin(&mut ws).await;
@@ -834,6 +2204,277 @@ mod tests {
         assert_eq!(payload, bytes);
     }
 
+    #[tokio::test]
+    async fn v1_blob_chunked_upload_then_chunked_reply() {
+        let (port, state) = setup_test_server().await;
+        let url = format!("ws://127.0.0.1:{}/sync", port);
+        let (mut ws, _) = connect_async(url).await.unwrap();
+        handshake(&mut ws).await;
+
+        // Two and a half chunks' worth, so the reply needs re-slicing.
+        let bytes: Vec<u8> = (0..BLOB_CHUNK_SIZE * 5 / 2).map(|i| (i % 241) as u8).collect();
+        let hash_hex = crate::v1::hash_hex(&bytes);
+        let total = bytes.len() as u64;
+        for (i, piece) in bytes.chunks(BLOB_CHUNK_SIZE).enumerate() {
+            let offset = (i * BLOB_CHUNK_SIZE) as u64;
+            assert!(!state.db.has_blob(&hash_hex).await.unwrap());
+            send_bin(&mut ws, encode_blob_chunk(&hash_hex, offset, total, piece)).await;
+        }
+        let mut stored = None;
+        for _ in 0..50 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            stored = state.db.load_blob(&hash_hex).await.unwrap();
+            if stored.is_some() {
+                break;
+            }
+        }
+        assert_eq!(stored.as_deref(), Some(bytes.as_slice()));
+
+        send_bin(
+            &mut ws,
+            encode_message(MSG_BLOB_REQUEST, &hash_hex, hash_hex.as_bytes()),
+        )
+        .await;
+        let mut reassembled = Vec::new();
+        while (reassembled.len() as u64) < total {
+            // The pattern deflates well, so chunks arrive compressed.
+            let resp = recv_bin(&mut ws).await;
+            let resp = crate::v1::sync::decompress_frame(&resp).unwrap();
+            let (t, d, payload) = decode_message(&resp).unwrap();
+            assert_eq!(t, MSG_BLOB_CHUNK);
+            assert_eq!(d, hash_hex);
+            let (offset, announced, piece) = decode_blob_chunk(payload).unwrap();
+            assert_eq!(offset, reassembled.len() as u64);
+            assert_eq!(announced, total);
+            assert!(piece.len() <= BLOB_CHUNK_SIZE);
+            reassembled.extend_from_slice(piece);
+        }
+        assert_eq!(reassembled, bytes);
+    }
+
+    #[tokio::test]
+    async fn v1_blob_chunks_out_of_sequence_are_discarded() {
+        let (port, state) = setup_test_server().await;
+        let url = format!("ws://127.0.0.1:{}/sync", port);
+        let (mut ws, _) = connect_async(url).await.unwrap();
+        handshake(&mut ws).await;
+
+        let bytes = b"0123456789";
+        let hash_hex = crate::v1::hash_hex(bytes);
+        send_bin(&mut ws, encode_blob_chunk(&hash_hex, 0, 10, &bytes[..4])).await;
+        // Skips bytes 4..6.
+        send_bin(&mut ws, encode_blob_chunk(&hash_hex, 6, 10, &bytes[6..])).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!state.db.has_blob(&hash_hex).await.unwrap());
+
+        // A fresh upload from offset 0 still goes through.
+        send_bin(&mut ws, encode_blob_chunk(&hash_hex, 0, 10, &bytes[..4])).await;
+        send_bin(&mut ws, encode_blob_chunk(&hash_hex, 4, 10, &bytes[4..])).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(state.db.has_blob(&hash_hex).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn blob_quota_closes_connection_with_reason() {
+        let (port, state) = setup_test_server_with_limits(Limits {
+            max_blob_bytes_per_token: Some(16),
+            ..Limits::default()
+        })
+        .await;
+        let url = format!("ws://127.0.0.1:{}/sync?token=laptop", port);
+        let (mut ws, _) = connect_async(url).await.unwrap();
+        handshake(&mut ws).await;
+
+        // Fits the quota.
+        send_bin(&mut ws, encode_message(MSG_BLOB_UPDATE, "a.bin", b"0123456789")).await;
+        // Already stored, so free; that leaves room for six more bytes.
+        send_bin(&mut ws, encode_message(MSG_BLOB_UPDATE, "a.bin", b"0123456789")).await;
+        send_bin(&mut ws, encode_message(MSG_BLOB_UPDATE, "c.bin", b"klmnop")).await;
+        // Would take the token to 26 of 16 bytes.
+        send_bin(&mut ws, encode_message(MSG_BLOB_UPDATE, "b.bin", b"abcdefghij")).await;
+
+        match tokio::time::timeout(Duration::from_secs(2), ws.next()).await {
+            Ok(Some(Ok(TungsteniteMessage::Close(Some(frame))))) => {
+                assert_eq!(u16::from(frame.code), close_code::POLICY);
+                assert!(frame.reason.contains("blob quota exceeded"), "{}", frame.reason);
+            }
+            other => panic!("expected close frame, got {:?}", other),
+        }
+        assert!(state.db.has_blob(&crate::v1::hash_hex(b"0123456789")).await.unwrap());
+        assert!(state.db.has_blob(&crate::v1::hash_hex(b"klmnop")).await.unwrap());
+        assert!(!state.db.has_blob(&crate::v1::hash_hex(b"abcdefghij")).await.unwrap());
+        assert_eq!(state.quotas.usage("laptop").await.1, 16);
+        assert_eq!(state.quotas.usage(ANONYMOUS_TOKEN).await.1, 0);
+    }
+
+    #[tokio::test]
+    async fn frame_rate_limit_throttles_reads() {
+        let (port, _) = setup_test_server_with_limits(Limits {
+            frames_per_sec: Some(5),
+            ..Limits::default()
+        })
+        .await;
+        let url = format!("ws://127.0.0.1:{}/sync", port);
+        let (mut ws, _) = connect_async(url).await.unwrap();
+        handshake(&mut ws).await;
+
+        let started = Instant::now();
+        // Five frames fill the burst; the next five cost 200 ms each.
+        for _ in 0..10 {
+            send_bin(&mut ws, encode_message(0x7f, "noise", b"")).await;
+        }
+        let m = Manifest::new(ActorId::new());
+        let step1 = manifest_step1_payload(&m);
+        send_bin(&mut ws, encode_message(MSG_MANIFEST_SYNC, MANIFEST_DOC_ID, &step1)).await;
+        match tokio::time::timeout(Duration::from_secs(5), ws.next()).await {
+            Ok(Some(Ok(TungsteniteMessage::Binary(_)))) => {}
+            other => panic!("expected binary frame, got {:?}", other),
+        }
+        assert!(started.elapsed() >= Duration::from_millis(1000));
+    }
+
+    #[tokio::test]
+    async fn revoked_device_is_closed_on_hello() {
+        let (port, state) = setup_test_server().await;
+        let revoked = ActorId::new();
+        crate::server::devices::revoke(state.db.as_ref(), revoked).await.unwrap();
+
+        let url = format!("ws://127.0.0.1:{}/sync", port);
+
+        // Another device identifies and carries on.
+        let (mut ok, _) = connect_async(&url).await.unwrap();
+        handshake(&mut ok).await;
+        let step1 = manifest_step1_payload(&Manifest::new(ActorId::new()));
+        send_bin(&mut ok, encode_message(MSG_MANIFEST_SYNC, MANIFEST_DOC_ID, &step1)).await;
+        let reply = recv_bin(&mut ok).await;
+        assert_eq!(decode_message(&reply).unwrap().0, MSG_MANIFEST_SYNC);
+
+        let (mut ws, _) = connect_async(&url).await.unwrap();
+        handshake_as(&mut ws, revoked).await;
+        match tokio::time::timeout(Duration::from_secs(2), ws.next()).await {
+            Ok(Some(Ok(TungsteniteMessage::Close(Some(frame))))) => {
+                assert_eq!(u16::from(frame.code), close_code::POLICY);
+                assert_eq!(frame.reason.as_str(), DEVICE_REVOKED_REASON);
+            }
+            other => panic!("expected close frame, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn frames_before_hello_close_the_connection() {
+        let (port, state) = setup_test_server().await;
+        let url = format!("ws://127.0.0.1:{}/sync", port);
+        let (mut ws, _) = connect_async(&url).await.unwrap();
+        send_bin(&mut ws, encode_message(MSG_VERSION, MANIFEST_DOC_ID, &encode_version_handshake()))
+            .await;
+        send_bin(&mut ws, encode_message(MSG_CAPS, MANIFEST_DOC_ID, &encode_caps(Caps::ALL))).await;
+        let _ = recv_bin(&mut ws).await;
+        let _ = recv_bin(&mut ws).await;
+
+        send_bin(&mut ws, encode_message(MSG_BLOB_UPDATE, "x.bin", b"sneaky")).await;
+        match tokio::time::timeout(Duration::from_secs(2), ws.next()).await {
+            Ok(Some(Ok(TungsteniteMessage::Close(Some(frame))))) => {
+                assert_eq!(u16::from(frame.code), close_code::POLICY);
+            }
+            other => panic!("expected close frame, got {:?}", other),
+        }
+        assert!(!state.db.has_blob(&crate::v1::hash_hex(b"sneaky")).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn partial_replica_syncs_only_its_view() {
+        use crate::v1::manifest::NodeKind;
+        let (port, state) = setup_test_server().await;
+        let hr = tokens::issue(state.db.as_ref(), "hr").await.unwrap();
+        acl::set_rule(
+            state.db.as_ref(),
+            acl::Rule {
+                folder: "HR".into(),
+                read: vec![],
+                write: vec!["hr".into()],
+            },
+        )
+        .await
+        .unwrap();
+        let (notes, salaries) = {
+            let mut m = state.manifest.lock().await;
+            let notes = m.create_node("notes.md", None, NodeKind::Text, None, 1);
+            let hr = m.create_node("HR", None, NodeKind::Directory, None, 0);
+            (notes, m.create_node("salaries.md", Some(hr), NodeKind::Text, None, 2))
+        };
+
+        // Only the secret makes a connection "hr".
+        let claimed = format!("ws://127.0.0.1:{}/sync?token=hr", port);
+        assert!(connect_async(&claimed).await.is_err());
+        let guessed = format!("ws://127.0.0.1:{}/sync?token=hr:guess", port);
+        assert!(connect_async(&guessed).await.is_err());
+        let (mut ws, _) = connect_async(format!("ws://127.0.0.1:{}/sync?token={hr}", port))
+            .await
+            .unwrap();
+        handshake(&mut ws).await;
+        let step1 = manifest_step1_payload(&Manifest::new(ActorId::new()));
+        send_bin(&mut ws, encode_message(MSG_MANIFEST_SYNC, MANIFEST_DOC_ID, &step1)).await;
+        let reply = recv_bin(&mut ws).await;
+        let reply = crate::v1::sync::decompress_frame(&reply).unwrap();
+        let mut full = Manifest::new(ActorId::new());
+        full.apply_update(&decode_message(&reply).unwrap().2[1..]).unwrap();
+        assert!(full.get_entry(salaries).is_some());
+        drop(ws);
+
+        let url = format!("ws://127.0.0.1:{}/sync?token=staff", port);
+        let (mut ws, _) = connect_async(&url).await.unwrap();
+        handshake(&mut ws).await;
+        let mut client = Manifest::new(ActorId::new());
+        let step1 = manifest_step1_payload(&client);
+        send_bin(&mut ws, encode_message(MSG_MANIFEST_SYNC, MANIFEST_DOC_ID, &step1)).await;
+        let reply = recv_bin(&mut ws).await;
+        let (_, _, payload) = decode_message(&reply).unwrap();
+        client.apply_update(&payload[1..]).unwrap();
+        let _ = recv_bin(&mut ws).await; // the server's own STEP_1
+        assert!(client.get_entry(notes).is_some());
+        assert!(client.get_entry(salaries).is_none());
+
+        // Content of the hidden node is refused; the visible one answers.
+        let sv = StateVector::default().encode_v1();
+        let hidden = format!("content:{}", salaries.to_string_hyphenated());
+        let shown = format!("content:{}", notes.to_string_hyphenated());
+        send_bin(&mut ws, encode_message(MSG_SYNC_STEP_1, &hidden, &sv)).await;
+        send_bin(&mut ws, encode_message(MSG_SYNC_STEP_1, &shown, &sv)).await;
+        let reply = recv_bin(&mut ws).await;
+        assert_eq!(decode_message(&reply).unwrap().1, shown);
+
+        // Writes the token may make reach the shared manifest.
+        let before = client.doc().transact().state_vector();
+        let todo = client.create_node("todo.md", None, NodeKind::Text, None, 0);
+        let diff = client.doc().transact().encode_state_as_update_v1(&before);
+        let p = encode_manifest_update(&diff);
+        send_bin(&mut ws, encode_message(MSG_MANIFEST_SYNC, MANIFEST_DOC_ID, &p)).await;
+        for _ in 0..50 {
+            if state.manifest.lock().await.get_entry(todo).is_some() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("todo.md never reached the shared manifest");
+    }
+
+    #[test]
+    fn connection_token_prefers_query_then_bearer() {
+        let mut query = HashMap::new();
+        let mut headers = HeaderMap::new();
+        assert_eq!(connection_token(&query, &headers), (ANONYMOUS_TOKEN.to_string(), None));
+        headers.insert(
+            axum::http::header::AUTHORIZATION,
+            "Bearer phone:s3cret".parse().unwrap(),
+        );
+        assert_eq!(
+            connection_token(&query, &headers),
+            ("phone".to_string(), Some("s3cret".to_string()))
+        );
+        query.insert("token".to_string(), "laptop".to_string());
+        assert_eq!(connection_token(&query, &headers), ("laptop".to_string(), None));
+    }
+
     /// Pins the bidirectional content-sync handshake. When a client
     /// sends `MSG_SYNC_STEP_1` for a content subdoc the server has
     /// nothing for, the server must still reciprocate with its own
@@ -848,16 +2489,7 @@ mod tests {
         let (mut ws, _) = connect_async(url).await.unwrap();
 
         // Handshake.
-        send_bin(
-            &mut ws,
-            encode_message(
-                MSG_VERSION,
-                MANIFEST_DOC_ID,
-                &encode_version_handshake(),
-            ),
-        )
-        .await;
-        let _ = recv_bin(&mut ws).await;
+        handshake(&mut ws).await;
 
         // Client subscribes to a content doc the server has never seen,
         // sending an empty state vector.
@@ -888,4 +2520,264 @@ mod tests {
             "server must reciprocate STEP_1 even when it has no content for the doc"
         );
     }
+
+    #[tokio::test]
+    async fn sync_batch_answers_only_changed_docs() {
+        use crate::v1::sync::{BatchEntry, content_digest, encode_sync_batch};
+        use yrs::{Doc, Text};
+
+        let (port, state) = setup_test_server().await;
+        let same = "content:019dc69a-1234-7000-8000-00000000000a";
+        let stale = "content:019dc69a-1234-7000-8000-00000000000b";
+        let doc = Doc::new();
+        doc.get_or_insert_text("text")
+            .insert(&mut doc.transact_mut(), 0, "hello");
+        let update = doc.transact().encode_state_as_update_v1(&StateVector::default());
+        state.db.save_update(same, &update).await.unwrap();
+        state.db.save_update(stale, &update).await.unwrap();
+
+        let url = format!("ws://127.0.0.1:{}/sync", port);
+        let (mut ws, _) = connect_async(&url).await.unwrap();
+        handshake(&mut ws).await;
+
+        let sv = doc.transact().state_vector().encode_v1();
+        let empty_sv = StateVector::default().encode_v1();
+        let batch = encode_sync_batch(&[
+            BatchEntry {
+                doc_id: same,
+                digest: content_digest(&doc.transact()),
+                state_vector: &sv,
+            },
+            BatchEntry {
+                doc_id: stale,
+                digest: content_digest(&Doc::new().transact()),
+                state_vector: &empty_sv,
+            },
+        ]);
+        send_bin(&mut ws, encode_message(MSG_SYNC_BATCH, MANIFEST_DOC_ID, &batch)).await;
+        for expected in [MSG_SYNC_STEP_2, MSG_SYNC_STEP_1] {
+            let reply = recv_bin(&mut ws).await;
+            let (t, d, _) = decode_message(&reply).unwrap();
+            assert_eq!((t, d), (expected, stale));
+        }
+
+        // Nothing came back for `same`, but the connection is subscribed
+        // to it: another peer's edit is forwarded.
+        let (mut other, _) = connect_async(&url).await.unwrap();
+        handshake(&mut other).await;
+        let before = doc.transact().state_vector();
+        doc.get_or_insert_text("text")
+            .insert(&mut doc.transact_mut(), 5, "!");
+        let edit = doc.transact().encode_state_as_update_v1(&before);
+        send_bin(&mut other, encode_message(MSG_UPDATE, same, &edit)).await;
+        let reply = recv_bin(&mut ws).await;
+        let (t, d, p) = decode_message(&reply).unwrap();
+        assert_eq!((t, d, p), (MSG_UPDATE, same, edit.as_slice()));
+    }
+
+    #[tokio::test]
+    async fn change_feed_lists_changed_docs_and_forwards_updates() {
+        use crate::v1::sync::{decode_changes_reply, encode_changes_request};
+
+        let (port, state) = setup_test_server().await;
+        let url = format!("ws://127.0.0.1:{}/sync", port);
+        let (mut ws, _) = connect_async(&url).await.unwrap();
+        handshake(&mut ws).await;
+        let ask = |cursor: Option<&Cursor>| {
+            encode_message(MSG_CHANGES, MANIFEST_DOC_ID, &encode_changes_request(cursor))
+        };
+
+        // No cursor yet: the server can't tell what we've missed.
+        send_bin(&mut ws, ask(None)).await;
+        let reply = recv_bin(&mut ws).await;
+        let first = decode_changes_reply(decode_message(&reply).unwrap().2).unwrap();
+        assert_eq!(first.docs, None);
+        assert_eq!(first.cursor.feed, state.feed.id());
+
+        // Another peer's edit reaches us without any per-doc subscribe.
+        let doc_id = "content:019dc69a-1234-7000-8000-00000000000c";
+        let (mut other, _) = connect_async(&url).await.unwrap();
+        handshake(&mut other).await;
+        let doc = yrs::Doc::new();
+        {
+            use yrs::Text;
+            doc.get_or_insert_text("text")
+                .insert(&mut doc.transact_mut(), 0, "hi");
+        }
+        let update = doc.transact().encode_state_as_update_v1(&StateVector::default());
+        send_bin(&mut other, encode_message(MSG_UPDATE, doc_id, &update)).await;
+        let fwd = recv_bin(&mut ws).await;
+        assert_eq!(decode_message(&fwd).unwrap().1, doc_id);
+
+        // Resuming from the first cursor names exactly that doc.
+        send_bin(&mut ws, ask(Some(&first.cursor))).await;
+        let reply = recv_bin(&mut ws).await;
+        let second = decode_changes_reply(decode_message(&reply).unwrap().2).unwrap();
+        assert_eq!(second.docs, Some(vec![doc_id.to_string()]));
+        assert!(second.cursor.seq > first.cursor.seq);
+
+        // A cursor from some other feed isn't trusted.
+        let foreign = Cursor {
+            feed: "elsewhere".into(),
+            seq: second.cursor.seq,
+        };
+        send_bin(&mut ws, ask(Some(&foreign))).await;
+        let reply = recv_bin(&mut ws).await;
+        assert_eq!(decode_changes_reply(decode_message(&reply).unwrap().2).unwrap().docs, None);
+    }
+
+    #[tokio::test]
+    async fn tagged_updates_are_acked_once_stored() {
+        use crate::protocol::{ACK_REFUSED, ACK_SAVED};
+        use crate::v1::sync::{decode_ack, encode_tagged};
+
+        let (port, state) = setup_test_server().await;
+        let url = format!("ws://127.0.0.1:{}/sync", port);
+        let (mut ws, _) = connect_async(&url).await.unwrap();
+        handshake(&mut ws).await;
+
+        let doc_id = "content:019dc69a-1234-7000-8000-00000000000d";
+        let doc = yrs::Doc::new();
+        {
+            use yrs::Text;
+            doc.get_or_insert_text("text")
+                .insert(&mut doc.transact_mut(), 0, "saved");
+        }
+        let update = doc.transact().encode_state_as_update_v1(&StateVector::default());
+        let tagged = encode_tagged(7, MSG_UPDATE, &update);
+        send_bin(&mut ws, encode_message(MSG_TAGGED, doc_id, &tagged)).await;
+        let reply = recv_bin(&mut ws).await;
+        let (t, d, p) = decode_message(&reply).unwrap();
+        assert_eq!((t, d), (MSG_ACK, doc_id));
+        assert_eq!(decode_ack(p), Some((7, ACK_SAVED)));
+        // The ack means it is in storage already.
+        assert!(!state.db.load_doc_updates(doc_id).await.unwrap().is_empty());
+
+        // A malformed blob chunk will never be stored.
+        let hash = "ab".repeat(32);
+        let tagged = encode_tagged(8, MSG_BLOB_CHUNK, &[0; 3]);
+        send_bin(&mut ws, encode_message(MSG_TAGGED, &hash, &tagged)).await;
+        let reply = recv_bin(&mut ws).await;
+        let (t, d, p) = decode_message(&reply).unwrap();
+        assert_eq!((t, d), (MSG_ACK, hash.as_str()));
+        assert_eq!(decode_ack(p), Some((8, ACK_REFUSED)));
+    }
+
+    #[tokio::test]
+    async fn compressed_uploads_are_stored_inflated_and_replies_compressed_on_request() {
+        use crate::protocol::ACK_SAVED;
+        use crate::v1::sync::{compress_frame, decode_ack, decompress_frame, encode_tagged};
+        use sha2::{Digest, Sha256};
+
+        let (port, state) = setup_test_server().await;
+        let url = format!("ws://127.0.0.1:{}/sync", port);
+        let (mut ws, _) = connect_async(&url).await.unwrap();
+        handshake(&mut ws).await;
+
+        // A CSV goes up deflated and is stored as the original bytes.
+        let csv = "date,steps,notes\n2026-10-18,9001,walked to the lake\n".repeat(200);
+        let hash = format!("{:x}", Sha256::digest(csv.as_bytes()));
+        let tagged = encode_tagged(1, MSG_BLOB_UPDATE, csv.as_bytes());
+        let frame = encode_message(MSG_TAGGED, &hash, &tagged);
+        let packed = compress_frame(&frame).unwrap();
+        assert!(packed.len() < frame.len() / 4);
+        send_bin(&mut ws, packed).await;
+        let reply = recv_bin(&mut ws).await;
+        let (t, _, p) = decode_message(&reply).unwrap();
+        assert_eq!((t, decode_ack(p)), (MSG_ACK, Some((1, ACK_SAVED))));
+        let stored = state.db.load_blob(&hash).await.unwrap();
+        assert_eq!(stored.as_deref(), Some(csv.as_bytes()));
+
+        // One that won't inflate is answered, and its tag failed.
+        let garbage = encode_tagged(2, MSG_COMPRESSED, &[MSG_BLOB_UPDATE, 0xff, 0xff]);
+        send_bin(&mut ws, encode_message(MSG_TAGGED, &hash, &garbage)).await;
+        let reply = recv_bin(&mut ws).await;
+        let (t, d, p) = decode_message(&reply).unwrap();
+        assert_eq!((t, d), (MSG_ERROR, hash.as_str()));
+        assert_eq!(decode_error(p).unwrap().0, ERR_COMPRESSION);
+        let reply = recv_bin(&mut ws).await;
+        let (t, _, p) = decode_message(&reply).unwrap();
+        assert_eq!((t, decode_ack(p)), (MSG_ACK, Some((2, ACK_FAILED))));
+
+        // Asked for again, it comes back compressed to a client that
+        // reads MSG_COMPRESSED...
+        send_bin(&mut ws, encode_message(MSG_BLOB_REQUEST, &hash, hash.as_bytes())).await;
+        let reply = recv_bin(&mut ws).await;
+        assert_eq!(decode_message(&reply).unwrap().0, MSG_COMPRESSED);
+        let reply = decompress_frame(&reply).unwrap();
+        let (t, _, p) = decode_message(&reply).unwrap();
+        assert_eq!(t, MSG_BLOB_CHUNK);
+        assert_eq!(decode_blob_chunk(p).unwrap().2, csv.as_bytes());
+
+        // ...and as is to one that doesn't.
+        let (mut plain, _) = connect_async(&url).await.unwrap();
+        let offered = Caps(Caps::ALL.0 & !CAP_COMPRESSION);
+        let hs = encode_message(MSG_VERSION, MANIFEST_DOC_ID, &encode_version_handshake());
+        send_bin(&mut plain, hs).await;
+        send_bin(&mut plain, encode_message(MSG_CAPS, MANIFEST_DOC_ID, &encode_caps(offered))).await;
+        let _ = recv_bin(&mut plain).await;
+        let _ = recv_bin(&mut plain).await;
+        send_bin(&mut plain, encode_message(MSG_HELLO, MANIFEST_DOC_ID, &encode_hello(ActorId::new())))
+            .await;
+        send_bin(&mut plain, encode_message(MSG_BLOB_REQUEST, &hash, hash.as_bytes())).await;
+        let reply = recv_bin(&mut plain).await;
+        let (t, _, p) = decode_message(&reply).unwrap();
+        assert_eq!(t, MSG_BLOB_CHUNK);
+        assert_eq!(decode_blob_chunk(p).unwrap().2, csv.as_bytes());
+
+        // Nor does it take compressed frames from that client; it says
+        // so, and the plain resend is stored.
+        let other = csv.replace("lake", "pond");
+        let other_hash = format!("{:x}", Sha256::digest(other.as_bytes()));
+        let tagged = encode_tagged(3, MSG_BLOB_UPDATE, other.as_bytes());
+        let frame = encode_message(MSG_TAGGED, &other_hash, &tagged);
+        send_bin(&mut plain, compress_frame(&frame).unwrap()).await;
+        let reply = recv_bin(&mut plain).await;
+        let (t, _, p) = decode_message(&reply).unwrap();
+        assert_eq!(t, MSG_ERROR);
+        assert_eq!(decode_error(p).unwrap().0, ERR_COMPRESSION);
+        let reply = recv_bin(&mut plain).await;
+        let (t, _, p) = decode_message(&reply).unwrap();
+        assert_eq!((t, decode_ack(p)), (MSG_ACK, Some((3, ACK_FAILED))));
+        assert!(state.db.load_blob(&other_hash).await.unwrap().is_none());
+
+        send_bin(&mut plain, frame).await;
+        let reply = recv_bin(&mut plain).await;
+        let (t, _, p) = decode_message(&reply).unwrap();
+        assert_eq!((t, decode_ack(p)), (MSG_ACK, Some((3, ACK_SAVED))));
+        let stored = state.db.load_blob(&other_hash).await.unwrap();
+        assert_eq!(stored.as_deref(), Some(other.as_bytes()));
+    }
+
+    #[tokio::test]
+    async fn errors_name_the_missing_blob_and_the_bad_handshake() {
+        let (port, _) = setup_test_server().await;
+        let url = format!("ws://127.0.0.1:{}/sync", port);
+        let (mut ws, _) = connect_async(&url).await.unwrap();
+        handshake(&mut ws).await;
+
+        let hash = "cd".repeat(32);
+        send_bin(&mut ws, encode_message(MSG_BLOB_REQUEST, &hash, hash.as_bytes())).await;
+        let reply = recv_bin(&mut ws).await;
+        let (t, d, p) = decode_message(&reply).unwrap();
+        assert_eq!((t, d), (MSG_ERROR, hash.as_str()));
+        assert_eq!(decode_error(p).unwrap().0, ERR_BLOB_NOT_FOUND);
+
+        // A client from a future major version is told why before the
+        // server hangs up on it.
+        let (mut ws, _) = connect_async(&url).await.unwrap();
+        send_bin(&mut ws, encode_message(MSG_VERSION, MANIFEST_DOC_ID, &[2, 0])).await;
+        let reply = recv_bin(&mut ws).await;
+        let (t, _, p) = decode_message(&reply).unwrap();
+        assert_eq!(t, MSG_ERROR);
+        let (code, message) = decode_error(p).unwrap();
+        assert_eq!(code, ERR_VERSION);
+        assert!(message.contains("2.0"), "{message}");
+        match tokio::time::timeout(Duration::from_secs(2), ws.next()).await {
+            Ok(Some(Ok(TungsteniteMessage::Close(Some(frame))))) => {
+                assert_eq!(frame.code, CloseCode::Protocol);
+            }
+            other => panic!("expected a close frame, got {:?}", other),
+        }
+    }
 }
@@ -0,0 +1,533 @@
+//! Pluggable persistence for the v1 server.
+//!
+//! Everything the server keeps across restarts goes through the
+//! [`Storage`] trait:
+//!
+//! - **update logs** — append-only yrs updates per `doc_id`
+//!   (`__manifest__`, `content:<node>`, legacy v0 doc ids)
+//! - **snapshots**   — a compacted replacement for a doc's whole log
+//! - **blobs**       — content-addressed binary payloads keyed by hex SHA-256
+//! - **meta**        — small string key/value pairs (schema version,
+//!   server actor id, …)
+//! - **change feed** — a sequence number stamped on every log write, so
+//!   a reconnecting client can ask which docs changed since it left
+//!
+//! Three backends ship in-tree:
+//!
+//! - [`Db`](super::db::Db)                          — SQLite, the default
+//! - [`MemoryStorage`](super::memory_storage::MemoryStorage) — in-process
+//!   maps, for tests and throwaway servers
+//! - [`FsStorage`](super::fs_storage::FsStorage)   — plain directory tree;
+//!   blobs are ordinary files so backups and replication can work at
+//!   file granularity
+//!
+//! Backends only implement raw persistence. The yrs-aware helpers that
+//! replay a log into a state vector or a diff are provided methods on
+//! the trait so every backend answers them identically.
+
+use crate::protocol::{BLOB_CHUNK_SIZE, MAX_BLOB_SIZE};
+use crate::v1::blob_store::BlobWriter;
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::stream::{self, BoxStream};
+use std::path::PathBuf;
+use tokio::io::AsyncReadExt;
+use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::Encode;
+use yrs::{Doc, ReadTxn, StateVector, Transact, Update};
+
+/// Doc id of the v0 path index. Never counted as a user document.
+pub const INDEX_DOC_ID: &str = "__index__";
+
+/// Answer to [`Storage::changes_since`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Changes {
+    /// Highest sequence number stamped so far; 0 if nothing ever was.
+    pub head: u64,
+    /// Live docs stamped after the caller's sequence, sorted ascending.
+    pub docs: Vec<String>,
+}
+
+/// A blob opened for reading: its total size plus a stream of chunks.
+/// Backends that can read incrementally (object stores, files) yield
+/// many small chunks so the caller never holds a second full copy.
+pub struct BlobReader {
+    pub size: u64,
+    pub chunks: BoxStream<'static, Result<Bytes>>,
+}
+
+/// A blob arriving piecewise over the wire (`MSG_BLOB_CHUNK`). Created
+/// by [`Storage::begin_blob`], fed with [`BlobUpload::write`] and handed
+/// back to [`Storage::finish_blob`] once [`BlobUpload::is_complete`].
+///
+/// File-backed stores stage the bytes on disk so memory stays at one
+/// chunk; the rest buffer in memory, so they only accept totals up to
+/// `MAX_BLOB_SIZE` (see [`Storage::max_upload_size`]).
+pub struct BlobUpload {
+    hash: String,
+    total: u64,
+    received: u64,
+    sink: UploadSink,
+}
+
+enum UploadSink {
+    Buffer(Vec<u8>),
+    Staged(BlobWriter),
+}
+
+impl BlobUpload {
+    /// Upload collected in memory, stored via [`Storage::save_blob`].
+    pub fn buffered(hash: &str, total: u64) -> Self {
+        Self {
+            hash: hash.to_string(),
+            total,
+            received: 0,
+            sink: UploadSink::Buffer(Vec::new()),
+        }
+    }
+
+    /// Upload streamed into a [`BlobWriter`] staging file.
+    pub fn staged(hash: &str, total: u64, writer: BlobWriter) -> Self {
+        Self {
+            hash: hash.to_string(),
+            total,
+            received: 0,
+            sink: UploadSink::Staged(writer),
+        }
+    }
+
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Bytes accepted so far; the offset the next chunk must start at.
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.received == self.total
+    }
+
+    /// Append the next chunk. Refuses to grow past the announced total.
+    pub fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        if self.received + bytes.len() as u64 > self.total {
+            bail!(
+                "blob {} overruns its announced size of {} bytes",
+                self.hash,
+                self.total
+            );
+        }
+        match &mut self.sink {
+            UploadSink::Buffer(buf) => buf.extend_from_slice(bytes),
+            UploadSink::Staged(w) => w.write_chunk(bytes)?,
+        }
+        self.received += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Hash-verified bytes of a complete in-memory upload.
+    pub fn into_bytes(self) -> Result<Vec<u8>> {
+        self.ensure_complete()?;
+        let UploadSink::Buffer(buf) = self.sink else {
+            bail!("blob {} was staged on disk, not buffered", self.hash);
+        };
+        let actual = crate::v1::hash_hex(&buf);
+        if actual != self.hash {
+            bail!(
+                "blob hash mismatch: expected {}, computed {}",
+                self.hash,
+                actual
+            );
+        }
+        Ok(buf)
+    }
+
+    /// The staging writer of a complete on-disk upload. The caller seals
+    /// it with [`BlobWriter::finish`], which verifies the hash.
+    pub fn into_writer(self) -> Result<BlobWriter> {
+        self.ensure_complete()?;
+        match self.sink {
+            UploadSink::Staged(w) => Ok(w),
+            UploadSink::Buffer(_) => bail!("blob {} was buffered, not staged", self.hash),
+        }
+    }
+
+    fn ensure_complete(&self) -> Result<()> {
+        if !self.is_complete() {
+            bail!(
+                "blob {} incomplete: {} of {} bytes",
+                self.hash,
+                self.received,
+                self.total
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Stream a blob file in [`BLOB_CHUNK_SIZE`] pieces. Shared by the
+/// backends that keep blobs as plain files.
+pub(crate) async fn file_blob_reader(path: PathBuf) -> Result<BlobReader> {
+    let file = tokio::fs::File::open(&path)
+        .await
+        .with_context(|| format!("opening blob {}", path.display()))?;
+    let size = file.metadata().await?.len();
+    let chunks = stream::try_unfold(file, |mut file| async move {
+        let mut buf = vec![0u8; BLOB_CHUNK_SIZE];
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.truncate(n);
+        Ok(Some((Bytes::from(buf), file)))
+    });
+    Ok(BlobReader {
+        size,
+        chunks: Box::pin(chunks),
+    })
+}
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    // -- update logs ------------------------------------------------------
+
+    /// Append one yrs update to `doc_id`'s log.
+    async fn save_update(&self, doc_id: &str, update: &[u8]) -> Result<()>;
+
+    /// Every update recorded for `doc_id`, oldest first. Empty if the
+    /// doc has never been written.
+    async fn load_doc_updates(&self, doc_id: &str) -> Result<Vec<Vec<u8>>>;
+
+    /// Distinct doc ids with at least one live update, sorted ascending.
+    async fn list_doc_ids(&self) -> Result<Vec<String>>;
+
+    // -- snapshots --------------------------------------------------------
+
+    /// Atomically replace `doc_id`'s whole log with a single update.
+    /// Readers observe either the old log or the snapshot, never a mix.
+    async fn save_snapshot(&self, doc_id: &str, snapshot: &[u8]) -> Result<()>;
+
+    /// Move the logs of `doc_ids` out of the live set into a backup area
+    /// that is kept for manual recovery but never read by the server.
+    async fn archive_docs(&self, doc_ids: &[String]) -> Result<()>;
+
+    // -- change feed ------------------------------------------------------
+
+    /// Docs written after sequence number `since`.
+    ///
+    /// Every [`Storage::save_update`] and [`Storage::save_snapshot`]
+    /// stamps its doc with a sequence number higher than any before it,
+    /// persisted with the write. Numbers are never reused, so a caller
+    /// that remembers `head` can later ask for exactly the docs touched
+    /// since. Archived docs are not reported.
+    async fn changes_since(&self, since: u64) -> Result<Changes>;
+
+    // -- blobs ------------------------------------------------------------
+
+    /// Store a binary blob by its SHA-256 hash. Content-addressable: if
+    /// the hash already exists the call is a no-op (first write wins).
+    async fn save_blob(&self, hash: &str, data: &[u8]) -> Result<()>;
+
+    /// Load a binary blob by its SHA-256 hash. Returns None if not found.
+    async fn load_blob(&self, hash: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Check whether a blob with the given hash exists.
+    async fn has_blob(&self, hash: &str) -> Result<bool>;
+
+    /// Open a blob for chunked reading. Returns None if not found.
+    ///
+    /// The default buffers the whole blob via [`Storage::load_blob`];
+    /// backends with a native streaming read should override it.
+    async fn open_blob(&self, hash: &str) -> Result<Option<BlobReader>> {
+        Ok(self.load_blob(hash).await?.map(|data| BlobReader {
+            size: data.len() as u64,
+            chunks: Box::pin(stream::once(async move { Ok(Bytes::from(data)) })),
+        }))
+    }
+
+    /// Largest `total` [`Storage::begin_blob`] accepts. Buffered uploads
+    /// sit in memory until the last chunk, so the default keeps them to
+    /// `MAX_BLOB_SIZE`; backends that stage to disk raise it.
+    fn max_upload_size(&self) -> u64 {
+        MAX_BLOB_SIZE as u64
+    }
+
+    /// Start receiving the blob `hash` (`total` bytes) piecewise.
+    ///
+    /// The default collects the chunks in memory for
+    /// [`Storage::save_blob`]; backends that can stage to disk override
+    /// this together with [`Storage::finish_blob`].
+    async fn begin_blob(&self, hash: &str, total: u64) -> Result<BlobUpload> {
+        if total > self.max_upload_size() {
+            bail!("blob {hash} is {total} bytes, over the upload limit");
+        }
+        Ok(BlobUpload::buffered(hash, total))
+    }
+
+    /// Verify and store a complete upload from [`Storage::begin_blob`].
+    async fn finish_blob(&self, upload: BlobUpload) -> Result<()> {
+        let hash = upload.hash().to_string();
+        let data = upload.into_bytes()?;
+        self.save_blob(&hash, &data).await
+    }
+
+    // -- meta -------------------------------------------------------------
+
+    async fn get_meta(&self, key: &str) -> Result<Option<String>>;
+
+    async fn set_meta(&self, key: &str, value: &str) -> Result<()>;
+
+    // -- provided ---------------------------------------------------------
+
+    /// Number of user documents, i.e. every doc id except the v0 index.
+    async fn count_docs(&self) -> Result<i64> {
+        let ids = self.list_doc_ids().await?;
+        Ok(ids.iter().filter(|id| id.as_str() != INDEX_DOC_ID).count() as i64)
+    }
+
+    /// Encode the current state vector for a doc as v1 bytes.
+    ///
+    /// Used by the server to advertise what it has so the client can
+    /// reply with the inverse diff (its updates the server is missing).
+    /// Without this the sync handshake is one-way: a client whose subdoc
+    /// has updates we never observed has no way to push them back after
+    /// reconnect.
+    async fn get_doc_state_vector(&self, doc_id: &str) -> Result<Vec<u8>> {
+        let all_updates = self.load_doc_updates(doc_id).await?;
+        tokio::task::spawn_blocking(move || {
+            let doc = replay_updates(all_updates);
+            let txn = doc.transact();
+            Ok(txn.state_vector().encode_v1())
+        })
+        .await?
+    }
+
+    /// [`content_digest`] of a doc's replayed history. An unknown doc
+    /// has the digest of an empty doc.
+    ///
+    /// [`content_digest`]: crate::v1::sync::content_digest
+    async fn get_doc_digest(&self, doc_id: &str) -> Result<[u8; 32]> {
+        let all_updates = self.load_doc_updates(doc_id).await?;
+        tokio::task::spawn_blocking(move || {
+            let doc = replay_updates(all_updates);
+            let txn = doc.transact();
+            Ok(crate::v1::sync::content_digest(&txn))
+        })
+        .await?
+    }
+
+    /// Everything recorded for `doc_id` that `since_sv` hasn't seen,
+    /// merged into a single yrs update.
+    ///
+    /// Loads the full history into a temporary doc and diffs it against
+    /// the caller's state vector. An unknown doc still yields a valid
+    /// (empty) update so callers can forward it unconditionally.
+    async fn get_all_updates_since(&self, doc_id: &str, since_sv: &StateVector) -> Result<Vec<u8>> {
+        let all_updates = self.load_doc_updates(doc_id).await?;
+        let since_sv = since_sv.clone();
+        tokio::task::spawn_blocking(move || {
+            let doc = replay_updates(all_updates);
+            let txn = doc.transact();
+            Ok(txn.encode_state_as_update_v1(&since_sv))
+        })
+        .await?
+    }
+
+    /// Merge `doc_id`'s log into one update and store it as a snapshot.
+    /// A no-op for docs with fewer than two updates.
+    async fn compact_doc(&self, doc_id: &str) -> Result<()> {
+        let all_updates = self.load_doc_updates(doc_id).await?;
+        if all_updates.len() < 2 {
+            return Ok(());
+        }
+        let merged = self
+            .get_all_updates_since(doc_id, &StateVector::default())
+            .await?;
+        self.save_snapshot(doc_id, &merged).await
+    }
+}
+
+/// Apply every decodable update to a fresh doc. Corrupt rows are skipped
+/// rather than failing the whole replay — one bad write shouldn't make
+/// a doc unreadable.
+fn replay_updates(updates: Vec<Vec<u8>>) -> Doc {
+    let doc = Doc::new();
+    if !updates.is_empty() {
+        let mut txn = doc.transact_mut();
+        for update_data in updates {
+            if let Ok(u) = Update::decode_v1(&update_data) {
+                txn.apply_update(u);
+            }
+        }
+    }
+    doc
+}
+
+/// Backend-agnostic conformance checks. Every backend's test module runs
+/// these against a fresh instance so behaviour can't drift between them.
+#[cfg(test)]
+pub(crate) mod conformance {
+    use super::*;
+    use yrs::{GetString, Text};
+
+    fn hex_hash(data: &[u8]) -> String {
+        crate::v1::hash_hex(data)
+    }
+
+    pub(crate) async fn update_log_roundtrip(s: &dyn Storage) {
+        assert!(s.load_doc_updates("doc").await.unwrap().is_empty());
+        s.save_update("doc", &[1, 2, 3]).await.unwrap();
+        s.save_update("doc", &[4, 5, 6]).await.unwrap();
+        s.save_update("other", &[7]).await.unwrap();
+        s.save_update(INDEX_DOC_ID, &[8]).await.unwrap();
+
+        let updates = s.load_doc_updates("doc").await.unwrap();
+        assert_eq!(updates, vec![vec![1, 2, 3], vec![4, 5, 6]]);
+        assert_eq!(
+            s.list_doc_ids().await.unwrap(),
+            vec![INDEX_DOC_ID.to_string(), "doc".to_string(), "other".to_string()]
+        );
+        assert_eq!(s.count_docs().await.unwrap(), 2);
+    }
+
+    pub(crate) async fn snapshot_replaces_log(s: &dyn Storage) {
+        let doc = Doc::new();
+        let text = doc.get_or_insert_text("t");
+        for chunk in ["Hello", ", ", "world"] {
+            let before = doc.transact().state_vector();
+            let mut txn = doc.transact_mut();
+            let len = text.get_string(&txn).len() as u32;
+            text.insert(&mut txn, len, chunk);
+            let update = txn.encode_state_as_update_v1(&before);
+            drop(txn);
+            s.save_update("content:x", &update).await.unwrap();
+        }
+        assert_eq!(s.load_doc_updates("content:x").await.unwrap().len(), 3);
+
+        s.compact_doc("content:x").await.unwrap();
+        let updates = s.load_doc_updates("content:x").await.unwrap();
+        assert_eq!(updates.len(), 1);
+
+        let replica = Doc::new();
+        let t2 = replica.get_or_insert_text("t");
+        let mut txn = replica.transact_mut();
+        txn.apply_update(Update::decode_v1(&updates[0]).unwrap());
+        assert_eq!(t2.get_string(&txn), "Hello, world");
+    }
+
+    pub(crate) async fn archive_hides_docs(s: &dyn Storage) {
+        s.save_update("old-a", &[1]).await.unwrap();
+        s.save_update("old-b", &[2]).await.unwrap();
+        s.save_update("keep", &[3]).await.unwrap();
+        s.archive_docs(&["old-a".to_string(), "old-b".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(s.list_doc_ids().await.unwrap(), vec!["keep".to_string()]);
+        assert!(s.load_doc_updates("old-a").await.unwrap().is_empty());
+    }
+
+    pub(crate) async fn blobs_are_content_addressed(s: &dyn Storage) {
+        let data = b"\x89PNG\r\n\x1a\nblob".to_vec();
+        let hash = hex_hash(&data);
+        assert!(!s.has_blob(&hash).await.unwrap());
+        assert!(s.load_blob(&hash).await.unwrap().is_none());
+
+        s.save_blob(&hash, &data).await.unwrap();
+        s.save_blob(&hash, &data).await.unwrap();
+        assert!(s.has_blob(&hash).await.unwrap());
+        assert_eq!(s.load_blob(&hash).await.unwrap().unwrap(), data);
+
+        let reader = s.open_blob(&hash).await.unwrap().unwrap();
+        assert_eq!(reader.size, data.len() as u64);
+        let chunks: Vec<Bytes> = futures_util::TryStreamExt::try_collect(reader.chunks)
+            .await
+            .unwrap();
+        assert_eq!(chunks.concat(), data);
+        assert!(s.open_blob(&"0".repeat(64)).await.unwrap().is_none());
+    }
+
+    pub(crate) async fn chunked_upload_roundtrip(s: &dyn Storage) {
+        let data: Vec<u8> = (0..3 * 1024 + 7).map(|i| (i % 251) as u8).collect();
+        let hash = hex_hash(&data);
+        let mut upload = s.begin_blob(&hash, data.len() as u64).await.unwrap();
+        for piece in data.chunks(1024) {
+            assert!(!upload.is_complete());
+            upload.write(piece).unwrap();
+        }
+        assert!(upload.write(b"x").is_err(), "must refuse to overrun total");
+        assert!(!s.has_blob(&hash).await.unwrap());
+        s.finish_blob(upload).await.unwrap();
+        assert_eq!(s.load_blob(&hash).await.unwrap().unwrap(), data);
+
+        // Corrupted bytes never become visible.
+        let wrong = "e".repeat(64);
+        let mut upload = s.begin_blob(&wrong, 4).await.unwrap();
+        upload.write(b"evil").unwrap();
+        assert!(s.finish_blob(upload).await.is_err());
+        assert!(!s.has_blob(&wrong).await.unwrap());
+
+        // Neither do truncated uploads.
+        let short = hex_hash(b"short");
+        let mut upload = s.begin_blob(&short, 5).await.unwrap();
+        upload.write(b"sho").unwrap();
+        assert!(s.finish_blob(upload).await.is_err());
+        assert!(!s.has_blob(&short).await.unwrap());
+
+        // Nothing is set up past the backend's limit.
+        let huge = "f".repeat(64);
+        assert!(s.max_upload_size() >= MAX_BLOB_SIZE as u64);
+        assert!(s.begin_blob(&huge, s.max_upload_size() + 1).await.is_err());
+    }
+
+    pub(crate) async fn meta_upserts(s: &dyn Storage) {
+        assert!(s.get_meta("db_version").await.unwrap().is_none());
+        s.set_meta("db_version", "1").await.unwrap();
+        s.set_meta("db_version", "2").await.unwrap();
+        assert_eq!(s.get_meta("db_version").await.unwrap().as_deref(), Some("2"));
+    }
+
+    pub(crate) async fn change_feed_tracks_writes(s: &dyn Storage) {
+        let empty = s.changes_since(0).await.unwrap();
+        assert_eq!(empty.docs, Vec::<String>::new());
+        let start = empty.head;
+
+        s.save_update("a", &[1]).await.unwrap();
+        s.save_update("b", &[2]).await.unwrap();
+        let all = s.changes_since(start).await.unwrap();
+        assert_eq!(all.docs, vec!["a".to_string(), "b".to_string()]);
+        assert!(all.head >= start + 2);
+
+        s.save_update("b", &[3]).await.unwrap();
+        s.save_snapshot("c", &[4]).await.unwrap();
+        let later = s.changes_since(all.head).await.unwrap();
+        assert_eq!(later.docs, vec!["b".to_string(), "c".to_string()]);
+        assert!(later.head > all.head);
+
+        let quiet = s.changes_since(later.head).await.unwrap();
+        assert_eq!(quiet, Changes { head: later.head, docs: vec![] });
+
+        s.archive_docs(&["a".to_string()]).await.unwrap();
+        assert!(!s.changes_since(start).await.unwrap().docs.contains(&"a".to_string()));
+    }
+
+    pub(crate) async fn run_all<S, F, Fut>(make: F)
+    where
+        S: Storage,
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = S>,
+    {
+        update_log_roundtrip(&make().await).await;
+        snapshot_replaces_log(&make().await).await;
+        archive_hides_docs(&make().await).await;
+        blobs_are_content_addressed(&make().await).await;
+        chunked_upload_roundtrip(&make().await).await;
+        meta_upserts(&make().await).await;
+        change_feed_tracks_writes(&make().await).await;
+    }
+}
@@ -0,0 +1,140 @@
+//! Token secrets: what turns an account token into a credential.
+//!
+//! On its own a token is a label the client picks (see
+//! [`limits`](crate::server::limits)). An admin can [`issue`] a secret
+//! for one; from then on a connection may only use that token by
+//! presenting `<token>:<secret>` — as `?token=` or an
+//! `Authorization: Bearer` header — and the server answers anything
+//! else with `401 Unauthorized` before the WebSocket upgrade. A token
+//! with no secret keeps working as a plain label, but it is not
+//! *verified*: access rules ([`acl`](crate::server::acl)) and token
+//! revocations only ever apply to verified tokens.
+//!
+//! Only a SHA-256 of each secret is kept, in server meta as JSON, and
+//! it is read on every connection, so issuing or removing a secret
+//! takes effect without a restart.
+
+use crate::server::storage::Storage;
+use crate::v1::hash::hash_hex;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+
+const SECRETS_KEY: &str = "token_secrets";
+
+/// How a connection's credential checked out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Authentication {
+    /// The token has a secret and the connection presented it.
+    Verified,
+    /// The token has no secret and the connection presented none.
+    Label,
+    /// A wrong or missing secret, or one for a token that has none.
+    Rejected,
+}
+
+/// Give `token` a fresh secret, replacing any it had. Returns the
+/// credential clients connect with, `<token>:<secret>`; the secret is
+/// not stored and can't be shown again.
+pub async fn issue(db: &dyn Storage, token: &str) -> Result<String> {
+    anyhow::ensure!(
+        !token.is_empty() && !token.contains(':'),
+        "token {token:?} must be non-empty and contain no ':'"
+    );
+    let secret = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    let mut secrets = load(db).await?;
+    secrets.insert(token.to_string(), hash_hex(secret.as_bytes()));
+    store(db, &secrets).await?;
+    Ok(format!("{token}:{secret}"))
+}
+
+/// Drop `token`'s secret, making it a plain label again. Returns
+/// `false` if it had none.
+pub async fn remove(db: &dyn Storage, token: &str) -> Result<bool> {
+    let mut secrets = load(db).await?;
+    if secrets.remove(token).is_none() {
+        return Ok(false);
+    }
+    store(db, &secrets).await?;
+    Ok(true)
+}
+
+/// Every token that has a secret, in order.
+pub async fn list(db: &dyn Storage) -> Result<Vec<String>> {
+    Ok(load(db).await?.into_keys().collect())
+}
+
+/// Check the `secret` a connection presented for `token`.
+pub(crate) async fn authenticate(
+    db: &dyn Storage,
+    token: &str,
+    secret: Option<&str>,
+) -> Result<Authentication> {
+    let secrets = load(db).await?;
+    Ok(match (secrets.get(token), secret) {
+        (None, None) => Authentication::Label,
+        (Some(hash), Some(secret)) if *hash == hash_hex(secret.as_bytes()) => {
+            Authentication::Verified
+        }
+        _ => Authentication::Rejected,
+    })
+}
+
+async fn load(db: &dyn Storage) -> Result<BTreeMap<String, String>> {
+    match db.get_meta(SECRETS_KEY).await? {
+        Some(json) => serde_json::from_str(&json).context("parsing meta token_secrets"),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+async fn store(db: &dyn Storage, secrets: &BTreeMap<String, String>) -> Result<()> {
+    db.set_meta(SECRETS_KEY, &serde_json::to_string(secrets)?)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::memory_storage::MemoryStorage;
+
+    #[tokio::test]
+    async fn only_the_issued_secret_verifies_a_token() {
+        let db = MemoryStorage::new();
+        assert_eq!(
+            authenticate(&db, "hr", None).await.unwrap(),
+            Authentication::Label
+        );
+        assert_eq!(
+            authenticate(&db, "hr", Some("guess")).await.unwrap(),
+            Authentication::Rejected
+        );
+
+        let credential = issue(&db, "hr").await.unwrap();
+        let (token, secret) = credential.split_once(':').unwrap();
+        assert_eq!(token, "hr");
+        assert_eq!(
+            authenticate(&db, "hr", Some(secret)).await.unwrap(),
+            Authentication::Verified
+        );
+        assert_eq!(
+            authenticate(&db, "hr", None).await.unwrap(),
+            Authentication::Rejected
+        );
+        assert_eq!(
+            authenticate(&db, "hr", Some("guess")).await.unwrap(),
+            Authentication::Rejected
+        );
+        assert_eq!(list(&db).await.unwrap(), vec!["hr"]);
+
+        assert!(remove(&db, "hr").await.unwrap());
+        assert!(!remove(&db, "hr").await.unwrap());
+        assert_eq!(
+            authenticate(&db, "hr", None).await.unwrap(),
+            Authentication::Label
+        );
+        assert!(issue(&db, "a:b").await.is_err());
+    }
+}
@@ -9,17 +9,25 @@
 //! Writes are atomic (tmp file + fsync + rename) and verified against the
 //! expected hash on `insert_verified`, so a peer pushing us a corrupted blob
 //! can't poison the store.
+//!
+//! Large blobs never need to be held in memory: [`BlobStore::insert_reader`]
+//! hashes while it copies, [`BlobStore::writer`] accepts a blob piecewise
+//! (e.g. one protocol chunk at a time) and [`BlobStore::open`] hands back a
+//! plain file for streaming reads.
 
 use super::hash::hash_hex;
 use anyhow::{Context, Result, anyhow, bail};
+use sha2::{Digest, Sha256};
 use std::fs;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 /// Length in hex characters of a SHA-256 digest.
 const HASH_HEX_LEN: usize = 64;
 
 /// On-disk CAS for binary blobs, keyed by hex SHA-256.
+#[derive(Clone)]
 pub struct BlobStore {
     root: PathBuf,
 }
@@ -58,6 +66,16 @@ impl BlobStore {
         self.path_for(hash_hex).is_file()
     }
 
+    /// Drop a blob from the store. Returns `false` if it wasn't there.
+    pub fn remove(&self, hash_hex: &str) -> Result<bool> {
+        validate_hex_hash(hash_hex)?;
+        match fs::remove_file(self.path_for(hash_hex)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e).with_context(|| format!("remove blob {hash_hex}")),
+        }
+    }
+
     /// Hash `bytes`, write them to the store (atomic), and return the hex
     /// digest. Idempotent — re-inserting the same bytes is a no-op.
     pub fn insert_bytes(&self, bytes: &[u8]) -> Result<String> {
@@ -82,6 +100,29 @@ impl BlobStore {
         self.write_atomic(&actual, bytes)
     }
 
+    /// Copy everything `reader` yields into the store, hashing on the
+    /// way through. Returns the hex digest and the byte count. Memory use
+    /// is one copy buffer regardless of the blob's size.
+    pub fn insert_reader<R: Read>(&self, mut reader: R) -> Result<(String, u64)> {
+        let mut w = self.staging()?;
+        std::io::copy(&mut reader, &mut w).context("copying into blob staging file")?;
+        let hash = format!("{:x}", w.hasher.clone().finalize());
+        let len = w.len;
+        w.commit(&hash)?;
+        Ok((hash, len))
+    }
+
+    /// Start a piecewise insert of the blob `expected_hash_hex`. Feed it
+    /// with [`BlobWriter::write_chunk`] and seal it with
+    /// [`BlobWriter::finish`], which verifies the hash before the blob
+    /// becomes visible. Dropping an unfinished writer discards it.
+    pub fn writer(&self, expected_hash_hex: &str) -> Result<BlobWriter> {
+        validate_hex_hash(expected_hash_hex)?;
+        let mut w = self.staging()?;
+        w.expected = Some(expected_hash_hex.to_string());
+        Ok(w)
+    }
+
     /// Read the blob with this hash. Errors if missing or malformed hash.
     pub fn read(&self, hash_hex: &str) -> Result<Vec<u8>> {
         validate_hex_hash(hash_hex)?;
@@ -89,6 +130,35 @@ impl BlobStore {
         fs::read(&path).with_context(|| format!("reading blob {}", path.display()))
     }
 
+    /// Open the blob with this hash for streaming reads.
+    pub fn open(&self, hash_hex: &str) -> Result<fs::File> {
+        validate_hex_hash(hash_hex)?;
+        let path = self.path_for(hash_hex);
+        fs::File::open(&path).with_context(|| format!("opening blob {}", path.display()))
+    }
+
+    /// Fresh staging file at the store root. Names are unique per process
+    /// and call so concurrent inserts of the same hash can't collide.
+    fn staging(&self) -> Result<BlobWriter> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        fs::create_dir_all(&self.root)
+            .with_context(|| format!("creating blob dir {}", self.root.display()))?;
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let tmp_path = self
+            .root
+            .join(format!(".incoming-{}-{}.tmp", std::process::id(), n));
+        let file = fs::File::create(&tmp_path)
+            .with_context(|| format!("creating tmp blob {}", tmp_path.display()))?;
+        Ok(BlobWriter {
+            root: self.root.clone(),
+            tmp_path,
+            file: Some(file),
+            hasher: Sha256::new(),
+            len: 0,
+            expected: None,
+        })
+    }
+
     fn write_atomic(&self, hash_hex: &str, bytes: &[u8]) -> Result<()> {
         validate_hex_hash(hash_hex)?;
         let final_path = self.path_for(hash_hex);
@@ -123,6 +193,106 @@ impl BlobStore {
     }
 }
 
+/// A blob being written into a [`BlobStore`] piece by piece. The bytes
+/// land in a staging file and are hashed as they arrive; nothing is
+/// visible under the blob's hash until [`BlobWriter::finish`] succeeds.
+pub struct BlobWriter {
+    root: PathBuf,
+    tmp_path: PathBuf,
+    file: Option<fs::File>,
+    hasher: Sha256,
+    len: u64,
+    expected: Option<String>,
+}
+
+impl BlobWriter {
+    /// Bytes written so far.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Append the next piece of the blob.
+    pub fn write_chunk(&mut self, bytes: &[u8]) -> Result<()> {
+        self.write_all(bytes)
+            .with_context(|| format!("writing tmp blob {}", self.tmp_path.display()))
+    }
+
+    /// Verify the streamed bytes against the expected hash and move them
+    /// into place. On mismatch the staging file is removed and nothing
+    /// is stored.
+    pub fn finish(self) -> Result<()> {
+        let expected = self
+            .expected
+            .clone()
+            .ok_or_else(|| anyhow!("blob writer has no expected hash"))?;
+        let actual = format!("{:x}", self.hasher.clone().finalize());
+        if actual != expected {
+            bail!(
+                "blob hash mismatch: expected {}, computed {}",
+                expected,
+                actual
+            );
+        }
+        self.commit(&actual)
+    }
+
+    fn commit(mut self, hash_hex: &str) -> Result<()> {
+        // Close before rename so Windows doesn't refuse the move.
+        drop(self.file.take());
+        let store = BlobStore::new(self.root.clone());
+        let final_path = store.path_for(hash_hex);
+        if final_path.is_file() {
+            return Ok(());
+        }
+        let parent = final_path
+            .parent()
+            .ok_or_else(|| anyhow!("blob path has no parent: {}", final_path.display()))?;
+        fs::create_dir_all(parent)
+            .with_context(|| format!("creating blob dir {}", parent.display()))?;
+        fs::rename(&self.tmp_path, &final_path).with_context(|| {
+            format!(
+                "renaming {} -> {}",
+                self.tmp_path.display(),
+                final_path.display()
+            )
+        })?;
+        Ok(())
+    }
+}
+
+impl Write for BlobWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let file = self
+            .file
+            .as_mut()
+            .ok_or_else(|| std::io::Error::other("blob writer already closed"))?;
+        let n = file.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.file.as_mut() {
+            Some(f) => f.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for BlobWriter {
+    fn drop(&mut self) {
+        // After a successful commit the tmp file has been renamed away
+        // and this is a harmless NotFound.
+        drop(self.file.take());
+        let _ = fs::remove_file(&self.tmp_path);
+    }
+}
+
 /// Return `Ok(())` iff `s` is exactly 64 lowercase-hex characters.
 fn validate_hex_hash(s: &str) -> Result<()> {
     if s.len() != HASH_HEX_LEN {
@@ -262,6 +432,60 @@ mod tests {
         assert!(s.path_for(&h).is_file());
     }
 
+    #[test]
+    fn insert_reader_streams_and_hashes() {
+        let (tmp, s) = store();
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i % 253) as u8).collect();
+        let (h, len) = s.insert_reader(&data[..]).unwrap();
+        assert_eq!(h, hash_hex(&data));
+        assert_eq!(len, data.len() as u64);
+        assert_eq!(s.read(&h).unwrap(), data);
+        let strays: Vec<_> = fs::read_dir(tmp.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|n| n.ends_with(".tmp"))
+            .collect();
+        assert!(strays.is_empty(), "found stray tmp files: {:?}", strays);
+    }
+
+    #[test]
+    fn writer_accepts_blob_piecewise() {
+        let (_tmp, s) = store();
+        let data = b"first half|second half";
+        let h = hash_hex(data);
+        let mut w = s.writer(&h).unwrap();
+        w.write_chunk(&data[..11]).unwrap();
+        assert!(!s.has(&h), "blob must stay invisible until finish");
+        w.write_chunk(&data[11..]).unwrap();
+        assert_eq!(w.len(), data.len() as u64);
+        w.finish().unwrap();
+        assert_eq!(s.read(&h).unwrap(), data);
+        let mut opened = String::new();
+        s.open(&h).unwrap().read_to_string(&mut opened).unwrap();
+        assert_eq!(opened.as_bytes(), data);
+    }
+
+    #[test]
+    fn writer_rejects_mismatch_and_cleans_up() {
+        let (tmp, s) = store();
+        let wrong = "b".repeat(64);
+        let mut w = s.writer(&wrong).unwrap();
+        w.write_chunk(b"not what was promised").unwrap();
+        let err = w.finish().unwrap_err().to_string();
+        assert!(err.contains("mismatch"), "{}", err);
+        assert!(!s.has(&wrong));
+        assert!(fs::read_dir(tmp.path()).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn dropped_writer_leaves_nothing_behind() {
+        let (tmp, s) = store();
+        let mut w = s.writer(&"c".repeat(64)).unwrap();
+        w.write_chunk(b"partial").unwrap();
+        drop(w);
+        assert!(fs::read_dir(tmp.path()).unwrap().next().is_none());
+    }
+
     #[test]
     fn read_rejects_malformed_hash() {
         let (_tmp, s) = store();
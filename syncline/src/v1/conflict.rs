@@ -0,0 +1,221 @@
+//! Conflict-copy naming (§6.4). Every name a conflict copy gets —
+//! whether projection moves a losing node aside or a client keeps
+//! local bytes that lost to a remote write — comes from one
+//! [`ConflictTemplate`].
+//!
+//! The template lives in the manifest config under
+//! [`CONFLICT_NAME_KEY`], so every peer renders the same names and
+//! `projection_hash` stays peer-independent. Every placeholder is
+//! derived from the manifest alone; wall-clock dates are deliberately
+//! not offered, since two peers would disagree on them.
+
+use super::ids::{NodeId, Stamp};
+use super::manifest::Manifest;
+use std::fmt;
+use std::str::FromStr;
+
+/// Manifest config key holding the vault's [`ConflictTemplate`].
+pub const CONFLICT_NAME_KEY: &str = "conflict_name";
+
+/// Placeholders a template may use.
+///
+/// | placeholder | expands to                                          |
+/// |-------------|-----------------------------------------------------|
+/// | `{stem}`    | file name without its extension (required)          |
+/// | `{ext}`     | `.` + extension, or nothing                         |
+/// | `{device}`  | name of the device that wrote the copy, else `{actor}` |
+/// | `{actor}`   | first 8 hex digits of the writing actor's id        |
+/// | `{lamport}` | Lamport clock of the write                          |
+/// | `{node}`    | first 8 hex digits of the copy's node id            |
+///
+/// Conflict copies are projected, not stored, so `{device}` always
+/// shows the writer's current name: renaming a device renames its
+/// unresolved copies on the next sync.
+const PLACEHOLDERS: &[&str] = &["stem", "ext", "device", "actor", "lamport", "node"];
+
+/// A conflict-copy file name pattern, e.g. the default
+/// `{stem}.conflict-{actor}-{lamport}-{node}{ext}`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConflictTemplate(String);
+
+impl ConflictTemplate {
+    pub const DEFAULT: &'static str = "{stem}.conflict-{actor}-{lamport}-{node}{ext}";
+
+    /// The template stored in `manifest`'s config, or the default.
+    pub fn of(manifest: &Manifest) -> Self {
+        manifest
+            .config(CONFLICT_NAME_KEY)
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default()
+    }
+
+    /// The conflict name for a copy of `base` (a vault path) written at
+    /// `stamp` as node `id`. Only the last segment changes. `device` is
+    /// the writer's device name, if it published one.
+    pub fn render(&self, base: &str, stamp: Stamp, id: NodeId, device: Option<&str>) -> String {
+        let (dir, stem, ext) = split_name(base);
+        let actor = stamp.actor.short();
+        let mut out = String::from(dir);
+        let mut rest = self.0.as_str();
+        while let Some(open) = rest.find('{') {
+            out.push_str(&rest[..open]);
+            let close = open + rest[open..].find('}').expect("validated on parse");
+            match &rest[open + 1..close] {
+                "stem" => out.push_str(stem),
+                "ext" => {
+                    if let Some(ext) = ext {
+                        out.push('.');
+                        out.push_str(ext);
+                    }
+                }
+                "device" => match device.map(sanitize_device).filter(|d| !d.is_empty()) {
+                    Some(d) => out.push_str(&d),
+                    None => out.push_str(&actor),
+                },
+                "actor" => out.push_str(&actor),
+                "lamport" => out.push_str(&stamp.lamport.get().to_string()),
+                "node" => out.push_str(&id.to_string_hyphenated()[..8]),
+                _ => unreachable!("validated on parse"),
+            }
+            rest = &rest[close + 1..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// `name` with the full node id spliced in before its extension. The
+    /// fallback when a rendered name is already taken — a template
+    /// without `{node}` can't tell two copies apart on its own.
+    pub fn disambiguate(name: &str, id: NodeId) -> String {
+        let (dir, stem, ext) = split_name(name);
+        let id = id.to_string_hyphenated();
+        match ext {
+            Some(ext) => format!("{dir}{stem}~{id}.{ext}"),
+            None => format!("{dir}{stem}~{id}"),
+        }
+    }
+}
+
+impl Default for ConflictTemplate {
+    fn default() -> Self {
+        Self(Self::DEFAULT.to_string())
+    }
+}
+
+impl fmt::Display for ConflictTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for ConflictTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains('/') || s.contains('\\') {
+            anyhow::bail!("conflict name template must not contain a path separator");
+        }
+        let mut rest = s;
+        let mut has_stem = false;
+        while let Some(open) = rest.find(['{', '}']) {
+            if rest.as_bytes()[open] == b'}' {
+                anyhow::bail!("unmatched '}}' in conflict name template {s:?}");
+            }
+            let Some(len) = rest[open..].find('}') else {
+                anyhow::bail!("unclosed '{{' in conflict name template {s:?}");
+            };
+            let name = &rest[open + 1..open + len];
+            if !PLACEHOLDERS.contains(&name) {
+                anyhow::bail!(
+                    "unknown placeholder {{{name}}} in conflict name template (known: {})",
+                    PLACEHOLDERS.iter().map(|p| format!("{{{p}}}")).collect::<Vec<_>>().join(", ")
+                );
+            }
+            has_stem |= name == "stem";
+            rest = &rest[open + len + 1..];
+        }
+        if !has_stem {
+            anyhow::bail!("conflict name template must contain {{stem}}");
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+/// Device names are free text; keep them to one path segment.
+fn sanitize_device(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| if c == '/' || c == '\\' || c.is_control() { '-' } else { c })
+        .collect()
+}
+
+/// `a/b/foo.tar.md` → (`a/b/`, `foo.tar`, Some(`md`)). Dotfiles like
+/// `.env` have no extension.
+fn split_name(path: &str) -> (&str, &str, Option<&str>) {
+    let last_slash = path.rfind('/').map(|i| i + 1).unwrap_or(0);
+    let (dir, last) = path.split_at(last_slash);
+    match last.rfind('.') {
+        Some(dot) if dot > 0 => (dir, &last[..dot], Some(&last[dot + 1..])),
+        _ => (dir, last, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::ids::{ActorId, Lamport};
+
+    fn stamp() -> (Stamp, NodeId) {
+        (Stamp::new(Lamport(7), ActorId::new()), NodeId::new())
+    }
+
+    #[test]
+    fn default_template_matches_legacy_projection_names() {
+        let (s, id) = stamp();
+        let t = ConflictTemplate::default();
+        let expected = format!(
+            "notes/foo.conflict-{}-7-{}.md",
+            s.actor.short(),
+            &id.to_string_hyphenated()[..8]
+        );
+        assert_eq!(t.render("notes/foo.md", s, id, None), expected);
+        assert!(t.render("README", s, id, None).starts_with("README.conflict-"));
+        assert!(!t.render("README", s, id, None).ends_with('.'));
+        assert!(t.render(".env", s, id, None).starts_with(".env.conflict-"));
+    }
+
+    #[test]
+    fn device_name_replaces_actor_hex() {
+        let (s, id) = stamp();
+        let t: ConflictTemplate = "{stem} (conflict on {device}){ext}".parse().unwrap();
+        assert_eq!(
+            t.render("a/foo.md", s, id, Some("work/laptop")),
+            "a/foo (conflict on work-laptop).md"
+        );
+        assert_eq!(
+            t.render("a/foo.md", s, id, None),
+            format!("a/foo (conflict on {}).md", s.actor.short())
+        );
+    }
+
+    #[test]
+    fn malformed_templates_are_rejected() {
+        for bad in ["{ext}", "{stem}{when}", "{stem", "{stem}}", "dir/{stem}"] {
+            assert!(bad.parse::<ConflictTemplate>().is_err(), "{bad:?} accepted");
+        }
+    }
+
+    #[test]
+    fn split_name_only_splits_the_last_segment() {
+        assert_eq!(split_name("foo.d/bar/baz.tar.gz"), ("foo.d/bar/", "baz.tar", Some("gz")));
+        assert_eq!(split_name("noext"), ("", "noext", None));
+        assert_eq!(split_name("a/.hidden"), ("a/", ".hidden", None));
+    }
+
+    #[test]
+    fn disambiguate_keeps_extension() {
+        let id = NodeId::new();
+        let got = ConflictTemplate::disambiguate("a/foo (copy).md", id);
+        assert_eq!(got, format!("a/foo (copy)~{}.md", id.to_string_hyphenated()));
+    }
+}
@@ -7,6 +7,7 @@
 //! .syncline/
 //!   version              — single line, "1"
 //!   actor_id             — UUIDv4 for the local client (lamport tiebreak)
+//!   client_id            — human-readable device name (kept from v0)
 //!   manifest.bin         — encoded state update of the manifest Y.Doc
 //!   content/<id>.bin     — per-text-file Y.Doc with a single "text" Y.Text
 //!   blobs/<sha256>       — preserved from v0 (binary CAS)
@@ -153,6 +154,33 @@ pub fn read_or_create_actor_id(syncline_dir: &Path) -> Result<ActorId> {
     Ok(id)
 }
 
+/// This device's name: `name_override` if given (and remembered for
+/// later runs), else the name saved in `.syncline/client_id`, else the
+/// hostname. v0 vaults already have a `client_id`, so a migrated vault
+/// keeps the name it used in v0 conflict files.
+pub fn read_or_create_device_name(
+    syncline_dir: &Path,
+    name_override: Option<&str>,
+) -> Result<String> {
+    let path = syncline_dir.join("client_id");
+    let name_override = name_override.map(str::trim).filter(|n| !n.is_empty());
+    if name_override.is_none()
+        && let Ok(s) = fs::read_to_string(&path)
+        && !s.trim().is_empty()
+    {
+        return Ok(s.trim().to_string());
+    }
+    let name = match name_override {
+        Some(n) => n.to_string(),
+        None => gethostname::gethostname()
+            .into_string()
+            .unwrap_or_else(|_| "unknown".to_string()),
+    };
+    fs::create_dir_all(syncline_dir).context("creating .syncline for client_id")?;
+    atomic_write(&path, format!("{name}\n").as_bytes()).context("writing client_id")?;
+    Ok(name)
+}
+
 /// Read the `.syncline/version` marker. `None` if absent or unreadable.
 pub fn read_vault_version(syncline_dir: &Path) -> Option<String> {
     fs::read_to_string(syncline_dir.join("version"))
@@ -321,6 +349,19 @@ mod tests {
         assert_eq!(id1, id2);
     }
 
+    #[test]
+    fn device_name_override_is_remembered() {
+        let dir = TempDir::new().unwrap();
+        let sd = dir.path().to_path_buf();
+        let host = read_or_create_device_name(&sd, None).unwrap();
+        assert!(!host.is_empty());
+        assert_eq!(
+            read_or_create_device_name(&sd, Some("work-laptop")).unwrap(),
+            "work-laptop"
+        );
+        assert_eq!(read_or_create_device_name(&sd, None).unwrap(), "work-laptop");
+    }
+
     #[test]
     fn migrate_relocates_stale_bak_without_data_loss() {
         // Simulate a situation where a previous migration left a
@@ -0,0 +1,290 @@
+//! Local file index: what the scanner last saw at each path.
+//!
+//! Kept in `.syncline/file-index.json` and never synced. For every file
+//! the scanner has folded into the vault it records the file's stat —
+//! size, mtime, and on Unix inode and ctime — plus the SHA-256 of the
+//! bytes it read. A later scan that finds the same stat skips reading
+//! the file at all, so a quiet 40 GB vault costs one directory walk.
+//!
+//! Stats are only ever compared for equality, never ordered against the
+//! local clock, so a skewed clock can't hide a change. Two things can
+//! still lie:
+//!
+//! - **Coarse or late mtimes.** A file rewritten within the mtime
+//!   granularity of its last scan keeps its stat. As in git's index,
+//!   an entry is only trusted if its mtime is older than the index file
+//!   itself — both stamped by the same filesystem clock — and re-read
+//!   otherwise.
+//! - **Restored mtimes** (`cp -p`, `rsync -t`, `touch -r`). These can't
+//!   restore the inode or ctime, so on Unix the swap is still seen.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+const INDEX_FILE: &str = "file-index.json";
+
+/// The parts of a file's metadata that change whenever its bytes do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stat {
+    pub size: u64,
+    /// Nanoseconds since the Unix epoch.
+    pub mtime: u64,
+    /// Inode number; 0 where the platform has none.
+    pub inode: u64,
+    /// Inode change time in nanoseconds; 0 where the platform has none.
+    pub ctime: i64,
+}
+
+impl Stat {
+    /// `None` if the platform reports no usable mtime, in which case the
+    /// file is read on every scan.
+    pub fn of(meta: &Metadata) -> Option<Self> {
+        let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        #[cfg(unix)]
+        let (inode, ctime) = {
+            use std::os::unix::fs::MetadataExt;
+            (meta.ino(), meta.ctime() * 1_000_000_000 + meta.ctime_nsec())
+        };
+        #[cfg(not(unix))]
+        let (inode, ctime) = (0, 0);
+        Some(Self {
+            size: meta.len(),
+            mtime: u64::try_from(mtime.as_nanos()).ok()?,
+            inode,
+            ctime,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    stat: Stat,
+    hash: String,
+}
+
+/// Stat and hash of every file the scanner has folded in, by manifest
+/// path.
+#[derive(Debug, Default)]
+pub struct FileIndex {
+    file: Option<PathBuf>,
+    entries: HashMap<String, Entry>,
+    /// mtime of the index file as last loaded or saved; entries at or
+    /// after it are racy (see the module docs).
+    written: Option<u64>,
+    dirty: bool,
+}
+
+impl FileIndex {
+    /// Load the index saved in `syncline_dir`. A missing or unreadable
+    /// index is empty, so the next scan reads everything.
+    pub fn load(syncline_dir: &Path) -> Self {
+        let file = syncline_dir.join(INDEX_FILE);
+        let mut index = Self {
+            file: Some(file.clone()),
+            ..Self::default()
+        };
+        let Ok(bytes) = fs::read(&file) else {
+            return index;
+        };
+        match serde_json::from_slice(&bytes) {
+            Ok(entries) => {
+                index.entries = entries;
+                index.written = mtime_of(&file);
+            }
+            Err(e) => tracing::warn!("ignoring unreadable {}: {e}", file.display()),
+        }
+        index
+    }
+
+    /// The hash recorded for `path` if the file still has the stat it
+    /// had then, and that stat can be trusted.
+    pub fn unchanged(&self, path: &str, stat: &Stat) -> Option<&str> {
+        let entry = self.entries.get(path)?;
+        let settled = self.written.is_some_and(|w| stat.mtime < w);
+        (settled && entry.stat == *stat).then_some(entry.hash.as_str())
+    }
+
+    /// Record that the file at `path`, with `stat` taken *before* it was
+    /// read, held bytes hashing to `hash`.
+    pub fn record(&mut self, path: &str, stat: Stat, hash: String) {
+        let entry = Entry { stat, hash };
+        // Rewriting the index is what lets a racy entry settle.
+        let racy = self.written.is_none_or(|w| stat.mtime >= w);
+        if racy || self.entries.get(path) != Some(&entry) {
+            self.entries.insert(path.to_string(), entry);
+            self.dirty = true;
+        }
+    }
+
+    /// Indexed paths by inode, for spotting a file that moved. Empty
+    /// where the platform has no inodes.
+    pub fn by_inode(&self) -> HashMap<u64, (&str, &Stat)> {
+        self.entries
+            .iter()
+            .filter(|(_, e)| e.stat.inode != 0)
+            .map(|(path, e)| (e.stat.inode, (path.as_str(), &e.stat)))
+            .collect()
+    }
+
+    /// Drop every entry whose path fails `keep`.
+    pub fn retain(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        let before = self.entries.len();
+        self.entries.retain(|path, _| keep(path));
+        self.dirty |= self.entries.len() != before;
+    }
+
+    /// Write the index back if anything changed since it was loaded.
+    pub fn save(&mut self) -> Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        if !self.dirty {
+            return Ok(());
+        }
+        let tmp = file.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(&self.entries)?)
+            .with_context(|| format!("writing {}", tmp.display()))?;
+        fs::rename(&tmp, file).with_context(|| format!("renaming onto {}", file.display()))?;
+        self.written = mtime_of(file);
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+fn mtime_of(file: &Path) -> Option<u64> {
+    fs::metadata(file).ok().and_then(|m| Stat::of(&m)).map(|s| s.mtime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    fn stat_of(path: &Path) -> Stat {
+        Stat::of(&fs::metadata(path).unwrap()).unwrap()
+    }
+
+    fn set_mtime(path: &Path, t: SystemTime) {
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(t)
+            .unwrap();
+    }
+
+    #[test]
+    fn settled_entries_survive_a_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.md");
+        fs::write(&file, "hello").unwrap();
+        set_mtime(&file, SystemTime::now() - Duration::from_secs(60));
+        let stat = stat_of(&file);
+
+        let mut index = FileIndex::load(dir.path());
+        assert_eq!(index.unchanged("a.md", &stat), None);
+        index.record("a.md", stat, "h1".into());
+        index.save().unwrap();
+
+        let index = FileIndex::load(dir.path());
+        assert_eq!(index.unchanged("a.md", &stat), Some("h1"));
+        assert_eq!(index.unchanged("b.md", &stat), None);
+    }
+
+    #[test]
+    fn saving_settles_entries_without_a_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.md");
+        fs::write(&file, "hello").unwrap();
+        set_mtime(&file, SystemTime::now() - Duration::from_secs(60));
+        let stat = stat_of(&file);
+
+        // A long-running client keeps one index across scans: the second
+        // scan must trust what the first one saved.
+        let mut index = FileIndex::load(dir.path());
+        index.record("a.md", stat, "h1".into());
+        assert_eq!(index.unchanged("a.md", &stat), None);
+        index.save().unwrap();
+        assert_eq!(index.unchanged("a.md", &stat), Some("h1"));
+
+        // Recording the same stat again leaves nothing to write.
+        index.record("a.md", stat, "h1".into());
+        assert!(!index.dirty);
+    }
+
+    #[test]
+    fn any_stat_change_forces_a_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("a.md");
+        let old = SystemTime::now() - Duration::from_secs(60);
+        fs::write(&file, "hello").unwrap();
+        set_mtime(&file, old);
+        let stat = stat_of(&file);
+        let mut index = FileIndex::load(dir.path());
+        index.record("a.md", stat, "h1".into());
+        index.save().unwrap();
+        let index = FileIndex::load(dir.path());
+
+        // Same size, mtime put back: the ctime (or inode, after a
+        // replace) still gives it away on Unix.
+        fs::write(&file, "world").unwrap();
+        set_mtime(&file, old);
+        let rewritten = stat_of(&file);
+        assert_eq!((rewritten.size, rewritten.mtime), (stat.size, stat.mtime));
+        if cfg!(unix) {
+            assert_eq!(index.unchanged("a.md", &rewritten), None);
+        }
+        for changed in [
+            Stat { size: 6, ..stat },
+            Stat { mtime: stat.mtime - 1, ..stat },
+            Stat { inode: stat.inode + 1, ..stat },
+        ] {
+            assert_eq!(index.unchanged("a.md", &changed), None);
+        }
+    }
+
+    #[test]
+    fn entries_as_new_as_the_index_are_racy() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut index = FileIndex::load(dir.path());
+        let future = (SystemTime::now() + Duration::from_secs(3600))
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        let racy = Stat {
+            size: 1,
+            mtime: future,
+            inode: 7,
+            ctime: 0,
+        };
+        index.record("a.md", racy, "h".into());
+        index.save().unwrap();
+        assert_eq!(FileIndex::load(dir.path()).unchanged("a.md", &racy), None);
+    }
+
+    #[test]
+    fn retain_prunes_and_corrupt_index_loads_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let stat = Stat {
+            size: 1,
+            mtime: 1,
+            inode: 1,
+            ctime: 1,
+        };
+        let mut index = FileIndex::load(dir.path());
+        index.record("keep.md", stat, "k".into());
+        index.record("gone.md", stat, "g".into());
+        index.retain(|p| p == "keep.md");
+        index.save().unwrap();
+        let index = FileIndex::load(dir.path());
+        assert_eq!(index.unchanged("keep.md", &stat), Some("k"));
+        assert_eq!(index.unchanged("gone.md", &stat), None);
+
+        fs::write(dir.path().join(INDEX_FILE), b"{not json").unwrap();
+        assert_eq!(FileIndex::load(dir.path()).unchanged("keep.md", &stat), None);
+    }
+}
@@ -3,12 +3,36 @@
 //! so it compiles on both native and `wasm32-unknown-unknown`.
 
 use sha2::{Digest, Sha256};
+use std::io::Read;
+
+/// Read buffer for [`hash_reader`]. Large enough to amortise syscalls,
+/// small enough that hashing a multi-GB file costs a fixed 64 KiB.
+const HASH_READ_BUF: usize = 64 * 1024;
 
 /// Hash `bytes` and return the 64-char lowercase hex digest.
 pub fn hash_hex(bytes: &[u8]) -> String {
     format!("{:x}", Sha256::digest(bytes))
 }
 
+/// Hash everything `reader` yields without buffering it. Returns the
+/// hex digest plus the number of bytes read.
+pub fn hash_reader<R: Read>(mut reader: R) -> std::io::Result<(String, u64)> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; HASH_READ_BUF];
+    let mut len = 0u64;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buf[..n]);
+        len += n as u64;
+    }
+    Ok((format!("{:x}", hasher.finalize()), len))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -24,4 +48,13 @@ mod tests {
             "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
         );
     }
+
+    #[test]
+    fn reader_hash_matches_slice_hash() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let (h, len) = hash_reader(&data[..]).unwrap();
+        assert_eq!(h, hash_hex(&data));
+        assert_eq!(len, data.len() as u64);
+        assert_eq!(hash_reader(&b""[..]).unwrap().0, hash_hex(b""));
+    }
 }
@@ -25,9 +25,31 @@
 //! The `(del_lamp, del_actor)` and `(mod_lamp, mod_actor)` stamps are
 //! compared at projection time to implement
 //! **modify-wins-over-delete** (§6.3 of the design doc).
+//!
+//! A second top-level `Y.Map` named `"config"` holds vault-wide
+//! settings as string key/value pairs. It syncs like everything else,
+//! so every peer projects under the same settings; concurrent writes to
+//! one key resolve last-writer-wins.
+//!
+//! A third top-level `Y.Map` named `"devices"` holds one nested `Y.Map`
+//! per actor (keyed by `ActorId`, hyphenated) describing the device
+//! behind it, so peers can show "work-laptop" instead of actor hex:
+//!
+//! | key         | type    | meaning                                       |
+//! |-------------|---------|-----------------------------------------------|
+//! | `name`      | String  | human-readable device name                    |
+//! | `platform`  | String  | OS the client runs on (`linux`, `macos`, …)   |
+//! | `version`   | String  | Syncline version of the client                |
+//! | `last_seen` | i64     | unix seconds of the device's last session     |
+//! | `retired`   | bool    | set by an admin; hides the device from lists  |
+//!
+//! Each actor writes only its own record, except `retired`. Device
+//! records carry no lamport stamps and never affect node history.
 
 use super::ids::{ActorId, Lamport, NodeId, Stamp};
+use super::projection::Projection;
 use std::collections::HashMap;
+use std::sync::Mutex;
 use yrs::{Any, Doc, Map, MapPrelim, MapRef, Out, ReadTxn, Transact};
 
 /// Classification of a node. Immutable after the node is created.
@@ -84,27 +106,38 @@ impl NodeEntry {
     }
 }
 
+/// One actor's published device record (see the module docs).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Device {
+    pub actor: ActorId,
+    pub name: String,
+    pub platform: String,
+    pub version: String,
+    pub last_seen: u64,
+    pub retired: bool,
+}
+
 /// Wraps a Yrs `Doc` with a typed API for the manifest schema. Owns the
 /// local actor id and lamport counter; each mutating method bumps the
 /// counter and stamps the written fields.
 pub struct Manifest {
     doc: Doc,
     nodes: MapRef,
+    config: MapRef,
+    devices: MapRef,
     actor: ActorId,
     lamport: Lamport,
+    /// Bumped by every method that writes to `doc`.
+    revision: u64,
+    /// Last projection and the revision it was taken at; see
+    /// [`cached_projection`](Self::cached_projection).
+    projected: Mutex<Option<(u64, Projection)>>,
 }
 
 impl Manifest {
     /// Create an empty manifest for `actor`. Lamport starts at 0.
     pub fn new(actor: ActorId) -> Self {
-        let doc = Doc::new();
-        let nodes = doc.get_or_insert_map("nodes");
-        Self {
-            doc,
-            nodes,
-            actor,
-            lamport: Lamport::ZERO,
-        }
+        Self::wrap(Doc::new(), actor, Lamport::ZERO)
     }
 
     /// Rehydrate a manifest from a Yrs state update (as produced by
@@ -112,18 +145,28 @@ impl Manifest {
     pub fn from_update(actor: ActorId, lamport: Lamport, update: &[u8]) -> anyhow::Result<Self> {
         use yrs::updates::decoder::Decode;
         let doc = Doc::new();
-        let nodes = doc.get_or_insert_map("nodes");
         {
             let mut txn = doc.transact_mut();
             let update = yrs::Update::decode_v1(update)?;
             txn.apply_update(update);
         }
-        Ok(Self {
+        Ok(Self::wrap(doc, actor, lamport))
+    }
+
+    fn wrap(doc: Doc, actor: ActorId, lamport: Lamport) -> Self {
+        let nodes = doc.get_or_insert_map("nodes");
+        let config = doc.get_or_insert_map("config");
+        let devices = doc.get_or_insert_map("devices");
+        Self {
             doc,
             nodes,
+            config,
+            devices,
             actor,
             lamport,
-        })
+            revision: 0,
+            projected: Mutex::new(None),
+        }
     }
 
     pub fn actor(&self) -> ActorId {
@@ -134,6 +177,29 @@ impl Manifest {
         self.lamport
     }
 
+    /// Moves on every write to the doc, local or a remote update.
+    /// Anything derived from the manifest and tagged with the revision
+    /// it was computed at is stale once they differ.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// The projection `compute` returns for the current revision,
+    /// computed at most once per revision. Projecting walks every node,
+    /// and the sync loop asks for the projection once or more per frame.
+    pub(super) fn cached_projection(&self, compute: impl FnOnce() -> Projection) -> Projection {
+        let revision = self.revision();
+        let mut cached = self.projected.lock().unwrap_or_else(|e| e.into_inner());
+        match &*cached {
+            Some((at, proj)) if *at == revision => proj.clone(),
+            _ => {
+                let proj = compute();
+                *cached = Some((revision, proj.clone()));
+                proj
+            }
+        }
+    }
+
     /// Expose the underlying Yrs doc so callers can subscribe to
     /// updates, encode state, apply remote updates, etc.
     pub fn doc(&self) -> &Doc {
@@ -152,6 +218,7 @@ impl Manifest {
         use yrs::updates::decoder::Decode;
         let update = yrs::Update::decode_v1(update)?;
         {
+            self.revision += 1;
             let mut txn = self.doc.transact_mut();
             txn.apply_update(update);
         }
@@ -191,6 +258,21 @@ impl Manifest {
         size: u64,
     ) -> NodeId {
         let id = NodeId::new();
+        self.create_node_with_id(id, name, parent, kind, blob_hash, size);
+        id
+    }
+
+    /// [`create_node`](Self::create_node) under a caller-chosen id.
+    /// Creating the same id twice keeps one of the two entries.
+    pub fn create_node_with_id(
+        &mut self,
+        id: NodeId,
+        name: &str,
+        parent: Option<NodeId>,
+        kind: NodeKind,
+        blob_hash: Option<&str>,
+        size: u64,
+    ) {
         let lamp = self.lamport.tick();
         let actor = self.actor;
         let parent_str = parent
@@ -211,9 +293,10 @@ impl Manifest {
             ("c_actor", Any::from(actor.to_string_hyphenated())),
         ]);
 
+        self.revision += 1;
+
         let mut txn = self.doc.transact_mut();
         self.nodes.insert(&mut txn, id.to_string_hyphenated(), entry);
-        id
     }
 
     /// Rename: update the `name` field on an existing node.
@@ -222,6 +305,7 @@ impl Manifest {
         let lamp = self.lamport.tick();
         let actor = self.actor;
         let actor_str = actor.to_string_hyphenated();
+        self.revision += 1;
         let mut txn = self.doc.transact_mut();
         let Some(entry) = get_entry_map(&self.nodes, &txn, id) else {
             return false;
@@ -239,6 +323,7 @@ impl Manifest {
         let parent_str = new_parent
             .map(|p| p.to_string_hyphenated())
             .unwrap_or_default();
+        self.revision += 1;
         let mut txn = self.doc.transact_mut();
         let Some(entry) = get_entry_map(&self.nodes, &txn, id) else {
             return false;
@@ -254,6 +339,7 @@ impl Manifest {
     pub fn delete(&mut self, id: NodeId) -> bool {
         let lamp = self.lamport.tick();
         let actor_str = self.actor.to_string_hyphenated();
+        self.revision += 1;
         let mut txn = self.doc.transact_mut();
         let Some(entry) = get_entry_map(&self.nodes, &txn, id) else {
             return false;
@@ -270,6 +356,7 @@ impl Manifest {
     pub fn record_modify(&mut self, id: NodeId) -> bool {
         let lamp = self.lamport.tick();
         let actor_str = self.actor.to_string_hyphenated();
+        self.revision += 1;
         let mut txn = self.doc.transact_mut();
         let Some(entry) = get_entry_map(&self.nodes, &txn, id) else {
             return false;
@@ -283,6 +370,7 @@ impl Manifest {
     pub fn set_blob_hash(&mut self, id: NodeId, hash: &str, size: u64) -> bool {
         let lamp = self.lamport.tick();
         let actor_str = self.actor.to_string_hyphenated();
+        self.revision += 1;
         let mut txn = self.doc.transact_mut();
         let Some(entry) = get_entry_map(&self.nodes, &txn, id) else {
             return false;
@@ -294,10 +382,197 @@ impl Manifest {
         true
     }
 
+    /// Set a vault-wide config value. Does not tick the lamport: config
+    /// is not part of any node's history.
+    pub fn set_config(&mut self, key: &str, value: &str) {
+        self.revision += 1;
+        let mut txn = self.doc.transact_mut();
+        self.config.insert(&mut txn, key, value.to_string());
+    }
+
+    /// Remove a config key, reverting it to its default.
+    pub fn remove_config(&mut self, key: &str) {
+        self.revision += 1;
+        let mut txn = self.doc.transact_mut();
+        self.config.remove(&mut txn, key);
+    }
+
+    /// Publish (or refresh) this actor's device record. Only fields
+    /// that differ are written, so an unchanged session leaves no
+    /// tombstones behind but `last_seen`. `retired` is never touched:
+    /// once an admin retires a device it stays retired.
+    pub fn publish_device(&mut self, name: &str, platform: &str, version: &str, last_seen: u64) {
+        let key = self.actor.to_string_hyphenated();
+        let fields = [
+            ("name", Any::from(name.to_string())),
+            ("platform", Any::from(platform.to_string())),
+            ("version", Any::from(version.to_string())),
+            ("last_seen", Any::from(last_seen as i64)),
+        ];
+        let mut txn = self.doc.transact_mut();
+        let Some(Out::YMap(record)) = self.devices.get(&txn, &key) else {
+            self.revision += 1;
+            self.devices.insert(&mut txn, key, MapPrelim::from(fields));
+            return;
+        };
+        for (field, value) in fields {
+            if !matches!(record.get(&txn, field), Some(Out::Any(ref old)) if *old == value) {
+                self.revision += 1;
+                record.insert(&mut txn, field, value);
+            }
+        }
+    }
+
+    /// Mark `actor`'s device retired. Returns `false` if it never
+    /// published a record.
+    pub fn retire_device(&mut self, actor: ActorId) -> bool {
+        self.revision += 1;
+        let mut txn = self.doc.transact_mut();
+        let Some(Out::YMap(record)) = self.devices.get(&txn, &actor.to_string_hyphenated()) else {
+            return false;
+        };
+        record.insert(&mut txn, "retired", true);
+        true
+    }
+
+    // ------------------------------------------------------------------
+    // Raw mirroring — copies entries field for field, stamps included,
+    // without ticking the lamport. The server uses it to keep the views
+    // of partial replicas in step with the shared manifest (see
+    // `server/acl.rs`); nothing else should.
+    // ------------------------------------------------------------------
+
+    /// Raw fields of every node, keyed by hyphenated `NodeId`.
+    pub fn raw_nodes(&self) -> HashMap<String, RawFields> {
+        read_raw_records(&self.doc, &self.nodes)
+    }
+
+    /// Raw fields of every device record, keyed by hyphenated `ActorId`.
+    pub fn raw_devices(&self) -> HashMap<String, RawFields> {
+        read_raw_records(&self.doc, &self.devices)
+    }
+
+    /// Make node `key` hold exactly `fields`, creating it if needed.
+    /// Returns whether anything changed.
+    pub fn put_raw_node(&mut self, key: &str, fields: &RawFields) -> bool {
+        self.revision += 1;
+        put_raw_record(&self.doc, &self.nodes, key, fields)
+    }
+
+    /// [`put_raw_node`](Self::put_raw_node) for an edit a less trusted
+    /// peer made: every stamp in `fields` that differs from the stored
+    /// one is replaced with one fresh tick of our lamport (keeping its
+    /// actor), and an existing node keeps its create stamp. So the peer
+    /// can't backdate an edit to win a collision, nor postdate one to
+    /// beat every later write. Returns whether anything changed.
+    pub fn put_raw_node_restamped(&mut self, key: &str, fields: &RawFields) -> bool {
+        let current = {
+            let txn = self.doc.transact();
+            match self.nodes.get(&txn, key) {
+                Some(Out::YMap(m)) => Some(read_raw_fields(&m, &txn)),
+                _ => None,
+            }
+        };
+        if let Some(m) = self.max_lamport_in_doc() {
+            self.lamport.observe(m);
+        }
+        let lamp = Any::from(self.lamport.tick().get() as i64);
+        let mut fields = fields.clone();
+        for (lamp_key, actor_key) in STAMP_FIELDS {
+            let old = |k: &str| current.as_ref().and_then(|c| c.get(k));
+            if current.is_some() && lamp_key == "created_at" {
+                for k in [lamp_key, actor_key] {
+                    match old(k) {
+                        Some(v) => fields.insert(k.to_string(), v.clone()),
+                        None => fields.remove(k),
+                    };
+                }
+            } else if fields.contains_key(lamp_key)
+                && (fields.get(lamp_key) != old(lamp_key) || fields.get(actor_key) != old(actor_key))
+            {
+                fields.insert(lamp_key.to_string(), lamp.clone());
+            }
+        }
+        self.revision += 1;
+        put_raw_record(&self.doc, &self.nodes, key, &fields)
+    }
+
+    /// Drop node `key` from the map outright — not a tombstone. Returns
+    /// `false` if it wasn't there.
+    pub fn remove_raw_node(&mut self, key: &str) -> bool {
+        self.revision += 1;
+        let mut txn = self.doc.transact_mut();
+        self.nodes.remove(&mut txn, key).is_some()
+    }
+
+    /// Make device record `key` hold exactly `fields`, creating it if
+    /// needed. Returns whether anything changed.
+    pub fn put_raw_device(&mut self, key: &str, fields: &RawFields) -> bool {
+        self.revision += 1;
+        put_raw_record(&self.doc, &self.devices, key, fields)
+    }
+
     // ------------------------------------------------------------------
     // Read API
     // ------------------------------------------------------------------
 
+    /// Every published device record, sorted by name then actor.
+    pub fn devices(&self) -> Vec<Device> {
+        let txn = self.doc.transact();
+        let mut out: Vec<Device> = self
+            .devices
+            .iter(&txn)
+            .filter_map(|(key, v)| {
+                let Out::YMap(m) = v else { return None };
+                Some(Device {
+                    actor: ActorId::parse_str(key)?,
+                    name: read_string(&m, &txn, "name").unwrap_or_default(),
+                    platform: read_string(&m, &txn, "platform").unwrap_or_default(),
+                    version: read_string(&m, &txn, "version").unwrap_or_default(),
+                    last_seen: read_u64(&m, &txn, "last_seen").unwrap_or(0),
+                    retired: matches!(m.get(&txn, "retired"), Some(Out::Any(Any::Bool(true)))),
+                })
+            })
+            .collect();
+        out.sort_by(|a, b| a.name.cmp(&b.name).then(a.actor.cmp(&b.actor)));
+        out
+    }
+
+    /// Device name per actor, for every actor that published a
+    /// non-empty one. Retired devices keep their names: old conflict
+    /// copies still say where they came from.
+    pub fn device_names(&self) -> HashMap<ActorId, String> {
+        self.devices()
+            .into_iter()
+            .filter(|d| !d.name.is_empty())
+            .map(|d| (d.actor, d.name))
+            .collect()
+    }
+
+    /// A vault-wide config value, if set.
+    pub fn config(&self, key: &str) -> Option<String> {
+        let txn = self.doc.transact();
+        match self.config.get(&txn, key)? {
+            Out::Any(Any::String(s)) => Some(s.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Every config key/value, sorted by key.
+    pub fn config_entries(&self) -> Vec<(String, String)> {
+        let txn = self.doc.transact();
+        let mut out: Vec<(String, String)> = self
+            .config
+            .iter(&txn)
+            .filter_map(|(k, v)| match v {
+                Out::Any(Any::String(s)) => Some((k.to_string(), s.to_string())),
+                _ => None,
+            })
+            .collect();
+        out.sort();
+        out
+    }
+
     pub fn get_entry(&self, id: NodeId) -> Option<NodeEntry> {
         let txn = self.doc.transact();
         let entry_map = match self.nodes.get(&txn, &id.to_string_hyphenated())? {
@@ -322,6 +597,25 @@ impl Manifest {
         out
     }
 
+    /// Highest Lamport among the create, delete and modify stamps
+    /// `actor` has left on any node, tombstones included. `None` if the
+    /// actor never wrote one.
+    pub fn max_stamp_by(&self, actor: ActorId) -> Option<Lamport> {
+        self.all_entries()
+            .values()
+            .flat_map(|e| {
+                [
+                    Some(Stamp::new(e.created_at, e.created_by)),
+                    e.delete_stamp,
+                    e.modify_stamp,
+                ]
+            })
+            .flatten()
+            .filter(|s| s.actor == actor)
+            .map(|s| s.lamport)
+            .max()
+    }
+
     pub fn live_entries(&self) -> Vec<NodeEntry> {
         self.all_entries()
             .into_values()
@@ -351,8 +645,14 @@ impl Manifest {
     /// not considered (directories never appear in
     /// `Projection::by_path`).
     pub fn find_entry_by_path(&self, path: &str) -> Option<NodeEntry> {
+        self.entries_by_path().remove(path)
+    }
+
+    /// [`find_entry_by_path`](Self::find_entry_by_path) for every path
+    /// at once, for callers that would otherwise look up many paths.
+    pub fn entries_by_path(&self) -> HashMap<String, NodeEntry> {
         let all = self.all_entries();
-        let mut best: Option<NodeEntry> = None;
+        let mut out: HashMap<String, NodeEntry> = HashMap::new();
         for entry in all.values() {
             if entry.kind == NodeKind::Directory {
                 continue;
@@ -360,17 +660,14 @@ impl Manifest {
             let Some(p) = build_path_ignoring_tombstones(entry, &all) else {
                 continue;
             };
-            if p != path {
-                continue;
-            }
-            match &best {
+            match out.get(&p) {
                 Some(b) if !b.deleted && entry.deleted => {}
                 _ => {
-                    best = Some(entry.clone());
+                    out.insert(p, entry.clone());
                 }
             }
         }
-        best
+        out
     }
 }
 
@@ -380,7 +677,7 @@ impl Manifest {
 /// directory chain itself is partially tombstoned but still on disk.
 /// Returns `None` if the chain is broken (parent NodeId not present)
 /// or pathologically deep.
-fn build_path_ignoring_tombstones(
+pub fn build_path_ignoring_tombstones(
     entry: &NodeEntry,
     all: &HashMap<NodeId, NodeEntry>,
 ) -> Option<String> {
@@ -401,6 +698,59 @@ fn build_path_ignoring_tombstones(
     Some(segments.join("/"))
 }
 
+/// The field values of one node or device record, as stored.
+pub type RawFields = HashMap<String, Any>;
+
+/// The `(lamport, actor)` field pairs of a node's stamps.
+const STAMP_FIELDS: [(&str, &str); 3] = [
+    ("created_at", "c_actor"),
+    ("del_lamp", "del_actor"),
+    ("mod_lamp", "mod_actor"),
+];
+
+fn read_raw_records(doc: &Doc, top: &MapRef) -> HashMap<String, RawFields> {
+    let txn = doc.transact();
+    top.iter(&txn)
+        .filter_map(|(key, v)| match v {
+            Out::YMap(m) => Some((key.to_string(), read_raw_fields(&m, &txn))),
+            _ => None,
+        })
+        .collect()
+}
+
+fn read_raw_fields<T: ReadTxn>(m: &MapRef, txn: &T) -> RawFields {
+    m.iter(txn)
+        .filter_map(|(field, v)| match v {
+            Out::Any(a) => Some((field.to_string(), a)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn put_raw_record(doc: &Doc, top: &MapRef, key: &str, fields: &RawFields) -> bool {
+    let mut txn = doc.transact_mut();
+    let Some(Out::YMap(record)) = top.get(&txn, key) else {
+        let prelim: MapPrelim = fields.iter().map(|(f, v)| (f.as_str(), v.clone())).collect();
+        top.insert(&mut txn, key, prelim);
+        return true;
+    };
+    let current = read_raw_fields(&record, &txn);
+    let mut changed = false;
+    for (field, value) in fields {
+        if current.get(field) != Some(value) {
+            record.insert(&mut txn, field.as_str(), value.clone());
+            changed = true;
+        }
+    }
+    for field in current.keys() {
+        if !fields.contains_key(field) {
+            record.remove(&mut txn, field);
+            changed = true;
+        }
+    }
+    changed
+}
+
 fn get_entry_map<T: ReadTxn>(nodes: &MapRef, txn: &T, id: NodeId) -> Option<MapRef> {
     match nodes.get(txn, &id.to_string_hyphenated())? {
         Out::YMap(m) => Some(m),
@@ -472,6 +822,13 @@ fn read_stamp<T: ReadTxn>(
     Some(Stamp::new(Lamport(lamp), actor))
 }
 
+fn read_string<T: ReadTxn>(m: &MapRef, txn: &T, key: &str) -> Option<String> {
+    match m.get(txn, key)? {
+        Out::Any(Any::String(s)) => Some(s.to_string()),
+        _ => None,
+    }
+}
+
 fn read_u64<T: ReadTxn>(m: &MapRef, txn: &T, key: &str) -> Option<u64> {
     match m.get(txn, key)? {
         Out::Any(Any::BigInt(v)) => Some(v as u64),
@@ -689,4 +1046,73 @@ mod tests {
             assert!(m.get_entry(id2).is_some());
         }
     }
+
+    #[test]
+    fn config_syncs_between_peers() {
+        let mut m1 = Manifest::new(ActorId::new());
+        let mut m2 = Manifest::new(ActorId::new());
+        assert_eq!(m1.config("colour"), None);
+
+        m1.set_config("colour", "blue");
+        m2.apply_update(&m1.encode_state_as_update()).unwrap();
+        assert_eq!(m2.config("colour").as_deref(), Some("blue"));
+        assert_eq!(
+            m2.config_entries(),
+            vec![("colour".to_string(), "blue".to_string())]
+        );
+
+        // Config writes leave node lamports alone.
+        assert_eq!(m1.lamport(), Lamport::ZERO);
+
+        m2.remove_config("colour");
+        m1.apply_update(&m2.encode_state_as_update()).unwrap();
+        assert_eq!(m1.config("colour"), None);
+    }
+
+    #[test]
+    fn max_stamp_by_covers_every_stamp_kind() {
+        let mut a = Manifest::new(ActorId::new());
+        let mut b = Manifest::new(ActorId::new());
+        assert_eq!(a.max_stamp_by(a.actor()), None);
+        let id = a.create_node("x.md", None, NodeKind::Text, None, 0);
+        a.record_modify(id);
+        b.apply_update(&a.encode_state_as_update()).unwrap();
+        b.delete(id);
+        assert_eq!(b.max_stamp_by(a.actor()), Some(Lamport(2)));
+        assert_eq!(b.max_stamp_by(b.actor()), Some(Lamport(4)));
+    }
+
+    #[test]
+    fn device_records_sync_and_retire() {
+        let mut laptop = Manifest::new(ActorId::new());
+        let mut admin = Manifest::new(ActorId::new());
+        laptop.publish_device("work-laptop", "linux", "1.2.0", 1_700_000_000);
+        admin.apply_update(&laptop.encode_state_as_update()).unwrap();
+
+        let devices = admin.devices();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].actor, laptop.actor());
+        assert_eq!(devices[0].name, "work-laptop");
+        assert_eq!(devices[0].platform, "linux");
+        assert_eq!(devices[0].last_seen, 1_700_000_000);
+        assert!(!devices[0].retired);
+        assert_eq!(laptop.lamport(), Lamport::ZERO, "device records leave lamports alone");
+
+        assert!(admin.retire_device(laptop.actor()));
+        assert!(!admin.retire_device(admin.actor()), "never published");
+        laptop.apply_update(&admin.encode_state_as_update()).unwrap();
+        assert!(laptop.devices()[0].retired);
+        assert_eq!(laptop.device_names()[&laptop.actor()], "work-laptop");
+
+        // Syncing again refreshes the record but keeps it retired.
+        laptop.publish_device("work-laptop", "linux", "1.2.1", 1_700_000_100);
+        let d = &laptop.devices()[0];
+        assert!(d.retired);
+        assert_eq!(d.version, "1.2.1");
+
+        // An unchanged session writes nothing.
+        let before = laptop.encode_state_as_update();
+        laptop.publish_device("work-laptop", "linux", "1.2.1", 1_700_000_100);
+        assert_eq!(laptop.encode_state_as_update(), before);
+    }
 }
@@ -10,22 +10,30 @@
 //! - [`ids`]        — `NodeId`, `ActorId`, `Lamport` newtypes. (portable)
 //! - [`hash`]       — `hash_hex` SHA-256 helper. (portable)
 //! - [`chunker`]    — content-defined chunking for binary blobs. (portable)
+//! - [`conflict`]   — the vault's conflict-copy name template. (portable)
 //! - [`manifest`]   — Yrs-backed manifest Y.Doc with `NodeEntry` CRUD. (portable)
 //! - [`projection`] — projects the manifest into the vault namespace. (portable)
 //! - [`ops`]        — high-level create/delete/rename/modify helpers. (portable)
+//! - [`placeholder`] — stubs standing in for binaries fetched on demand. (portable)
 //! - [`sync`]       — wire encoders/decoders + projection hash. (portable)
 //! - [`blob_store`] — on-disk CAS for binary blobs. (native-only)
 //! - [`disk`]       — `.syncline/` layout + version tripwire. (native-only)
+//! - [`file_index`] — stat cache that lets the scanner skip unchanged files. (native-only)
 //! - [`migration`]  — one-shot v0 → v1 local migration. (native-only)
+//! - [`names`]      — per-platform escaping of unrepresentable file names. (native-only)
+//! - [`outbox`]     — local changes staged on disk until they're uploaded. (native-only)
+//! - [`selective`]  — per-device choice of what is kept on disk. (native-only)
 //!
 //! The portable core compiles on `wasm32-unknown-unknown` so the Obsidian
 //! plugin can drive a v1 client directly from its WASM build.
 
 pub mod chunker;
+pub mod conflict;
 pub mod hash;
 pub mod ids;
 pub mod manifest;
 pub mod ops;
+pub mod placeholder;
 pub mod projection;
 pub mod sync;
 
@@ -34,19 +42,27 @@ pub mod blob_store;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod disk;
 #[cfg(not(target_arch = "wasm32"))]
+pub mod file_index;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod migration;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod names;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod outbox;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod selective;
 
 pub use hash::hash_hex;
 pub use ids::{ActorId, Lamport, NodeId};
 pub use manifest::{Manifest, NodeEntry, NodeKind};
 pub use ops::{
-    create_binary, create_text, create_text_allowing_collision, delete as delete_path,
-    record_modify_binary, record_modify_text, rename,
+    create_binary, create_binary_allowing_collision, create_text, create_text_allowing_collision,
+    delete as delete_path, record_modify_binary, record_modify_text, rename,
 };
 pub use projection::{ProjectedEntry, Projection};
 pub use sync::{
-    decode_verify_payload, decode_version_handshake, encode_manifest_step1,
-    encode_manifest_step2, encode_manifest_update, encode_verify_payload,
+    decode_hello, decode_verify_payload, decode_version_handshake, encode_hello,
+    encode_manifest_step1, encode_manifest_step2, encode_manifest_update, encode_verify_payload,
     encode_version_handshake, handle_manifest_payload, handle_verify_payload,
     manifest_step1_payload, manifest_step2_payload, projection_hash, split_manifest_payload,
 };
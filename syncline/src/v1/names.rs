@@ -0,0 +1,425 @@
+//! Portable file names: the layer between projected paths and the
+//! local disk.
+//!
+//! Manifest names are canonical and may contain anything but `/`. Not
+//! every filesystem can hold them: Windows (and Android's shared
+//! storage) reject `a:b?.md`, `CON.md` or `trailing dot.`. On such a
+//! disk each offending path segment is escaped to a lookalike that is
+//! representable, following the convention rclone and WSL use:
+//!
+//! | manifest                       | disk                               |
+//! |--------------------------------|------------------------------------|
+//! | `< > : " \ \| ? *`             | fullwidth forms (`：`, `？`, …)      |
+//! | control characters             | Control Pictures (`␀`–`␟`)         |
+//! | trailing `.` / trailing space  | `．` / `␠`                          |
+//! | reserved device name (`CON`…)  | last letter fullwidth (`COＮ.md`)   |
+//!
+//! Escaping is not self-describing — a user may type `：` on purpose —
+//! so the client remembers which disk paths it escaped in
+//! `.syncline/names.json` and only translates those back. Prefixes are
+//! recorded, not whole paths, so a file created or renamed inside an
+//! escaped directory still lands under the directory's real name.
+
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Which filesystem naming rules apply to a vault folder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum NameRules {
+    /// Anything but `/` and NUL: Linux, macOS.
+    Posix,
+    /// Win32 rules, also enforced by Android shared storage and by
+    /// FAT/exFAT volumes on any OS.
+    Windows,
+}
+
+impl NameRules {
+    /// Every rule set, for portability reports.
+    pub const ALL: [NameRules; 2] = [NameRules::Posix, NameRules::Windows];
+
+    /// The rules of the platform we were built for.
+    pub fn native() -> Self {
+        if cfg!(any(windows, target_os = "android")) {
+            NameRules::Windows
+        } else {
+            NameRules::Posix
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NameRules::Posix => "posix",
+            NameRules::Windows => "windows",
+        }
+    }
+
+    /// Why `segment` can't be used as-is under these rules, if it can't.
+    pub fn problem(self, segment: &str) -> Option<&'static str> {
+        match self {
+            NameRules::Posix => segment.contains('\0').then_some("contains NUL"),
+            NameRules::Windows => windows_problem(segment),
+        }
+    }
+
+    /// `segment` made representable under these rules. Identity for
+    /// names that already are.
+    pub fn escape(self, segment: &str) -> String {
+        if self.problem(segment).is_none() {
+            return segment.to_string();
+        }
+        match self {
+            NameRules::Posix => segment.replace('\0', "\u{2400}"),
+            NameRules::Windows => escape_windows(segment),
+        }
+    }
+}
+
+impl FromStr for NameRules {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "native" => Ok(NameRules::native()),
+            "posix" => Ok(NameRules::Posix),
+            "windows" => Ok(NameRules::Windows),
+            other => anyhow::bail!("unknown filename rules {other:?} (expected native, posix or windows)"),
+        }
+    }
+}
+
+const WINDOWS_RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '\\', '|', '?', '*'];
+
+fn is_windows_reserved_name(segment: &str) -> bool {
+    // The device name is reserved with any extension: `nul.txt` too.
+    let stem = segment.split('.').next().unwrap_or("").trim_end();
+    let upper = stem.to_ascii_uppercase();
+    matches!(upper.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || ((upper.starts_with("COM") || upper.starts_with("LPT"))
+            && upper.len() == 4
+            && upper.as_bytes()[3].is_ascii_digit())
+}
+
+fn windows_problem(segment: &str) -> Option<&'static str> {
+    if segment.chars().any(|c| WINDOWS_RESERVED_CHARS.contains(&c)) {
+        Some("contains a character Windows reserves (<>:\"\\|?*)")
+    } else if segment.chars().any(|c| (c as u32) < 0x20) {
+        Some("contains a control character")
+    } else if segment.ends_with('.') || segment.ends_with(' ') {
+        Some("ends with a dot or space")
+    } else if is_windows_reserved_name(segment) {
+        Some("is a reserved device name")
+    } else {
+        None
+    }
+}
+
+/// Fullwidth form of a printable ASCII character (U+FF01–U+FF5E).
+fn fullwidth(c: char) -> char {
+    char::from_u32(c as u32 + 0xFEE0).unwrap_or(c)
+}
+
+fn escape_windows(segment: &str) -> String {
+    let mut out: Vec<char> = segment
+        .chars()
+        .map(|c| match c {
+            c if WINDOWS_RESERVED_CHARS.contains(&c) => fullwidth(c),
+            c if (c as u32) < 0x20 => char::from_u32(0x2400 + c as u32).unwrap_or(c),
+            c => c,
+        })
+        .collect();
+    match out.last() {
+        Some('.') => *out.last_mut().unwrap() = fullwidth('.'),
+        Some(' ') => *out.last_mut().unwrap() = '\u{2420}',
+        _ => {}
+    }
+    let escaped: String = out.iter().collect();
+    if !is_windows_reserved_name(&escaped) {
+        return escaped;
+    }
+    // `CON.md` → `COＮ.md`: the stem is plain ASCII, so byte offsets
+    // are char offsets.
+    let stem_len = escaped.split('.').next().unwrap_or("").trim_end().len();
+    let last = escaped[..stem_len].chars().last().unwrap_or('_');
+    format!("{}{}{}", &escaped[..stem_len - 1], fullwidth(last), &escaped[stem_len..])
+}
+
+/// Translation between manifest paths and disk paths for one vault
+/// folder, remembered across runs.
+pub struct NameMap {
+    rules: NameRules,
+    file: Option<PathBuf>,
+    /// Manifest path prefix → disk path prefix, for every prefix whose
+    /// disk spelling differs.
+    to_disk: BTreeMap<String, String>,
+    /// The inverse of `to_disk`.
+    to_manifest: HashMap<String, String>,
+}
+
+impl NameMap {
+    /// An empty, unpersisted map. Used by tests and one-shot tools.
+    pub fn new(rules: NameRules) -> Self {
+        Self {
+            rules,
+            file: None,
+            to_disk: BTreeMap::new(),
+            to_manifest: HashMap::new(),
+        }
+    }
+
+    /// Load the map saved in `syncline_dir`. Mappings recorded under
+    /// different rules are dropped; the next [`NameMap::rebuild`]
+    /// recreates what still applies.
+    pub fn load(syncline_dir: &Path, rules: NameRules) -> Result<Self> {
+        let file = syncline_dir.join("names.json");
+        let mut map = Self::new(rules);
+        map.file = Some(file.clone());
+        let bytes = match fs::read(&file) {
+            Ok(b) => b,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(map),
+            Err(e) => return Err(e).with_context(|| format!("reading {}", file.display())),
+        };
+        let saved: Saved = serde_json::from_slice(&bytes)
+            .with_context(|| format!("decoding {}", file.display()))?;
+        if saved.rules == rules.as_str() {
+            map.set(saved.paths);
+        }
+        Ok(map)
+    }
+
+    pub fn rules(&self) -> NameRules {
+        self.rules
+    }
+
+    /// Where `manifest_path` lives on disk.
+    pub fn to_disk(&self, manifest_path: &str) -> String {
+        if self.to_disk.is_empty() && self.rules == NameRules::Posix {
+            return manifest_path.to_string();
+        }
+        if let Some(disk) = self.to_disk.get(manifest_path) {
+            return disk.clone();
+        }
+        // Longest recorded parent, then escape the rest afresh.
+        let segments: Vec<&str> = manifest_path.split('/').collect();
+        let (mut out, from) = longest_prefix(&segments, |p| self.to_disk.get(p));
+        for seg in &segments[from..] {
+            if !out.is_empty() {
+                out.push('/');
+            }
+            out.push_str(&self.rules.escape(seg));
+        }
+        out
+    }
+
+    /// The manifest path a disk path stands for. Only translates what
+    /// this map escaped; anything else is taken literally.
+    pub fn to_manifest(&self, disk_path: &str) -> String {
+        if self.to_manifest.is_empty() {
+            return disk_path.to_string();
+        }
+        let segments: Vec<&str> = disk_path.split('/').collect();
+        let (mut out, from) = longest_prefix(&segments, |p| self.to_manifest.get(p));
+        for seg in &segments[from..] {
+            if !out.is_empty() {
+                out.push('/');
+            }
+            out.push_str(seg);
+        }
+        out
+    }
+
+    /// Recompute the map for the given projected paths, disambiguating
+    /// escaped names that land on a path another entry already uses.
+    /// Persists the result if it changed.
+    pub fn rebuild<'a>(&mut self, manifest_paths: impl IntoIterator<Item = &'a str>) -> Result<()> {
+        let mut paths: Vec<&str> = manifest_paths.into_iter().collect();
+        // Names that need no escaping claim their disk path first, so an
+        // escaped lookalike never displaces a real file.
+        paths.sort_by_key(|p| (p.split('/').any(|s| self.rules.problem(s).is_some()), *p));
+
+        let mut next: BTreeMap<String, String> = BTreeMap::new();
+        let mut claimed: HashSet<String> = HashSet::new();
+        for path in paths {
+            let mut manifest_prefix = String::new();
+            let mut disk_prefix = String::new();
+            let segments: Vec<&str> = path.split('/').collect();
+            for (i, seg) in segments.iter().enumerate() {
+                if i > 0 {
+                    manifest_prefix.push('/');
+                    disk_prefix.push('/');
+                }
+                manifest_prefix.push_str(seg);
+                if let Some(known) = next.get(&manifest_prefix) {
+                    disk_prefix = known.clone();
+                    continue;
+                }
+                let mut disk_seg = self.rules.escape(seg);
+                if i + 1 == segments.len() && disk_seg != *seg {
+                    let mut n = 1;
+                    let base = disk_seg.clone();
+                    while claimed.contains(&format!("{disk_prefix}{disk_seg}")) {
+                        disk_seg = numbered(&base, n);
+                        n += 1;
+                    }
+                }
+                disk_prefix.push_str(&disk_seg);
+                if disk_prefix != manifest_prefix {
+                    next.insert(manifest_prefix.clone(), disk_prefix.clone());
+                }
+            }
+            claimed.insert(disk_prefix);
+        }
+        if next != self.to_disk {
+            self.set(next);
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, to_disk: BTreeMap<String, String>) {
+        self.to_manifest = to_disk.iter().map(|(m, d)| (d.clone(), m.clone())).collect();
+        self.to_disk = to_disk;
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let saved = Saved {
+            rules: self.rules.as_str().to_string(),
+            paths: self.to_disk.clone(),
+        };
+        let tmp = file.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&saved)?)
+            .with_context(|| format!("writing {}", tmp.display()))?;
+        fs::rename(&tmp, file).with_context(|| format!("renaming onto {}", file.display()))
+    }
+}
+
+/// The longest leading run of `segments` that `lookup` knows, as
+/// (translated prefix, segments consumed).
+fn longest_prefix<'a>(
+    segments: &[&str],
+    lookup: impl Fn(&str) -> Option<&'a String>,
+) -> (String, usize) {
+    for n in (1..=segments.len()).rev() {
+        if let Some(hit) = lookup(&segments[..n].join("/")) {
+            return (hit.clone(), n);
+        }
+    }
+    (String::new(), 0)
+}
+
+/// `a：b.md` → `a：b~1.md`.
+fn numbered(name: &str, n: usize) -> String {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => format!("{}~{}{}", &name[..dot], n, &name[dot..]),
+        _ => format!("{name}~{n}"),
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Saved {
+    rules: String,
+    paths: BTreeMap<String, String>,
+}
+
+/// One projected path that some platform can't store as-is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unportable {
+    pub path: String,
+    pub rules: NameRules,
+    pub segment: String,
+    pub problem: &'static str,
+}
+
+/// Every path in `paths` that can't be stored unchanged under one of
+/// [`NameRules::ALL`], sorted by path.
+pub fn unportable<'a>(paths: impl IntoIterator<Item = &'a str>) -> Vec<Unportable> {
+    let mut out = Vec::new();
+    for path in paths {
+        for rules in NameRules::ALL {
+            for seg in path.split('/') {
+                if let Some(problem) = rules.problem(seg) {
+                    out.push(Unportable {
+                        path: path.to_string(),
+                        rules,
+                        segment: seg.to_string(),
+                        problem,
+                    });
+                    break;
+                }
+            }
+        }
+    }
+    out.sort_by(|a, b| a.path.cmp(&b.path).then(a.rules.as_str().cmp(b.rules.as_str())));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_escapes_reserved_characters_and_names() {
+        let w = NameRules::Windows;
+        assert_eq!(w.escape("a:b?.md"), "a：b？.md");
+        assert_eq!(w.escape("CON.md"), "COＮ.md");
+        assert_eq!(w.escape("com1"), "com１");
+        assert_eq!(w.escape("trailing dot."), "trailing dot．");
+        assert_eq!(w.escape("trailing space "), "trailing space␠");
+        assert_eq!(w.escape("tab\there"), "tab␉here");
+        assert_eq!(w.escape("plain.md"), "plain.md");
+        assert_eq!(w.escape("CONSOLE.md"), "CONSOLE.md");
+        for s in ["a:b?.md", "CON.md", "com1", "trailing dot.", "x\u{1}"] {
+            assert!(w.problem(&w.escape(s)).is_none(), "{s:?} still unrepresentable");
+        }
+        assert_eq!(NameRules::Posix.escape("a:b?.md"), "a:b?.md");
+    }
+
+    #[test]
+    fn map_round_trips_and_follows_escaped_directories() {
+        let mut names = NameMap::new(NameRules::Windows);
+        names.rebuild(["Q: notes/todo.md", "plain/x.md"]).unwrap();
+        assert_eq!(names.to_disk("Q: notes/todo.md"), "Q： notes/todo.md");
+        assert_eq!(names.to_manifest("Q： notes/todo.md"), "Q: notes/todo.md");
+        assert_eq!(names.to_disk("plain/x.md"), "plain/x.md");
+        // A file the user adds inside the escaped directory.
+        assert_eq!(names.to_manifest("Q： notes/new.md"), "Q: notes/new.md");
+        // A name typed with a fullwidth colon that we never escaped.
+        assert_eq!(names.to_manifest("R： notes/x.md"), "R： notes/x.md");
+        // Not yet rebuilt for this path: escaped on the fly.
+        assert_eq!(names.to_disk("new?/a.md"), "new？/a.md");
+    }
+
+    #[test]
+    fn escaped_name_never_displaces_a_literal_one() {
+        let mut names = NameMap::new(NameRules::Windows);
+        names.rebuild(["a:b.md", "a：b.md"]).unwrap();
+        assert_eq!(names.to_disk("a：b.md"), "a：b.md");
+        assert_eq!(names.to_disk("a:b.md"), "a：b~1.md");
+        assert_eq!(names.to_manifest("a：b~1.md"), "a:b.md");
+        assert_eq!(names.to_manifest("a：b.md"), "a：b.md");
+    }
+
+    #[test]
+    fn map_persists_per_rule_set() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut names = NameMap::load(dir.path(), NameRules::Windows).unwrap();
+        names.rebuild(["a?.md"]).unwrap();
+
+        let again = NameMap::load(dir.path(), NameRules::Windows).unwrap();
+        assert_eq!(again.to_manifest("a？.md"), "a?.md");
+        let posix = NameMap::load(dir.path(), NameRules::Posix).unwrap();
+        assert_eq!(posix.to_manifest("a？.md"), "a？.md");
+    }
+
+    #[test]
+    fn report_lists_each_platform_once() {
+        let report = unportable(["ok.md", "x/a:b.md", "NUL"]);
+        let rows: Vec<_> = report.iter().map(|u| (u.path.as_str(), u.rules)).collect();
+        assert_eq!(rows, vec![("NUL", NameRules::Windows), ("x/a:b.md", NameRules::Windows)]);
+    }
+}
@@ -51,6 +51,19 @@ pub fn create_binary(
     create_at_path(manifest, path, NodeKind::Binary, Some(blob_hash), size)
 }
 
+/// Create a binary entry at `path` even when another live entry already
+/// projects there. Used to keep local bytes that lost to a remote write:
+/// the new node is the newer one, so projection gives it the conflict
+/// name (§6.4).
+pub fn create_binary_allowing_collision(
+    manifest: &mut Manifest,
+    path: &str,
+    blob_hash: &str,
+    size: u64,
+) -> Result<NodeId> {
+    create_at_path_allowing_collision(manifest, path, NodeKind::Binary, Some(blob_hash), size)
+}
+
 /// Mark the entry currently projected at `path` as deleted.
 pub fn delete(manifest: &mut Manifest, path: &str) -> Result<()> {
     let id = resolve_path(manifest, path)?;
@@ -80,23 +93,30 @@ pub fn rename(manifest: &mut Manifest, from: &str, to: &str) -> Result<()> {
         }
     }
 
-    let (parent_path, leaf) = split_path(to);
-    if leaf.is_empty() {
-        return Err(anyhow!("rename target {:?} has empty leaf", to));
-    }
-
-    let current = manifest
-        .get_entry(src_id)
-        .ok_or_else(|| anyhow!("source {:?} vanished mid-rename", src_id))?;
-    let new_parent = ensure_parent_chain(manifest, parent_path)?;
+    move_node(manifest, src_id, to)
+}
 
-    if current.name != leaf {
-        manifest.set_name(src_id, leaf);
+/// Move the directory at `from`, and everything under it, to `to`: a
+/// single name / parent change on the directory node, so entries a peer
+/// concurrently adds inside it follow along.
+///
+/// Errors:
+/// - no live directory projects to `from`.
+/// - `to` is already occupied by a live entry or directory.
+/// - `to` is inside `from`, or malformed (empty leaf).
+pub fn rename_directory(manifest: &mut Manifest, from: &str, to: &str) -> Result<()> {
+    if from == to {
+        return Ok(());
     }
-    if current.parent != new_parent {
-        manifest.set_parent(src_id, new_parent);
+    if to.strip_prefix(from).is_some_and(|rest| rest.starts_with('/')) {
+        return Err(anyhow!("cannot move {:?} inside itself", from));
     }
-    Ok(())
+    let src_id =
+        find_directory(manifest, from).ok_or_else(|| anyhow!("no directory at path {:?}", from))?;
+    if find_directory(manifest, to).is_some() || project(manifest).by_path.contains_key(to) {
+        return Err(anyhow!("target path {:?} already occupied", to));
+    }
+    move_node(manifest, src_id, to)
 }
 
 /// Stamp a text-content modification on the entry at `path`. Used to
@@ -132,6 +152,42 @@ fn resolve_path(manifest: &Manifest, path: &str) -> Result<NodeId> {
         .ok_or_else(|| anyhow!("no entry at path {:?}", path))
 }
 
+/// Give `id` the name and parent that project it to `to`.
+fn move_node(manifest: &mut Manifest, id: NodeId, to: &str) -> Result<()> {
+    let (parent_path, leaf) = split_path(to);
+    if leaf.is_empty() {
+        return Err(anyhow!("rename target {:?} has empty leaf", to));
+    }
+
+    let current = manifest
+        .get_entry(id)
+        .ok_or_else(|| anyhow!("source {:?} vanished mid-rename", id))?;
+    let new_parent = ensure_parent_chain(manifest, parent_path)?;
+
+    if current.name != leaf {
+        manifest.set_name(id, leaf);
+    }
+    if current.parent != new_parent {
+        manifest.set_parent(id, new_parent);
+    }
+    Ok(())
+}
+
+/// The live directory node at `path`, following the same parent chain
+/// `ensure_parent_chain` builds.
+fn find_directory(manifest: &Manifest, path: &str) -> Option<NodeId> {
+    let dirs: Vec<_> = manifest
+        .live_entries()
+        .into_iter()
+        .filter(|e| e.kind == NodeKind::Directory)
+        .collect();
+    let mut parent = None;
+    for seg in path.split('/') {
+        parent = Some(dirs.iter().find(|e| e.name == seg && e.parent == parent)?.id);
+    }
+    parent
+}
+
 fn create_at_path(
     manifest: &mut Manifest,
     path: &str,
@@ -347,6 +403,35 @@ mod tests {
         assert!(rename(&mut m, "ghost.md", "new.md").is_err());
     }
 
+    #[test]
+    fn rename_directory_moves_children_with_one_parent_change() {
+        let mut m = Manifest::new(ActorId::new());
+        let a = create_text(&mut m, "src/notes/a.md", 0).unwrap();
+        let b = create_text(&mut m, "src/notes/deep/b.md", 0).unwrap();
+        let dir = find_directory(&m, "src/notes").unwrap();
+        rename_directory(&mut m, "src/notes", "archive/notes").unwrap();
+
+        let p = project(&m);
+        assert_eq!(p.by_id[&a].path, "archive/notes/a.md");
+        assert_eq!(p.by_id[&b].path, "archive/notes/deep/b.md");
+        assert_eq!(find_directory(&m, "archive/notes"), Some(dir));
+        // The files themselves were not touched.
+        assert_eq!(m.get_entry(a).unwrap().parent, Some(dir));
+    }
+
+    #[test]
+    fn rename_directory_rejects_bad_targets() {
+        let mut m = Manifest::new(ActorId::new());
+        create_text(&mut m, "a/x.md", 0).unwrap();
+        create_text(&mut m, "b/y.md", 0).unwrap();
+        create_text(&mut m, "c.md", 0).unwrap();
+        assert!(rename_directory(&mut m, "a", "b").is_err());
+        assert!(rename_directory(&mut m, "a", "c.md").is_err());
+        assert!(rename_directory(&mut m, "a", "a/inner").is_err());
+        assert!(rename_directory(&mut m, "ghost", "d").is_err());
+        assert!(rename_directory(&mut m, "a/x.md", "d").is_err());
+    }
+
     #[test]
     fn rename_same_path_is_noop() {
         let mut m = Manifest::new(ActorId::new());
@@ -0,0 +1,347 @@
+//! Outbox: local changes waiting to reach the server.
+//!
+//! Kept in `.syncline/outbox/` and never synced. Every batch of changes
+//! the client is about to upload — a scan's new blobs, manifest delta
+//! and content deltas, a conflict copy, a device record — is written
+//! here first, one file per batch named by a sequence number so batches
+//! go out in the order they were made. Against a server that
+//! acknowledges updates (minor >= 5) a batch is removed only once the
+//! server has confirmed storing every item in it; against an older one,
+//! once all of it has been sent. A dropped connection or a crash in
+//! between leaves it for the next session to send again, and an item the
+//! server failed to store sends its batch again on a later pass.
+//!
+//! Unlike the manifest and content caches next to it, an outbox file is
+//! fsynced: nothing else holds the changes it carries. Every item is a
+//! CRDT update or a content-addressed blob, so sending one twice is
+//! harmless.
+//!
+//! Batch file format, a sequence of items:
+//!
+//! ```text
+//! [kind u8][key_len u16 BE][key][payload_len u32 BE][payload]
+//! ```
+//!
+//! where `kind` is 0 for a manifest update (empty key), 1 for a content
+//! update (key = doc id) and 2 for a blob (key = hash, empty payload).
+
+use anyhow::{Context, Result, anyhow, bail};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const OUTBOX_DIR: &str = "outbox";
+
+const KIND_MANIFEST: u8 = 0;
+const KIND_CONTENT: u8 = 1;
+const KIND_BLOB: u8 = 2;
+
+/// One change waiting to be uploaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outgoing {
+    /// A manifest Y.Doc update, as `encode_state_as_update_v1` gives it.
+    Manifest(Vec<u8>),
+    /// A Y.Doc update for the content subdoc `doc_id`.
+    Content { doc_id: String, update: Vec<u8> },
+    /// A blob in the local store, by hash.
+    Blob(String),
+}
+
+/// The batches in `.syncline/outbox/`, and which of them this session
+/// has sent and awaits acknowledgements for.
+#[derive(Debug)]
+pub struct Outbox {
+    dir: PathBuf,
+    next: u64,
+    /// Whether the server acknowledges updates this session.
+    acked: bool,
+    /// Tags sent this session and not yet acknowledged, by the batch
+    /// they belong to.
+    unacked: HashMap<u64, u64>,
+    next_tag: u64,
+}
+
+impl Outbox {
+    /// Open the outbox in `syncline_dir`, creating it if needed.
+    pub fn open(syncline_dir: &Path) -> Result<Self> {
+        let dir = syncline_dir.join(OUTBOX_DIR);
+        fs::create_dir_all(&dir).with_context(|| format!("mkdir -p {}", dir.display()))?;
+        let next = list(&dir)?.last().map_or(1, |seq| seq + 1);
+        Ok(Self {
+            dir,
+            next,
+            acked: false,
+            unacked: HashMap::new(),
+            next_tag: 1,
+        })
+    }
+
+    /// Forget what the last session had in flight: its acknowledgements
+    /// will never arrive. `acked` says whether this session's server
+    /// acknowledges updates.
+    pub fn start_session(&mut self, acked: bool) {
+        self.acked = acked;
+        self.unacked.clear();
+    }
+
+    /// Durably stage `items` as one batch, behind every batch already
+    /// waiting. Returns its sequence number, or `None` for no items.
+    pub fn push(&mut self, items: &[Outgoing]) -> Result<Option<u64>> {
+        if items.is_empty() {
+            return Ok(None);
+        }
+        let seq = self.next;
+        let path = self.dir.join(format!("{seq:020}"));
+        let tmp = path.with_extension("tmp");
+        {
+            let mut f = fs::File::create(&tmp)
+                .with_context(|| format!("create tmp {}", tmp.display()))?;
+            f.write_all(&encode_batch(items)?)
+                .with_context(|| format!("write tmp {}", tmp.display()))?;
+            f.sync_all()
+                .with_context(|| format!("fsync {}", tmp.display()))?;
+        }
+        fs::rename(&tmp, &path)
+            .with_context(|| format!("rename {} -> {}", tmp.display(), path.display()))?;
+        self.next += 1;
+        Ok(Some(seq))
+    }
+
+    /// Every batch waiting, oldest first. A batch that can't be read is
+    /// left in place and skipped.
+    pub fn batches(&self) -> Result<Vec<(u64, Vec<Outgoing>)>> {
+        let mut out = Vec::new();
+        for seq in list(&self.dir)? {
+            let path = self.dir.join(format!("{seq:020}"));
+            match fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| decode_batch(&bytes))
+            {
+                Ok(items) => out.push((seq, items)),
+                Err(e) => tracing::warn!("skipping unreadable {}: {e}", path.display()),
+            }
+        }
+        Ok(out)
+    }
+
+    /// Every batch waiting that isn't already in flight this session.
+    pub fn unsent(&self) -> Result<Vec<(u64, Vec<Outgoing>)>> {
+        let mut batches = self.batches()?;
+        batches.retain(|(seq, _)| !self.unacked.values().any(|b| b == seq));
+        Ok(batches)
+    }
+
+    /// A fresh tag for an item of batch `seq`, if this session's server
+    /// acknowledges updates.
+    pub fn tag(&mut self, seq: u64) -> Option<u64> {
+        if !self.acked {
+            return None;
+        }
+        let tag = self.next_tag;
+        self.next_tag += 1;
+        self.unacked.insert(tag, seq);
+        Some(tag)
+    }
+
+    /// All of batch `seq` is on the wire. Without acknowledgements, or
+    /// with nothing in it tagged, that is as good as it gets.
+    pub fn sent(&mut self, seq: u64) -> Result<()> {
+        if self.unacked.values().any(|b| *b == seq) {
+            return Ok(());
+        }
+        self.remove(seq)
+    }
+
+    /// The server is done with the item sent under `tag`: stored it, or
+    /// refused it for good. Removes its batch once nothing else in it is
+    /// unacknowledged.
+    pub fn acked(&mut self, tag: u64) -> Result<()> {
+        let Some(seq) = self.unacked.remove(&tag) else {
+            return Ok(());
+        };
+        self.sent(seq)
+    }
+
+    /// The server failed to store the item sent under `tag`: take its
+    /// whole batch out of flight, so the next send covers it again.
+    pub fn failed(&mut self, tag: u64) {
+        if let Some(seq) = self.unacked.remove(&tag) {
+            self.unacked.retain(|_, b| *b != seq);
+        }
+    }
+
+    /// Drop batch `seq`.
+    pub fn remove(&self, seq: u64) -> Result<()> {
+        let path = self.dir.join(format!("{seq:020}"));
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("remove {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// How many changes are waiting, across every batch.
+    pub fn len(&self) -> Result<usize> {
+        Ok(self.batches()?.iter().map(|(_, items)| items.len()).sum())
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(list(&self.dir)?.is_empty())
+    }
+}
+
+/// Sequence numbers of the batch files in `dir`, ascending.
+fn list(dir: &Path) -> Result<Vec<u64>> {
+    let mut seqs: Vec<u64> = fs::read_dir(dir)
+        .with_context(|| format!("reading {}", dir.display()))?
+        .filter_map(|e| e.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    seqs.sort_unstable();
+    Ok(seqs)
+}
+
+fn encode_batch(items: &[Outgoing]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for item in items {
+        let (kind, key, payload): (u8, &[u8], &[u8]) = match item {
+            Outgoing::Manifest(update) => (KIND_MANIFEST, b"", update),
+            Outgoing::Content { doc_id, update } => (KIND_CONTENT, doc_id.as_bytes(), update),
+            Outgoing::Blob(hash) => (KIND_BLOB, hash.as_bytes(), b""),
+        };
+        let key_len = u16::try_from(key.len()).map_err(|_| anyhow!("outbox key too long"))?;
+        let payload_len =
+            u32::try_from(payload.len()).map_err(|_| anyhow!("outbox payload too long"))?;
+        out.push(kind);
+        out.extend_from_slice(&key_len.to_be_bytes());
+        out.extend_from_slice(key);
+        out.extend_from_slice(&payload_len.to_be_bytes());
+        out.extend_from_slice(payload);
+    }
+    Ok(out)
+}
+
+fn decode_batch(mut bytes: &[u8]) -> Result<Vec<Outgoing>> {
+    fn take<'a>(bytes: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+        if bytes.len() < n {
+            bail!("truncated outbox batch");
+        }
+        let (head, rest) = bytes.split_at(n);
+        *bytes = rest;
+        Ok(head)
+    }
+    let mut items = Vec::new();
+    while !bytes.is_empty() {
+        let kind = take(&mut bytes, 1)?[0];
+        let key_len = u16::from_be_bytes(take(&mut bytes, 2)?.try_into()?) as usize;
+        let key = String::from_utf8(take(&mut bytes, key_len)?.to_vec())?;
+        let payload_len = u32::from_be_bytes(take(&mut bytes, 4)?.try_into()?) as usize;
+        let payload = take(&mut bytes, payload_len)?.to_vec();
+        items.push(match kind {
+            KIND_MANIFEST => Outgoing::Manifest(payload),
+            KIND_CONTENT => Outgoing::Content {
+                doc_id: key,
+                update: payload,
+            },
+            KIND_BLOB => Outgoing::Blob(key),
+            other => bail!("unknown outbox item kind {other}"),
+        });
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Vec<Outgoing> {
+        vec![
+            Outgoing::Blob("ab".repeat(32)),
+            Outgoing::Manifest(vec![1, 2, 3]),
+            Outgoing::Content {
+                doc_id: "content:0190".into(),
+                update: vec![9; 300],
+            },
+        ]
+    }
+
+    #[test]
+    fn batches_survive_reopen_in_order_until_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut outbox = Outbox::open(dir.path()).unwrap();
+        assert!(outbox.is_empty().unwrap());
+        assert_eq!(outbox.push(&[]).unwrap(), None);
+        let first = outbox.push(&sample()).unwrap().unwrap();
+        let second = outbox.push(&[Outgoing::Manifest(vec![4])]).unwrap().unwrap();
+        assert!(first < second);
+
+        let mut reopened = Outbox::open(dir.path()).unwrap();
+        assert_eq!(reopened.len().unwrap(), 4);
+        assert_eq!(
+            reopened.batches().unwrap(),
+            vec![(first, sample()), (second, vec![Outgoing::Manifest(vec![4])])]
+        );
+        // New batches queue behind the ones already waiting.
+        let third = reopened.push(&[Outgoing::Blob("cd".into())]).unwrap().unwrap();
+        assert!(third > second);
+
+        reopened.remove(first).unwrap();
+        reopened.remove(first).unwrap();
+        let seqs: Vec<u64> = reopened.batches().unwrap().iter().map(|(s, _)| *s).collect();
+        assert_eq!(seqs, vec![second, third]);
+    }
+
+    #[test]
+    fn batches_wait_for_every_ack_and_failures_resend() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut outbox = Outbox::open(dir.path()).unwrap();
+        let first = outbox.push(&sample()).unwrap().unwrap();
+        let second = outbox.push(&[Outgoing::Manifest(vec![4])]).unwrap().unwrap();
+        outbox.start_session(true);
+
+        let tags: Vec<u64> = (0..3).map(|_| outbox.tag(first).unwrap()).collect();
+        outbox.sent(first).unwrap();
+        let lone = outbox.tag(second).unwrap();
+        outbox.sent(second).unwrap();
+        assert!(outbox.unsent().unwrap().is_empty());
+
+        // One failure puts the whole batch back in line; acks that
+        // straggle in for it afterwards change nothing.
+        outbox.acked(tags[0]).unwrap();
+        outbox.failed(tags[1]);
+        outbox.acked(tags[2]).unwrap();
+        assert_eq!(outbox.unsent().unwrap(), vec![(first, sample())]);
+        assert_eq!(outbox.len().unwrap(), 4);
+
+        outbox.acked(lone).unwrap();
+        assert_eq!(outbox.batches().unwrap(), vec![(first, sample())]);
+        let retry: Vec<u64> = (0..3).map(|_| outbox.tag(first).unwrap()).collect();
+        outbox.sent(first).unwrap();
+        for tag in retry {
+            outbox.acked(tag).unwrap();
+        }
+        assert!(outbox.is_empty().unwrap());
+
+        // A server without acks: sent is done.
+        let third = outbox.push(&sample()).unwrap().unwrap();
+        outbox.start_session(false);
+        assert_eq!(outbox.tag(third), None);
+        outbox.sent(third).unwrap();
+        assert!(outbox.is_empty().unwrap());
+    }
+
+    #[test]
+    fn unreadable_batches_and_stray_files_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut outbox = Outbox::open(dir.path()).unwrap();
+        let good = outbox.push(&sample()).unwrap().unwrap();
+        let outbox_dir = dir.path().join(OUTBOX_DIR);
+        fs::write(outbox_dir.join(format!("{:020}", good + 1)), [KIND_CONTENT, 0]).unwrap();
+        fs::write(outbox_dir.join("00000000000000000009.tmp"), b"half written").unwrap();
+
+        let batches = outbox.batches().unwrap();
+        assert_eq!(batches, vec![(good, sample())]);
+        assert!(decode_batch(&[7, 0, 0, 0, 0, 0, 0]).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+//! Placeholder files for binaries fetched on demand.
+//!
+//! A device that leaves large binaries on the server (see
+//! [`crate::v1::selective`]) still shows them in the vault: each one is
+//! materialised as a small text stub at its real path, naming the blob
+//! it stands for. Scanners must recognise a stub and never upload it as
+//! the file's content; fetching the blob replaces it with the real bytes.
+//!
+//! The format is shared by the native client and the Obsidian plugin.
+
+/// First line of every placeholder.
+const MAGIC: &str = "syncline-placeholder 1\n";
+
+/// Placeholders are always smaller than this; anything larger is a real
+/// file and needn't be read to find out.
+pub const MAX_LEN: u64 = 1024;
+
+/// What a placeholder stands for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placeholder {
+    pub hash: String,
+    pub size: u64,
+}
+
+/// The stub written in place of blob `hash` (`size` bytes).
+pub fn render(hash: &str, size: u64) -> Vec<u8> {
+    format!(
+        "{MAGIC}hash {hash}\nsize {size}\n\n\
+         This file is in your Syncline vault but hasn't been downloaded to this\n\
+         device. Open it in Obsidian, or run `syncline fetch <path>`, to get it.\n"
+    )
+    .into_bytes()
+}
+
+/// The placeholder `bytes` hold, if they are one.
+pub fn parse(bytes: &[u8]) -> Option<Placeholder> {
+    if bytes.len() as u64 >= MAX_LEN {
+        return None;
+    }
+    let text = std::str::from_utf8(bytes).ok()?;
+    let mut lines = text.strip_prefix(MAGIC)?.lines();
+    let hash = lines.next()?.strip_prefix("hash ")?;
+    let size = lines.next()?.strip_prefix("size ")?.parse().ok()?;
+    if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(Placeholder {
+        hash: hash.to_string(),
+        size,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrips_and_rejects_lookalikes() {
+        let hash = "ab".repeat(32);
+        let stub = render(&hash, 20 << 30);
+        assert_eq!(
+            parse(&stub),
+            Some(Placeholder {
+                hash: hash.clone(),
+                size: 20 << 30
+            })
+        );
+        assert_eq!(
+            parse(b"syncline-placeholder 1\nhash nothex\nsize 1\n"),
+            None
+        );
+        assert_eq!(parse(&stub[1..]), None);
+        assert_eq!(parse(&[0x89, b'P', b'N', b'G']), None);
+    }
+}
@@ -6,10 +6,123 @@
 //! The projection is pure and deterministic given identical input —
 //! two peers running with the same manifest will produce identical
 //! path strings, including conflict suffixes.
-
-use super::ids::{NodeId, Stamp};
+//!
+//! "Same path" is decided under the vault's [`PathEquivalence`] policy,
+//! read from the manifest's synced config so every peer applies the
+//! same one. New vaults are seeded with [`PathEquivalence::PORTABLE`],
+//! under which `Notes/Todo.md` and `notes/todo.md`, or the NFC and NFD
+//! spellings of `Café.md`, are one path — as they are on macOS and
+//! Windows filesystems. Vaults that predate the setting keep comparing
+//! paths exactly, so upgrading never renames anything.
+
+use super::conflict::ConflictTemplate;
+use super::ids::NodeId;
 use super::manifest::{Manifest, NodeEntry, NodeKind};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use unicode_normalization::UnicodeNormalization;
+
+/// Manifest config key holding the vault's [`PathEquivalence`].
+pub const PATH_EQUIVALENCE_KEY: &str = "path_equivalence";
+
+/// Which spellings of a path the projection treats as the same file.
+///
+/// Serialised in the manifest config as `exact`, `nfc`, `casefold` or
+/// `nfc+casefold`. Unset or unparseable means `exact`, which is how
+/// vaults compared paths before the setting existed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PathEquivalence {
+    /// Compare under Unicode canonical equivalence (NFC).
+    pub normalize_unicode: bool,
+    /// Compare case-insensitively (Unicode lowercase mapping).
+    pub fold_case: bool,
+}
+
+impl PathEquivalence {
+    /// Byte-exact comparison, as on a typical Linux filesystem.
+    pub const EXACT: Self = Self {
+        normalize_unicode: false,
+        fold_case: false,
+    };
+
+    /// Both foldings — what macOS (APFS/HFS+) and Windows (NTFS) do.
+    pub const PORTABLE: Self = Self {
+        normalize_unicode: true,
+        fold_case: true,
+    };
+
+    /// The policy stored in `manifest`'s config, or the default.
+    pub fn of(manifest: &Manifest) -> Self {
+        manifest
+            .config(PATH_EQUIVALENCE_KEY)
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_default()
+    }
+
+    /// Comparison key for `path`. Two paths are equivalent iff their keys
+    /// are equal. Folds each `/`-separated segment on its own so a key
+    /// prefix is always the key of the path prefix.
+    pub fn key(&self, path: &str) -> String {
+        if *self == Self::EXACT {
+            return path.to_string();
+        }
+        path.split('/')
+            .map(|seg| self.key_segment(seg))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn key_segment(&self, seg: &str) -> String {
+        let folded = if self.fold_case {
+            seg.to_lowercase()
+        } else {
+            seg.to_string()
+        };
+        if self.normalize_unicode {
+            folded.nfc().collect()
+        } else {
+            folded
+        }
+    }
+}
+
+impl Default for PathEquivalence {
+    fn default() -> Self {
+        Self::EXACT
+    }
+}
+
+impl fmt::Display for PathEquivalence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match (self.normalize_unicode, self.fold_case) {
+            (false, false) => "exact",
+            (true, false) => "nfc",
+            (false, true) => "casefold",
+            (true, true) => "nfc+casefold",
+        })
+    }
+}
+
+impl FromStr for PathEquivalence {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (normalize_unicode, fold_case) = match s {
+            "exact" => (false, false),
+            "nfc" => (true, false),
+            "casefold" => (false, true),
+            "nfc+casefold" | "casefold+nfc" => (true, true),
+            other => anyhow::bail!(
+                "unknown path equivalence {other:?} (expected exact, nfc, casefold or nfc+casefold)"
+            ),
+        };
+        Ok(Self {
+            normalize_unicode,
+            fold_case,
+        })
+    }
+}
 
 /// One row of the projection.
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -53,8 +166,15 @@ impl Projection {
 /// Project the manifest. Applies, in order:
 /// 1. Modify-wins-over-delete (§6.3).
 /// 2. Path assembly via parent chains.
-/// 3. Same-path collision → deterministic conflict suffixes (§6.4).
+/// 3. Same-path collision → deterministic conflict suffixes (§6.4),
+///    where "same" is decided by the vault's [`PathEquivalence`].
 pub fn project(manifest: &Manifest) -> Projection {
+    manifest.cached_projection(|| project_with(manifest, PathEquivalence::of(manifest)))
+}
+
+/// [`project`] under an explicit equivalence policy instead of the one
+/// in the manifest's config.
+pub fn project_with(manifest: &Manifest, policy: PathEquivalence) -> Projection {
     let all = manifest.all_entries();
 
     // Step 1: decide which entries are live after §6.3.
@@ -66,64 +186,146 @@ pub fn project(manifest: &Manifest) -> Projection {
     // Step 2: assemble paths. Directories contribute to paths but are
     // not themselves projected as files (they are emergent — §5.6).
     let mut rows: Vec<Row> = Vec::new();
+    let mut dirs: Vec<(&NodeEntry, String)> = Vec::new();
     for e in &live {
-        if e.kind == NodeKind::Directory {
-            continue; // directories don't get a row of their own
-        }
         let Some(path) = build_path(e, &all) else {
             continue; // broken parent chain — drop
         };
+        if e.kind == NodeKind::Directory {
+            dirs.push((e, path)); // directories don't get a row of their own
+            continue;
+        }
         rows.push(Row {
             entry: (*e).clone(),
             base_path: path,
         });
     }
 
+    // Step 2b: equivalent directories (`Notes/` and `notes/`) are one
+    // directory on disk. Spell every such prefix like its oldest
+    // directory node so all files land under the same name.
+    let spelling = directory_spellings(&policy, dirs);
+    for r in &mut rows {
+        r.base_path = respell_parents(&policy, &spelling, &r.base_path);
+    }
+
     // Step 3: collapse same-path collisions deterministically.
-    // Group by base_path, sort by stamp ascending, winner = argmin, losers get suffixes.
+    // Group by equivalence key, sort by stamp ascending, winner = argmin,
+    // losers get conflict names on their own spelling of the name.
     let mut groups: HashMap<String, Vec<Row>> = HashMap::new();
     for r in rows {
-        groups.entry(r.base_path.clone()).or_default().push(r);
+        groups.entry(policy.key(&r.base_path)).or_default().push(r);
     }
 
     let mut out = Projection::default();
-    for (base_path, mut group) in groups {
+    let mut taken: HashSet<String> = HashSet::with_capacity(groups.len());
+    let mut losers: Vec<Row> = Vec::new();
+    for (key, mut group) in groups {
         // Sort ascending by stamp; winner = first.
-        group.sort_by(|a, b| {
-            a.entry
-                .effective_stamp()
-                .cmp(&b.entry.effective_stamp())
-                .then_with(|| a.entry.id.cmp(&b.entry.id))
-        });
+        group.sort_by(|a, b| oldest_first(&a.entry, &b.entry));
+        let mut group = group.into_iter();
+        let winner = group.next().expect("groups are never empty");
+        taken.insert(key);
+        out.insert(&winner.entry, winner.base_path, false);
+        losers.extend(group);
+    }
 
-        for (i, row) in group.into_iter().enumerate() {
-            let is_conflict = i > 0;
-            let final_path = if is_conflict {
-                conflict_path(&base_path, row.entry.effective_stamp(), row.entry.id)
-            } else {
-                base_path.clone()
-            };
-            let projected = ProjectedEntry {
-                id: row.entry.id,
-                path: final_path.clone(),
-                kind: row.entry.kind,
-                blob_hash: row.entry.blob_hash.clone(),
-                size: row.entry.size,
-                is_conflict_copy: is_conflict,
-            };
-            out.by_id.insert(projected.id, projected.clone());
-            out.by_path.insert(final_path, projected);
+    // Winners own their paths; a conflict name that lands on one, or on
+    // an earlier conflict name, falls back to the full node id. Losers go
+    // in (stamp, id) order so that fallback is peer-independent too.
+    let template = ConflictTemplate::of(manifest);
+    let devices = manifest.device_names();
+    losers.sort_by(|a, b| oldest_first(&a.entry, &b.entry));
+    for row in losers {
+        let stamp = row.entry.effective_stamp();
+        let device = devices.get(&stamp.actor).map(String::as_str);
+        let mut path = template.render(&row.base_path, stamp, row.entry.id, device);
+        if !taken.insert(policy.key(&path)) {
+            path = ConflictTemplate::disambiguate(&path, row.entry.id);
+            taken.insert(policy.key(&path));
         }
+        out.insert(&row.entry, path, true);
     }
 
     out
 }
 
+impl Projection {
+    fn insert(&mut self, entry: &NodeEntry, path: String, is_conflict_copy: bool) {
+        let projected = ProjectedEntry {
+            id: entry.id,
+            path: path.clone(),
+            kind: entry.kind,
+            blob_hash: entry.blob_hash.clone(),
+            size: entry.size,
+            is_conflict_copy,
+        };
+        self.by_id.insert(projected.id, projected.clone());
+        self.by_path.insert(path, projected);
+    }
+}
+
 struct Row {
     entry: NodeEntry,
     base_path: String,
 }
 
+/// The collision tiebreak: earliest effective stamp, then lowest id.
+fn oldest_first(a: &NodeEntry, b: &NodeEntry) -> std::cmp::Ordering {
+    a.effective_stamp()
+        .cmp(&b.effective_stamp())
+        .then_with(|| a.id.cmp(&b.id))
+}
+
+/// Map from the key of each directory path to the last-segment spelling
+/// of the winning directory under that key.
+fn directory_spellings(
+    policy: &PathEquivalence,
+    dirs: Vec<(&NodeEntry, String)>,
+) -> HashMap<String, String> {
+    if *policy == PathEquivalence::EXACT {
+        return HashMap::new();
+    }
+    let mut winners: HashMap<String, &NodeEntry> = HashMap::new();
+    for (entry, path) in dirs {
+        let best = winners.entry(policy.key(&path)).or_insert(entry);
+        if oldest_first(entry, best).is_lt() {
+            *best = entry;
+        }
+    }
+    winners
+        .into_iter()
+        .map(|(key, entry)| (key, entry.name.clone()))
+        .collect()
+}
+
+/// Rewrite every parent segment of `path` to its canonical spelling. The
+/// final segment (the file's own name) is left as written.
+fn respell_parents(
+    policy: &PathEquivalence,
+    spelling: &HashMap<String, String>,
+    path: &str,
+) -> String {
+    if spelling.is_empty() {
+        return path.to_string();
+    }
+    let segments: Vec<&str> = path.split('/').collect();
+    let mut key = String::new();
+    let mut out: Vec<&str> = Vec::with_capacity(segments.len());
+    for (i, seg) in segments.iter().enumerate() {
+        if i + 1 == segments.len() {
+            out.push(seg);
+            break;
+        }
+        if i > 0 {
+            key.push('/');
+        }
+        key.push_str(&policy.key_segment(seg));
+        out.push(spelling.get(&key).map_or(seg, String::as_str));
+    }
+    out.join("/")
+}
+
 fn is_live(entry: &NodeEntry) -> bool {
     if !entry.deleted {
         return true;
@@ -163,40 +365,10 @@ fn build_path(entry: &NodeEntry, all: &HashMap<NodeId, NodeEntry>) -> Option<Str
     Some(segments.join("/"))
 }
 
-/// Conflict-copy naming — deterministic across peers.
-///
-/// `foo.md` → `foo.conflict-<actor8>-<lamp>.md`
-/// `bin`    → `bin.conflict-<actor8>-<lamp>`
-fn conflict_path(base: &str, stamp: Stamp, id: NodeId) -> String {
-    let (stem, ext) = split_ext(base);
-    let actor_short = stamp.actor.short();
-    let lamp = stamp.lamport.get();
-    let node_short = &id.to_string_hyphenated()[..8];
-    if let Some(ext) = ext {
-        format!("{stem}.conflict-{actor_short}-{lamp}-{node_short}.{ext}")
-    } else {
-        format!("{stem}.conflict-{actor_short}-{lamp}-{node_short}")
-    }
-}
-
-fn split_ext(path: &str) -> (&str, Option<&str>) {
-    // Split on the last '.', but only in the final segment (don't
-    // corrupt "foo.bar/baz").
-    let last_slash = path.rfind('/').map(|i| i + 1).unwrap_or(0);
-    let last = &path[last_slash..];
-    if let Some(dot) = last.rfind('.') {
-        if dot == 0 {
-            return (path, None); // ".hidden" → no extension
-        }
-        let stem_end = last_slash + dot;
-        return (&path[..stem_end], Some(&path[stem_end + 1..]));
-    }
-    (path, None)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::v1::conflict::CONFLICT_NAME_KEY;
     use crate::v1::ids::ActorId;
 
     #[test]
@@ -311,18 +483,65 @@ mod tests {
     }
 
     #[test]
-    fn conflict_suffix_preserves_extension() {
-        let (stem, ext) = split_ext("foo/bar/baz.tar.gz");
-        assert_eq!(stem, "foo/bar/baz.tar");
-        assert_eq!(ext, Some("gz"));
+    fn conflict_names_follow_vault_template() {
+        let mut m1 = Manifest::new(ActorId::new());
+        m1.set_config(CONFLICT_NAME_KEY, "{stem} (conflict {lamport}){ext}");
+        m1.create_node("same.md", None, NodeKind::Text, None, 0);
+        let mut m2 = Manifest::new(ActorId::new());
+        m2.create_node("same.md", None, NodeKind::Text, None, 0);
+        let (m1, m2) = merged(m1, m2);
 
-        let (stem, ext) = split_ext("noext");
-        assert_eq!(stem, "noext");
-        assert_eq!(ext, None);
+        assert_eq!(sorted_paths(&project(&m1)), sorted_paths(&project(&m2)));
+        let paths = sorted_paths(&project(&m1));
+        assert_eq!(paths[0], "same (conflict 1).md", "{paths:?}");
+        assert_eq!(paths[1], "same.md");
+    }
 
-        let (stem, ext) = split_ext(".hidden");
-        assert_eq!(stem, ".hidden");
-        assert_eq!(ext, None);
+    #[test]
+    fn conflict_names_can_use_device_names() {
+        let mut m1 = Manifest::new(ActorId::new());
+        m1.set_config(CONFLICT_NAME_KEY, "{stem} ({device}){ext}");
+        m1.create_node("same.md", None, NodeKind::Text, None, 0);
+        let mut m2 = Manifest::new(ActorId::new());
+        m2.publish_device("work-laptop", "linux", "1.0.0", 0);
+        let id = m2.create_node("same.md", None, NodeKind::Text, None, 0);
+        m2.record_modify(id); // make m2's node the loser
+        let (mut m1, mut m2) = merged(m1, m2);
+
+        assert_eq!(sorted_paths(&project(&m1)), vec!["same (work-laptop).md", "same.md"]);
+
+        // The name is read at projection time, so renaming the device
+        // renames its unresolved copies too.
+        m2.publish_device("old-laptop", "linux", "1.0.0", 0);
+        m1.apply_update(&m2.encode_state_as_update()).unwrap();
+        assert_eq!(sorted_paths(&project(&m1)), vec!["same (old-laptop).md", "same.md"]);
+    }
+
+    #[test]
+    fn conflict_name_never_displaces_another_path() {
+        // A template without `{node}` renders both losers, and a real
+        // file, to one name.
+        let template = "{stem} copy{ext}";
+        let mut peers: Vec<Manifest> = (0..3).map(|_| Manifest::new(ActorId::new())).collect();
+        peers[0].set_config(CONFLICT_NAME_KEY, template);
+        for m in &mut peers {
+            m.create_node("a.md", None, NodeKind::Text, None, 0);
+        }
+        peers[0].create_node("a copy.md", None, NodeKind::Text, None, 0);
+        let updates: Vec<_> = peers.iter().map(|m| m.encode_state_as_update()).collect();
+        for m in &mut peers {
+            for u in &updates {
+                m.apply_update(u).unwrap();
+            }
+        }
+
+        let p = project(&peers[0]);
+        assert_eq!(p.len(), 4, "{:?}", sorted_paths(&p));
+        assert!(!p.by_path["a copy.md"].is_conflict_copy);
+        assert_eq!(p.by_path.values().filter(|e| e.is_conflict_copy).count(), 2);
+        for m in &peers[1..] {
+            assert_eq!(sorted_paths(&project(m)), sorted_paths(&p));
+        }
     }
 
     #[test]
@@ -373,4 +592,141 @@ mod tests {
         // Child becomes unprojectable under the cascade-safety net.
         assert!(p.get_by_id(f).is_none());
     }
+
+    /// Merge two independently-edited manifests both ways.
+    fn merged(mut m1: Manifest, mut m2: Manifest) -> (Manifest, Manifest) {
+        let u1 = m1.encode_state_as_update();
+        let u2 = m2.encode_state_as_update();
+        m1.apply_update(&u2).unwrap();
+        m2.apply_update(&u1).unwrap();
+        (m1, m2)
+    }
+
+    fn sorted_paths(p: &Projection) -> Vec<String> {
+        let mut paths: Vec<_> = p.by_path.keys().cloned().collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn case_variants_collide_under_portable_policy() {
+        let mut m1 = Manifest::new(ActorId::new());
+        m1.set_config(PATH_EQUIVALENCE_KEY, "nfc+casefold");
+        let id1 = m1.create_node("Todo.md", None, NodeKind::Text, None, 0);
+        let mut m2 = Manifest::new(ActorId::new());
+        let id2 = m2.create_node("todo.md", None, NodeKind::Text, None, 0);
+        let (m1, m2) = merged(m1, m2);
+
+        let (p1, p2) = (project(&m1), project(&m2));
+        assert_eq!(sorted_paths(&p1), sorted_paths(&p2));
+        assert_eq!(
+            crate::v1::projection_hash(&m1),
+            crate::v1::projection_hash(&m2)
+        );
+        let (a, b) = (p1.get_by_id(id1).unwrap(), p1.get_by_id(id2).unwrap());
+        assert_ne!(a.is_conflict_copy, b.is_conflict_copy);
+        // The loser keeps its own spelling in front of the suffix.
+        let loser = if a.is_conflict_copy { a } else { b };
+        let own = if loser.id == id1 { "Todo" } else { "todo" };
+        assert!(loser.path.starts_with(&format!("{own}.conflict-")), "{}", loser.path);
+    }
+
+    #[test]
+    fn nfc_and_nfd_spellings_collide() {
+        let nfc = "Caf\u{e9}.md";
+        let nfd = "Cafe\u{301}.md";
+        let mut m1 = Manifest::new(ActorId::new());
+        m1.set_config(PATH_EQUIVALENCE_KEY, "nfc+casefold");
+        m1.create_node(nfc, None, NodeKind::Text, None, 0);
+        let mut m2 = Manifest::new(ActorId::new());
+        m2.create_node(nfd, None, NodeKind::Text, None, 0);
+        let (m1, m2) = merged(m1, m2);
+
+        let p = project(&m1);
+        assert_eq!(p.by_path.values().filter(|e| e.is_conflict_copy).count(), 1);
+        assert_eq!(sorted_paths(&p), sorted_paths(&project(&m2)));
+    }
+
+    #[test]
+    fn unset_policy_keeps_case_variants_apart() {
+        // Vaults from before the setting must project as they always did.
+        let mut m1 = Manifest::new(ActorId::new());
+        m1.create_node("Todo.md", None, NodeKind::Text, None, 0);
+        let mut m2 = Manifest::new(ActorId::new());
+        m2.create_node("todo.md", None, NodeKind::Text, None, 0);
+        let (m1, m2) = merged(m1, m2);
+
+        for m in [&m1, &m2] {
+            assert_eq!(sorted_paths(&project(m)), vec!["Todo.md", "todo.md"]);
+        }
+    }
+
+    #[test]
+    fn equivalent_directories_share_one_spelling() {
+        let mut m1 = Manifest::new(ActorId::new());
+        m1.set_config(PATH_EQUIVALENCE_KEY, "nfc+casefold");
+        let d1 = m1.create_node("Notes", None, NodeKind::Directory, None, 0);
+        m1.create_node("a.md", Some(d1), NodeKind::Text, None, 0);
+        let mut m2 = Manifest::new(ActorId::new());
+        let d2 = m2.create_node("notes", None, NodeKind::Directory, None, 0);
+        m2.create_node("b.md", Some(d2), NodeKind::Text, None, 0);
+        let (m1, m2) = merged(m1, m2);
+
+        let winner = if oldest_first(
+            &m1.get_entry(d1).unwrap(),
+            &m1.get_entry(d2).unwrap(),
+        )
+        .is_lt()
+        {
+            "Notes"
+        } else {
+            "notes"
+        };
+        let expected = vec![format!("{winner}/a.md"), format!("{winner}/b.md")];
+        assert_eq!(sorted_paths(&project(&m1)), expected);
+        assert_eq!(sorted_paths(&project(&m2)), expected);
+    }
+
+    #[test]
+    fn path_equivalence_round_trips_through_config() {
+        for s in ["exact", "nfc", "casefold", "nfc+casefold"] {
+            let p: PathEquivalence = s.parse().unwrap();
+            assert_eq!(p.to_string(), s);
+        }
+        assert!("bogus".parse::<PathEquivalence>().is_err());
+
+        let mut m = Manifest::new(ActorId::new());
+        assert_eq!(PathEquivalence::of(&m), PathEquivalence::EXACT);
+        m.set_config(PATH_EQUIVALENCE_KEY, "nfc");
+        assert_eq!(
+            PathEquivalence::of(&m),
+            PathEquivalence {
+                normalize_unicode: true,
+                fold_case: false,
+            }
+        );
+        assert_eq!(PathEquivalence::PORTABLE.key("Dir/CAF\u{c9}.md"), "dir/caf\u{e9}.md");
+    }
+
+    #[test]
+    fn cached_projection_follows_every_kind_of_write() {
+        let mut m = Manifest::new(ActorId::new());
+        let a = m.create_node("a.md", None, NodeKind::Text, None, 0);
+        m.create_node("A.md", None, NodeKind::Text, None, 0);
+        assert_eq!(project(&m).by_path.len(), 2);
+        assert_eq!(project(&m).by_id[&a].path, "a.md");
+
+        // A remote rename.
+        let mut peer = Manifest::new(ActorId::new());
+        peer.apply_update(&m.encode_state_as_update()).unwrap();
+        peer.set_name(a, "b.md");
+        m.apply_update(&peer.encode_state_as_update()).unwrap();
+        assert_eq!(project(&m).by_id[&a].path, "b.md");
+
+        // A config change, and a removal that adds no new items.
+        m.set_config(PATH_EQUIVALENCE_KEY, "exact");
+        assert!(project(&m).by_path.contains_key("A.md"));
+        m.remove_raw_node(&a.to_string_hyphenated());
+        assert!(!project(&m).by_id.contains_key(&a));
+    }
 }
@@ -0,0 +1,200 @@
+//! Selective sync: what this device materialises.
+//!
+//! Two per-device choices, kept under `.syncline/` and never synced —
+//! each device picks its own:
+//!
+//! - `excluded`: folders not kept on disk at all, one vault-relative
+//!   folder per line. This is separate from `.synclineignore`: an
+//!   ignored path is invisible to the vault, an excluded folder is part
+//!   of the vault that this device simply doesn't keep on disk. Writing
+//!   files, fetching blobs and subscribing to content skip excluded
+//!   subtrees, and the scanner neither uploads nor deletes anything
+//!   inside them.
+//! - `fetch-limit`: the size in bytes above which a binary's blob is
+//!   fetched on demand rather than up front. Until then the file is a
+//!   [`placeholder`](crate::v1::placeholder).
+//!
+//! The manifest still syncs in full either way, so verification is
+//! unaffected.
+
+use anyhow::{Context, Result};
+use std::path::Path;
+
+const EXCLUDED_FILE: &str = "excluded";
+const FETCH_LIMIT_FILE: &str = "fetch-limit";
+
+/// What this device keeps on disk.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selection {
+    excluded: Vec<String>,
+    fetch_limit: Option<u64>,
+}
+
+impl Selection {
+    /// Reads the selection from `<vault>/.syncline/`. A missing or
+    /// unreadable file excludes nothing and fetches everything.
+    pub fn load(folder: &Path) -> Self {
+        let dir = folder.join(".syncline");
+        let text = std::fs::read_to_string(dir.join(EXCLUDED_FILE)).unwrap_or_default();
+        let mut selection = Self {
+            fetch_limit: std::fs::read_to_string(dir.join(FETCH_LIMIT_FILE))
+                .ok()
+                .and_then(|s| s.trim().parse().ok()),
+            ..Self::default()
+        };
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(f) = normalize_folder(line) {
+                selection.insert(f);
+            }
+        }
+        selection
+    }
+
+    pub fn save(&self, folder: &Path) -> Result<()> {
+        let dir = folder.join(".syncline");
+        let path = dir.join(EXCLUDED_FILE);
+        let mut text = String::new();
+        for f in &self.excluded {
+            text.push_str(f);
+            text.push('\n');
+        }
+        std::fs::write(&path, text).with_context(|| format!("write {}", path.display()))?;
+        let path = dir.join(FETCH_LIMIT_FILE);
+        match self.fetch_limit {
+            Some(limit) => std::fs::write(&path, format!("{limit}\n")),
+            None => match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+        }
+        .with_context(|| format!("write {}", path.display()))
+    }
+
+    /// Excluded folders, sorted.
+    pub fn folders(&self) -> &[String] {
+        &self.excluded
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.excluded.is_empty()
+    }
+
+    /// True if manifest path `path` is an excluded folder or inside one.
+    pub fn excludes(&self, path: &str) -> bool {
+        self.excluded.iter().any(|f| {
+            path.strip_prefix(f.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+
+    /// Exclude `folder`. Returns the normalized folder, or `None` if it
+    /// names the vault root or climbs out of it.
+    pub fn exclude(&mut self, folder: &str) -> Option<String> {
+        let f = normalize_folder(folder)?;
+        self.insert(f.clone());
+        Some(f)
+    }
+
+    /// Stop excluding `folder`. Returns `false` if it wasn't excluded.
+    pub fn include(&mut self, folder: &str) -> bool {
+        let Some(f) = normalize_folder(folder) else {
+            return false;
+        };
+        let before = self.excluded.len();
+        self.excluded.retain(|e| *e != f);
+        self.excluded.len() != before
+    }
+
+    /// Binaries larger than this many bytes are fetched on demand.
+    pub fn fetch_limit(&self) -> Option<u64> {
+        self.fetch_limit
+    }
+
+    pub fn set_fetch_limit(&mut self, limit: Option<u64>) {
+        self.fetch_limit = limit;
+    }
+
+    /// True if a binary of `size` bytes is fetched as soon as it shows
+    /// up in the manifest, false if it waits to be asked for.
+    pub fn fetches_up_front(&self, size: u64) -> bool {
+        self.fetch_limit.is_none_or(|limit| size <= limit)
+    }
+
+    fn insert(&mut self, folder: String) {
+        if let Err(at) = self.excluded.binary_search(&folder) {
+            self.excluded.insert(at, folder);
+        }
+    }
+}
+
+/// `folder` as a vault-relative path with `/` separators and no empty,
+/// `.` or trailing segments, or `None` if it names the vault root or
+/// climbs out of it. Server access rules store their folders this way
+/// too.
+pub fn normalize_folder(folder: &str) -> Option<String> {
+    let segments: Vec<&str> = folder
+        .split(['/', '\\'])
+        .filter(|s| !s.is_empty() && *s != ".")
+        .collect();
+    if segments.is_empty() || segments.contains(&"..") {
+        return None;
+    }
+    Some(segments.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_folder_rejects_root_and_escapes() {
+        assert_eq!(
+            normalize_folder("/HR/Payroll/").as_deref(),
+            Some("HR/Payroll")
+        );
+        assert_eq!(normalize_folder("./Finance"), Some("Finance".to_string()));
+        assert_eq!(normalize_folder("/"), None);
+        assert_eq!(normalize_folder("HR/../x"), None);
+    }
+
+    #[test]
+    fn excludes_folders_and_their_contents_only() {
+        let mut s = Selection::default();
+        assert_eq!(
+            s.exclude("/Attachments/Archive/").as_deref(),
+            Some("Attachments/Archive")
+        );
+        assert!(s.excludes("Attachments/Archive"));
+        assert!(s.excludes("Attachments/Archive/2019/scan.pdf"));
+        assert!(!s.excludes("Attachments/Archive2/a.png"));
+        assert!(!s.excludes("Attachments/a.png"));
+        assert_eq!(s.exclude(".."), None);
+        assert!(s.include("Attachments/Archive"));
+        assert!(!s.include("Attachments/Archive"));
+        assert!(s.is_empty());
+    }
+
+    #[test]
+    fn roundtrips_through_the_syncline_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join(".syncline")).unwrap();
+        assert!(Selection::load(dir.path()).is_empty());
+        let mut s = Selection::default();
+        s.exclude("b");
+        s.exclude("a/x");
+        s.exclude("b");
+        s.set_fetch_limit(Some(1000));
+        s.save(dir.path()).unwrap();
+        let loaded = Selection::load(dir.path());
+        assert_eq!(loaded.folders(), ["a/x".to_string(), "b".to_string()]);
+        assert!(loaded.fetches_up_front(1000));
+        assert!(!loaded.fetches_up_front(1001));
+        s.set_fetch_limit(None);
+        s.save(dir.path()).unwrap();
+        assert!(Selection::load(dir.path()).fetches_up_front(u64::MAX));
+    }
+}
@@ -23,14 +23,26 @@
 //! | `0x02`   | [`MANIFEST_UPDATE`] | yrs update bytes (`v1`)   |
 //!
 //! [`MSG_VERSION`] carries a separate two-byte `[major][minor]`
-//! handshake frame.
+//! handshake frame; [`MSG_HELLO`] follows it with the client's actor id.
+//!
+//! [`MSG_HELLO`]: crate::protocol::MSG_HELLO
 
+use super::ids::ActorId;
 use super::manifest::Manifest;
 use super::projection::project;
 use crate::protocol::{
-    MANIFEST_STEP_1, MANIFEST_STEP_2, MANIFEST_UPDATE, V1_PROTOCOL_MAJOR, V1_PROTOCOL_MINOR,
+    BLOB_CHUNK_HEADER_LEN, BLOB_CHUNK_SIZE, CAP_ACKS, CAP_BLOB_CHUNKS, CAP_CHANGES,
+    CAP_COMPRESSION, CAP_ERRORS, CAP_HELLO, CAP_PATH_EQUIVALENCE, CAP_SYNC_BATCH,
+    COMPRESS_MIN_SIZE, MANIFEST_STEP_1, MANIFEST_STEP_2, MANIFEST_UPDATE, MAX_BLOB_SIZE,
+    MAX_INFLATED_SIZE, MSG_BLOB_CHUNK, MSG_BLOB_UPDATE, MSG_COMPRESSED, MSG_MANIFEST_SYNC,
+    MSG_SYNC_STEP_2, MSG_TAGGED, V1_MINOR_ACKS, V1_MINOR_BLOB_CHUNKS, V1_MINOR_CHANGES,
+    V1_MINOR_ERRORS, V1_MINOR_HELLO, V1_MINOR_SYNC_BATCH, V1_PROTOCOL_MAJOR, V1_PROTOCOL_MINOR,
+    decode_message, encode_message,
 };
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
 use sha2::{Digest, Sha256};
+use std::borrow::Cow;
 use yrs::updates::decoder::Decode;
 use yrs::updates::encoder::Encode;
 use yrs::{ReadTxn, StateVector, Transact};
@@ -48,6 +60,76 @@ pub fn decode_version_handshake(payload: &[u8]) -> Option<(u8, u8)> {
     Some((payload[0], payload[1]))
 }
 
+/// What one end of a connection supports, as a bitmap of the `CAP_*`
+/// bits in [`crate::protocol`]. The intersection of both ends' is what
+/// the connection uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Caps(pub u64);
+
+impl Caps {
+    /// Every feature the native client and the server implement.
+    pub const ALL: Self = Self(
+        CAP_BLOB_CHUNKS
+            | CAP_HELLO
+            | CAP_SYNC_BATCH
+            | CAP_CHANGES
+            | CAP_ACKS
+            | CAP_ERRORS
+            | CAP_COMPRESSION
+            | CAP_PATH_EQUIVALENCE,
+    );
+
+    /// What a peer below [`crate::protocol::V1_MINOR_CAPS`], which sends
+    /// no `MSG_CAPS`, supports: everything its minor version introduced.
+    pub fn of_minor(minor: u8) -> Self {
+        let mut bits = 0;
+        for (since, cap) in [
+            (V1_MINOR_BLOB_CHUNKS, CAP_BLOB_CHUNKS),
+            (V1_MINOR_HELLO, CAP_HELLO),
+            (V1_MINOR_SYNC_BATCH, CAP_SYNC_BATCH),
+            (V1_MINOR_CHANGES, CAP_CHANGES),
+            (V1_MINOR_ACKS, CAP_ACKS),
+            (V1_MINOR_ERRORS, CAP_ERRORS),
+        ] {
+            if minor >= since {
+                bits |= cap;
+            }
+        }
+        Self(bits)
+    }
+
+    /// The features both `self` and `other` support.
+    pub fn intersect(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    pub fn has(self, cap: u64) -> bool {
+        self.0 & cap == cap
+    }
+}
+
+/// Encode the payload for a `MSG_CAPS` frame: `[bits u64 BE]`.
+pub fn encode_caps(caps: Caps) -> Vec<u8> {
+    caps.0.to_be_bytes().to_vec()
+}
+
+/// Decode a `MSG_CAPS` payload. Bytes past the first eight are left for
+/// a wider bitmap and ignored.
+pub fn decode_caps(payload: &[u8]) -> Option<Caps> {
+    let (bits, _) = payload.split_first_chunk::<8>()?;
+    Some(Caps(u64::from_be_bytes(*bits)))
+}
+
+/// Encode the payload for a `MSG_HELLO` frame.
+pub fn encode_hello(actor: ActorId) -> Vec<u8> {
+    actor.to_string_hyphenated().into_bytes()
+}
+
+/// Decode a `MSG_HELLO` payload into the sender's actor id.
+pub fn decode_hello(payload: &[u8]) -> Option<ActorId> {
+    ActorId::parse_str(std::str::from_utf8(payload).ok()?)
+}
+
 /// Build a SyncStep1 payload from a pre-encoded state vector.
 pub fn encode_manifest_step1(state_vector: &[u8]) -> Vec<u8> {
     let mut out = Vec::with_capacity(1 + state_vector.len());
@@ -205,6 +287,355 @@ pub fn handle_verify_payload(
     }
 }
 
+// ---------------------------------------------------------------------------
+// Batched content sync (§4.3.1)
+// ---------------------------------------------------------------------------
+
+/// SHA-256 over what a content subdoc has seen: its state vector plus
+/// its delete set. Two replicas of a doc that have integrated the same
+/// updates produce the same digest however those updates were split
+/// into blocks, so a matching digest means neither side has anything to
+/// send the other. The state vector alone isn't enough — deletions
+/// don't advance it.
+///
+/// Canonical form: `(client u64 BE, clock u32 BE)` pairs sorted by
+/// client, a `0xFF` separator, then for each client (sorted) with
+/// deletions `client u64 BE` followed by its merged `(start, end)`
+/// ranges as u32 BE pairs and a `0x00` terminator.
+pub fn content_digest<T: ReadTxn>(txn: &T) -> [u8; 32] {
+    let snapshot = txn.snapshot();
+    let mut clocks: Vec<_> = snapshot.state_map.iter().collect();
+    clocks.sort();
+    let mut deletes: Vec<_> = snapshot.delete_set.iter().collect();
+    deletes.sort_by_key(|(client, _)| **client);
+
+    let mut hasher = Sha256::new();
+    for (client, clock) in clocks {
+        hasher.update(client.to_be_bytes());
+        hasher.update(clock.to_be_bytes());
+    }
+    hasher.update([0xFFu8]);
+    for (client, ranges) in deletes {
+        let mut ranges: Vec<_> = ranges.iter().filter(|r| !r.is_empty()).collect();
+        if ranges.is_empty() {
+            continue;
+        }
+        ranges.sort_by_key(|r| r.start);
+        hasher.update(client.to_be_bytes());
+        let mut merged = ranges[0].clone();
+        for r in &ranges[1..] {
+            if r.start <= merged.end {
+                merged.end = merged.end.max(r.end);
+            } else {
+                hasher.update(merged.start.to_be_bytes());
+                hasher.update(merged.end.to_be_bytes());
+                merged = (*r).clone();
+            }
+        }
+        hasher.update(merged.start.to_be_bytes());
+        hasher.update(merged.end.to_be_bytes());
+        hasher.update([0u8]);
+    }
+    hasher.finalize().into()
+}
+
+/// One doc listed in a `MSG_SYNC_BATCH` frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchEntry<'a> {
+    pub doc_id: &'a str,
+    /// The sender's [`content_digest`] for the doc.
+    pub digest: [u8; 32],
+    /// The sender's state vector (`v1`), as a `MSG_SYNC_STEP_1` would
+    /// carry it.
+    pub state_vector: &'a [u8],
+}
+
+/// Encode the payload for a `MSG_SYNC_BATCH` frame: per entry,
+/// `[doc_id_len u16 BE][doc_id][digest 32][sv_len u32 BE][sv]`.
+pub fn encode_sync_batch(entries: &[BatchEntry<'_>]) -> Vec<u8> {
+    let len = entries
+        .iter()
+        .map(|e| 2 + e.doc_id.len() + 32 + 4 + e.state_vector.len())
+        .sum();
+    let mut out = Vec::with_capacity(len);
+    for e in entries {
+        out.extend_from_slice(&(e.doc_id.len() as u16).to_be_bytes());
+        out.extend_from_slice(e.doc_id.as_bytes());
+        out.extend_from_slice(&e.digest);
+        out.extend_from_slice(&(e.state_vector.len() as u32).to_be_bytes());
+        out.extend_from_slice(e.state_vector);
+    }
+    out
+}
+
+/// Decode a `MSG_SYNC_BATCH` payload. `None` if any entry is truncated
+/// or names a doc id that isn't UTF-8.
+pub fn decode_sync_batch(mut payload: &[u8]) -> Option<Vec<BatchEntry<'_>>> {
+    let mut entries = Vec::new();
+    while !payload.is_empty() {
+        let (len, rest) = payload.split_first_chunk::<2>()?;
+        let len = u16::from_be_bytes(*len) as usize;
+        let doc_id = std::str::from_utf8(rest.get(..len)?).ok()?;
+        let (digest, rest) = rest[len..].split_first_chunk::<32>()?;
+        let (sv_len, rest) = rest.split_first_chunk::<4>()?;
+        let sv_len = u32::from_be_bytes(*sv_len) as usize;
+        let state_vector = rest.get(..sv_len)?;
+        payload = &rest[sv_len..];
+        entries.push(BatchEntry {
+            doc_id,
+            digest: *digest,
+            state_vector,
+        });
+    }
+    Some(entries)
+}
+
+// ---------------------------------------------------------------------------
+// Acknowledgements and errors (MSG_TAGGED / MSG_ACK / MSG_ERROR — §4.1)
+// ---------------------------------------------------------------------------
+
+/// Encode a `MSG_TAGGED` payload wrapping a `msg_type` frame's
+/// `payload`: `[seq u64 BE][msg_type u8][payload]`.
+pub fn encode_tagged(seq: u64, msg_type: u8, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(9 + payload.len());
+    out.extend_from_slice(&seq.to_be_bytes());
+    out.push(msg_type);
+    out.extend_from_slice(payload);
+    out
+}
+
+/// Decode a `MSG_TAGGED` payload into `(seq, msg_type, payload)`.
+pub fn decode_tagged(payload: &[u8]) -> Option<(u64, u8, &[u8])> {
+    let (seq, rest) = payload.split_first_chunk::<8>()?;
+    let (msg_type, rest) = rest.split_first()?;
+    Some((u64::from_be_bytes(*seq), *msg_type, rest))
+}
+
+/// Encode a `MSG_ACK` payload: `[seq u64 BE][status u8]`.
+pub fn encode_ack(seq: u64, status: u8) -> Vec<u8> {
+    let mut out = Vec::with_capacity(9);
+    out.extend_from_slice(&seq.to_be_bytes());
+    out.push(status);
+    out
+}
+
+/// Decode a `MSG_ACK` payload into `(seq, status)`.
+pub fn decode_ack(payload: &[u8]) -> Option<(u64, u8)> {
+    let (seq, rest) = payload.split_first_chunk::<8>()?;
+    match rest {
+        [status] => Some((u64::from_be_bytes(*seq), *status)),
+        _ => None,
+    }
+}
+
+/// Encode a `MSG_ERROR` payload: `[code u16 BE][message utf8]`.
+pub fn encode_error(code: u16, message: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(2 + message.len());
+    out.extend_from_slice(&code.to_be_bytes());
+    out.extend_from_slice(message.as_bytes());
+    out
+}
+
+/// Decode a `MSG_ERROR` payload into `(code, message)`.
+pub fn decode_error(payload: &[u8]) -> Option<(u16, &str)> {
+    let (code, message) = payload.split_first_chunk::<2>()?;
+    Some((u16::from_be_bytes(*code), std::str::from_utf8(message).ok()?))
+}
+
+// ---------------------------------------------------------------------------
+// Compression (MSG_COMPRESSED — §4.1)
+// ---------------------------------------------------------------------------
+
+/// Deflate level for outgoing frames: zlib's default, which gets most
+/// of the saving on text at a fraction of level 9's cost on a phone.
+const COMPRESSION_LEVEL: u8 = 6;
+
+/// How much of a large payload is deflated first to see whether the
+/// whole is worth it. A chunk of a photo or an archive fails this
+/// without paying for a full pass.
+const COMPRESS_PROBE_SIZE: usize = 16 * 1024;
+
+/// `frame` with its payload deflated into a `MSG_COMPRESSED` wrapper, or
+/// `None` to send it as is: it isn't a `MSG_SYNC_STEP_2`,
+/// `MSG_MANIFEST_SYNC`, `MSG_BLOB_UPDATE` or `MSG_BLOB_CHUNK`, its payload
+/// is under [`COMPRESS_MIN_SIZE`], or deflate saves less than an eighth.
+/// A `MSG_TAGGED` frame keeps its tag outside the compressed frame.
+pub fn compress_frame(frame: &[u8]) -> Option<Vec<u8>> {
+    let (msg_type, doc_id, payload) = decode_message(frame)?;
+    if msg_type == MSG_TAGGED {
+        let (seq, msg_type, payload) = decode_tagged(payload)?;
+        let packed = encode_compressed(msg_type, payload)?;
+        let tagged = encode_tagged(seq, MSG_COMPRESSED, &packed);
+        return Some(encode_message(MSG_TAGGED, doc_id, &tagged));
+    }
+    let packed = encode_compressed(msg_type, payload)?;
+    Some(encode_message(MSG_COMPRESSED, doc_id, &packed))
+}
+
+/// Encode a `MSG_COMPRESSED` payload for a `msg_type` frame's `payload`:
+/// `[msg_type u8][raw deflate stream]`, if compressing it is worth it.
+fn encode_compressed(msg_type: u8, payload: &[u8]) -> Option<Vec<u8>> {
+    if inflate_limit(msg_type).is_none() || payload.len() < COMPRESS_MIN_SIZE {
+        return None;
+    }
+    if payload.len() > COMPRESS_PROBE_SIZE {
+        let probe = &payload[..COMPRESS_PROBE_SIZE];
+        if !worth_it(probe.len(), compress_to_vec(probe, COMPRESSION_LEVEL).len()) {
+            return None;
+        }
+    }
+    let deflated = compress_to_vec(payload, COMPRESSION_LEVEL);
+    if !worth_it(payload.len(), deflated.len()) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(1 + deflated.len());
+    out.push(msg_type);
+    out.extend_from_slice(&deflated);
+    Some(out)
+}
+
+fn worth_it(raw: usize, deflated: usize) -> bool {
+    deflated <= raw - raw / 8
+}
+
+/// The most a compressed `msg_type` payload may inflate to, or `None`
+/// if `msg_type` is never sent compressed. A chunk is bounded by the
+/// chunk size, a single-frame blob by [`MAX_BLOB_SIZE`].
+fn inflate_limit(msg_type: u8) -> Option<usize> {
+    match msg_type {
+        MSG_SYNC_STEP_2 | MSG_MANIFEST_SYNC => Some(MAX_INFLATED_SIZE),
+        MSG_BLOB_UPDATE => Some(MAX_BLOB_SIZE),
+        MSG_BLOB_CHUNK => Some(BLOB_CHUNK_HEADER_LEN + BLOB_CHUNK_SIZE),
+        _ => None,
+    }
+}
+
+/// Decode a `MSG_COMPRESSED` payload into `(msg_type, payload)`. `None`
+/// if it wraps a type [`compress_frame`] never compresses, or the
+/// deflate stream is corrupt or inflates past that type's limit.
+pub fn decode_compressed(payload: &[u8]) -> Option<(u8, Vec<u8>)> {
+    let (&msg_type, deflated) = payload.split_first()?;
+    let limit = inflate_limit(msg_type)?;
+    let inflated = decompress_to_vec_with_limit(deflated, limit).ok()?;
+    Some((msg_type, inflated))
+}
+
+/// `frame` with a `MSG_COMPRESSED` wrapper undone, or as is if it has
+/// none. `None` if the wrapper doesn't decode.
+pub fn decompress_frame(frame: &[u8]) -> Option<Cow<'_, [u8]>> {
+    match decode_message(frame) {
+        Some((MSG_COMPRESSED, doc_id, payload)) => {
+            let (msg_type, payload) = decode_compressed(payload)?;
+            Some(Cow::Owned(encode_message(msg_type, doc_id, &payload)))
+        }
+        _ => Some(Cow::Borrowed(frame)),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Change feed (§4.3.2)
+// ---------------------------------------------------------------------------
+
+/// Where a client left off in a server's change feed. `feed` names the
+/// feed (a fresh server or wiped store starts a new one), `seq` is the
+/// last change sequence number the client has caught up to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub feed: String,
+    pub seq: u64,
+}
+
+/// Encode a client's `MSG_CHANGES` payload: `[seq u64 BE][feed utf8]`.
+/// A client without a cursor sends seq 0 and an empty feed id.
+pub fn encode_changes_request(cursor: Option<&Cursor>) -> Vec<u8> {
+    let (seq, feed) = cursor.map_or((0, ""), |c| (c.seq, c.feed.as_str()));
+    let mut out = Vec::with_capacity(8 + feed.len());
+    out.extend_from_slice(&seq.to_be_bytes());
+    out.extend_from_slice(feed.as_bytes());
+    out
+}
+
+/// Decode a client's `MSG_CHANGES` payload.
+pub fn decode_changes_request(payload: &[u8]) -> Option<Cursor> {
+    let (seq, feed) = payload.split_first_chunk::<8>()?;
+    Some(Cursor {
+        feed: std::str::from_utf8(feed).ok()?.to_string(),
+        seq: u64::from_be_bytes(*seq),
+    })
+}
+
+/// The server's `MSG_CHANGES` answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangesReply {
+    /// The cursor to resume from next time.
+    pub cursor: Cursor,
+    /// Content docs changed since the client's cursor, or `None` if the
+    /// server can't tell (no cursor, or one from another feed) and the
+    /// client must check every doc.
+    pub docs: Option<Vec<String>>,
+    /// Sent unasked, because the connection fell behind on the feed;
+    /// not the answer to the client's last request.
+    pub pushed: bool,
+}
+
+/// Encode the server's `MSG_CHANGES` payload: `[seq u64 BE][feed_len
+/// u16 BE][feed][pushed u8][known u8]`, then for a known list
+/// `[id_len u16 BE][id]` per doc.
+pub fn encode_changes_reply(reply: &ChangesReply) -> Vec<u8> {
+    let feed = reply.cursor.feed.as_bytes();
+    let mut out = Vec::new();
+    out.extend_from_slice(&reply.cursor.seq.to_be_bytes());
+    out.extend_from_slice(&(feed.len() as u16).to_be_bytes());
+    out.extend_from_slice(feed);
+    out.push(reply.pushed as u8);
+    out.push(reply.docs.is_some() as u8);
+    for doc_id in reply.docs.iter().flatten() {
+        out.extend_from_slice(&(doc_id.len() as u16).to_be_bytes());
+        out.extend_from_slice(doc_id.as_bytes());
+    }
+    out
+}
+
+/// Decode the server's `MSG_CHANGES` payload.
+pub fn decode_changes_reply(payload: &[u8]) -> Option<ChangesReply> {
+    fn string(bytes: &[u8]) -> Option<(String, &[u8])> {
+        let (len, rest) = bytes.split_first_chunk::<2>()?;
+        let len = u16::from_be_bytes(*len) as usize;
+        let s = std::str::from_utf8(rest.get(..len)?).ok()?;
+        Some((s.to_string(), &rest[len..]))
+    }
+    let (seq, rest) = payload.split_first_chunk::<8>()?;
+    let (feed, rest) = string(rest)?;
+    let (pushed, rest) = rest.split_first()?;
+    let pushed = match pushed {
+        0 => false,
+        1 => true,
+        _ => return None,
+    };
+    let (known, mut rest) = rest.split_first()?;
+    let docs = match known {
+        0 if rest.is_empty() => None,
+        1 => {
+            let mut docs = Vec::new();
+            while !rest.is_empty() {
+                let (doc_id, tail) = string(rest)?;
+                docs.push(doc_id);
+                rest = tail;
+            }
+            Some(docs)
+        }
+        _ => return None,
+    };
+    Some(ChangesReply {
+        cursor: Cursor {
+            feed,
+            seq: u64::from_be_bytes(*seq),
+        },
+        docs,
+        pushed,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::ids::{ActorId, NodeId};
@@ -229,6 +660,82 @@ mod tests {
         assert!(decode_version_handshake(&[1, 0, 0]).is_none());
     }
 
+    #[test]
+    fn caps_roundtrip_and_old_minors_imply_their_features() {
+        let caps = Caps(CAP_BLOB_CHUNKS | CAP_ERRORS);
+        assert_eq!(decode_caps(&encode_caps(caps)), Some(caps));
+        assert_eq!(decode_caps(&[0, 0, 0, 0, 0, 0, 0, 3, 0xff]), Some(Caps(3)));
+        assert_eq!(decode_caps(&[0; 7]), None);
+
+        assert_eq!(Caps::of_minor(0), Caps(0));
+        let v1_4 = Caps::of_minor(4);
+        assert!(v1_4.has(CAP_BLOB_CHUNKS) && v1_4.has(CAP_CHANGES));
+        assert!(!v1_4.has(CAP_ACKS) && !v1_4.has(CAP_ERRORS));
+        let both = Caps::ALL.intersect(caps);
+        assert_eq!(both, caps);
+        assert!(!both.has(CAP_BLOB_CHUNKS | CAP_HELLO));
+    }
+
+    #[test]
+    fn compressed_frames_roundtrip_and_skip_what_does_not_pay() {
+        let text = "- [ ] a task in a long markdown note\n".repeat(100);
+        let frame = encode_message(MSG_SYNC_STEP_2, "content:x", text.as_bytes());
+        let packed = compress_frame(&frame).unwrap();
+        assert!(packed.len() < frame.len() / 4);
+        assert_eq!(decode_message(&packed).unwrap().0, MSG_COMPRESSED);
+        assert_eq!(decompress_frame(&packed).unwrap().as_ref(), &frame[..]);
+
+        // The tag stays readable outside the compressed frame.
+        let tagged = encode_message(
+            MSG_TAGGED,
+            "content:x",
+            &encode_tagged(7, MSG_SYNC_STEP_2, text.as_bytes()),
+        );
+        let packed = compress_frame(&tagged).unwrap();
+        let (t, _, p) = decode_message(&packed).unwrap();
+        assert_eq!(t, MSG_TAGGED);
+        let (seq, t, p) = decode_tagged(p).unwrap();
+        assert_eq!((seq, t), (7, MSG_COMPRESSED));
+        assert_eq!(decode_compressed(p).unwrap(), (MSG_SYNC_STEP_2, text.clone().into_bytes()));
+
+        // Small payloads, other frame types and incompressible bytes go
+        // out as they are.
+        let small = encode_message(MSG_SYNC_STEP_2, "content:x", b"tiny");
+        assert_eq!(compress_frame(&small), None);
+        let update = encode_message(crate::protocol::MSG_UPDATE, "content:x", text.as_bytes());
+        assert_eq!(compress_frame(&update), None);
+        let noise: Vec<u8> = (0..2048u32)
+            .flat_map(|i| Sha256::digest(i.to_be_bytes()))
+            .collect();
+        let blob = encode_message(MSG_BLOB_UPDATE, "ab", &noise);
+        assert_eq!(compress_frame(&blob), None);
+        assert_eq!(decompress_frame(&blob).unwrap().as_ref(), &blob[..]);
+
+        // A corrupt stream is refused rather than passed on.
+        let bad = encode_message(MSG_COMPRESSED, "ab", &[MSG_BLOB_UPDATE, 0xff, 0xff]);
+        assert!(decompress_frame(&bad).is_none());
+
+        // So are types never sent compressed, and chunks that inflate
+        // past a chunk.
+        let mut hello = vec![crate::protocol::MSG_HELLO];
+        hello.extend(compress_to_vec(text.as_bytes(), COMPRESSION_LEVEL));
+        assert!(decode_compressed(&hello).is_none());
+        let zeros = vec![0; BLOB_CHUNK_HEADER_LEN + BLOB_CHUNK_SIZE + 1];
+        let mut chunk = vec![MSG_BLOB_CHUNK];
+        chunk.extend(compress_to_vec(&zeros, COMPRESSION_LEVEL));
+        assert!(decode_compressed(&chunk).is_none());
+        chunk[0] = MSG_SYNC_STEP_2;
+        assert!(decode_compressed(&chunk).is_some());
+    }
+
+    #[test]
+    fn hello_roundtrip() {
+        let actor = ActorId::new();
+        assert_eq!(decode_hello(&encode_hello(actor)), Some(actor));
+        assert_eq!(decode_hello(b"not-an-actor"), None);
+        assert_eq!(decode_hello(&[0xff, 0xfe]), None);
+    }
+
     #[test]
     fn version_handshake_wraps_in_outer_frame() {
         let payload = encode_version_handshake();
@@ -596,4 +1103,105 @@ mod tests {
         let s = id.to_string_hyphenated();
         assert_eq!(NodeId::parse_str(&s), Some(id));
     }
+
+    #[test]
+    fn content_digest_tracks_deletes_not_block_layout() {
+        use yrs::{Doc, Text, Update};
+
+        // `a` types one character per transaction; `b` receives the same
+        // history as a single merged update, so its blocks differ.
+        let a = Doc::new();
+        let text = a.get_or_insert_text("text");
+        for (i, c) in "hello".chars().enumerate() {
+            text.insert(&mut a.transact_mut(), i as u32, &c.to_string());
+        }
+        let b = Doc::new();
+        let full = a.transact().encode_state_as_update_v1(&StateVector::default());
+        b.transact_mut()
+            .apply_update(Update::decode_v1(&full).unwrap());
+        assert_eq!(content_digest(&a.transact()), content_digest(&b.transact()));
+
+        // A delete leaves the state vector alone but changes the digest.
+        let before = content_digest(&a.transact());
+        let sv_before = a.transact().state_vector();
+        text.remove_range(&mut a.transact_mut(), 1, 3);
+        assert_eq!(a.transact().state_vector(), sv_before);
+        assert_ne!(content_digest(&a.transact()), before);
+
+        let diff = a.transact().encode_state_as_update_v1(&b.transact().state_vector());
+        b.transact_mut()
+            .apply_update(Update::decode_v1(&diff).unwrap());
+        assert_eq!(content_digest(&a.transact()), content_digest(&b.transact()));
+    }
+
+    #[test]
+    fn sync_batch_roundtrip_and_truncation() {
+        let entries = vec![
+            BatchEntry {
+                doc_id: "content:a",
+                digest: [1; 32],
+                state_vector: &[0],
+            },
+            BatchEntry {
+                doc_id: "content:b",
+                digest: [2; 32],
+                state_vector: &[1, 2, 3],
+            },
+        ];
+        let payload = encode_sync_batch(&entries);
+        assert_eq!(decode_sync_batch(&payload), Some(entries));
+        assert_eq!(decode_sync_batch(&[]), Some(vec![]));
+        assert_eq!(decode_sync_batch(&payload[..payload.len() - 1]), None);
+        assert_eq!(decode_sync_batch(&payload[..5]), None);
+    }
+
+    #[test]
+    fn changes_request_and_reply_roundtrip() {
+        let cursor = Cursor {
+            feed: "feed-1".into(),
+            seq: 42,
+        };
+        let req = encode_changes_request(Some(&cursor));
+        assert_eq!(decode_changes_request(&req), Some(cursor.clone()));
+        let none = decode_changes_request(&encode_changes_request(None)).unwrap();
+        assert_eq!((none.seq, none.feed.as_str()), (0, ""));
+        assert_eq!(decode_changes_request(&[0; 7]), None);
+
+        for (docs, pushed) in [
+            (None, false),
+            (Some(vec![]), false),
+            (Some(vec!["content:a".into(), "content:b".into()]), true),
+        ] {
+            let reply = ChangesReply {
+                cursor: cursor.clone(),
+                docs,
+                pushed,
+            };
+            let payload = encode_changes_reply(&reply);
+            assert_eq!(decode_changes_reply(&payload), Some(reply));
+            assert_eq!(decode_changes_reply(&payload[..payload.len() - 1]), None);
+        }
+    }
+
+    #[test]
+    fn tagged_and_ack_roundtrip() {
+        let tagged = encode_tagged(7, 2, b"update");
+        assert_eq!(decode_tagged(&tagged), Some((7, 2, &b"update"[..])));
+        assert_eq!(decode_tagged(&encode_tagged(1, 4, &[])), Some((1, 4, &[][..])));
+        assert_eq!(decode_tagged(&tagged[..8]), None);
+
+        let ack = encode_ack(u64::MAX, 1);
+        assert_eq!(decode_ack(&ack), Some((u64::MAX, 1)));
+        assert_eq!(decode_ack(&ack[..8]), None);
+        assert_eq!(decode_ack(&[0; 10]), None);
+    }
+
+    #[test]
+    fn error_roundtrip() {
+        let payload = encode_error(3, "blob not found");
+        assert_eq!(decode_error(&payload), Some((3, "blob not found")));
+        assert_eq!(decode_error(&encode_error(1, "")), Some((1, "")));
+        assert_eq!(decode_error(&[0]), None);
+        assert_eq!(decode_error(&[0, 1, 0xff]), None);
+    }
 }
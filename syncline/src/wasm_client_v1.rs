@@ -27,20 +27,29 @@ use yrs::updates::encoder::Encode;
 use yrs::{Doc, GetString, ReadTxn, StateVector, Subscription, Text, Transact, Update};
 
 use crate::protocol::{
-    decode_message, encode_message, MANIFEST_DOC_ID, MSG_BLOB_REQUEST, MSG_BLOB_UPDATE,
-    MSG_MANIFEST_SYNC, MSG_MANIFEST_VERIFY, MSG_SYNC_STEP_1, MSG_SYNC_STEP_2, MSG_UPDATE,
-    MSG_VERSION,
+    decode_blob_chunk, decode_message, encode_message, ERR_BLOB_NOT_FOUND, ERR_BLOB_TOO_LARGE,
+    ERR_COMPRESSION, ERR_STORAGE, ERR_VERSION, MANIFEST_DOC_ID, MAX_BLOB_SIZE, MSG_BLOB_CHUNK, MSG_BLOB_REQUEST,
+    MSG_BLOB_UPDATE, MSG_ERROR, MSG_MANIFEST_SYNC, MSG_MANIFEST_VERIFY, MSG_HELLO,
+    MSG_SYNC_STEP_1, MSG_SYNC_STEP_2, MSG_UPDATE, MSG_VERSION, CAP_BLOB_CHUNKS, CAP_COMPRESSION,
+    CAP_ERRORS, CAP_HELLO, MSG_CAPS, V1_MINOR_CAPS,
 };
 use crate::v1::hash::hash_hex;
 use crate::v1::ids::{ActorId, Lamport, NodeId};
 use crate::v1::manifest::Manifest;
 use crate::v1::ops;
+use crate::v1::placeholder;
 use crate::v1::projection::{project, ProjectedEntry};
 use crate::v1::sync::{
-    decode_verify_payload, decode_version_handshake, encode_manifest_update, encode_verify_payload,
+    compress_frame, decode_caps, decode_error, decode_verify_payload, decode_version_handshake,
+    decompress_frame, encode_caps, encode_hello, encode_manifest_update, encode_verify_payload,
     encode_version_handshake, handle_manifest_payload, manifest_step1_payload, projection_hash,
+    Caps,
 };
 
+/// Features this client implements; the plugin neither batches
+/// subscribes, follows the change feed nor waits for acks.
+const WASM_CAPS: Caps = Caps(CAP_BLOB_CHUNKS | CAP_HELLO | CAP_ERRORS | CAP_COMPRESSION);
+
 // ---------------------------------------------------------------------------
 // Per-subdoc state
 // ---------------------------------------------------------------------------
@@ -65,11 +74,11 @@ struct ContentDoc {
 ///   2. Either `initManifest()` for a fresh vault, or
 ///      `loadManifestState(bytes, lamport)` to resume.
 ///   3. Register callbacks (`onManifestChanged`, `onContentChanged`,
-///      `onBlob`, `onStatus`).
+///      `onBlob`, `onStatus`, `onError`).
 ///   4. `connect()`.
 ///
-/// The client awaits the server's `MSG_VERSION` before sending any
-/// manifest sync frames.
+/// The client awaits the server's `MSG_VERSION` (and, from 1.7, its
+/// `MSG_CAPS`) before sending any manifest sync frames.
 #[wasm_bindgen]
 pub struct SynclineV1Client {
     url: String,
@@ -80,8 +89,15 @@ pub struct SynclineV1Client {
     content: Rc<RefCell<HashMap<NodeId, ContentDoc>>>,
     ws: Rc<RefCell<Option<WebSocket>>>,
     is_connected: Rc<RefCell<bool>>,
+    /// Whether the server reads `MSG_COMPRESSED`; settled by each
+    /// connection's handshake.
+    compress: Rc<RefCell<bool>>,
     closures: Rc<RefCell<Vec<Closure<dyn FnMut(JsValue)>>>>,
     requested_blobs: Rc<RefCell<HashSet<String>>>,
+    /// Blob replies arriving as `MSG_BLOB_CHUNK` streams, keyed by hash.
+    /// JS receives a blob as one `Uint8Array`, so pieces are collected
+    /// here until the last one lands.
+    partial_blobs: Rc<RefCell<HashMap<String, Vec<u8>>>>,
     /// Content node ids whose STEP_1 was deferred because the WebSocket
     /// hadn't completed its handshake yet. Drained on `onopen`. Without
     /// this, `subscribeContent` calls during the connect() → onopen
@@ -92,6 +108,7 @@ pub struct SynclineV1Client {
     on_content_changed: Rc<RefCell<Option<Function>>>,
     on_blob: Rc<RefCell<Option<Function>>>,
     on_status: Rc<RefCell<Option<Function>>>,
+    on_error: Rc<RefCell<Option<Function>>>,
 }
 
 #[wasm_bindgen]
@@ -117,13 +134,16 @@ impl SynclineV1Client {
             content: Rc::new(RefCell::new(HashMap::new())),
             ws: Rc::new(RefCell::new(None)),
             is_connected: Rc::new(RefCell::new(false)),
+            compress: Rc::new(RefCell::new(false)),
             closures: Rc::new(RefCell::new(Vec::new())),
             requested_blobs: Rc::new(RefCell::new(HashSet::new())),
+            partial_blobs: Rc::new(RefCell::new(HashMap::new())),
             pending_step1: Rc::new(RefCell::new(HashSet::new())),
             on_manifest_changed: Rc::new(RefCell::new(None)),
             on_content_changed: Rc::new(RefCell::new(None)),
             on_blob: Rc::new(RefCell::new(None)),
             on_status: Rc::new(RefCell::new(None)),
+            on_error: Rc::new(RefCell::new(None)),
         })
     }
 
@@ -184,6 +204,7 @@ impl SynclineV1Client {
         let is_receiving = self.manifest_is_receiving.clone();
         let ws = self.ws.clone();
         let is_connected = self.is_connected.clone();
+        let compress = self.compress.clone();
         let on_changed = self.on_manifest_changed.clone();
 
         let sub = manifest
@@ -196,7 +217,7 @@ impl SynclineV1Client {
                     if let Some(ws) = ws.borrow().as_ref() {
                         let payload = encode_manifest_update(&event.update);
                         let frame = encode_message(MSG_MANIFEST_SYNC, MANIFEST_DOC_ID, &payload);
-                        send_frame(ws, &frame);
+                        send_compressible(ws, *compress.borrow(), &frame);
                     }
                 }
                 // Callback is snapshot-cloned to drop the borrow before calling into JS.
@@ -233,6 +254,17 @@ impl SynclineV1Client {
         *self.on_status.borrow_mut() = Some(cb);
     }
 
+    /// `cb(kind, docId, message)` for each `MSG_ERROR` from the server.
+    /// `kind` is `"incompatible"` (the server refused our protocol
+    /// version and is closing; reconnecting won't help),
+    /// `"blob-too-large"`, `"blob-not-found"` (not uploaded yet; a later
+    /// `requestBlob` may succeed), `"storage"` (a server-side failure
+    /// worth retrying) or `"unknown"`.
+    #[wasm_bindgen(js_name = onError)]
+    pub fn set_on_error(&self, cb: Function) {
+        *self.on_error.borrow_mut() = Some(cb);
+    }
+
     // ---------------------------------------------------------------
     // Connection
     // ---------------------------------------------------------------
@@ -253,6 +285,7 @@ impl SynclineV1Client {
         // ON OPEN
         let ws_open = ws.clone();
         let is_connected_open = self.is_connected.clone();
+        let compress_open = self.compress.clone();
         let on_status_open = self.on_status.clone();
         let pending_step1_open = self.pending_step1.clone();
         let content_open = self.content.clone();
@@ -260,6 +293,9 @@ impl SynclineV1Client {
             let payload = encode_version_handshake();
             let frame = encode_message(MSG_VERSION, MANIFEST_DOC_ID, &payload);
             send_frame(&ws_open, &frame);
+            let frame = encode_message(MSG_CAPS, MANIFEST_DOC_ID, &encode_caps(WASM_CAPS));
+            send_frame(&ws_open, &frame);
+            *compress_open.borrow_mut() = false;
             *is_connected_open.borrow_mut() = true;
             fire_status(&on_status_open, "connected");
 
@@ -641,7 +677,7 @@ impl SynclineV1Client {
         let ws = self.ws.borrow();
         let ws = ws.as_ref().ok_or_else(|| JsValue::from_str("send_blob: no socket"))?;
         let frame = encode_message(MSG_BLOB_UPDATE, &hash, bytes);
-        send_frame(ws, &frame);
+        send_compressible(ws, *self.compress.borrow(), &frame);
         Ok(hash)
     }
 
@@ -664,6 +700,21 @@ impl SynclineV1Client {
         Ok(())
     }
 
+    /// The stub to write in place of a binary fetched on demand; see
+    /// `v1::placeholder`. Request the blob when the file is opened and
+    /// `onBlob` delivers the bytes that replace it.
+    #[wasm_bindgen(js_name = placeholderBytes)]
+    pub fn placeholder_bytes(&self, blob_hash_hex: String, size: f64) -> Uint8Array {
+        Uint8Array::from(&placeholder::render(&blob_hash_hex, size as u64)[..])
+    }
+
+    /// The blob hash `bytes` stand in for, if they're a placeholder.
+    /// Placeholder bytes must never be uploaded as a file's content.
+    #[wasm_bindgen(js_name = placeholderHash)]
+    pub fn placeholder_hash(&self, bytes: &[u8]) -> Option<String> {
+        placeholder::parse(bytes).map(|p| p.hash)
+    }
+
     // ---------------------------------------------------------------
     // Shared handles for the message dispatcher
     // ---------------------------------------------------------------
@@ -673,10 +724,13 @@ impl SynclineV1Client {
             manifest: self.manifest.clone(),
             manifest_is_receiving: self.manifest_is_receiving.clone(),
             content: self.content.clone(),
+            compress: self.compress.clone(),
             requested_blobs: self.requested_blobs.clone(),
+            partial_blobs: self.partial_blobs.clone(),
             on_manifest_changed: self.on_manifest_changed.clone(),
             on_content_changed: self.on_content_changed.clone(),
             on_blob: self.on_blob.clone(),
+            on_error: self.on_error.clone(),
         }
     }
 }
@@ -690,14 +744,40 @@ struct Handles {
     manifest: Rc<RefCell<Option<Manifest>>>,
     manifest_is_receiving: Rc<RefCell<bool>>,
     content: Rc<RefCell<HashMap<NodeId, ContentDoc>>>,
+    compress: Rc<RefCell<bool>>,
     requested_blobs: Rc<RefCell<HashSet<String>>>,
+    partial_blobs: Rc<RefCell<HashMap<String, Vec<u8>>>>,
     on_manifest_changed: Rc<RefCell<Option<Function>>>,
     on_content_changed: Rc<RefCell<Option<Function>>>,
     on_blob: Rc<RefCell<Option<Function>>>,
+    on_error: Rc<RefCell<Option<Function>>>,
+}
+
+/// Identify ourselves if the server knows how, then open the manifest
+/// sync.
+fn start_session(h: &Handles, ws: &WebSocket, caps: Caps) {
+    *h.compress.borrow_mut() = caps.has(CAP_COMPRESSION);
+    let (hello, frame) = {
+        let manifest = h.manifest.borrow();
+        let Some(m) = manifest.as_ref() else {
+            return;
+        };
+        let hello = encode_message(MSG_HELLO, MANIFEST_DOC_ID, &encode_hello(m.actor()));
+        let payload = manifest_step1_payload(m);
+        (hello, encode_message(MSG_MANIFEST_SYNC, MANIFEST_DOC_ID, &payload))
+    };
+    if caps.has(CAP_HELLO) {
+        send_frame(ws, &hello);
+    }
+    send_frame(ws, &frame);
 }
 
 fn dispatch_frame(h: &Handles, ws: &WebSocket, data: &[u8]) {
-    let Some((msg_type, doc_id, payload)) = decode_message(data) else {
+    let Some(data) = decompress_frame(data) else {
+        web_sys::console::error_1(&JsValue::from_str("[SynclineV1] malformed compressed frame"));
+        return;
+    };
+    let Some((msg_type, doc_id, payload)) = decode_message(&data) else {
         web_sys::console::error_1(&JsValue::from_str("[SynclineV1] malformed frame"));
         return;
     };
@@ -708,15 +788,15 @@ fn dispatch_frame(h: &Handles, ws: &WebSocket, data: &[u8]) {
                 web_sys::console::log_1(&JsValue::from_str(&format!(
                     "[SynclineV1] server v{major}.{minor}"
                 )));
-                let frame = {
-                    let manifest = h.manifest.borrow();
-                    let Some(m) = manifest.as_ref() else {
-                        return;
-                    };
-                    let payload = manifest_step1_payload(m);
-                    encode_message(MSG_MANIFEST_SYNC, MANIFEST_DOC_ID, &payload)
-                };
-                send_frame(ws, &frame);
+                // From 1.7 the server's MSG_CAPS follows; wait for it.
+                if minor < V1_MINOR_CAPS {
+                    start_session(h, ws, WASM_CAPS.intersect(Caps::of_minor(minor)));
+                }
+            }
+        }
+        MSG_CAPS => {
+            if let Some(server) = decode_caps(payload) {
+                start_session(h, ws, WASM_CAPS.intersect(server));
             }
         }
         MSG_MANIFEST_SYNC => {
@@ -736,7 +816,7 @@ fn dispatch_frame(h: &Handles, ws: &WebSocket, data: &[u8]) {
             match response {
                 Ok(Some(resp)) => {
                     let frame = encode_message(MSG_MANIFEST_SYNC, MANIFEST_DOC_ID, &resp);
-                    send_frame(ws, &frame);
+                    send_compressible(ws, *h.compress.borrow(), &frame);
                 }
                 Ok(None) => {}
                 Err(e) => {
@@ -818,28 +898,83 @@ fn dispatch_frame(h: &Handles, ws: &WebSocket, data: &[u8]) {
                 Some(encode_message(MSG_SYNC_STEP_2, doc_id, &update))
             };
             if let Some(f) = frame_opt {
-                send_frame(ws, &f);
+                send_compressible(ws, *h.compress.borrow(), &f);
             }
         }
         MSG_BLOB_UPDATE => {
-            // Verify hash before surfacing bytes to JS.
-            let expected = doc_id.to_string();
             if payload.is_empty() {
                 return;
             }
-            let actual = hash_hex(payload);
-            if actual != expected {
-                web_sys::console::error_1(&JsValue::from_str(&format!(
-                    "[SynclineV1] blob hash mismatch: expected {expected}, got {actual}"
-                )));
+            deliver_blob(h, doc_id, payload);
+        }
+        MSG_BLOB_CHUNK => {
+            let Some((offset, total, bytes)) = decode_blob_chunk(payload) else {
                 return;
+            };
+            let complete = {
+                let mut partial = h.partial_blobs.borrow_mut();
+                if offset == 0 {
+                    if total > MAX_BLOB_SIZE as u64 {
+                        web_sys::console::error_1(&JsValue::from_str(&format!(
+                            "[SynclineV1] blob {doc_id} too large for the browser: {total} bytes"
+                        )));
+                        return;
+                    }
+                    partial.insert(doc_id.to_string(), Vec::with_capacity(total as usize));
+                }
+                let Some(buf) = partial.get_mut(doc_id) else {
+                    return;
+                };
+                if buf.len() as u64 != offset {
+                    // Gap in the stream: drop it and let a later
+                    // requestBlob start over.
+                    partial.remove(doc_id);
+                    h.requested_blobs.borrow_mut().remove(doc_id);
+                    return;
+                }
+                buf.extend_from_slice(bytes);
+                if (buf.len() as u64) < total {
+                    None
+                } else {
+                    partial.remove(doc_id)
+                }
+            };
+            if let Some(blob) = complete {
+                deliver_blob(h, doc_id, &blob);
+            }
+        }
+        MSG_ERROR => {
+            let Some((code, message)) = decode_error(payload) else {
+                return;
+            };
+            let kind = match code {
+                ERR_VERSION => "incompatible",
+                ERR_BLOB_TOO_LARGE => "blob-too-large",
+                ERR_BLOB_NOT_FOUND => "blob-not-found",
+                ERR_STORAGE => "storage",
+                ERR_COMPRESSION => "compression",
+                _ => "unknown",
+            };
+            web_sys::console::error_1(&JsValue::from_str(&format!(
+                "[SynclineV1] server error ({kind}) for {doc_id}: {message}"
+            )));
+            if code == ERR_COMPRESSION {
+                // Send plain frames for the rest of this connection.
+                *h.compress.borrow_mut() = false;
+            }
+            if matches!(code, ERR_BLOB_NOT_FOUND | ERR_STORAGE) {
+                // Let a later requestBlob ask again.
+                h.requested_blobs.borrow_mut().remove(doc_id);
+                h.partial_blobs.borrow_mut().remove(doc_id);
             }
-            h.requested_blobs.borrow_mut().remove(&expected);
-            let cb = h.on_blob.borrow().clone();
+            let cb = h.on_error.borrow().clone();
             if let Some(cb) = cb {
-                let js_hash = JsValue::from_str(&expected);
-                let js_bytes = Uint8Array::from(payload);
-                let _ = cb.call2(&JsValue::NULL, &js_hash, &js_bytes);
+                let _ = cb.call3(
+                    &JsValue::NULL,
+                    &JsValue::from_str(kind),
+                    &JsValue::from_str(doc_id),
+                    &JsValue::from_str(message),
+                );
             }
         }
         _ => {
@@ -854,6 +989,34 @@ fn dispatch_frame(h: &Handles, ws: &WebSocket, data: &[u8]) {
 // Helpers
 // ---------------------------------------------------------------------------
 
+/// Verify a fully received blob against its hash and hand it to the
+/// `onBlob` callback. Mismatches are logged and dropped.
+fn deliver_blob(h: &Handles, expected: &str, bytes: &[u8]) {
+    let actual = hash_hex(bytes);
+    if actual != expected {
+        web_sys::console::error_1(&JsValue::from_str(&format!(
+            "[SynclineV1] blob hash mismatch: expected {expected}, got {actual}"
+        )));
+        return;
+    }
+    h.requested_blobs.borrow_mut().remove(expected);
+    let cb = h.on_blob.borrow().clone();
+    if let Some(cb) = cb {
+        let js_hash = JsValue::from_str(expected);
+        let js_bytes = Uint8Array::from(bytes);
+        let _ = cb.call2(&JsValue::NULL, &js_hash, &js_bytes);
+    }
+}
+
+/// Send `frame`, deflated if the server reads `MSG_COMPRESSED` and
+/// that pays off.
+fn send_compressible(ws: &WebSocket, compress: bool, frame: &[u8]) {
+    match compress.then(|| compress_frame(frame)).flatten() {
+        Some(packed) => send_frame(ws, &packed),
+        None => send_frame(ws, frame),
+    }
+}
+
 fn send_frame(ws: &WebSocket, bytes: &[u8]) {
     let array = Uint8Array::from(bytes);
     if let Err(e) = ws.send_with_array_buffer_view(&array) {
@@ -8,7 +8,7 @@ use tempfile::TempDir;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::{Child, Command};
 use tracing::error;
-use yrs::{Any, GetString, Map, Out, Transact};
+use yrs::{Any, GetString, Map, Out, ReadTxn, Transact};
 
 use std::sync::atomic::{AtomicU16, Ordering};
 
@@ -414,6 +414,363 @@ async fn test_two_client_sync() {
     assert_eq!(content1, "client 0 data");
 }
 
+/// On a case-sensitive disk, `todo.md` next to a synced `Todo.md` is a
+/// second file that a case-insensitive peer could not hold. Under the
+/// policy a new vault is seeded with it must be moved to a conflict name
+/// on every peer rather than synced as a clobbering sibling.
+#[tokio::test]
+async fn test_case_variant_becomes_conflict_copy() {
+    let env = TestEnv::new(2).await;
+
+    fs::write(env.client_path(0).join("Todo.md"), "upper").unwrap();
+    assert!(
+        wait_for_convergence(&env.dirs(), Duration::from_secs(5)).await,
+        "Todo.md did not sync"
+    );
+
+    fs::write(env.client_path(1).join("todo.md"), "lower").unwrap();
+    let deadline = std::time::Instant::now() + Duration::from_secs(15);
+    loop {
+        let settled = (0..2).all(|i| {
+            let dir = env.client_path(i);
+            !dir.join("todo.md").exists()
+                && fs::read_to_string(dir.join("Todo.md")).ok().as_deref() == Some("upper")
+                && find_conflict_sibling(dir, "todo", "md")
+                    .and_then(|p| fs::read_to_string(p).ok())
+                    .as_deref()
+                    == Some("lower")
+        });
+        if settled {
+            break;
+        }
+        assert!(
+            std::time::Instant::now() < deadline,
+            "case variant was not turned into a conflict copy on both peers: {:?} / {:?}",
+            collect_user_files(env.client_path(0)),
+            collect_user_files(env.client_path(1)),
+        );
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
+
+#[tokio::test]
+async fn test_windows_unsafe_name_is_escaped_and_maps_back() {
+    let mut env = TestEnv::new(2).await;
+
+    // Client 1 plays a Windows peer.
+    env.clients[1].kill().await.unwrap();
+    env.clients[1] = Command::new(syncline_bin())
+        .arg("sync")
+        .arg("--folder")
+        .arg(env.client_path(1))
+        .arg("--filename-rules")
+        .arg("windows")
+        .env("SYNCLINE_URL", format!("ws://127.0.0.1:{}/sync", env.port))
+        .env("RUST_LOG", "debug")
+        .kill_on_drop(true)
+        .spawn()
+        .expect("Failed to spawn client");
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+
+    fs::write(env.client_path(0).join("a:b?.md"), "hello").unwrap();
+    let escaped = env.client_path(1).join("a：b？.md");
+    let deadline = std::time::Instant::now() + Duration::from_secs(10);
+    while fs::read_to_string(&escaped).ok().as_deref() != Some("hello") {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "escaped name never appeared: {:?}",
+            collect_user_files(env.client_path(1))
+        );
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+
+    // An edit under the escaped name lands on the canonical one.
+    fs::write(&escaped, "edited").unwrap();
+    let original = env.client_path(0).join("a:b?.md");
+    let deadline = std::time::Instant::now() + Duration::from_secs(10);
+    while fs::read_to_string(&original).ok().as_deref() != Some("edited") {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "edit did not map back: {:?}",
+            collect_user_files(env.client_path(0))
+        );
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    assert_eq!(collect_user_files(env.client_path(0)), vec!["a:b?.md".to_string()]);
+
+    let out = std::process::Command::new(syncline_bin())
+        .arg("check-names")
+        .arg("--folder")
+        .arg(env.client_path(0))
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&out.stdout).starts_with("a:b?.md: windows:"));
+}
+
+#[tokio::test]
+async fn test_devices_lists_named_peers() {
+    let mut env = TestEnv::new(2).await;
+    for (i, name) in ["work-laptop", "home-desktop"].into_iter().enumerate() {
+        env.clients[i].kill().await.unwrap();
+        let dir = env.client_path(i).to_path_buf();
+        env.clients[i] = spawn_client_with_name(&dir, env.port, name).await;
+    }
+
+    let list = |idx: usize| {
+        let out = std::process::Command::new(syncline_bin())
+            .arg("devices")
+            .arg("--folder")
+            .arg(env.client_path(idx))
+            .output()
+            .unwrap();
+        assert!(out.status.success());
+        String::from_utf8_lossy(&out.stdout).into_owned()
+    };
+    let deadline = std::time::Instant::now() + Duration::from_secs(10);
+    loop {
+        let seen = list(1);
+        if seen.contains("work-laptop") && seen.contains("home-desktop") {
+            break;
+        }
+        assert!(std::time::Instant::now() < deadline, "devices never synced: {seen}");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    assert_eq!(
+        fs::read_to_string(env.client_path(0).join(".syncline/client_id")).unwrap().trim(),
+        "work-laptop"
+    );
+}
+
+/// A revoked device is turned away when it reconnects and stops
+/// retrying; the delete it made while offline never reaches its peers.
+#[tokio::test]
+async fn test_revoked_device_cannot_replay_offline_deletes() {
+    let mut env = TestEnv::new(2).await;
+    fs::write(env.client_path(1).join("keep.md"), "precious").unwrap();
+    assert!(wait_for_convergence(&env.dirs(), Duration::from_secs(5)).await);
+
+    env.clients[0].kill().await.unwrap();
+    let actor = fs::read_to_string(env.client_path(0).join(".syncline/actor_id")).unwrap();
+    let out = std::process::Command::new(syncline_bin())
+        .args(["server-devices", "--revoke", actor.trim(), "--db-path"])
+        .arg(env.server_dir.path().join("test.db"))
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+
+    fs::remove_file(env.client_path(0).join("keep.md")).unwrap();
+    env.clients[0] = spawn_client(env.client_path(0), env.port).await;
+    let status = tokio::time::timeout(Duration::from_secs(10), env.clients[0].wait())
+        .await
+        .expect("revoked client kept reconnecting")
+        .unwrap();
+    assert!(!status.success());
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert_eq!(
+        fs::read_to_string(env.client_path(1).join("keep.md")).unwrap(),
+        "precious"
+    );
+}
+
+#[tokio::test]
+async fn test_folder_acl_keeps_hidden_folders_off_partial_replicas() {
+    let mut env = TestEnv::new(1).await;
+    let db = env.server_dir.path().join("test.db");
+    let admin = |args: &[&str]| {
+        std::process::Command::new(syncline_bin())
+            .args(args)
+            .arg("--db-path")
+            .arg(&db)
+            .output()
+            .unwrap()
+    };
+    // A bare label can't be granted anything.
+    let out = admin(&["server-acl", "--folder", "HR", "--write", "anonymous"]);
+    assert!(!out.status.success());
+    let out = admin(&["server-tokens", "--issue", "hr"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let credential = stdout.trim().rsplit("?token=").next().unwrap().to_string();
+    let out = admin(&["server-acl", "--folder", "HR", "--write", "hr"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+
+    let full = env.client_path(0).to_path_buf();
+    env.clients[0].kill().await.unwrap();
+    env.clients[0] = Command::new(syncline_bin())
+        .arg("sync")
+        .arg("--folder")
+        .arg(&full)
+        .env("SYNCLINE_URL", format!("ws://127.0.0.1:{}/sync?token={credential}", env.port))
+        .env("RUST_LOG", "debug")
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+    fs::create_dir_all(full.join("HR")).unwrap();
+    fs::write(full.join("HR/salaries.md"), "secret").unwrap();
+    fs::write(full.join("notes.md"), "shared").unwrap();
+    tokio::time::sleep(Duration::from_secs(2)).await;
+
+    let staff_dir = TempDir::new().unwrap();
+    let staff = staff_dir.path().to_path_buf();
+    env.clients.push(
+        Command::new(syncline_bin())
+            .arg("sync")
+            .arg("--folder")
+            .arg(&staff)
+            .env("SYNCLINE_URL", format!("ws://127.0.0.1:{}/sync?token=staff", env.port))
+            .env("RUST_LOG", "debug")
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap(),
+    );
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    while fs::read_to_string(staff.join("notes.md")).ok().as_deref() != Some("shared") {
+        assert!(tokio::time::Instant::now() < deadline, "notes.md never reached staff");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    assert!(!staff.join("HR").exists());
+
+    // Staff writes where it may; its write into HR stays local.
+    fs::write(staff.join("todo.md"), "from staff").unwrap();
+    fs::create_dir_all(staff.join("HR")).unwrap();
+    fs::write(staff.join("HR/evil.md"), "overwrite?").unwrap();
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    while !full.join("todo.md").exists() {
+        assert!(tokio::time::Instant::now() < deadline, "todo.md never reached the full replica");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert!(!full.join("HR/evil.md").exists());
+    assert_eq!(fs::read_to_string(full.join("HR/salaries.md")).unwrap(), "secret");
+    assert_eq!(fs::read_to_string(staff.join("HR/evil.md")).unwrap(), "overwrite?");
+    drop(staff_dir);
+}
+
+/// Excluding a folder removes it from one device only; the eviction
+/// isn't a delete, and later changes in it stay off that device until
+/// it's included again.
+#[tokio::test]
+async fn test_selective_sync_excludes_folder_on_one_device() {
+    let mut env = TestEnv::new(2).await;
+    let (laptop, phone) = (env.client_path(0).to_path_buf(), env.client_path(1).to_path_buf());
+    fs::create_dir_all(laptop.join("Archive/2019")).unwrap();
+    fs::write(laptop.join("Archive/2019/scan.png"), [0x89, b'P', b'N', b'G', 0, 1, 2, 3]).unwrap();
+    fs::write(laptop.join("Archive/index.md"), "old stuff").unwrap();
+    fs::write(laptop.join("notes.md"), "current").unwrap();
+    assert!(wait_for_convergence(&env.dirs(), Duration::from_secs(10)).await);
+
+    let selective = |args: &[&str]| {
+        let out = std::process::Command::new(syncline_bin())
+            .arg("selective")
+            .args(args)
+            .arg("--folder")
+            .arg(&phone)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+        String::from_utf8_lossy(&out.stdout).into_owned()
+    };
+    let out = selective(&["--exclude", "Archive"]);
+    assert!(out.contains("removed 2 local files"), "{out}");
+    assert!(!phone.join("Archive").exists());
+
+    fs::write(laptop.join("Archive/new.md"), "newer stuff").unwrap();
+    fs::write(laptop.join("notes.md"), "current, edited").unwrap();
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    while fs::read_to_string(phone.join("notes.md")).unwrap() != "current, edited" {
+        assert!(tokio::time::Instant::now() < deadline, "notes.md edit never reached phone");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert!(!phone.join("Archive").exists());
+    assert!(laptop.join("Archive/2019/scan.png").exists());
+    assert_eq!(fs::read_to_string(laptop.join("Archive/index.md")).unwrap(), "old stuff");
+
+    selective(&["--include", "Archive"]);
+    env.clients[1].kill().await.unwrap();
+    env.clients[1] = spawn_client(&phone, env.port).await;
+    assert!(wait_for_convergence(&env.dirs(), Duration::from_secs(10)).await);
+    assert_eq!(fs::read_to_string(phone.join("Archive/new.md")).unwrap(), "newer stuff");
+}
+
+/// Binaries over the fetch limit arrive as placeholders that never
+/// sync back, and `syncline fetch` swaps in the real bytes.
+#[tokio::test]
+async fn test_large_binaries_fetched_on_demand() {
+    let mut env = TestEnv::new(2).await;
+    let (laptop, phone) = (env.client_path(0).to_path_buf(), env.client_path(1).to_path_buf());
+    env.clients[1].kill().await.unwrap();
+    let out = std::process::Command::new(syncline_bin())
+        .args(["selective", "--fetch-limit", "1000", "--folder"])
+        .arg(&phone)
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    env.clients[1] = spawn_client(&phone, env.port).await;
+
+    let big: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+    fs::create_dir_all(laptop.join("media")).unwrap();
+    fs::write(laptop.join("media/big.bin"), &big).unwrap();
+    fs::write(laptop.join("media/small.png"), [0x89, b'P', b'N', b'G']).unwrap();
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    while !(phone.join("media/small.png").exists() && phone.join("media/big.bin").exists()) {
+        assert!(tokio::time::Instant::now() < deadline, "files never reached phone");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert_eq!(fs::read(phone.join("media/small.png")).unwrap(), [0x89, b'P', b'N', b'G']);
+    let stub = fs::read(phone.join("media/big.bin")).unwrap();
+    assert!(stub.starts_with(b"syncline-placeholder 1\n"));
+    assert_eq!(fs::read(laptop.join("media/big.bin")).unwrap(), big);
+
+    let out = std::process::Command::new(syncline_bin())
+        .args(["fetch", "media", "--folder"])
+        .arg(&phone)
+        .env("SYNCLINE_URL", format!("ws://127.0.0.1:{}/sync", env.port))
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert!(String::from_utf8_lossy(&out.stdout).contains("fetched media/big.bin"));
+    assert_eq!(fs::read(phone.join("media/big.bin")).unwrap(), big);
+    assert!(wait_for_convergence(&env.dirs(), Duration::from_secs(10)).await);
+}
+
+/// Fetching a binary whose device hasn't uploaded it yet fails at once
+/// rather than waiting out the timeout.
+#[tokio::test]
+async fn test_fetch_fails_fast_for_blobs_not_uploaded() {
+    let mut env = TestEnv::new(1).await;
+    let folder = env.client_path(0).to_path_buf();
+    fs::write(folder.join("doc.md"), "synced").unwrap();
+    assert!(wait_for_convergence(&env.dirs(), Duration::from_secs(10)).await);
+    env.clients[0].kill().await.unwrap();
+
+    // A peer's binary the manifest knows about but the server never got.
+    let manifest = folder.join(".syncline/manifest.bin");
+    let mut m = syncline::v1::Manifest::from_update(
+        syncline::v1::ActorId::new(),
+        syncline::v1::Lamport::ZERO,
+        &fs::read(&manifest).unwrap(),
+    )
+    .unwrap();
+    syncline::v1::create_binary(&mut m, "media/late.bin", &"ab".repeat(32), 5000).unwrap();
+    fs::write(&manifest, m.encode_state_as_update()).unwrap();
+
+    let started = std::time::Instant::now();
+    let out = std::process::Command::new(syncline_bin())
+        .args(["fetch", "media", "--timeout-secs", "60", "--folder"])
+        .arg(&folder)
+        .env("SYNCLINE_URL", format!("ws://127.0.0.1:{}/sync", env.port))
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("media/late.bin is not uploaded yet"), "{stderr}");
+    assert!(started.elapsed() < Duration::from_secs(30));
+}
+
 #[tokio::test]
 async fn test_offline_edits_and_reconnection() {
     let mut env = TestEnv::new(2).await;
@@ -442,6 +799,45 @@ async fn test_offline_edits_and_reconnection() {
     assert_eq!(content1, "offline edit");
 }
 
+/// A change staged in a client's outbox but never sent — the client died
+/// first — goes out when it next connects, and `syncline status` counts
+/// it until then.
+#[tokio::test]
+async fn test_outbox_survives_client_restart() {
+    let mut env = TestEnv::new(2).await;
+    fs::write(env.client_path(0).join("doc.md"), "first").unwrap();
+    assert!(wait_for_convergence(&env.dirs(), Duration::from_secs(10)).await);
+    env.clients[0].kill().await.unwrap();
+    assert_eq!(run_status_cli(env.client_path(0)).await, "all changes saved to server");
+
+    // Stage a manifest entry the way the client would have, minus the send.
+    let syncline_dir = env.client_path(0).join(".syncline");
+    let bytes = fs::read(syncline_dir.join("manifest.bin")).unwrap();
+    let mut m = syncline::v1::Manifest::from_update(
+        syncline::v1::ActorId::new(),
+        syncline::v1::Lamport::ZERO,
+        &bytes,
+    )
+    .unwrap();
+    let pre_sv = m.doc().transact().state_vector();
+    syncline::v1::create_text(&mut m, "from-outbox.md", 0).unwrap();
+    let update = m.doc().transact().encode_state_as_update_v1(&pre_sv);
+    let mut outbox = syncline::v1::outbox::Outbox::open(&syncline_dir).unwrap();
+    outbox
+        .push(&[syncline::v1::outbox::Outgoing::Manifest(update)])
+        .unwrap();
+    assert_eq!(run_status_cli(env.client_path(0)).await, "1 change waiting to upload");
+
+    env.clients[0] = spawn_client(env.client_path(0), env.port).await;
+    let landed = env.client_path(1).join("from-outbox.md");
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(15);
+    while !landed.exists() && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    assert!(landed.exists(), "staged entry never reached client 1");
+    assert_eq!(run_status_cli(env.client_path(0)).await, "all changes saved to server");
+}
+
 #[tokio::test]
 async fn test_concurrent_conflicts() {
     let mut env = TestEnv::new(2).await;
@@ -554,6 +950,40 @@ async fn test_filter_ignored_files() {
     );
 }
 
+/// A `.synclineignore` in a subfolder applies below it, and a file it
+/// starts ignoring stops syncing but isn't deleted anywhere.
+#[tokio::test]
+async fn test_nested_ignore_file_stops_syncing_without_deleting() {
+    let env = TestEnv::new(2).await;
+    let (a, b) = (env.client_path(0), env.client_path(1));
+    fs::create_dir_all(a.join("site/build")).unwrap();
+    fs::create_dir_all(a.join("build")).unwrap();
+    fs::write(a.join("site/build/out.txt"), "v1").unwrap();
+    fs::write(a.join("build/notes.md"), "root build").unwrap();
+    assert!(wait_for_convergence(&env.dirs(), Duration::from_secs(10)).await);
+
+    fs::write(a.join("site/.synclineignore"), "/build/\n").unwrap();
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    while !b.join("site/.synclineignore").exists() {
+        assert!(tokio::time::Instant::now() < deadline, "ignore file never synced");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    fs::write(a.join("site/build/out.txt"), "v2").unwrap();
+    fs::write(a.join("site/build/new.txt"), "local only").unwrap();
+    fs::write(a.join("build/notes.md"), "root build, edited").unwrap();
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    while fs::read_to_string(b.join("build/notes.md")).unwrap() != "root build, edited" {
+        assert!(tokio::time::Instant::now() < deadline, "unignored edit never synced");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert_eq!(fs::read_to_string(b.join("site/build/out.txt")).unwrap(), "v1");
+    assert!(!b.join("site/build/new.txt").exists());
+    assert_eq!(fs::read_to_string(a.join("site/build/out.txt")).unwrap(), "v2");
+}
+
 /// Regression test for the CI failure on `propagates deletes CLI →
 /// Obsidian` after 70fde18 landed bidirectional STEP_1 reciprocation.
 ///
@@ -1062,6 +1492,40 @@ async fn test_rename_sync() {
     );
 }
 
+/// Client A moves a whole folder, text and binary files alike, into
+/// another one. Client B ends up with the files at their new paths and
+/// nothing left behind at the old ones.
+#[tokio::test]
+async fn test_directory_move_sync() {
+    let env = TestEnv::new(2).await;
+
+    let alpha = env.client_path(0).join("projects").join("alpha");
+    fs::create_dir_all(alpha.join("deep")).unwrap();
+    fs::write(alpha.join("plan.md"), "the plan").unwrap();
+    fs::write(alpha.join("deep").join("logo.png"), [0x89u8, b'P', b'N', b'G', 1, 2, 3]).unwrap();
+    assert!(
+        wait_for_convergence(&env.dirs(), Duration::from_secs(10)).await,
+        "Initial sync failed before move"
+    );
+
+    fs::create_dir(env.client_path(0).join("archive")).unwrap();
+    fs::rename(&alpha, env.client_path(0).join("archive").join("alpha")).unwrap();
+
+    assert!(
+        wait_for_convergence(&env.dirs(), Duration::from_secs(10)).await,
+        "Clients did not converge after directory move"
+    );
+    let moved = env.client_path(1).join("archive").join("alpha");
+    assert_eq!(fs::read_to_string(moved.join("plan.md")).unwrap(), "the plan");
+    assert_eq!(
+        fs::read(moved.join("deep").join("logo.png")).unwrap(),
+        [0x89u8, b'P', b'N', b'G', 1, 2, 3]
+    );
+    let old = env.client_path(1).join("projects").join("alpha");
+    assert!(!old.join("plan.md").exists());
+    assert!(!old.join("deep").join("logo.png").exists());
+}
+
 // =============================================================================
 // Binary file tests
 // =============================================================================
@@ -1097,6 +1561,25 @@ async fn test_binary_file_sync() {
     );
 }
 
+/// A binary spanning several `MSG_BLOB_CHUNK` frames must arrive intact,
+/// including a short trailing chunk.
+#[tokio::test]
+async fn test_large_binary_file_streams_in_chunks() {
+    let env = TestEnv::new(2).await;
+
+    let size = syncline::protocol::BLOB_CHUNK_SIZE * 3 + 12_345;
+    let data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+    fs::write(env.client_path(0).join("video.bin"), &data).unwrap();
+
+    tokio::time::sleep(Duration::from_secs(10)).await;
+
+    let client_b_path = env.client_path(1).join("video.bin");
+    assert!(client_b_path.exists(), "video.bin should exist on Client B");
+    let synced = fs::read(&client_b_path).unwrap();
+    assert_eq!(synced.len(), data.len());
+    assert!(synced == data, "multi-chunk binary arrived corrupted");
+}
+
 /// Client A creates a binary file, syncs it, then modifies it.
 /// The updated binary should propagate to Client B.
 #[tokio::test]
@@ -1384,6 +1867,19 @@ async fn run_verify_cli(dir: &Path, port: u16, timeout_secs: u64) -> std::proces
         .expect("failed to run verify CLI")
 }
 
+async fn run_status_cli(dir: &Path) -> String {
+    let out = Command::new(syncline_bin())
+        .arg("status")
+        .arg("--folder")
+        .arg(dir)
+        .stderr(Stdio::inherit())
+        .output()
+        .await
+        .expect("failed to run status CLI");
+    assert!(out.status.success(), "status CLI failed: {:?}", out.status);
+    String::from_utf8(out.stdout).unwrap().trim().to_string()
+}
+
 async fn run_migrate_cli(dir: &Path) -> std::process::ExitStatus {
     Command::new(syncline_bin())
         .arg("migrate")